@@ -0,0 +1,81 @@
+-- Initial schema for the relational (SQLite / PostgreSQL) storage backends.
+-- Every statement here has to run unchanged on both databases.
+-- Timestamps are stored as unix epoch milliseconds and flags as 0/1 because
+-- the sqlx Any driver can't decode SQLite booleans.
+
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT NOT NULL,
+    uid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL,
+    password TEXT NOT NULL,
+    email_verified BIGINT NOT NULL,
+    is_active BIGINT NOT NULL,
+    failed_login_attempts BIGINT NOT NULL,
+    blocked_until BIGINT,
+    created_at BIGINT,
+    updated_at BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS deks (
+    id TEXT NOT NULL,
+    uid TEXT PRIMARY KEY,
+    email TEXT NOT NULL,
+    dek TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS deks_email_idx ON deks (email);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id TEXT PRIMARY KEY,
+    uid TEXT NOT NULL,
+    email TEXT NOT NULL,
+    id_token TEXT NOT NULL,
+    refresh_token TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    os TEXT NOT NULL,
+    os_version TEXT NOT NULL,
+    vendor TEXT NOT NULL,
+    device TEXT NOT NULL,
+    browser TEXT NOT NULL,
+    browser_version TEXT NOT NULL,
+    is_revoked BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_uid_idx ON sessions (uid);
+
+CREATE TABLE IF NOT EXISTS forget_password_requests (
+    id TEXT NOT NULL,
+    req_id TEXT PRIMARY KEY,
+    email TEXT NOT NULL,
+    is_used BIGINT NOT NULL,
+    valid_till BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS email_verification_requests (
+    id TEXT NOT NULL,
+    req_id TEXT PRIMARY KEY,
+    uid TEXT NOT NULL,
+    email TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT,
+    updated_at BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS users_block_requests (
+    id TEXT NOT NULL,
+    req_id TEXT PRIMARY KEY,
+    uid TEXT NOT NULL,
+    email TEXT NOT NULL,
+    is_used BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT,
+    updated_at BIGINT
+);
@@ -0,0 +1,24 @@
+-- OAuth clients and the authorization codes issued to them.
+-- redirect_uris holds a JSON array of strings.
+
+CREATE TABLE IF NOT EXISTS oauth_clients (
+    id TEXT NOT NULL,
+    client_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    redirect_uris TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS authorization_codes (
+    id TEXT NOT NULL,
+    code_hash TEXT PRIMARY KEY,
+    client_id TEXT NOT NULL,
+    uid TEXT NOT NULL,
+    redirect_uri TEXT NOT NULL,
+    code_challenge TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    nonce TEXT,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL
+);
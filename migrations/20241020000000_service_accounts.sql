@@ -0,0 +1,12 @@
+-- Service accounts for the client_credentials grant. Like users they are
+-- encrypted with their own DEK, only uid (the client_id) is plaintext.
+
+CREATE TABLE IF NOT EXISTS service_accounts (
+    id TEXT NOT NULL,
+    uid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
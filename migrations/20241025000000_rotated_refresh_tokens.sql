@@ -0,0 +1,13 @@
+-- Refresh tokens that were already rotated, used to detect a replayed token.
+-- session_id is encrypted with the DEK of the user like in sessions.
+
+CREATE TABLE IF NOT EXISTS rotated_refresh_tokens (
+    id TEXT NOT NULL,
+    token_hash TEXT PRIMARY KEY,
+    uid TEXT NOT NULL,
+    session_id TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    rotated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS rotated_refresh_tokens_expires_at_idx ON rotated_refresh_tokens (expires_at);
@@ -0,0 +1,19 @@
+-- TOTP second factor. totp_secret is encrypted with the DEK of the user like
+-- the other user fields, totp_last_step keeps a code from being used twice.
+
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+ALTER TABLE users ADD COLUMN totp_enabled BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- Pending second steps of a sign in, only the hash of the challenge token is stored.
+CREATE TABLE IF NOT EXISTS mfa_challenges (
+    id TEXT NOT NULL,
+    token_hash TEXT PRIMARY KEY,
+    uid TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    attempts BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS mfa_challenges_expires_at_idx ON mfa_challenges (expires_at);
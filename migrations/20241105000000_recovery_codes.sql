@@ -0,0 +1,3 @@
+-- One-time MFA recovery codes, a JSON array of salted hashes.
+
+ALTER TABLE users ADD COLUMN recovery_codes TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,27 @@
+-- WebAuthn credentials (passkeys and security keys). credential_id, public_key
+-- and name are encrypted with the DEK of the user.
+
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+    id TEXT NOT NULL,
+    uid TEXT NOT NULL,
+    credential_id TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    alg BIGINT NOT NULL,
+    sign_count BIGINT NOT NULL,
+    name TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT,
+    PRIMARY KEY (uid, credential_id)
+);
+
+-- Pending registration and sign in ceremonies, only the hash of the challenge is stored.
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+    id TEXT NOT NULL,
+    challenge_hash TEXT PRIMARY KEY,
+    uid TEXT,
+    purpose TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webauthn_challenges_expires_at_idx ON webauthn_challenges (expires_at);
@@ -0,0 +1,11 @@
+-- Pending passwordless sign ins by email, only the hash of the token is stored.
+CREATE TABLE IF NOT EXISTS magic_links (
+    id TEXT NOT NULL,
+    token_hash TEXT PRIMARY KEY,
+    uid TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS magic_links_expires_at_idx ON magic_links (expires_at);
@@ -0,0 +1,15 @@
+-- Pending one-time passcodes, at most one per user and channel. The code is
+-- salted and hashed like a password.
+CREATE TABLE IF NOT EXISTS otp_codes (
+    id TEXT NOT NULL,
+    uid TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    attempts BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (uid, channel)
+);
+
+CREATE INDEX IF NOT EXISTS otp_codes_expires_at_idx ON otp_codes (expires_at);
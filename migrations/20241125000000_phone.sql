@@ -0,0 +1,5 @@
+-- Phone numbers for SMS codes. phone is encrypted with the DEK of the user
+-- like the other user fields.
+
+ALTER TABLE users ADD COLUMN phone TEXT;
+ALTER TABLE users ADD COLUMN phone_verified BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,43 @@
+-- Upstream OpenID Connect providers, client_secret is encrypted with the KEK.
+CREATE TABLE IF NOT EXISTS identity_providers (
+    id TEXT NOT NULL,
+    provider_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    issuer TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    client_secret TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+-- Provider accounts linked to users. subject and email are encrypted with the
+-- DEK of the user, subject_hash is a sha256 of the provider id and subject.
+CREATE TABLE IF NOT EXISTS user_identities (
+    id TEXT NOT NULL,
+    uid TEXT NOT NULL,
+    provider_id TEXT NOT NULL,
+    subject_hash TEXT PRIMARY KEY,
+    subject TEXT NOT NULL,
+    email TEXT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_uid_idx ON user_identities (uid);
+
+-- Sign ins through a provider, first keyed by the hash of the state and then
+-- by the hash of the one-time token the app redeems.
+CREATE TABLE IF NOT EXISTS federation_states (
+    id TEXT NOT NULL,
+    token_hash TEXT PRIMARY KEY,
+    provider_id TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    code_verifier TEXT NOT NULL,
+    redirect_url TEXT NOT NULL,
+    user_agent TEXT NOT NULL,
+    uid TEXT,
+    expires_at BIGINT NOT NULL,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS federation_states_expires_at_idx ON federation_states (expires_at);
@@ -0,0 +1,6 @@
+-- Users created through an identity provider have no password of their own
+-- until they reset it, everyone before had one.
+ALTER TABLE users ADD COLUMN has_password BIGINT NOT NULL DEFAULT 1;
+
+-- Set when the sign in with the provider links it to a signed in user.
+ALTER TABLE federation_states ADD COLUMN link_uid TEXT;
@@ -0,0 +1,18 @@
+-- SAML 2.0 identity providers. certificates, domains and role_mapping are
+-- JSON arrays.
+CREATE TABLE IF NOT EXISTS saml_connections (
+    id TEXT NOT NULL,
+    connection_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    idp_entity_id TEXT NOT NULL,
+    sso_url TEXT NOT NULL,
+    certificates TEXT NOT NULL,
+    domains TEXT NOT NULL,
+    email_attribute TEXT NOT NULL,
+    name_attribute TEXT NOT NULL,
+    role_attribute TEXT NOT NULL,
+    role_mapping TEXT NOT NULL,
+    default_role TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
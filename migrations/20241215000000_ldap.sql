@@ -0,0 +1,19 @@
+-- LDAP directories users sign in against. domains and role_mapping are JSON
+-- arrays.
+CREATE TABLE IF NOT EXISTS ldap_connections (
+    id TEXT NOT NULL,
+    connection_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    url TEXT NOT NULL,
+    start_tls BIGINT NOT NULL,
+    bind_dn TEXT NOT NULL,
+    search_base TEXT NOT NULL,
+    user_filter TEXT NOT NULL,
+    domains TEXT NOT NULL,
+    name_attribute TEXT NOT NULL,
+    group_attribute TEXT NOT NULL,
+    role_mapping TEXT NOT NULL,
+    default_role TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
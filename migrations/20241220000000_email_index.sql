@@ -0,0 +1,6 @@
+-- Deks are looked up by a blind index of the email instead of its ciphertext,
+-- which is different every time now. Existing rows get it on the next start.
+ALTER TABLE deks ADD COLUMN email_index TEXT;
+
+DROP INDEX IF EXISTS deks_email_idx;
+CREATE UNIQUE INDEX IF NOT EXISTS deks_email_index_idx ON deks (email_index);
@@ -0,0 +1,5 @@
+-- The id of the KEK the email and dek of a row are wrapped with. NULL for the
+-- rows from before it was recorded, they could be wrapped with any known KEK.
+ALTER TABLE deks ADD COLUMN kek_id TEXT;
+
+CREATE INDEX IF NOT EXISTS deks_kek_id_idx ON deks (kek_id);
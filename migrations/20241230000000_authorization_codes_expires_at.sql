@@ -0,0 +1,2 @@
+-- expired codes are now purged, by expires_at
+CREATE INDEX IF NOT EXISTS authorization_codes_expires_at_idx ON authorization_codes (expires_at);
@@ -0,0 +1,9 @@
+-- Bumped by every rotation of a dek. The writes of the records encrypted with
+-- it only go through while it still has the version they were encrypted with.
+ALTER TABLE deks ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
+
+-- The email of a forget password request is encrypted with the DEK of the user,
+-- so the request is rotated with it. The ones from before have expired.
+ALTER TABLE forget_password_requests ADD COLUMN uid TEXT NOT NULL DEFAULT '';
+
+CREATE INDEX IF NOT EXISTS forget_password_requests_uid_idx ON forget_password_requests (uid);
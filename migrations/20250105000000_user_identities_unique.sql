@@ -0,0 +1,3 @@
+-- A provider account is linked to one user at most. subject_hash is already the
+-- primary key, the index is the same one the mongo collection has.
+CREATE UNIQUE INDEX IF NOT EXISTS user_identities_provider_subject_idx ON user_identities (provider_id, subject_hash);
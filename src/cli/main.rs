@@ -8,13 +8,11 @@ pub fn create_kek() -> String {
     // convert the iv to hex string
     let hex_iv = iv.iter().map(|b| format!("{:02x}", b)).collect::<String>().chars().take(12).collect::<String>();
     // connect the key and iv with . between them
-    let key_iv = format!("{}.{}", hex_key, hex_iv);
-    return key_iv;
+    format!("{}.{}", hex_key, hex_iv)
 }
 
 pub fn generate_key_encryption_key() -> String {
-    let key = create_kek();
-    key
+    create_kek()
 }
 
 fn main() {
@@ -0,0 +1,87 @@
+use std::error::Error;
+
+use crate::{
+    config::db_connection_handler::connect_store,
+    core::{
+        dek::Dek,
+        kek::Kek,
+        key_provider,
+        keyfile::Keyfile,
+    },
+};
+
+const BATCH_SIZE: i64 = 100;
+
+// Wraps every DEK with the current KEK of the key provider. The KEK it replaces
+// has to be known to the provider, or be in PREVIOUS_SERVER_KEKS, while this
+// runs. The deks already wrapped with the current KEK are skipped, so it can be
+// stopped and run again at any time.
+pub async fn rewrap_deks() -> Result<(), Box<dyn Error>> {
+    let provider = key_provider::init()?;
+    let store = connect_store().await?;
+    let kek_id = provider.key_id();
+    println!(">> Re-wrapping the DEKs with KEK {}", kek_id);
+
+    let mut after_uid = String::new();
+    let mut rewrapped = 0;
+    let mut failed = 0;
+    loop {
+        let deks = store
+            .get_deks_to_rewrap(&kek_id, &after_uid, BATCH_SIZE)
+            .await?;
+        let last = match deks.last() {
+            Some(dek) => dek.uid.clone(),
+            None => break,
+        };
+        for dek in &deks {
+            match Dek::rewrap(store.as_ref(), dek, provider).await {
+                Ok(true) => rewrapped += 1,
+                // changed since it was read, it gets picked up by the next run
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!(">> Error re-wrapping the DEK of {}: {:?}", dek.uid, e);
+                    failed += 1;
+                }
+            }
+        }
+        after_uid = last;
+        println!(">> {} DEKs re-wrapped so far", rewrapped);
+    }
+
+    println!(">> Done. {} DEKs re-wrapped, {} failed", rewrapped, failed);
+    if failed > 0 {
+        return Err(format!("{} DEKs could not be re-wrapped", failed).into());
+    }
+    Ok(())
+}
+
+// Puts a new KEK first in the keyfile, the ones it had stay in it to unwrap the
+// DEKs not re-wrapped yet. A new keyfile starts with the KEKs set in the env, so
+// it can take over from them.
+pub fn add_keyfile_kek() -> Result<(), Box<dyn Error>> {
+    let keyfile = Keyfile::from_env()?;
+    let mut keks = match keyfile.exists() {
+        true => keyfile.read()?,
+        false => Kek::configured(),
+    };
+    let kek = Kek::new(&Dek::generate());
+    keks.insert(0, kek.clone());
+    keyfile.write(&keks)?;
+    println!(
+        ">> Added KEK {} to {}. Restart the server with KEY_PROVIDER=keyfile and run rewrap-deks",
+        kek.id,
+        keyfile.path()
+    );
+    Ok(())
+}
+
+// the key is generated in the HSM and never leaves it
+#[cfg(feature = "pkcs11")]
+pub fn create_pkcs11_kek() -> Result<(), Box<dyn Error>> {
+    let kek_id = crate::core::pkcs11::Pkcs11KeyProvider::create_key()?;
+    println!(
+        ">> Created KEK {}. Restart the server with KEY_PROVIDER=pkcs11 and run rewrap-deks",
+        kek_id
+    );
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::error::Error;
+
+pub mod kek;
+pub mod signing_key;
+
+const USAGE: &str = "Usage: inhouse-auth [COMMAND]
+
+Starts the server when no command is passed.
+
+Commands:
+  signing-keys         List the signing keys and their states
+  rotate-signing-key   Activate a new signing key right away
+  rewrap-deks          Wrap every DEK with the current KEK of the key provider
+  add-keyfile-kek      Add a new current KEK to the keyfile, creating it if needed
+  create-pkcs11-kek    Generate the PKCS11_KEY_LABEL key in the HSM (pkcs11 feature)";
+
+// one-off admin commands, run with the same environment as the server
+pub async fn run(command: &str) -> Result<(), Box<dyn Error>> {
+    match command {
+        "signing-keys" => signing_key::list(),
+        "rotate-signing-key" => signing_key::rotate(),
+        "rewrap-deks" => kek::rewrap_deks().await,
+        "add-keyfile-kek" => kek::add_keyfile_kek(),
+        #[cfg(feature = "pkcs11")]
+        "create-pkcs11-kek" => kek::create_pkcs11_kek(),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => {
+            eprintln!("Unknown command: {}\n\n{}", command, USAGE);
+            Err(format!("Unknown command: {}", command).into())
+        }
+    }
+}
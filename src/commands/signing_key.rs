@@ -0,0 +1,24 @@
+use std::error::Error;
+
+use crate::core::keyring::{KeyState, Keyring};
+
+pub fn list() -> Result<(), Box<dyn Error>> {
+    let keyring = Keyring::load()?;
+    for key in keyring.keys.iter().filter(|k| k.state != KeyState::Retired) {
+        println!(
+            "{}  {:?}  activated: {}  retires: {}",
+            key.kid,
+            key.state,
+            key.activated_at.map_or("-".to_string(), |t| t.to_rfc3339()),
+            key.retires_at.map_or("-".to_string(), |t| t.to_rfc3339()),
+        );
+    }
+    Ok(())
+}
+
+// a running server picks the new key up on its next sign, no restart needed
+pub fn rotate() -> Result<(), Box<dyn Error>> {
+    let key = Keyring::rotate()?;
+    println!(">> Signing key rotated. Active kid: {}", key.kid);
+    Ok(())
+}
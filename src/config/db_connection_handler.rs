@@ -4,6 +4,44 @@ use mongodb::{
 };
 use std::env;
 use std::error::Error;
+use std::sync::Arc;
+
+use crate::{
+    core::dek_rotation::rotation_days,
+    store::{memory_store::MemoryStore, mongo_store::MongoStore},
+    traits::store::Store,
+};
+
+// Picks the storage backend from the DB_BACKEND env variable. Defaults to mongo.
+// sqlite and postgres are only available when built with the matching cargo feature
+// and read their connection string from DATABASE_URL.
+pub async fn connect_store() -> Result<Arc<dyn Store>, Box<dyn Error>> {
+    let backend = env::var("DB_BACKEND").unwrap_or("mongo".to_string());
+
+    match backend.as_str() {
+        "mongo" => {
+            let client = connect().await?;
+            let store = MongoStore::open(client).await?;
+            // the rotation swaps in the records of a user in a transaction
+            if rotation_days().is_some() && !store.has_transactions() {
+                return Err("DEK rotation needs MongoDB to run as a replica set, unset DEK_ROTATION_DAYS or start mongod with --replSet".into());
+            }
+            Ok(Arc::new(store))
+        }
+        "memory" => {
+            println!(">> Using the in-memory store. Nothing will be persisted.");
+            Ok(Arc::new(MemoryStore::new()))
+        }
+        #[cfg(any(feature = "sqlite", feature = "postgres"))]
+        "sqlite" | "postgres" => {
+            let database_url = env::var("DATABASE_URL").expect("DATABASE_URL required");
+            let store = crate::store::sql_store::SqlStore::connect(&database_url).await?;
+            println!(">> Successfully connected to the {} database", backend);
+            Ok(Arc::new(store))
+        }
+        _ => Err(format!("Unknown DB_BACKEND: {}", backend).into()),
+    }
+}
 
 pub async fn connect() -> Result<Client, Box<dyn Error>> {
     // Load the MongoDB connection string from an environment variable:
@@ -1,6 +1,7 @@
-use mongodb::{Client, Collection};
-
-use crate::core::{dek::Dek, user::User};
+use crate::{
+    core::{dek::Dek, user::User},
+    traits::store::Store,
+};
 
 struct InitUser {
     name: String,
@@ -9,7 +10,7 @@ struct InitUser {
     password: String,
 }
 
-pub async fn init_users(mongo_client: Client) {
+pub async fn init_users(store: &dyn Store) {
     // create a few users
     let users = vec![
         InitUser {
@@ -33,9 +34,7 @@ pub async fn init_users(mongo_client: Client) {
     ];
 
     // check if the users already exist
-    let db = mongo_client.database("auth");
-    let collection: Collection<User> = db.collection("users");
-    let cursor = collection.count_documents(None, None).await.unwrap();
+    let cursor = store.count_users().await.unwrap();
 
     if cursor > 0 {
         println!(">> Users already exist. Skipping user creation.");
@@ -47,7 +46,7 @@ pub async fn init_users(mongo_client: Client) {
         // create a new user
         let new_user = User::new(&user.name, &user.email, &user.role, &user.password);
         let dek = Dek::generate();
-        match new_user.encrypt_and_add(&mongo_client, &dek).await {
+        match new_user.encrypt_and_add(store, &dek).await {
             Ok(_) => {}
             Err(e) => {
                 println!(">> Error adding user: {:?}", e);
@@ -57,7 +56,7 @@ pub async fn init_users(mongo_client: Client) {
 
         // add the dek to the deks collection
         match Dek::new(&new_user.uid, &new_user.email, &dek)
-            .encrypt_and_add(&mongo_client)
+            .encrypt_and_add(store)
             .await
         {
             Ok(_) => {}
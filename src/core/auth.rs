@@ -1,38 +1,50 @@
-use bson::{doc, DateTime};
-use mongodb::{Client, Collection};
+use bson::DateTime;
 
 use crate::{
-    core::{dek::Dek, session::Session, user::User},
+    core::{
+        dek::Dek,
+        federation::Federation,
+        key_cache::KeyCache,
+        ldap::Ldap,
+        ldap_connection::LdapConnection,
+        magic_link::MagicLink,
+        mfa::{Mfa, MfaProof},
+        mfa_challenge::{MfaChallenge, CHALLENGE_TTL_SECS},
+        otp::{OtpCode, CHANNEL_EMAIL, CHANNEL_SMS, OTP_TTL_SECS},
+        session::Session,
+        user::User,
+        webauthn::Webauthn,
+    },
     errors::{Error, Result},
-    models::auth_model::{SessionResponseForSignInOrSignUp, SignInOrSignUpResponse},
-    utils::{encryption_utils::Encryption, password_utils::Password},
+    models::{
+        auth_model::{
+            MfaChallengeResponse, SessionResponseForSignInOrSignUp, SignInOrSignUpResponse,
+            SignInResponse,
+        },
+        webauthn_model::{AssertionCredential, RequestOptionsResponse},
+    },
+    traits::store::Store,
+    utils::{
+        email_utils::Email, encryption_utils::Encryption, password_utils::Password, sms_utils::Sms,
+    },
 };
 
+// how long after signing in a user counts as having just authenticated
+pub const REAUTH_MAX_AGE_SECS: i64 = 300;
+
 pub struct Auth;
 
 impl Auth {
     pub async fn sign_up(
-        mongo_client: &Client,
+        store: &dyn Store,
+        keys: &KeyCache,
         name: &str,
         email: &str,
         role: &str,
         password: &str,
         user_agent: &str,
     ) -> Result<SignInOrSignUpResponse> {
-        let db = mongo_client.database("auth");
-
-        let collection: Collection<User> = db.collection("users");
-        let cursor = collection
-            .find_one(
-                Some(doc! {
-                    "email": email
-                }),
-                None,
-            )
-            .await
-            .unwrap();
-
-        if cursor.is_some() {
+        if User::get_from_email(store, email).await.is_ok() {
             return Err(Error::UserAlreadyExists {
                 message: "User already exists".to_string(),
             });
@@ -40,7 +52,7 @@ impl Auth {
 
         let dek = Dek::generate(); // create a data encryption key for new user
         let user = match User::new(name, email, role, password)
-            .encrypt_and_add(&mongo_client, &dek)
+            .encrypt_and_add(store, &dek)
             .await
         {
             Ok(user) => user,
@@ -49,15 +61,15 @@ impl Auth {
 
         // add the dek to the deks collection
         let dek_data = match Dek::new(&user.uid, &user.email, &dek)
-            .encrypt_and_add(&mongo_client)
+            .encrypt_and_add(store)
             .await
         {
             Ok(dek_data) => dek_data,
             Err(e) => return Err(e),
         };
 
-        let session = match Session::new(&user, user_agent)
-            .encrypt_add(&mongo_client, &dek)
+        let session = match Session::new(keys, &user, user_agent)
+            .encrypt_add(store, &dek_data)
             .await
         {
             Ok(session) => session,
@@ -82,17 +94,7 @@ impl Auth {
         })
     }
 
-    pub async fn sign_in(
-        mongo_client: &Client,
-        email: &str,
-        password: &str,
-        user_agent: &str,
-    ) -> Result<SignInOrSignUpResponse> {
-        let user = match User::get_from_email(&mongo_client, email).await {
-            Ok(user) => user,
-            Err(e) => return Err(e),
-        };
-
+    fn check_blocked(user: &User) -> Result<()> {
         // check if the user has a blocked_until date greater than the current date check in milliseconds from DateTime type
         match user.blocked_until {
             Some(blocked_until_time) => {
@@ -105,55 +107,539 @@ impl Auth {
             }
             None => {}
         }
+        Ok(())
+    }
 
-        let dek_data = match Dek::get(&mongo_client, &user.uid).await {
-            Ok(dek_data) => dek_data,
+    // Checks the email and password and keeps track of the failed attempts.
+    // Returns the decrypted user without creating a session. Users at the
+    // domain of an LDAP connection sign in with their directory password.
+    pub async fn verify_credentials(store: &dyn Store, email: &str, password: &str) -> Result<User> {
+        let connection = match LdapConnection::for_email(store, email).await {
+            Ok(connection) => connection,
             Err(e) => return Err(e),
         };
+        // directory users are created with the email as the directory has it, lowercase
+        let email = match &connection {
+            Some(_) => email.to_lowercase(),
+            None => email.to_string(),
+        };
 
-        // verify the password
-        if Password::verify_hash(password, &user.password) {
-            let session = match Session::new(&user, &user_agent)
-                .encrypt_add(&mongo_client, &dek_data.dek)
-                .await
-            {
-                Ok(session) => session,
-                Err(e) => return Err(e),
-            };
+        let user = match User::get_from_email(store, &email).await {
+            Ok(user) => Some(user),
+            // there is no DEK for an email without a user, directory users get one on their first sign in
+            Err(Error::KeyNotFound { .. }) if connection.is_some() => None,
+            Err(e) => return Err(e),
+        };
 
-            // make the failed login attempts to 0
-            match User::reset_failed_login_attempt(&mongo_client, &user.email).await {
+        if let Some(user) = &user {
+            match Self::check_blocked(user) {
                 Ok(_) => {}
                 Err(e) => return Err(e),
             }
+        }
 
-            let res = SignInOrSignUpResponse {
-                message: "Signin successful".to_string(),
-                uid: user.uid,
-                name: user.name,
-                email: user.email,
-                role: user.role,
-                created_at: user.created_at,
-                updated_at: user.updated_at,
-                email_verified: user.email_verified,
-                is_active: user.is_active,
-                session: SessionResponseForSignInOrSignUp {
-                    session_id: Encryption::encrypt_data(&session.session_id, &dek_data.dek),
-                    id_token: session.id_token,
-                    refresh_token: session.refresh_token,
-                },
-            };
+        // verify the password
+        let known_user = user.is_some();
+        let user = match &connection {
+            Some(connection) => match Ldap::authenticate(store, connection, &email, password, user).await {
+                Ok(user) => user,
+                Err(e) => return Err(e),
+            },
+            None => user.filter(|user| Password::verify_hash(password, &user.password)),
+        };
+
+        match user {
+            Some(user) => {
+                let methods = match Mfa::methods(store, &user).await {
+                    Ok(methods) => methods,
+                    Err(e) => return Err(e),
+                };
+                // make the failed login attempts to 0, with a second factor only
+                // once that was passed too, else wrong codes would never block
+                if methods.is_empty() {
+                    match User::reset_failed_login_attempt(store, &user.email).await {
+                        Ok(_) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(user)
+            }
+            None => {
+                // the directory keeps track of the attempts of users it has not signed in here yet
+                if known_user {
+                    match User::increase_failed_login_attempt(store, &email).await {
+                        Ok(_) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Err(Error::WrongCredentials {
+                    message: "Invalid credentials".to_string(),
+                })
+            }
+        }
+    }
+
+    // Sensitive account changes need the user to prove it is them again, with
+    // the password or by having signed in within the last 5 minutes.
+    pub async fn reauthenticate(
+        store: &dyn Store,
+        keys: &KeyCache,
+        id_token: &str,
+        password: Option<&str>,
+    ) -> Result<User> {
+        let uid = match Session::verify(store, keys, id_token).await {
+            Ok((token, true)) => token.uid,
+            Ok(_) => {
+                return Err(Error::SessionExpired {
+                    message: "Session expired".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        let user = match User::get_from_uid(store, &uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(password) = password {
+            // wrong passwords count towards blocking the user like on sign in
+            return Self::verify_credentials(store, &user.email, password).await;
+        }
+
+        // refreshing a session keeps its created_at, so this is the time of the sign in
+        let session = match Session::get_from_token(store, &uid, id_token).await {
+            Ok(Some(session)) => session,
+            Ok(None) => {
+                return Err(Error::SessionNotFound {
+                    message: "Session not found".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let signed_in_for = DateTime::now().timestamp_millis() - session.created_at.timestamp_millis();
+        if signed_in_for > REAUTH_MAX_AGE_SECS * 1000 {
+            return Err(Error::ReauthenticationRequired {
+                message: "Please enter your password or sign in again".to_string(),
+            });
+        }
+        Ok(user)
+    }
+
+    // Completes the second step of a sign in. Wrong codes count as failed
+    // login attempts, so they block the user like a wrong password does.
+    pub async fn verify_mfa_challenge(
+        store: &dyn Store,
+        mfa_token: &str,
+        proof: MfaProof<'_>,
+        user_agent: &str,
+    ) -> Result<User> {
+        let challenge = match MfaChallenge::get(store, mfa_token, user_agent).await {
+            Ok(challenge) => challenge,
+            Err(e) => return Err(e),
+        };
+
+        let user = match User::get_from_uid(store, &challenge.uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Self::check_blocked(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        match challenge.count_attempt(store).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        match Mfa::verify(store, &user.uid, proof).await {
+            Ok(_) => {}
+            Err(e @ (Error::InvalidMfaCode { .. } | Error::InvalidWebauthnResponse { .. })) => {
+                match User::increase_failed_login_attempt(store, &user.email).await {
+                    Ok(_) => {}
+                    Err(e) => return Err(e),
+                }
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+
+        match challenge.complete(store).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        match User::reset_failed_login_attempt(store, &user.email).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(user)
+    }
+
+    pub async fn sign_in(
+        store: &dyn Store,
+        keys: &KeyCache,
+        email: &str,
+        password: &str,
+        user_agent: &str,
+    ) -> Result<SignInResponse> {
+        let user = match Self::verify_credentials(store, email, password).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        Self::session_or_mfa_challenge(store, keys, user, user_agent).await
+    }
+
+    // Passwordless sign in with a link from the email. Opening the link proves
+    // the user owns the email, so it is marked as verified too.
+    pub async fn sign_in_with_magic_link(
+        store: &dyn Store,
+        keys: &KeyCache,
+        token: &str,
+        user_agent: &str,
+    ) -> Result<SignInResponse> {
+        let uid = match MagicLink::redeem(store, token, user_agent).await {
+            Ok(uid) => uid,
+            Err(e) => return Err(e),
+        };
+
+        let user = match User::get_from_uid(store, &uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        // a blocked user gets nothing out of the link, not even a verified email
+        match Self::check_blocked(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        match Self::mark_email_verified(store, &uid).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let user = match User::get_from_uid(store, &uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        // the link only replaces the password, not the second factor
+        Self::session_or_mfa_challenge(store, keys, user, user_agent).await
+    }
+
+    // Finishes a sign in through an upstream identity provider with the token
+    // the app got on its redirect url.
+    pub async fn sign_in_with_federation(
+        store: &dyn Store,
+        keys: &KeyCache,
+        token: &str,
+        user_agent: &str,
+    ) -> Result<SignInResponse> {
+        let uid = match Federation::redeem(store, token, user_agent).await {
+            Ok(uid) => uid,
+            Err(e) => return Err(e),
+        };
+
+        let user = match User::get_from_uid(store, &uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Self::check_blocked(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        // the provider only replaces the password, not the second factor
+        Self::session_or_mfa_challenge(store, keys, user, user_agent).await
+    }
+
+    // mails a 6 digit code to sign in with
+    pub async fn request_email_otp(store: &dyn Store, email: &str, user_agent: &str) -> Result<()> {
+        let user = match User::get_from_email(store, email).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Self::check_blocked(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let code = match OtpCode::issue(store, &user.uid, CHANNEL_EMAIL, user_agent).await {
+            Ok(code) => code,
+            Err(e) => return Err(e),
+        };
+
+        Email::new(
+            &user.name,
+            &user.email,
+            "Your sign in code",
+            &format!("Your sign in code is {} . It expires in {} minutes. If it was not you, you can ignore this email.", code, OTP_TTL_SECS / 60),
+        ).send().await;
 
-            Ok(res)
-        } else {
-            match User::increase_failed_login_attempt(&mongo_client, &user.email).await {
+        Ok(())
+    }
+
+    pub async fn sign_in_with_email_otp(
+        store: &dyn Store,
+        keys: &KeyCache,
+        email: &str,
+        code: &str,
+        user_agent: &str,
+    ) -> Result<SignInResponse> {
+        let user = match Self::verify_otp(store, email, CHANNEL_EMAIL, code, user_agent).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Self::mark_email_verified(store, &user.uid).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        let user = User {
+            email_verified: true,
+            ..user
+        };
+
+        Self::session_or_mfa_challenge(store, keys, user, user_agent).await
+    }
+
+    // texts a 6 digit code to the verified phone number of the user
+    pub async fn request_sms_otp(store: &dyn Store, email: &str, user_agent: &str) -> Result<()> {
+        let user = match User::get_from_email(store, email).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Self::check_blocked(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let phone = match (&user.phone, user.phone_verified) {
+            (Some(phone), true) => phone,
+            _ => {
+                return Err(Error::PhoneNotVerified {
+                    message: "The user has no verified phone number".to_string(),
+                })
+            }
+        };
+
+        let code = match OtpCode::issue(store, &user.uid, CHANNEL_SMS, user_agent).await {
+            Ok(code) => code,
+            Err(e) => return Err(e),
+        };
+
+        Sms::new(
+            phone,
+            &format!("Your FlexAuth sign in code is {}. It expires in {} minutes.", code, OTP_TTL_SECS / 60),
+        ).send().await
+    }
+
+    pub async fn sign_in_with_sms_otp(
+        store: &dyn Store,
+        keys: &KeyCache,
+        email: &str,
+        code: &str,
+        user_agent: &str,
+    ) -> Result<SignInResponse> {
+        let user = match Self::verify_otp(store, email, CHANNEL_SMS, code, user_agent).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        Self::session_or_mfa_challenge(store, keys, user, user_agent).await
+    }
+
+    // Checks a code sent to the user for a passwordless sign in. Wrong codes count
+    // as failed login attempts, so they block the user like a wrong password does.
+    async fn verify_otp(
+        store: &dyn Store,
+        email: &str,
+        channel: &str,
+        code: &str,
+        user_agent: &str,
+    ) -> Result<User> {
+        let user = match User::get_from_email(store, email).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Self::check_blocked(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        match OtpCode::verify(store, &user.uid, channel, code, user_agent).await {
+            Ok(_) => {}
+            Err(e @ Error::InvalidOtp { .. }) => {
+                match User::increase_failed_login_attempt(store, &user.email).await {
+                    Ok(_) => {}
+                    Err(e) => return Err(e),
+                }
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        }
+
+        let methods = match Mfa::methods(store, &user).await {
+            Ok(methods) => methods,
+            Err(e) => return Err(e),
+        };
+        // like after a correct password, with a second factor only once that was passed too
+        if methods.is_empty() {
+            match User::reset_failed_login_attempt(store, &user.email).await {
                 Ok(_) => {}
                 Err(e) => return Err(e),
             }
-            Err(Error::WrongCredentials {
-                message: "Invalid credentials".to_string(),
-            })
         }
+
+        Ok(user)
+    }
+
+    // a code or link that arrived in the inbox proves the user owns the email
+    async fn mark_email_verified(store: &dyn Store, uid: &str) -> Result<()> {
+        let (mut stored_user, dek_data) = match User::get_stored(store, uid).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        if stored_user.email_verified {
+            return Ok(());
+        }
+        stored_user.email_verified = true;
+        stored_user.updated_at = Some(DateTime::now());
+        match store.update_user(&stored_user, dek_data.version).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // creates the session right away, or hands out an MFA challenge first
+    async fn session_or_mfa_challenge(
+        store: &dyn Store,
+        keys: &KeyCache,
+        user: User,
+        user_agent: &str,
+    ) -> Result<SignInResponse> {
+        let methods = match Mfa::methods(store, &user).await {
+            Ok(methods) => methods,
+            Err(e) => return Err(e),
+        };
+        // the session is only created once the second factor was passed too
+        if !methods.is_empty() {
+            let mfa_token = match MfaChallenge::issue(store, &user.uid, user_agent).await {
+                Ok(token) => token,
+                Err(e) => return Err(e),
+            };
+            return Ok(SignInResponse::MfaRequired(MfaChallengeResponse {
+                message: "MFA required".to_string(),
+                mfa_required: true,
+                mfa_token,
+                methods,
+                expires_in: CHALLENGE_TTL_SECS,
+            }));
+        }
+
+        match Self::create_session(store, keys, user, user_agent).await {
+            Ok(res) => Ok(SignInResponse::Session(res)),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn sign_in_with_mfa(
+        store: &dyn Store,
+        keys: &KeyCache,
+        mfa_token: &str,
+        proof: MfaProof<'_>,
+        user_agent: &str,
+    ) -> Result<SignInOrSignUpResponse> {
+        let user = match Self::verify_mfa_challenge(store, mfa_token, proof, user_agent).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        Self::create_session(store, keys, user, user_agent).await
+    }
+
+    // the WebAuthn options for the second step, limited to the passkeys of the user
+    pub async fn mfa_webauthn_options(
+        store: &dyn Store,
+        mfa_token: &str,
+        user_agent: &str,
+    ) -> Result<RequestOptionsResponse> {
+        let challenge = match MfaChallenge::get(store, mfa_token, user_agent).await {
+            Ok(challenge) => challenge,
+            Err(e) => return Err(e),
+        };
+
+        Webauthn::authentication_options(store, Some(&challenge.uid)).await
+    }
+
+    // Passwordless sign in with a passkey. The passkey has verified the user
+    // itself (PIN or biometrics), so it stands in for both factors.
+    pub async fn sign_in_with_passkey(
+        store: &dyn Store,
+        keys: &KeyCache,
+        credential: &AssertionCredential,
+        user_agent: &str,
+    ) -> Result<SignInOrSignUpResponse> {
+        let uid = match Webauthn::verify_assertion(store, None, credential).await {
+            Ok(uid) => uid,
+            Err(e) => return Err(e),
+        };
+
+        let user = match User::get_from_uid(store, &uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Self::check_blocked(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        Self::create_session(store, keys, user, user_agent).await
+    }
+
+    async fn create_session(
+        store: &dyn Store,
+        keys: &KeyCache,
+        user: User,
+        user_agent: &str,
+    ) -> Result<SignInOrSignUpResponse> {
+        let dek_data = match Dek::get(store, &user.uid).await {
+            Ok(dek_data) => dek_data,
+            Err(e) => return Err(e),
+        };
+
+        let session = match Session::new(keys, &user, &user_agent)
+            .encrypt_add(store, &dek_data)
+            .await
+        {
+            Ok(session) => session,
+            Err(e) => return Err(e),
+        };
+
+        Ok(SignInOrSignUpResponse {
+            message: "Signin successful".to_string(),
+            uid: user.uid,
+            name: user.name,
+            email: user.email,
+            role: user.role,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            email_verified: user.email_verified,
+            is_active: user.is_active,
+            session: SessionResponseForSignInOrSignUp {
+                session_id: Encryption::encrypt_data(&session.session_id, &dek_data.dek),
+                id_token: session.id_token,
+                refresh_token: session.refresh_token,
+            },
+        })
     }
-    
 }
@@ -0,0 +1,195 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    traits::store::Store,
+    utils::oauth_utils::{generate_token, hash_token, pkce_challenge},
+};
+
+// codes are exchanged by the client right after the redirect, so they are short lived
+const CODE_TTL_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationCode {
+    pub _id: ObjectId,
+    // only the hash of the code is stored, the code itself is handed to the client once
+    pub code_hash: String,
+    pub client_id: String,
+    pub uid: String,
+    pub redirect_uri: String,
+    // S256 challenge, the client has to present the matching verifier on /oauth/token
+    pub code_challenge: String,
+    pub scope: String,
+    pub nonce: Option<String>,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+impl AuthorizationCode {
+    // stores a new code and returns the plain code to hand to the client
+    pub async fn issue(
+        store: &dyn Store,
+        client_id: &str,
+        uid: &str,
+        redirect_uri: &str,
+        code_challenge: &str,
+        scope: &str,
+        nonce: Option<&str>,
+    ) -> Result<String> {
+        let code = generate_token(32);
+        let authorization_code = Self {
+            _id: ObjectId::new(),
+            code_hash: hash_token(&code),
+            client_id: client_id.to_string(),
+            uid: uid.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            code_challenge: code_challenge.to_string(),
+            scope: scope.to_string(),
+            nonce: nonce.map(|n| n.to_string()),
+            expires_at: DateTime::from_millis(
+                DateTime::now().timestamp_millis() + CODE_TTL_SECS * 1000,
+            ),
+            created_at: DateTime::now(),
+        };
+
+        match store.insert_authorization_code(&authorization_code).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        // nothing else cleans up after the unused ones
+        match store.delete_expired_authorization_codes().await {
+            Ok(_) => Ok(code),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Redeems the code. It is consumed even if the checks fail so a leaked code
+    // can't be retried.
+    pub async fn redeem(
+        store: &dyn Store,
+        code: &str,
+        client_id: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+    ) -> Result<Self> {
+        let authorization_code = match store.take_authorization_code(&hash_token(code)).await {
+            Ok(Some(authorization_code)) => authorization_code,
+            Ok(None) => {
+                return Err(Error::InvalidGrant {
+                    message: "Invalid authorization code".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        if authorization_code.expires_at.timestamp_millis() < DateTime::now().timestamp_millis() {
+            return Err(Error::InvalidGrant {
+                message: "Authorization code expired".to_string(),
+            });
+        }
+        if authorization_code.client_id != client_id
+            || authorization_code.redirect_uri != redirect_uri
+        {
+            return Err(Error::InvalidGrant {
+                message: "Authorization code was issued to another client".to_string(),
+            });
+        }
+        if pkce_challenge(code_verifier) != authorization_code.code_challenge {
+            return Err(Error::InvalidGrant {
+                message: "Invalid code verifier".to_string(),
+            });
+        }
+
+        Ok(authorization_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::memory_store::MemoryStore, traits::store::OAuthStore};
+
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CLIENT_ID: &str = "app";
+    const REDIRECT_URI: &str = "https://app.example.com/callback";
+
+    async fn issue(store: &dyn Store) -> String {
+        AuthorizationCode::issue(store, CLIENT_ID, "u1", REDIRECT_URI, &pkce_challenge(VERIFIER), "openid", None)
+            .await
+            .unwrap()
+    }
+
+    fn assert_invalid_grant(res: Result<AuthorizationCode>, message: &str) {
+        match res {
+            Err(Error::InvalidGrant { message: m }) => assert_eq!(m, message),
+            other => panic!("expected InvalidGrant, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn redeems_a_code_once() {
+        let store = MemoryStore::new();
+        let code = issue(&store).await;
+        let redeemed = AuthorizationCode::redeem(&store, &code, CLIENT_ID, REDIRECT_URI, VERIFIER)
+            .await
+            .unwrap();
+        assert_eq!(redeemed.uid, "u1");
+        assert_invalid_grant(
+            AuthorizationCode::redeem(&store, &code, CLIENT_ID, REDIRECT_URI, VERIFIER).await,
+            "Invalid authorization code",
+        );
+    }
+
+    // every failed check uses the code up, the right values don't work after it
+    #[tokio::test]
+    async fn rejects_and_consumes_a_code_on_a_failed_check() {
+        let store = MemoryStore::new();
+        let other_verifier = "a".repeat(43);
+        let failed_checks = [
+            (CLIENT_ID, REDIRECT_URI, other_verifier.as_str(), "Invalid code verifier"),
+            ("other", REDIRECT_URI, VERIFIER, "Authorization code was issued to another client"),
+            (CLIENT_ID, "https://evil.example.com/callback", VERIFIER, "Authorization code was issued to another client"),
+        ];
+        for (client_id, redirect_uri, verifier, message) in failed_checks {
+            let code = issue(&store).await;
+            assert_invalid_grant(
+                AuthorizationCode::redeem(&store, &code, client_id, redirect_uri, verifier).await,
+                message,
+            );
+            assert_invalid_grant(
+                AuthorizationCode::redeem(&store, &code, CLIENT_ID, REDIRECT_URI, VERIFIER).await,
+                "Invalid authorization code",
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_and_consumes_an_expired_code() {
+        let store = MemoryStore::new();
+        let code = generate_token(32);
+        store
+            .insert_authorization_code(&AuthorizationCode {
+                _id: ObjectId::new(),
+                code_hash: hash_token(&code),
+                client_id: CLIENT_ID.to_string(),
+                uid: "u1".to_string(),
+                redirect_uri: REDIRECT_URI.to_string(),
+                code_challenge: pkce_challenge(VERIFIER),
+                scope: "openid".to_string(),
+                nonce: None,
+                expires_at: DateTime::from_millis(DateTime::now().timestamp_millis() - 1000),
+                created_at: DateTime::now(),
+            })
+            .await
+            .unwrap();
+        assert_invalid_grant(
+            AuthorizationCode::redeem(&store, &code, CLIENT_ID, REDIRECT_URI, VERIFIER).await,
+            "Authorization code expired",
+        );
+        assert_invalid_grant(
+            AuthorizationCode::redeem(&store, &code, CLIENT_ID, REDIRECT_URI, VERIFIER).await,
+            "Invalid authorization code",
+        );
+    }
+}
@@ -1,21 +1,52 @@
-use std::env;
+use std::{env, sync::OnceLock};
 
 use aes_gcm::{aead::OsRng, AeadCore, Aes256Gcm, KeyInit};
-use bson::{doc, oid::ObjectId, DateTime};
-use mongodb::{Client, Collection};
+use bson::{oid::ObjectId, DateTime};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    core::key_provider::key_provider,
     errors::{Error, Result},
-    traits::{decryption::Decrypt, encryption::Encrypt}, utils::encryption_utils::Encryption,
+    traits::{key_provider::KeyProvider, store::Store},
+    utils::encryption_utils::Encryption,
 };
 
+// loaded once, the index of an email has to be the same for the whole process
+static EMAIL_INDEX_KEY: OnceLock<String> = OnceLock::new();
+
+// loads EMAIL_INDEX_KEY, so a missing key fails at startup instead of on the first request
+pub fn init_email_index_key() -> Result<&'static str> {
+    if let Some(key) = EMAIL_INDEX_KEY.get() {
+        return Ok(key);
+    }
+    let key = match env::var("EMAIL_INDEX_KEY") {
+        Ok(key) if !key.trim().is_empty() => key,
+        _ => {
+            return Err(Error::KeyNotFound {
+                message: "EMAIL_INDEX_KEY must be set".to_string(),
+            })
+        }
+    };
+    Ok(EMAIL_INDEX_KEY.get_or_init(|| key))
+}
+
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct Dek {
     pub _id: ObjectId,
     pub uid: String,
     pub email: String,
+    // blind index of the email, the record is looked up by it. Records from
+    // before it existed get one when the server starts.
+    #[serde(default)]
+    pub email_index: Option<String>,
     pub dek: String,
+    // the KEK the email and dek are wrapped with, None for the ones from before it was recorded
+    #[serde(default)]
+    pub kek_id: Option<String>,
+    // Bumped by every rotation. Writes of records encrypted with the dek carry
+    // the version they were encrypted with and fail once it is not current.
+    #[serde(default)]
+    pub version: i64,
     pub created_at: DateTime,
     pub updated_at: DateTime,
 }
@@ -26,12 +57,21 @@ impl Dek {
             _id: ObjectId::new(),
             uid: uid.to_string(),
             email: email.to_string(),
+            email_index: None,
             dek: dek.to_string(),
+            kek_id: None,
+            version: 0,
             created_at: DateTime::now(),
             updated_at: DateTime::now(),
         }
     }
 
+    // emails are matched case-insensitively, so the index is of the normalized email
+    pub fn email_index(email: &str) -> Result<String> {
+        let index_key = init_email_index_key()?;
+        Ok(Encryption::blind_index(&email.trim().to_lowercase(), index_key))
+    }
+
     pub fn generate() -> String {
         let key = Aes256Gcm::generate_key(OsRng);
         // convert the key to hex string
@@ -56,75 +96,98 @@ impl Dek {
         return key_iv;
     }
     
-    pub async fn encrypt_and_add(&self, mongo_client: &Client) -> Result<Self> {
-        let db = mongo_client.database("auth");
-        let collection_dek: Collection<Dek> = db.collection("deks");
+    // the email index and kek id are not wrapped, only the email and the dek are
+    fn unwrap_with(&self, provider: &dyn KeyProvider) -> Option<Self> {
+        let key_id = self.kek_id.as_deref();
+        Some(Self {
+            email: provider.unwrap(&self.email, key_id)?,
+            dek: provider.unwrap(&self.dek, key_id)?,
+            ..self.clone()
+        })
+    }
 
-        let server_kek = env::var("SERVER_KEK").expect("Server Kek must be set.");
+    pub fn wrap_with(&self, provider: &dyn KeyProvider) -> Result<Self> {
+        Ok(Self {
+            email: provider.wrap(&self.email)?,
+            email_index: Some(Self::email_index(&self.email)?),
+            dek: provider.wrap(&self.dek)?,
+            kek_id: Some(provider.key_id()),
+            ..self.clone()
+        })
+    }
 
-        let encrypted_dek = self.encrypt(&server_kek);
+    pub async fn encrypt_and_add(&self, store: &dyn Store) -> Result<Self> {
+        let encrypted_dek = self.wrap_with(key_provider())?;
 
-        match collection_dek.insert_one(&encrypted_dek, None).await {
+        match store.insert_dek(&encrypted_dek).await {
             Ok(_) => return Ok(self.clone()),
-            Err(e) => return Err(Error::ServerError {
-                message: e.to_string(),
-            }),
+            Err(e) => return Err(e),
         }
     }
 
-    pub async fn get(mongo_client: &Client, identifier: &str) -> Result<Self> {
-        let db = mongo_client.database("auth");
-        let collection_dek: Collection<Dek> = db.collection("deks");
-
-        let server_kek = env::var("SERVER_KEK").expect("Server Kek must be set.");
+    pub async fn get(store: &dyn Store, identifier: &str) -> Result<Self> {
+        let stored = match Self::find(store, identifier).await {
+            Ok(dek) => dek,
+            Err(e) => return Err(e),
+        };
+        stored.unwrap()
+    }
 
+    // the dek as it is stored, still wrapped
+    pub async fn find(store: &dyn Store, identifier: &str) -> Result<Self> {
         // check if the identifier is a email or uid using regex
         let email_regex =
             regex::Regex::new(r"^[a-zA-Z0-9_.+-]+@[a-zA-Z0-9-]+\.[a-zA-Z0-9-.]+$").unwrap();
         let is_email = email_regex.is_match(identifier);
-        match is_email {
-            true => {
-                // encrypt the email using kek
-                let encrypted_identifier = Encryption::encrypt_data(&identifier, &server_kek);
-                let cursor_dek = collection_dek
-                    .find_one(
-                        Some(doc! {
-                            "email": encrypted_identifier.clone(),
-                        }),
-                        None,
-                    )
-                    .await
-                    .unwrap();
-
-                match cursor_dek {
-                    Some(data) => return Ok(data.decrypt(&server_kek)),
-                    None => {
-                        return Err(Error::KeyNotFound {
-                            message: "DEK not found".to_string(),
-                        });
-                    }
-                };
-            }
-            false => {
-                let cursor_dek = collection_dek
-                    .find_one(
-                        Some(doc! {
-                            "uid": identifier,
-                        }),
-                        None,
-                    )
-                    .await
-                    .unwrap();
-
-                match cursor_dek {
-                    Some(data) => return Ok(data.decrypt(&server_kek)),
-                    None => {
-                        return Err(Error::KeyNotFound {
-                            message: "DEK not found".to_string(),
-                        });
-                    }
-                };
-            }
+        let dek = match is_email {
+            true => store.get_dek_by_email_index(&Self::email_index(identifier)?).await?,
+            false => store.get_dek(identifier).await?,
+        };
+
+        match dek {
+            Some(data) => Ok(data),
+            None => Err(Error::KeyNotFound {
+                message: "DEK not found".to_string(),
+            }),
         }
     }
+
+    // a stored dek unwrapped with the configured provider
+    pub fn unwrap(&self) -> Result<Self> {
+        match self.unwrap_with(key_provider()) {
+            Some(dek) => Ok(dek),
+            None => Err(Error::KeyNotFound {
+                message: "None of the KEKs unwraps the DEK".to_string(),
+            }),
+        }
+    }
+
+    // Wraps a stored dek with the current KEK of the provider. It is only
+    // replaced if it was not changed in the meantime, returns false if it was.
+    pub async fn rewrap(store: &dyn Store, stored: &Self, provider: &dyn KeyProvider) -> Result<bool> {
+        let dek = match stored.unwrap_with(provider) {
+            Some(dek) => dek,
+            None => {
+                return Err(Error::KeyNotFound {
+                    message: "None of the KEKs unwraps the DEK".to_string(),
+                });
+            }
+        };
+        let mut dek = dek.wrap_with(provider)?;
+        dek.updated_at = DateTime::now();
+        store.rewrap_dek(&dek, stored.kek_id.as_deref()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_index_ignores_case_and_surrounding_whitespace() {
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+        let index = Dek::email_index("jane@example.com").unwrap();
+        assert_eq!(Dek::email_index(" Jane@Example.com ").unwrap(), index);
+        assert_ne!(Dek::email_index("john@example.com").unwrap(), index);
+    }
 }
@@ -0,0 +1,315 @@
+use std::{collections::HashSet, env};
+
+use bson::DateTime;
+
+use crate::{
+    core::{
+        dek::Dek, key_provider::key_provider, rotated_refresh_token::RotatedRefreshToken, session::Session,
+        user::User, user_identity::UserIdentity, webauthn::WebauthnCredential,
+    },
+    errors::{Error, Result},
+    models::{
+        password_model::ForgetPasswordRequest,
+        user_model::{EmailVerificationRequest, UserBlockRequest},
+    },
+    traits::{decryption::Decrypt, encryption::Encrypt, store::Store},
+    utils::encryption_utils::Encryption,
+};
+
+// A user and every record of them encrypted with their DEK
+#[derive(Clone)]
+pub struct UserRecords {
+    pub user: User,
+    pub sessions: Vec<Session>,
+    pub rotated_refresh_tokens: Vec<RotatedRefreshToken>,
+    pub webauthn_credentials: Vec<WebauthnCredential>,
+    pub identities: Vec<UserIdentity>,
+    pub forget_password_requests: Vec<ForgetPasswordRequest>,
+    pub email_verification_requests: Vec<EmailVerificationRequest>,
+    pub block_requests: Vec<UserBlockRequest>,
+}
+
+// The new DEK of a user and the one it replaces. The store reads the records
+// of the user in the same transaction it swaps the DEK in, and has them
+// re-encrypted with reencrypt before writing them back.
+pub struct DekRotation {
+    // wrapped, with the version after the current one
+    pub dek: Dek,
+    from: String,
+    to: String,
+}
+
+// DEK_ROTATION_DAYS turns the scheduled rotation on, DEKs older than it get rotated
+pub fn rotation_days() -> Option<i64> {
+    match env::var("DEK_ROTATION_DAYS").map(|days| days.parse::<i64>()) {
+        Ok(Ok(days)) if days > 0 => Some(days),
+        _ => None,
+    }
+}
+
+impl DekRotation {
+    // the records encrypted with the new DEK instead of the current one
+    pub fn reencrypt(&self, records: &UserRecords) -> UserRecords {
+        let reencrypt = |cipher_text: &str| {
+            Encryption::encrypt_data(&Encryption::decrypt_data(cipher_text, &self.from), &self.to)
+        };
+        UserRecords {
+            user: records.user.decrypt(&self.from).encrypt(&self.to),
+            sessions: records
+                .sessions
+                .iter()
+                .map(|session| session.decrypt(&self.from).encrypt(&self.to))
+                .collect(),
+            rotated_refresh_tokens: records
+                .rotated_refresh_tokens
+                .iter()
+                .map(|token| RotatedRefreshToken {
+                    session_id: reencrypt(&token.session_id),
+                    ..token.clone()
+                })
+                .collect(),
+            webauthn_credentials: records
+                .webauthn_credentials
+                .iter()
+                .map(|credential| credential.decrypt(&self.from).encrypt(&self.to))
+                .collect(),
+            identities: records
+                .identities
+                .iter()
+                .map(|identity| UserIdentity {
+                    subject: reencrypt(&identity.subject),
+                    email: identity.email.as_deref().map(reencrypt),
+                    ..identity.clone()
+                })
+                .collect(),
+            forget_password_requests: records
+                .forget_password_requests
+                .iter()
+                .map(|request| ForgetPasswordRequest {
+                    email: reencrypt(&request.email),
+                    ..request.clone()
+                })
+                .collect(),
+            email_verification_requests: records
+                .email_verification_requests
+                .iter()
+                .map(|request| EmailVerificationRequest {
+                    email: reencrypt(&request.email),
+                    ..request.clone()
+                })
+                .collect(),
+            block_requests: records
+                .block_requests
+                .iter()
+                .map(|request| UserBlockRequest {
+                    email: reencrypt(&request.email),
+                    ..request.clone()
+                })
+                .collect(),
+        }
+    }
+
+    // Gives the user a new DEK and re-encrypts everything of them with it. The
+    // store swaps it all in at once, so nothing is ever left encrypted with a
+    // DEK that is gone. Returns the uid of the user.
+    pub async fn rotate(store: &dyn Store, identifier: &str) -> Result<String> {
+        // the rotation only goes through if the stored dek still has this version
+        let stored = match Dek::find(store, identifier).await {
+            Ok(dek) => dek,
+            Err(e) => return Err(e),
+        };
+        let current = match stored.unwrap() {
+            Ok(dek) => dek,
+            Err(e) => return Err(e),
+        };
+        let uid = current.uid.clone();
+
+        let to = Dek::generate();
+        let rotation = Self {
+            dek: Dek {
+                dek: to.clone(),
+                version: stored.version + 1,
+                created_at: DateTime::now(),
+                updated_at: DateTime::now(),
+                ..current.clone()
+            }
+            .wrap_with(key_provider())?,
+            from: current.dek,
+            to,
+        };
+
+        match store.rotate_dek(&rotation, stored.version).await {
+            Ok(true) => Ok(uid),
+            // the dek was rotated while this ran
+            Ok(false) => Err(Error::DekRotated {
+                message: "The DEK changed during the rotation, please try again".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Rotates the DEKs of the users that are older than max_age_days. Returns
+    // how many were rotated.
+    pub async fn run_policy(store: &dyn Store, max_age_days: i64) -> Result<u64> {
+        let cutoff = DateTime::now().timestamp_millis() - max_age_days * 24 * 60 * 60 * 1000;
+
+        // service accounts have a DEK too, only the ones of users are rotated
+        let uids: HashSet<String> = match store.get_users().await {
+            Ok(users) => users.into_iter().map(|user| user.uid).collect(),
+            Err(e) => return Err(e),
+        };
+        let deks = match store.get_deks().await {
+            Ok(deks) => deks,
+            Err(e) => return Err(e),
+        };
+
+        let mut rotated = 0;
+        for dek in deks
+            .iter()
+            .filter(|dek| uids.contains(&dek.uid) && dek.created_at.timestamp_millis() < cutoff)
+        {
+            match Self::rotate(store, &dek.uid).await {
+                Ok(_) => rotated += 1,
+                Err(e) => eprintln!(">> Error rotating the DEK of {}: {:?}", dek.uid, e),
+            }
+        }
+        Ok(rotated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory_store::MemoryStore;
+
+    fn set_keys() {
+        env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+    }
+
+    fn session(uid: &str, session_id: &str, refresh_token: &str) -> Session {
+        Session {
+            uid: uid.to_string(),
+            session_id: session_id.to_string(),
+            email: "ada@example.com".to_string(),
+            id_token: "id token".to_string(),
+            refresh_token: refresh_token.to_string(),
+            user_agent: "curl".to_string(),
+            os: "Linux".to_string(),
+            os_version: "6".to_string(),
+            vendor: "".to_string(),
+            device: "pc".to_string(),
+            browser: "curl".to_string(),
+            browser_version: "8".to_string(),
+            is_revoked: false,
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    // the plain sessions of the user, decrypted with their current DEK
+    async fn plain_sessions(store: &dyn Store, uid: &str) -> Vec<Session> {
+        let dek = Dek::get(store, uid).await.unwrap();
+        let mut sessions: Vec<Session> = store
+            .get_sessions_by_uid(uid)
+            .await
+            .unwrap()
+            .iter()
+            .map(|session| session.decrypt(&dek.dek))
+            .collect();
+        sessions.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        sessions
+    }
+
+    // A revoke and a refresh around a rotation: the revoke made before it is
+    // kept, the refresh that read the DEK before it fails instead of writing
+    // tokens encrypted with the DEK that is gone.
+    async fn writes_around_a_rotation(store: &dyn Store) {
+        set_keys();
+        let dek = Dek::generate();
+        let user = User::new("Ada", "ada@example.com", "user", "secret")
+            .encrypt_and_add(store, &dek)
+            .await
+            .unwrap();
+        let before = Dek::new(&user.uid, &user.email, &dek)
+            .encrypt_and_add(store)
+            .await
+            .unwrap();
+        session(&user.uid, "s1", "r1").encrypt_add(store, &before).await.unwrap();
+        session(&user.uid, "s2", "r2").encrypt_add(store, &before).await.unwrap();
+
+        // the refresh reads the session and the DEK
+        let mut refreshed = store
+            .get_sessions_by_uid(&user.uid)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|stored| stored.decrypt(&before.dek).session_id == "s1")
+            .unwrap();
+        let stored_user = store.get_user(&user.uid).await.unwrap().unwrap();
+
+        Session::revoke(store, "s2", &user.uid).await.unwrap();
+        DekRotation::rotate(store, &user.uid).await.unwrap();
+
+        // then writes what it made with the DEK it read
+        refreshed.refresh_token = Encryption::encrypt_data("r1 refreshed", &before.dek);
+        assert!(matches!(
+            store.update_session(&refreshed, before.version).await,
+            Err(Error::DekRotated { .. })
+        ));
+        assert!(matches!(
+            store.update_user(&stored_user, before.version).await,
+            Err(Error::DekRotated { .. })
+        ));
+        assert!(matches!(
+            session(&user.uid, "s3", "r3").encrypt_add(store, &before).await,
+            Err(Error::DekRotated { .. })
+        ));
+        // as does a rotation that read the DEK before the other one went through
+        let stale = DekRotation {
+            dek: Dek {
+                version: before.version + 1,
+                ..before.clone()
+            }
+            .wrap_with(key_provider())
+            .unwrap(),
+            from: before.dek.clone(),
+            to: before.dek.clone(),
+        };
+        assert!(matches!(store.rotate_dek(&stale, before.version).await, Ok(false)));
+
+        let after = Dek::get(store, &user.uid).await.unwrap();
+        assert_eq!(after.version, before.version + 1);
+        assert_ne!(after.dek, before.dek);
+        let stored_user = store.get_user(&user.uid).await.unwrap().unwrap();
+        assert_eq!(stored_user.decrypt(&after.dek).name, "Ada");
+
+        let sessions = plain_sessions(store, &user.uid).await;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].session_id, "s1");
+        assert_eq!(sessions[0].refresh_token, "r1");
+        assert!(!sessions[0].is_revoked);
+        assert_eq!(sessions[1].session_id, "s2");
+        assert!(sessions[1].is_revoked);
+
+        // and everything keeps working with the new DEK
+        Session::revoke(store, "s1", &user.uid).await.unwrap();
+        assert!(plain_sessions(store, &user.uid).await.iter().all(|session| session.is_revoked));
+    }
+
+    #[tokio::test]
+    async fn writes_around_a_rotation_in_memory() {
+        writes_around_a_rotation(&MemoryStore::new()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn writes_around_a_rotation_in_sqlite() {
+        let path = env::temp_dir().join(format!("dek-rotation-test-{}.db", std::process::id()));
+        let store = crate::store::sql_store::SqlStore::connect(&format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        writes_around_a_rotation(&store).await;
+        let _ = std::fs::remove_file(path);
+    }
+}
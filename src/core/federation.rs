@@ -0,0 +1,610 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{dek::Dek, identity_provider::IdentityProvider, user::User, user_identity::UserIdentity},
+    errors::{Error, Result},
+    traits::store::Store,
+    utils::{
+        federation_utils::{discover, exchange_code, verify_id_token, IdTokenClaims},
+        oauth_utils::{self, generate_token, hash_token, pkce_challenge},
+        validation_utils::Validation,
+    },
+};
+
+// the user has 10 minutes to sign in at the provider
+pub const FEDERATION_TTL_SECS: i64 = 600;
+// and the app 5 minutes to redeem the sign in token after that
+pub const FEDERATION_SIGNIN_TTL_SECS: i64 = 300;
+
+// A sign in through an upstream provider. It is first stored under the hash
+// of the state sent to the provider and, once the provider signed the user
+// in, under the hash of the one-time token the app redeems for a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationState {
+    pub _id: ObjectId,
+    pub token_hash: String,
+    pub provider_id: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub redirect_url: String,
+    pub user_agent: String,
+    // set once the provider has signed the user in
+    pub uid: Option<String>,
+    // set when the provider account is linked to this signed in user instead
+    #[serde(default)]
+    pub link_uid: Option<String>,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+pub struct Federation;
+
+// the redirect uri registered at every provider
+pub fn callback_url() -> String {
+    let server_url = dotenv::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+    format!("{}/federation/callback", server_url.trim_end_matches('/'))
+}
+
+impl Federation {
+    // Starts a sign in with the provider and returns the url to send the
+    // browser to. The user ends up back at redirect_url with a token in the
+    // query (or an error), which the app redeems for a session.
+    pub async fn start(store: &dyn Store, provider_id: &str, redirect_url: &str, user_agent: &str) -> Result<String> {
+        Self::begin(store, provider_id, redirect_url, user_agent, None).await
+    }
+
+    // Like start, but the provider account gets linked to the user. The user
+    // ends up back at redirect_url with linked=provider_id in the query.
+    pub async fn start_link(
+        store: &dyn Store,
+        uid: &str,
+        provider_id: &str,
+        redirect_url: &str,
+        user_agent: &str,
+    ) -> Result<String> {
+        Self::begin(store, provider_id, redirect_url, user_agent, Some(uid)).await
+    }
+
+    async fn begin(
+        store: &dyn Store,
+        provider_id: &str,
+        redirect_url: &str,
+        user_agent: &str,
+        link_uid: Option<&str>,
+    ) -> Result<String> {
+        match Self::check_redirect_url(redirect_url) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let provider = match IdentityProvider::get(store, provider_id).await {
+            Ok(provider) => provider,
+            Err(e) => return Err(e),
+        };
+        let discovery = match discover(&provider.issuer).await {
+            Ok(discovery) => discovery,
+            Err(e) => return Err(e),
+        };
+
+        let nonce = generate_token(32);
+        let code_verifier = generate_token(32);
+        let state = match Self::insert_pending(
+            store,
+            &provider.provider_id,
+            &nonce,
+            &code_verifier,
+            redirect_url,
+            user_agent,
+            link_uid,
+        )
+        .await
+        {
+            Ok(state) => state,
+            Err(e) => return Err(e),
+        };
+
+        Ok(oauth_utils::redirect_url(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &provider.client_id),
+                ("redirect_uri", &callback_url()),
+                ("scope", &provider.scope),
+                ("state", &state),
+                ("nonce", &nonce),
+                ("code_challenge", &pkce_challenge(&code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        ))
+    }
+
+    pub fn check_redirect_url(redirect_url: &str) -> Result<()> {
+        if !redirect_url.starts_with("https://") && !redirect_url.starts_with("http://") {
+            return Err(Error::InvalidPayload {
+                message: "Invalid redirect url".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // stores a sign in until the provider redirects back, returns the state to send along
+    pub async fn insert_pending(
+        store: &dyn Store,
+        provider_id: &str,
+        nonce: &str,
+        code_verifier: &str,
+        redirect_url: &str,
+        user_agent: &str,
+        link_uid: Option<&str>,
+    ) -> Result<String> {
+        let state = generate_token(32);
+        let pending = FederationState {
+            _id: ObjectId::new(),
+            token_hash: hash_token(&state),
+            provider_id: provider_id.to_string(),
+            nonce: nonce.to_string(),
+            code_verifier: code_verifier.to_string(),
+            redirect_url: redirect_url.to_string(),
+            user_agent: user_agent.to_string(),
+            uid: None,
+            link_uid: link_uid.map(|uid| uid.to_string()),
+            expires_at: DateTime::from_millis(
+                DateTime::now().timestamp_millis() + FEDERATION_TTL_SECS * 1000,
+            ),
+            created_at: DateTime::now(),
+        };
+        match store.insert_federation_state(&pending).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        // nothing else cleans up after the abandoned ones
+        match store.delete_expired_federation_states().await {
+            Ok(_) => Ok(state),
+            Err(e) => Err(e),
+        }
+    }
+
+    // uses up the sign in the provider redirected back to with the state
+    pub async fn take_pending(store: &dyn Store, state: &str) -> Result<FederationState> {
+        // taken out right away, so a state can't be used twice even at the same time
+        let pending = match store.take_federation_state(&hash_token(state)).await {
+            Ok(Some(pending)) if pending.uid.is_none() => pending,
+            Ok(_) => {
+                return Err(Error::InvalidToken {
+                    message: "Unknown sign in request. Please start over.".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        if pending.expires_at.timestamp_millis() < DateTime::now().timestamp_millis() {
+            return Err(Error::InvalidToken {
+                message: "The sign in request has expired. Please start over.".to_string(),
+            });
+        }
+        Ok(pending)
+    }
+
+    // Redeems the code of the provider, checks the id token and signs in the
+    // user it belongs to. Returns the one-time token for the app.
+    pub async fn complete(store: &dyn Store, pending: &FederationState, code: &str) -> Result<String> {
+        let (provider, claims) = match Self::verified_claims(store, pending, code).await {
+            Ok(verified) => verified,
+            Err(e) => return Err(e),
+        };
+
+        let uid = match Self::find_or_create_user(store, &provider, &claims).await {
+            Ok(uid) => uid,
+            Err(e) => return Err(e),
+        };
+
+        Self::issue_signin_token(store, pending, &uid).await
+    }
+
+    // the one-time token the app redeems for a session of the signed in user
+    pub async fn issue_signin_token(store: &dyn Store, pending: &FederationState, uid: &str) -> Result<String> {
+        let token = generate_token(32);
+        let signed_in = FederationState {
+            _id: ObjectId::new(),
+            token_hash: hash_token(&token),
+            provider_id: pending.provider_id.clone(),
+            nonce: String::new(),
+            code_verifier: String::new(),
+            redirect_url: pending.redirect_url.clone(),
+            user_agent: pending.user_agent.clone(),
+            uid: Some(uid.to_string()),
+            link_uid: None,
+            expires_at: DateTime::from_millis(
+                DateTime::now().timestamp_millis() + FEDERATION_SIGNIN_TTL_SECS * 1000,
+            ),
+            created_at: DateTime::now(),
+        };
+        match store.insert_federation_state(&signed_in).await {
+            Ok(_) => Ok(token),
+            Err(e) => Err(e),
+        }
+    }
+
+    // links the provider account the code belongs to to the user who started the link
+    pub async fn complete_link(store: &dyn Store, pending: &FederationState, uid: &str, code: &str) -> Result<()> {
+        let (provider, claims) = match Self::verified_claims(store, pending, code).await {
+            Ok(verified) => verified,
+            Err(e) => return Err(e),
+        };
+
+        // the user is signed in, so the email does not have to match
+        match UserIdentity::link(store, uid, &provider.provider_id, &claims.sub, claims.email.as_deref()).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // redeems the code of the provider and checks the id token it returns
+    async fn verified_claims(store: &dyn Store, pending: &FederationState, code: &str) -> Result<(IdentityProvider, IdTokenClaims)> {
+        let provider = match IdentityProvider::get(store, &pending.provider_id).await {
+            Ok(provider) => provider,
+            Err(e) => return Err(e),
+        };
+        let discovery = match discover(&provider.issuer).await {
+            Ok(discovery) => discovery,
+            Err(e) => return Err(e),
+        };
+
+        let id_token = match exchange_code(
+            &discovery,
+            &provider.client_id,
+            &provider.decrypted_client_secret(),
+            code,
+            &callback_url(),
+            &pending.code_verifier,
+        )
+        .await
+        {
+            Ok(id_token) => id_token,
+            Err(e) => return Err(e),
+        };
+
+        let claims = match verify_id_token(&discovery, &provider.client_id, &id_token).await {
+            Ok(claims) => claims,
+            Err(e) => return Err(e),
+        };
+        // ties the id token to this sign in so it can't be replayed into another one
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(Error::InvalidToken {
+                message: "The nonce of the id token does not match".to_string(),
+            });
+        }
+        Ok((provider, claims))
+    }
+
+    // uses up the token the app got and returns the uid it was issued for
+    pub async fn redeem(store: &dyn Store, token: &str, user_agent: &str) -> Result<String> {
+        let signed_in = match store.take_federation_state(&hash_token(token)).await {
+            Ok(Some(signed_in)) if signed_in.uid.is_some() => signed_in,
+            Ok(_) => {
+                return Err(Error::InvalidToken {
+                    message: "Invalid sign in token. Please sign in again.".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        if signed_in.expires_at.timestamp_millis() < DateTime::now().timestamp_millis() {
+            return Err(Error::InvalidToken {
+                message: "The sign in token has expired. Please sign in again.".to_string(),
+            });
+        }
+        if signed_in.user_agent != user_agent {
+            return Err(Error::InvalidToken {
+                message: "The sign in has to be finished on the device it was started from.".to_string(),
+            });
+        }
+        Ok(signed_in.uid.unwrap_or_default())
+    }
+
+    // The user the provider account is linked to. Unknown accounts are linked
+    // to the user with the same email, but only when the provider vouches for
+    // the email, otherwise anyone could take over an account by signing up at
+    // a provider with its email. Without such a user a new one is created.
+    async fn find_or_create_user(store: &dyn Store, provider: &IdentityProvider, claims: &IdTokenClaims) -> Result<String> {
+        match UserIdentity::find_uid(store, &provider.provider_id, &claims.sub).await {
+            Ok(Some(uid)) => return Ok(uid),
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+
+        let email = match &claims.email {
+            Some(email) if Validation::email(email) => email.clone(),
+            _ => {
+                return Err(Error::InvalidToken {
+                    message: "The identity provider did not share a valid email".to_string(),
+                })
+            }
+        };
+
+        let uid = match User::get_from_email(store, &email).await {
+            Ok(user) if claims.is_email_verified() => user.uid,
+            Ok(_) => {
+                return Err(Error::UserAlreadyExists {
+                    message: "An account with this email already exists. Please sign in to it first.".to_string(),
+                })
+            }
+            // there is no DEK for an email without a user
+            Err(Error::KeyNotFound { .. }) => match Self::create_user(
+                store,
+                &email,
+                claims.name.as_deref(),
+                "user",
+                claims.is_email_verified(),
+            )
+            .await
+            {
+                Ok(uid) => uid,
+                Err(e) => return Err(e),
+            },
+            Err(e) => return Err(e),
+        };
+
+        match UserIdentity::link(store, &uid, &provider.provider_id, &claims.sub, Some(&email)).await {
+            Ok(_) => Ok(uid),
+            Err(e) => Err(e),
+        }
+    }
+
+    // a user who signs in through a provider, it has no password of its own
+    pub async fn create_user(
+        store: &dyn Store,
+        email: &str,
+        name: Option<&str>,
+        role: &str,
+        email_verified: bool,
+    ) -> Result<String> {
+        let name = match name {
+            Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+            _ => email.split('@').next().unwrap_or(email).to_string(),
+        };
+
+        // the random password is never handed out
+        let dek = Dek::generate();
+        let mut user = User::new(&name, email, role, &generate_token(32));
+        user.email_verified = email_verified;
+        user.has_password = false;
+        let user = match user.encrypt_and_add(store, &dek).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+
+        match Dek::new(&user.uid, &user.email, &dek)
+            .encrypt_and_add(store)
+            .await
+        {
+            Ok(_) => Ok(user.uid),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// The whole sign in against a provider served from a local port: its metadata,
+// keys and token endpoint. The token endpoint hands out whatever id token the
+// test signed last.
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::State, routing::get, routing::post, Json, Router};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::store::memory_store::MemoryStore;
+
+    const CLIENT_ID: &str = "client";
+
+    fn set_keys() {
+        env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+    }
+
+    fn signing_key() -> (EncodingKey, Value) {
+        let rsa = Rsa::generate(2048).unwrap();
+        let key = EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap();
+        let jwk = json!({
+            "kty": "RSA",
+            "kid": "k1",
+            "alg": "RS256",
+            "use": "sig",
+            "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+            "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+        });
+        (key, jwk)
+    }
+
+    struct MockProvider {
+        issuer: String,
+        key: EncodingKey,
+        // the token endpoint answers with it
+        id_token: Arc<Mutex<String>>,
+    }
+
+    impl MockProvider {
+        async fn start() -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let (key, jwk) = signing_key();
+            let id_token = Arc::new(Mutex::new(String::new()));
+
+            let metadata = json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+            });
+            let jwks = json!({ "keys": [jwk] });
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(move || async move { Json(metadata) }))
+                .route("/jwks", get(move || async move { Json(jwks) }))
+                .route(
+                    "/token",
+                    post(|State(id_token): State<Arc<Mutex<String>>>| async move {
+                        let id_token = id_token.lock().unwrap().clone();
+                        Json(json!({ "access_token": "access", "token_type": "Bearer", "id_token": id_token }))
+                    }),
+                )
+                .with_state(id_token.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self { issuer, key, id_token }
+        }
+
+        // the claims of a valid id token for the sign in with this nonce
+        fn claims(&self, nonce: &str) -> Value {
+            let now = DateTime::now().timestamp_millis() / 1000;
+            json!({
+                "iss": self.issuer,
+                "aud": CLIENT_ID,
+                "sub": "subject",
+                "email": "ada@example.com",
+                "email_verified": true,
+                "name": "Ada",
+                "nonce": nonce,
+                "iat": now,
+                "exp": now + 300,
+            })
+        }
+
+        fn issue(&self, claims: &Value, key: &EncodingKey) {
+            let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+            header.kid = Some("k1".to_string());
+            *self.id_token.lock().unwrap() = encode(&header, claims, key).unwrap();
+        }
+    }
+
+    // a provider added to the store and a sign in started with it, with the
+    // state and nonce it sent the browser to the provider with
+    async fn started(store: &dyn Store) -> (MockProvider, String, String) {
+        set_keys();
+        let provider = MockProvider::start().await;
+        IdentityProvider::create(store, "mock", "Mock", &provider.issuer, CLIENT_ID, "secret", "openid email")
+            .await
+            .unwrap();
+        let url = Federation::start(store, "mock", "https://app.example.com", "curl")
+            .await
+            .unwrap();
+
+        let query: Vec<(String, String)> = serde_urlencoded::from_str(url.split_once('?').unwrap().1).unwrap();
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        (provider, param("state"), param("nonce"))
+    }
+
+    async fn complete(store: &dyn Store, state: &str) -> Result<String> {
+        let pending = Federation::take_pending(store, state).await?;
+        Federation::complete(store, &pending, "code").await
+    }
+
+    // the sign in failed for the reason it should have
+    fn assert_rejected(result: Result<String>, reason: &str) {
+        match result {
+            Err(Error::InvalidToken { message }) => assert!(message.contains(reason), "{}", message),
+            other => panic!("expected InvalidToken with {}, got {:?}", reason, other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn signs_in_with_a_valid_id_token() {
+        let store = MemoryStore::new();
+        let (provider, state, nonce) = started(&store).await;
+        provider.issue(&provider.claims(&nonce), &provider.key);
+
+        let token = complete(&store, &state).await.unwrap();
+        let uid = Federation::redeem(&store, &token, "curl").await.unwrap();
+        assert_eq!(
+            UserIdentity::find_uid(&store, "mock", "subject").await.unwrap(),
+            Some(uid)
+        );
+        // the sign in token is used up
+        assert!(matches!(
+            Federation::redeem(&store, &token, "curl").await,
+            Err(Error::InvalidToken { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_bad_signature() {
+        let store = MemoryStore::new();
+        let (provider, state, nonce) = started(&store).await;
+        // same kid, another key
+        let (other_key, _) = signing_key();
+        provider.issue(&provider.claims(&nonce), &other_key);
+
+        assert_rejected(complete(&store, &state).await, "InvalidSignature");
+    }
+
+    #[tokio::test]
+    async fn rejects_another_audience() {
+        let store = MemoryStore::new();
+        let (provider, state, nonce) = started(&store).await;
+        let mut claims = provider.claims(&nonce);
+        claims["aud"] = json!("another client");
+        provider.issue(&claims, &provider.key);
+
+        assert_rejected(complete(&store, &state).await, "InvalidAudience");
+    }
+
+    #[tokio::test]
+    async fn rejects_another_issuer() {
+        let store = MemoryStore::new();
+        let (provider, state, nonce) = started(&store).await;
+        let mut claims = provider.claims(&nonce);
+        claims["iss"] = json!("https://evil.example.com");
+        provider.issue(&claims, &provider.key);
+
+        assert_rejected(complete(&store, &state).await, "InvalidIssuer");
+    }
+
+    #[tokio::test]
+    async fn rejects_an_expired_id_token() {
+        let store = MemoryStore::new();
+        let (provider, state, nonce) = started(&store).await;
+        let mut claims = provider.claims(&nonce);
+        // past the leeway for clock skew
+        claims["exp"] = json!(DateTime::now().timestamp_millis() / 1000 - 600);
+        provider.issue(&claims, &provider.key);
+
+        assert_rejected(complete(&store, &state).await, "ExpiredSignature");
+    }
+
+    #[tokio::test]
+    async fn rejects_the_nonce_of_another_sign_in() {
+        let store = MemoryStore::new();
+        let (provider, state, _) = started(&store).await;
+        provider.issue(&provider.claims("another nonce"), &provider.key);
+
+        assert_rejected(complete(&store, &state).await, "nonce");
+    }
+
+    #[tokio::test]
+    async fn rejects_an_unknown_or_used_state() {
+        let store = MemoryStore::new();
+        let (provider, state, nonce) = started(&store).await;
+        provider.issue(&provider.claims(&nonce), &provider.key);
+
+        assert_rejected(
+            Federation::take_pending(&store, "another state").await.map(|_| String::new()),
+            "Unknown sign in request",
+        );
+        complete(&store, &state).await.unwrap();
+        assert_rejected(complete(&store, &state).await, "Unknown sign in request");
+    }
+}
@@ -0,0 +1,144 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::key_provider::key_provider,
+    errors::{Error, Result},
+    models::federation_model::IdentityProviderResponse,
+    traits::store::Store,
+    utils::federation_utils::discover,
+};
+
+// An upstream OpenID Connect provider (Google, Microsoft, Okta, Keycloak...)
+// users can sign in with. flexauth is a confidential client of the provider,
+// the secret is stored encrypted with the KEK.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityProvider {
+    pub _id: ObjectId,
+    // short name used in the api, e.g. "google"
+    pub provider_id: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl IdentityProvider {
+    pub async fn create(
+        store: &dyn Store,
+        provider_id: &str,
+        name: &str,
+        issuer: &str,
+        client_id: &str,
+        client_secret: &str,
+        scope: &str,
+    ) -> Result<IdentityProviderResponse> {
+        let is_slug = provider_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if provider_id.is_empty() || !is_slug {
+            return Err(Error::InvalidPayload {
+                message: "The provider id can only contain lowercase letters, digits and -".to_string(),
+            });
+        }
+        // the openid scope is what makes the provider return an id token
+        if !scope.split(' ').any(|s| s == "openid") {
+            return Err(Error::InvalidScope {
+                message: "The scope has to include openid".to_string(),
+            });
+        }
+
+        match store.get_identity_provider(provider_id).await {
+            Ok(Some(_)) => {
+                return Err(Error::InvalidPayload {
+                    message: "An identity provider with this id already exists".to_string(),
+                })
+            }
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+        // the provider id of linked identities is shared with the SAML connections
+        match store.get_saml_connection(provider_id).await {
+            Ok(Some(_)) => {
+                return Err(Error::InvalidPayload {
+                    message: "A SAML connection with this id already exists".to_string(),
+                })
+            }
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+
+        // fails early on a wrong issuer instead of on the first sign in
+        match discover(issuer).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let provider = Self {
+            _id: ObjectId::new(),
+            provider_id: provider_id.to_string(),
+            name: name.to_string(),
+            issuer: issuer.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: key_provider().wrap(client_secret)?,
+            scope: scope.to_string(),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        };
+        match store.insert_identity_provider(&provider).await {
+            Ok(_) => Ok(provider.to_response()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get(store: &dyn Store, provider_id: &str) -> Result<Self> {
+        match store.get_identity_provider(provider_id).await {
+            Ok(Some(provider)) => Ok(provider),
+            Ok(None) => Err(Error::IdentityProviderNotFound {
+                message: "Identity provider not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_all(store: &dyn Store) -> Result<Vec<IdentityProviderResponse>> {
+        let mut providers = match store.get_identity_providers().await {
+            Ok(providers) => providers,
+            Err(e) => return Err(e),
+        };
+        providers.sort_by_key(|p| p.created_at);
+        Ok(providers.iter().map(|p| p.to_response()).collect())
+    }
+
+    pub async fn delete(store: &dyn Store, provider_id: &str) -> Result<()> {
+        match store.delete_identity_provider(provider_id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::IdentityProviderNotFound {
+                message: "Identity provider not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn decrypted_client_secret(&self) -> String {
+        // it stays wrapped with the KEK that was current when the provider was added
+        key_provider()
+            .unwrap(&self.client_secret, None)
+            .expect("Could not decrypt the client secret")
+    }
+
+    fn to_response(&self) -> IdentityProviderResponse {
+        IdentityProviderResponse {
+            provider_id: self.provider_id.clone(),
+            name: self.name.clone(),
+            issuer: self.issuer.clone(),
+            client_id: self.client_id.clone(),
+            scope: self.scope.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
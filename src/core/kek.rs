@@ -0,0 +1,108 @@
+use std::env;
+
+use crate::{
+    errors::Result,
+    traits::key_provider::KeyProvider,
+    utils::encryption_utils::Encryption,
+};
+
+// SERVER_KEK wraps every new DEK. The KEKs it replaced go in PREVIOUS_SERVER_KEKS,
+// comma separated, and are only used to unwrap the DEKs not re-wrapped yet.
+// A KEK is known by the start of an HMAC of KEK_ID_LABEL keyed with it, so the
+// ids need no setting of their own and say nothing about other hashes of the key.
+#[derive(Debug, Clone)]
+pub struct Kek {
+    pub id: String,
+    pub key: String,
+}
+
+const KEK_ID_LABEL: &str = "inhouse-auth kek id v1";
+
+impl Kek {
+    pub fn new(key: &str) -> Self {
+        Self {
+            id: Encryption::blind_index(KEK_ID_LABEL, key).chars().take(16).collect(),
+            key: key.to_string(),
+        }
+    }
+
+    pub fn current() -> Self {
+        let server_kek = env::var("SERVER_KEK").expect("Server Kek must be set.");
+        Self::new(&server_kek)
+    }
+
+    pub fn previous() -> Vec<Self> {
+        env::var("PREVIOUS_SERVER_KEKS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|key| key.trim())
+            .filter(|key| !key.is_empty())
+            .map(Self::new)
+            .collect()
+    }
+
+    // every known KEK, the current one first
+    pub fn all() -> Vec<Self> {
+        let mut keks = vec![Self::current()];
+        keks.extend(Self::previous());
+        keks
+    }
+
+    // the KEKs set in the env, without requiring SERVER_KEK for the other providers
+    pub fn configured() -> Vec<Self> {
+        match env::var("SERVER_KEK") {
+            Ok(key) if !key.trim().is_empty() => Self::all(),
+            _ => Self::previous(),
+        }
+    }
+}
+
+// The value unwrapped with the KEK of key_id. Values from before KEK ids were
+// recorded could be wrapped with any of them.
+pub fn unwrap_with_keks(keks: &[Kek], wrapped: &str, key_id: Option<&str>) -> Option<String> {
+    keks.iter()
+        .filter(|kek| key_id.is_none_or(|key_id| kek.id == key_id))
+        .find_map(|kek| Encryption::try_decrypt_data(wrapped, &kek.key))
+}
+
+// KEY_PROVIDER=env, the default
+pub struct EnvKeyProvider {
+    // the current one first
+    keks: Vec<Kek>,
+}
+
+impl EnvKeyProvider {
+    pub fn from_env() -> Self {
+        Self { keks: Kek::all() }
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn key_id(&self) -> String {
+        self.keks[0].id.clone()
+    }
+
+    fn wrap(&self, data: &str) -> Result<String> {
+        Ok(Encryption::encrypt_data(data, &self.keks[0].key))
+    }
+
+    fn unwrap(&self, wrapped: &str, key_id: Option<&str>) -> Option<String> {
+        unwrap_with_keks(&self.keks, wrapped, key_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::oauth_utils::hash_token;
+
+    const KEY: &str = "11112222333344445555666677778888.aaaabbbbcccc";
+
+    #[test]
+    fn id_is_not_the_plain_hash_of_the_key() {
+        let kek = Kek::new(KEY);
+        assert_eq!(kek.id.len(), 16);
+        assert!(!hash_token(KEY).starts_with(&kek.id));
+        assert_eq!(kek.id, Kek::new(KEY).id);
+    }
+}
@@ -0,0 +1,199 @@
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    DecodingKey, EncodingKey,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    core::keyring::Keyring,
+    errors::{Error, Result},
+    utils::jwk_utils::rsa_jwk,
+};
+
+// how often the keyring manifest is checked for changes made by other processes
+const WATCH_INTERVAL_SECS: u64 = 5;
+
+pub struct CachedKey {
+    pub kid: String,
+    pub encoding_key: EncodingKey,
+    pub decoding_key: DecodingKey,
+    pub jwk: Jwk,
+}
+
+struct Keys {
+    active_kid: String,
+    // every key that is not retired, active key first
+    keys: Vec<Arc<CachedKey>>,
+    by_kid: HashMap<String, Arc<CachedKey>>,
+    manifest_modified: Option<SystemTime>,
+}
+
+// The parsed signing keys, loaded once from the keyring and shared through the
+// AppState. Parsing a PEM with OpenSSL is slow, so no token operation touches the disk.
+#[derive(Clone)]
+pub struct KeyCache {
+    keys: Arc<RwLock<Arc<Keys>>>,
+}
+
+fn manifest_modified() -> Option<SystemTime> {
+    fs::metadata(Keyring::manifest_path())
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+fn load_keys() -> Result<Keys> {
+    // read the time before loading so a change while loading triggers another reload
+    let modified = manifest_modified();
+    let keyring = Keyring::load()?;
+    // on the first start the manifest only exists after the load
+    let modified = modified.or_else(manifest_modified);
+    let active_kid = keyring.active()?.kid.clone();
+
+    let mut keys = vec![];
+    let mut by_kid = HashMap::new();
+    for key in keyring.published() {
+        let private_key = keyring.private_key(&key.kid)?;
+        let public_key = keyring.public_key(&key.kid)?;
+        let encoding_key = match EncodingKey::from_rsa_pem(&private_key) {
+            Ok(key) => key,
+            Err(err) => {
+                return Err(Error::PrivateKeyLoadError {
+                    message: err.to_string(),
+                })
+            }
+        };
+        let decoding_key = match DecodingKey::from_rsa_pem(&public_key) {
+            Ok(key) => key,
+            Err(err) => {
+                return Err(Error::PublicKeyLoadError {
+                    message: err.to_string(),
+                })
+            }
+        };
+        let cached = Arc::new(CachedKey {
+            kid: key.kid.clone(),
+            encoding_key,
+            decoding_key,
+            jwk: rsa_jwk(&public_key)?,
+        });
+        by_kid.insert(key.kid.clone(), cached.clone());
+        keys.push(cached);
+    }
+
+    Ok(Keys {
+        active_kid,
+        keys,
+        by_kid,
+        manifest_modified: modified,
+    })
+}
+
+impl KeyCache {
+    pub fn load() -> Result<Self> {
+        let keys = load_keys()?;
+        Ok(Self {
+            keys: Arc::new(RwLock::new(Arc::new(keys))),
+        })
+    }
+
+    // swaps in the current keyring, on error the old keys stay in use
+    pub fn reload(&self) -> Result<()> {
+        let keys = load_keys()?;
+        println!(">> Signing keys reloaded. Active kid: {}", keys.active_kid);
+        *self.keys.write().unwrap() = Arc::new(keys);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Arc<Keys> {
+        self.keys.read().unwrap().clone()
+    }
+
+    pub fn signing_key(&self) -> Result<Arc<CachedKey>> {
+        let keys = self.snapshot();
+        match keys.by_kid.get(&keys.active_kid) {
+            Some(key) => Ok(key.clone()),
+            None => Err(Error::SigningKeyError {
+                message: "No active signing key".to_string(),
+            }),
+        }
+    }
+
+    // Tokens without a kid were signed before the keyring existed and are
+    // checked against the active key.
+    pub fn verification_key(&self, kid: Option<&str>) -> Result<Arc<CachedKey>> {
+        let keys = self.snapshot();
+        let kid = kid.unwrap_or(&keys.active_kid);
+        match keys.by_kid.get(kid) {
+            Some(key) => Ok(key.clone()),
+            None => Err(Error::SignatureVerificationError {
+                message: "Unknown signing key".to_string(),
+            }),
+        }
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.snapshot().keys.iter().map(|k| k.jwk.clone()).collect(),
+        }
+    }
+
+    fn reload_or_log(&self) {
+        if let Err(e) = self.reload() {
+            eprintln!(">> Error reloading the signing keys: {:?}", e);
+        }
+    }
+
+    // reloads the keys on SIGHUP and whenever the keyring manifest changes,
+    // e.g. after `inhouse-auth rotate-signing-key`
+    pub fn watch(&self) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(WATCH_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                if manifest_modified() != cache.snapshot().manifest_modified {
+                    cache.reload_or_log();
+                }
+            }
+        });
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let cache = self.clone();
+            tokio::spawn(async move {
+                let mut hangup = match signal(SignalKind::hangup()) {
+                    Ok(hangup) => hangup,
+                    Err(e) => {
+                        eprintln!(">> Error listening for SIGHUP: {}", e);
+                        return;
+                    }
+                };
+                while hangup.recv().await.is_some() {
+                    cache.reload_or_log();
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+impl KeyCache {
+    // the keys of a keyring in the temp dir, made on first use
+    pub fn for_tests() -> Self {
+        let _dir = crate::core::keyring::KEYS_DIR
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        std::env::set_var(
+            "SIGNING_KEYS_DIR",
+            std::env::temp_dir().join(format!("signing-keys-test-{}", std::process::id())),
+        );
+        KeyCache::load().unwrap()
+    }
+}
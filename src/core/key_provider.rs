@@ -0,0 +1,71 @@
+use std::{env, sync::OnceLock};
+
+use crate::{
+    core::{
+        kek::{unwrap_with_keks, EnvKeyProvider, Kek},
+        keyfile::KeyfileKeyProvider,
+    },
+    errors::{Error, Result},
+    traits::key_provider::KeyProvider,
+};
+
+// loaded once, a PKCS#11 library can only be initialized once per process
+static KEY_PROVIDER: OnceLock<Box<dyn KeyProvider>> = OnceLock::new();
+
+// Another provider in front of the KEKs still set in the env. What was wrapped
+// with them before KEY_PROVIDER was changed stays readable until rewrap-deks
+// moved it over.
+struct EnvFallback {
+    provider: Box<dyn KeyProvider>,
+    keks: Vec<Kek>,
+}
+
+impl KeyProvider for EnvFallback {
+    fn key_id(&self) -> String {
+        self.provider.key_id()
+    }
+
+    fn wrap(&self, data: &str) -> Result<String> {
+        self.provider.wrap(data)
+    }
+
+    fn unwrap(&self, wrapped: &str, key_id: Option<&str>) -> Option<String> {
+        self.provider
+            .unwrap(wrapped, key_id)
+            .or_else(|| unwrap_with_keks(&self.keks, wrapped, key_id))
+    }
+}
+
+// KEY_PROVIDER=env|keyfile|pkcs11, env when not set
+fn load() -> Result<Box<dyn KeyProvider>> {
+    let name = env::var("KEY_PROVIDER").unwrap_or("env".to_string());
+    let provider: Box<dyn KeyProvider> = match name.as_str() {
+        "env" => return Ok(Box::new(EnvKeyProvider::from_env())),
+        "keyfile" => Box::new(KeyfileKeyProvider::open()?),
+        #[cfg(feature = "pkcs11")]
+        "pkcs11" => Box::new(crate::core::pkcs11::Pkcs11KeyProvider::open()?),
+        _ => {
+            return Err(Error::KeyProviderFailed {
+                message: format!("Unknown KEY_PROVIDER: {}", name),
+            })
+        }
+    };
+    let keks = Kek::configured();
+    if keks.is_empty() {
+        return Ok(provider);
+    }
+    Ok(Box::new(EnvFallback { provider, keks }))
+}
+
+// loads the configured provider, so a wrong configuration fails at startup instead of on the first request
+pub fn init() -> Result<&'static dyn KeyProvider> {
+    if let Some(provider) = KEY_PROVIDER.get() {
+        return Ok(provider.as_ref());
+    }
+    let provider = load()?;
+    Ok(KEY_PROVIDER.get_or_init(|| provider).as_ref())
+}
+
+pub fn key_provider() -> &'static dyn KeyProvider {
+    init().expect("Could not load the key provider")
+}
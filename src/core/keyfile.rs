@@ -0,0 +1,287 @@
+use std::{
+    env, fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::kek::{unwrap_with_keks, Kek},
+    errors::{Error, Result},
+    traits::key_provider::KeyProvider,
+    utils::encryption_utils::Encryption,
+};
+
+const KEYFILE_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+
+// The keyfile at KEYFILE_PATH (default: keys/keks.json) holds the KEKs, the
+// current one first, encrypted with a key derived from KEYFILE_PASSPHRASE.
+// A new salt is picked every time it is written.
+#[derive(Serialize, Deserialize)]
+struct KeyfileContents {
+    version: u8,
+    salt: String,
+    keks: String,
+}
+
+pub struct Keyfile {
+    path: PathBuf,
+    passphrase: String,
+}
+
+fn keyfile_error(message: String) -> Error {
+    Error::KeyProviderFailed { message }
+}
+
+// the passphrase stretched with argon2 into a key for Encryption
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<String> {
+    let mut key = [0u8; 16];
+    match Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key) {
+        Ok(_) => Ok(hex::encode(key)),
+        Err(err) => Err(keyfile_error(err.to_string())),
+    }
+}
+
+impl Keyfile {
+    pub fn from_env() -> Result<Self> {
+        let passphrase = match env::var("KEYFILE_PASSPHRASE") {
+            Ok(passphrase) if !passphrase.is_empty() => passphrase,
+            _ => return Err(keyfile_error("KEYFILE_PASSPHRASE must be set".to_string())),
+        };
+        Ok(Self {
+            path: PathBuf::from(
+                env::var("KEYFILE_PATH").unwrap_or_else(|_| "keys/keks.json".to_string()),
+            ),
+            passphrase,
+        })
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn path(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    pub fn read(&self) -> Result<Vec<Kek>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) => return Err(keyfile_error(format!("{}: {}", self.path(), err))),
+        };
+        let contents: KeyfileContents = match serde_json::from_str(&contents) {
+            Ok(contents) => contents,
+            Err(err) => return Err(keyfile_error(format!("{}: {}", self.path(), err))),
+        };
+        if contents.version != KEYFILE_VERSION {
+            return Err(keyfile_error(format!(
+                "{}: unknown keyfile version {}",
+                self.path(),
+                contents.version
+            )));
+        }
+        let salt = match hex::decode(&contents.salt) {
+            Ok(salt) => salt,
+            Err(err) => return Err(keyfile_error(format!("{}: {}", self.path(), err))),
+        };
+        let key = derive_key(&self.passphrase, &salt)?;
+        match Encryption::try_decrypt_data(&contents.keks, &key) {
+            Some(keks) => Ok(keks.lines().map(Kek::new).collect()),
+            None => Err(keyfile_error(format!(
+                "Could not decrypt {}, check KEYFILE_PASSPHRASE",
+                self.path()
+            ))),
+        }
+    }
+
+    // Written next to the keyfile and moved over it, so it is never left half
+    // written. The file is synced before the move and the directory after it, so
+    // a crash cannot leave the new name pointing at data that never hit the disk.
+    pub fn write(&self, keks: &[Kek]) -> Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        if let Err(err) = openssl::rand::rand_bytes(&mut salt) {
+            return Err(keyfile_error(err.to_string()));
+        }
+        let key = derive_key(&self.passphrase, &salt)?;
+        let keks = keks.iter().map(|kek| kek.key.as_str()).collect::<Vec<_>>().join("\n");
+        let contents = KeyfileContents {
+            version: KEYFILE_VERSION,
+            salt: hex::encode(salt),
+            keks: Encryption::encrypt_data(&keks, &key),
+        };
+        let json = match serde_json::to_string_pretty(&contents) {
+            Ok(json) => json,
+            Err(err) => return Err(keyfile_error(err.to_string())),
+        };
+
+        let dir = match self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => dir,
+            None => Path::new("."),
+        };
+        if let Err(err) = fs::create_dir_all(dir) {
+            return Err(keyfile_error(err.to_string()));
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(json.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .and_then(|_| fs::File::open(dir)?.sync_all());
+        match written {
+            Ok(_) => Ok(()),
+            Err(err) => Err(keyfile_error(format!("{}: {}", self.path(), err))),
+        }
+    }
+}
+
+// KEY_PROVIDER=keyfile
+pub struct KeyfileKeyProvider {
+    // the current one first
+    keks: Vec<Kek>,
+}
+
+impl KeyfileKeyProvider {
+    pub fn open() -> Result<Self> {
+        let keyfile = Keyfile::from_env()?;
+        let keks = keyfile.read()?;
+        if keks.is_empty() {
+            return Err(keyfile_error(format!("{} has no KEKs", keyfile.path())));
+        }
+        Ok(Self { keks })
+    }
+}
+
+impl KeyProvider for KeyfileKeyProvider {
+    fn key_id(&self) -> String {
+        self.keks[0].id.clone()
+    }
+
+    fn wrap(&self, data: &str) -> Result<String> {
+        Ok(Encryption::encrypt_data(data, &self.keks[0].key))
+    }
+
+    fn unwrap(&self, wrapped: &str, key_id: Option<&str>) -> Option<String> {
+        unwrap_with_keks(&self.keks, wrapped, key_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+    // a keyfile in a directory of its own, removed when the test ends
+    struct TestKeyfile {
+        dir: PathBuf,
+        keyfile: Keyfile,
+    }
+
+    impl TestKeyfile {
+        fn new(passphrase: &str) -> Self {
+            let dir = env::temp_dir().join(format!(
+                "keyfile-test-{}-{}",
+                std::process::id(),
+                NEXT_DIR.fetch_add(1, Ordering::SeqCst)
+            ));
+            let keyfile = Keyfile {
+                path: dir.join("keks.json"),
+                passphrase: passphrase.to_string(),
+            };
+            Self { dir, keyfile }
+        }
+
+        fn with_passphrase(&self, passphrase: &str) -> Keyfile {
+            Keyfile {
+                path: self.keyfile.path.clone(),
+                passphrase: passphrase.to_string(),
+            }
+        }
+    }
+
+    impl Drop for TestKeyfile {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn keks() -> Vec<Kek> {
+        vec![
+            Kek::new("11112222333344445555666677778888.aaaabbbbcccc"),
+            Kek::new("88887777666655554444333322221111.ccccbbbbaaaa"),
+        ]
+    }
+
+    #[test]
+    fn write_then_read() {
+        let test = TestKeyfile::new("correct horse");
+        assert!(!test.keyfile.exists());
+        test.keyfile.write(&keks()).unwrap();
+        assert!(test.keyfile.exists());
+        assert!(!test.keyfile.path.with_extension("tmp").exists());
+
+        let read = test.keyfile.read().unwrap();
+        let written = keks();
+        assert_eq!(read.len(), written.len());
+        for (read, written) in read.iter().zip(written.iter()) {
+            assert_eq!(read.id, written.id);
+            assert_eq!(read.key, written.key);
+        }
+    }
+
+    #[test]
+    fn file_is_only_readable_by_its_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let test = TestKeyfile::new("correct horse");
+        test.keyfile.write(&keks()).unwrap();
+        let mode = fs::metadata(&test.keyfile.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let test = TestKeyfile::new("correct horse");
+        test.keyfile.write(&keks()).unwrap();
+        assert!(matches!(
+            test.with_passphrase("battery staple").read(),
+            Err(Error::KeyProviderFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let test = TestKeyfile::new("correct horse");
+        test.keyfile.write(&keks()).unwrap();
+        let contents = fs::read_to_string(&test.keyfile.path).unwrap();
+        let mut contents: KeyfileContents = serde_json::from_str(&contents).unwrap();
+        contents.version = KEYFILE_VERSION + 1;
+        fs::write(&test.keyfile.path, serde_json::to_string(&contents).unwrap()).unwrap();
+
+        match test.keyfile.read() {
+            Err(Error::KeyProviderFailed { message }) => {
+                assert!(message.contains("unknown keyfile version"), "{}", message)
+            }
+            _ => panic!("a keyfile of another version was read"),
+        }
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let test = TestKeyfile::new("correct horse");
+        assert!(matches!(test.keyfile.read(), Err(Error::KeyProviderFailed { .. })));
+    }
+}
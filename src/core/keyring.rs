@@ -0,0 +1,488 @@
+use chrono::{DateTime, Duration, Utc};
+use openssl::{pkey::PKey, rsa::Rsa};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    errors::{Error, Result},
+    utils::jwk_utils::key_id,
+};
+
+// the key used before the keyring existed, it is imported as the first active key
+const LEGACY_PRIVATE_KEY: &str = "private_key.pem";
+const MANIFEST: &str = "keyring.json";
+const LOCK: &str = "keyring.lock";
+// new keys are published in the JWKS this long before they start signing so
+// downstream services already have them cached when the first token shows up
+const PENDING_PERIOD_DAYS: i64 = 1;
+// a replaced key keeps verifying until the longest lived token it signed (the
+// 45 days refresh token) has expired
+const RETIRING_PERIOD_DAYS: i64 = 45;
+const DEFAULT_ROTATION_DAYS: i64 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyState {
+    // published but not signing yet
+    Pending,
+    // signs every new token, there is always exactly one
+    Active,
+    // replaced by a newer key, still published and verifying
+    Retiring,
+    // not used for anything anymore, the private key is deleted
+    Retired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    pub kid: String,
+    pub state: KeyState,
+    pub created_at: DateTime<Utc>,
+    // when a pending key is due to become active or when the key was activated
+    pub activated_at: Option<DateTime<Utc>>,
+    // when a retiring key is due to retire or when the key was retired
+    pub retires_at: Option<DateTime<Utc>>,
+}
+
+// The keyring lives in SIGNING_KEYS_DIR (default: keys). keyring.json holds the
+// state of every key and each private key is stored next to it as <kid>.pem
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Keyring {
+    pub keys: Vec<SigningKey>,
+}
+
+fn keys_dir() -> PathBuf {
+    PathBuf::from(env::var("SIGNING_KEYS_DIR").unwrap_or_else(|_| "keys".to_string()))
+}
+
+fn key_path(kid: &str) -> PathBuf {
+    keys_dir().join(format!("{}.pem", kid))
+}
+
+fn keyring_error(message: String) -> Error {
+    Error::SigningKeyError { message }
+}
+
+// Held while the manifest is read, changed and written back, so the schedule
+// and the rotate-signing-key command can't overwrite each other's changes.
+// The lock is released with the file, also when the process dies.
+fn lock() -> Result<fs::File> {
+    if let Err(err) = fs::create_dir_all(keys_dir()) {
+        return Err(keyring_error(err.to_string()));
+    }
+    let locked = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(keys_dir().join(LOCK))
+        .and_then(|file| file.lock().map(|_| file));
+    match locked {
+        Ok(file) => Ok(file),
+        Err(err) => Err(keyring_error(err.to_string())),
+    }
+}
+
+// Written next to the path and moved over it, readable by the owner only. The
+// file is synced before the move and the directory after it, the same way as
+// the keyfile.
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    let dir = keys_dir();
+    if let Err(err) = fs::create_dir_all(&dir) {
+        return Err(keyring_error(err.to_string()));
+    }
+    let tmp_path = path.with_extension("tmp");
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .and_then(|_| fs::File::open(&dir)?.sync_all());
+    match written {
+        Ok(_) => Ok(()),
+        Err(err) => Err(keyring_error(format!("{}: {}", path.display(), err))),
+    }
+}
+
+// SIGNING_KEY_ROTATION_DAYS=0 turns the scheduled rotation off
+fn rotation_period() -> Option<Duration> {
+    let days = match env::var("SIGNING_KEY_ROTATION_DAYS") {
+        Ok(days) => days.parse::<i64>().unwrap_or(DEFAULT_ROTATION_DAYS),
+        Err(_) => DEFAULT_ROTATION_DAYS,
+    };
+    if days > 0 {
+        Some(Duration::days(days))
+    } else {
+        None
+    }
+}
+
+// stores the private key as PKCS#8 and returns a new pending key for it
+fn store_private_key(private_key: &PKey<openssl::pkey::Private>) -> Result<SigningKey> {
+    let public_key = match private_key.public_key_to_pem() {
+        Ok(key) => key,
+        Err(err) => {
+            return Err(Error::PublicKeyLoadError {
+                message: err.to_string(),
+            })
+        }
+    };
+    let kid = key_id(&public_key)?;
+    let pem = match private_key.private_key_to_pem_pkcs8() {
+        Ok(pem) => pem,
+        Err(err) => {
+            return Err(Error::PrivateKeyLoadError {
+                message: err.to_string(),
+            })
+        }
+    };
+    write_private(&key_path(&kid), &pem)?;
+
+    Ok(SigningKey {
+        kid,
+        state: KeyState::Pending,
+        created_at: Utc::now(),
+        activated_at: None,
+        retires_at: None,
+    })
+}
+
+fn generate_key() -> Result<SigningKey> {
+    let rsa = match Rsa::generate(2048) {
+        Ok(rsa) => rsa,
+        Err(err) => return Err(keyring_error(err.to_string())),
+    };
+    match PKey::from_rsa(rsa) {
+        Ok(private_key) => store_private_key(&private_key),
+        Err(err) => Err(keyring_error(err.to_string())),
+    }
+}
+
+impl Keyring {
+    pub fn manifest_path() -> PathBuf {
+        keys_dir().join(MANIFEST)
+    }
+
+    fn read() -> Result<Option<Self>> {
+        let manifest = Keyring::manifest_path();
+        if !manifest.exists() {
+            return Ok(None);
+        }
+        let content = match fs::read(&manifest) {
+            Ok(content) => content,
+            Err(err) => return Err(keyring_error(err.to_string())),
+        };
+        match serde_json::from_slice::<Keyring>(&content) {
+            Ok(keyring) => Ok(Some(keyring)),
+            Err(err) => Err(keyring_error(format!("Invalid {}: {}", MANIFEST, err))),
+        }
+    }
+
+    // loads the keyring and creates it on the first start
+    pub fn load() -> Result<Self> {
+        if let Some(keyring) = Keyring::read()? {
+            return Ok(keyring);
+        }
+        let _lock = lock()?;
+        Keyring::load_locked()
+    }
+
+    // load for the holder of the lock, another process may have created the keyring while it waited
+    fn load_locked() -> Result<Self> {
+        if let Some(keyring) = Keyring::read()? {
+            return Ok(keyring);
+        }
+
+        // import the existing private_key.pem so tokens issued before the keyring keep working
+        let mut key = match fs::read(LEGACY_PRIVATE_KEY) {
+            Ok(pem) => match Rsa::private_key_from_pem(&pem).and_then(PKey::from_rsa) {
+                Ok(private_key) => store_private_key(&private_key)?,
+                Err(err) => {
+                    return Err(Error::PrivateKeyLoadError {
+                        message: err.to_string(),
+                    })
+                }
+            },
+            Err(_) => generate_key()?,
+        };
+        key.state = KeyState::Active;
+        key.activated_at = Some(Utc::now());
+
+        let keyring = Keyring { keys: vec![key] };
+        keyring.save()?;
+        Ok(keyring)
+    }
+
+    // only with the lock held
+    fn save(&self) -> Result<()> {
+        let content = match serde_json::to_vec_pretty(self) {
+            Ok(content) => content,
+            Err(err) => return Err(keyring_error(err.to_string())),
+        };
+        // a reader never sees a half written manifest
+        write_private(&Keyring::manifest_path(), &content)
+    }
+
+    pub fn active(&self) -> Result<&SigningKey> {
+        match self.keys.iter().find(|k| k.state == KeyState::Active) {
+            Some(key) => Ok(key),
+            None => Err(keyring_error("No active signing key".to_string())),
+        }
+    }
+
+    // any key that is not retired can be used to verify a token
+    pub fn find(&self, kid: &str) -> Option<&SigningKey> {
+        self.keys
+            .iter()
+            .find(|k| k.kid == kid && k.state != KeyState::Retired)
+    }
+
+    // the keys that go into the JWKS, active key first
+    pub fn published(&self) -> Vec<&SigningKey> {
+        let mut keys: Vec<&SigningKey> = self
+            .keys
+            .iter()
+            .filter(|k| k.state != KeyState::Retired)
+            .collect();
+        keys.sort_by_key(|k| k.state != KeyState::Active);
+        keys
+    }
+
+    pub fn private_key(&self, kid: &str) -> Result<Vec<u8>> {
+        match fs::read(key_path(kid)) {
+            Ok(pem) => Ok(pem),
+            Err(_) => Err(Error::PrivateKeyLoadError {
+                message: format!("Error loading private key {}", kid),
+            }),
+        }
+    }
+
+    pub fn public_key(&self, kid: &str) -> Result<Vec<u8>> {
+        let pem = self.private_key(kid)?;
+        match PKey::private_key_from_pem(&pem).and_then(|key| key.public_key_to_pem()) {
+            Ok(key) => Ok(key),
+            Err(_) => Err(Error::PublicKeyLoadError {
+                message: "Error deriving public key from private key".to_string(),
+            }),
+        }
+    }
+
+    // makes the key active and moves the current active key to retiring
+    fn promote(&mut self, kid: &str, now: DateTime<Utc>) {
+        for key in self.keys.iter_mut() {
+            if key.kid == kid {
+                key.state = KeyState::Active;
+                key.activated_at = Some(now);
+            } else if key.state == KeyState::Active {
+                key.state = KeyState::Retiring;
+                key.retires_at = Some(now + Duration::days(RETIRING_PERIOD_DAYS));
+            }
+        }
+    }
+
+    // Rotates right away. A scheduled pending key is used if there is one so the
+    // new key is already known downstream, otherwise a new key is generated.
+    pub fn rotate() -> Result<SigningKey> {
+        let _lock = lock()?;
+        let mut keyring = Keyring::load_locked()?;
+
+        let kid = match keyring
+            .keys
+            .iter()
+            .filter(|k| k.state == KeyState::Pending)
+            .min_by_key(|k| k.activated_at)
+        {
+            Some(key) => key.kid.clone(),
+            None => {
+                let key = generate_key()?;
+                let kid = key.kid.clone();
+                keyring.keys.push(key);
+                kid
+            }
+        };
+        keyring.promote(&kid, Utc::now());
+        keyring.save()?;
+
+        match keyring.find(&kid) {
+            Some(key) => Ok(key.clone()),
+            None => Err(keyring_error("Rotated key not found".to_string())),
+        }
+    }
+
+    // Moves the keys through their states based on the time. Returns true if
+    // anything changed.
+    pub fn run_schedule() -> Result<bool> {
+        let _lock = lock()?;
+        let mut keyring = Keyring::load_locked()?;
+        let changed = keyring.schedule(Utc::now())?;
+        if changed {
+            keyring.save()?;
+        }
+        Ok(changed)
+    }
+
+    // the state changes due at now, without saving them
+    fn schedule(&mut self, now: DateTime<Utc>) -> Result<bool> {
+        let mut changed = false;
+
+        for key in self.keys.iter_mut() {
+            if key.state == KeyState::Retiring && key.retires_at.is_none_or(|t| t <= now) {
+                key.state = KeyState::Retired;
+                key.retires_at = Some(now);
+                // nothing can use the key anymore, so there is no reason to keep it around
+                let _ = fs::remove_file(key_path(&key.kid));
+                changed = true;
+            }
+        }
+
+        let due = self
+            .keys
+            .iter()
+            .filter(|k| k.state == KeyState::Pending && k.activated_at.is_none_or(|t| t <= now))
+            .min_by_key(|k| k.activated_at)
+            .map(|k| k.kid.clone());
+        if let Some(kid) = due {
+            self.promote(&kid, now);
+            changed = true;
+        }
+
+        // stage the next key ahead of time when the active key is getting old
+        let has_pending = self.keys.iter().any(|k| k.state == KeyState::Pending);
+        if let Some(period) = rotation_period() {
+            let activated_at = self.active()?.activated_at.unwrap_or(now);
+            if !has_pending && activated_at + period <= now + Duration::days(PENDING_PERIOD_DAYS) {
+                let mut key = generate_key()?;
+                key.activated_at = Some(now + Duration::days(PENDING_PERIOD_DAYS));
+                self.keys.push(key);
+                changed = true;
+            }
+        }
+
+        Ok(changed)
+    }
+}
+
+// SIGNING_KEYS_DIR is shared by every test of the process, tests that set it
+// hold this while they use it
+#[cfg(test)]
+pub static KEYS_DIR: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use std::sync::MutexGuard;
+
+    use super::*;
+    use crate::{
+        core::{key_cache::KeyCache, user::User},
+        utils::session_utils::IDToken,
+    };
+
+    // an empty keyring of the test in the temp dir, SIGNING_KEYS_DIR points to
+    // it while the guard is held
+    fn empty_keys_dir(test: &str) -> MutexGuard<'static, ()> {
+        let guard = KEYS_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = env::temp_dir().join(format!("keyring-test-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        env::set_var("SIGNING_KEYS_DIR", &dir);
+        guard
+    }
+
+    fn state(kid: &str) -> KeyState {
+        let keyring = Keyring::load().unwrap();
+        keyring.keys.iter().find(|k| k.kid == kid).unwrap().state
+    }
+
+    // schedules and saves the keyring as run_schedule does, at the given time
+    fn run_schedule_at(now: DateTime<Utc>) -> bool {
+        let _lock = lock().unwrap();
+        let mut keyring = Keyring::load_locked().unwrap();
+        let changed = keyring.schedule(now).unwrap();
+        keyring.save().unwrap();
+        changed
+    }
+
+    #[test]
+    fn rotate_promotes_the_pending_key() {
+        let _dir = empty_keys_dir("rotate");
+        let active = Keyring::load().unwrap().active().unwrap().kid.clone();
+        let pending = {
+            let _lock = lock().unwrap();
+            let mut keyring = Keyring::load_locked().unwrap();
+            let mut key = generate_key().unwrap();
+            key.activated_at = Some(Utc::now() + Duration::days(PENDING_PERIOD_DAYS));
+            keyring.keys.push(key.clone());
+            keyring.save().unwrap();
+            key.kid
+        };
+
+        assert_eq!(Keyring::rotate().unwrap().kid, pending);
+        assert_eq!(state(&pending), KeyState::Active);
+        assert_eq!(state(&active), KeyState::Retiring);
+        let keyring = Keyring::load().unwrap();
+        let retiring = keyring.find(&active).unwrap();
+        assert!(retiring.retires_at.unwrap() > Utc::now() + Duration::days(RETIRING_PERIOD_DAYS - 1));
+        assert_eq!(keyring.published().len(), 2);
+    }
+
+    #[test]
+    fn moves_the_keys_on_at_their_deadlines() {
+        let _dir = empty_keys_dir("schedule");
+        let first = Keyring::load().unwrap().active().unwrap().clone();
+        let start = first.activated_at.unwrap();
+        let days = Duration::days;
+
+        // the next key is staged a pending period before the active one is due
+        assert!(!run_schedule_at(start + days(DEFAULT_ROTATION_DAYS - PENDING_PERIOD_DAYS) - Duration::hours(12)));
+        assert!(run_schedule_at(start + days(DEFAULT_ROTATION_DAYS - PENDING_PERIOD_DAYS)));
+        let keyring = Keyring::load().unwrap();
+        let next = keyring.keys.iter().find(|k| k.state == KeyState::Pending).unwrap().clone();
+        assert_eq!(next.activated_at, Some(start + days(DEFAULT_ROTATION_DAYS)));
+        assert_eq!(keyring.published().len(), 2);
+
+        assert!(!run_schedule_at(start + days(DEFAULT_ROTATION_DAYS) - Duration::seconds(1)));
+        assert_eq!(state(&first.kid), KeyState::Active);
+        assert!(run_schedule_at(start + days(DEFAULT_ROTATION_DAYS)));
+        assert_eq!(state(&next.kid), KeyState::Active);
+        assert_eq!(state(&first.kid), KeyState::Retiring);
+
+        let retires_at = start + days(DEFAULT_ROTATION_DAYS + RETIRING_PERIOD_DAYS);
+        assert!(!run_schedule_at(retires_at - Duration::seconds(1)));
+        assert!(run_schedule_at(retires_at));
+        assert_eq!(state(&first.kid), KeyState::Retired);
+        assert!(!key_path(&first.kid).exists());
+        assert_eq!(Keyring::load().unwrap().published().len(), 1);
+    }
+
+    #[test]
+    fn verifies_tokens_of_a_retiring_key_but_not_of_a_retired_one() {
+        let _dir = empty_keys_dir("verify");
+        let keys = KeyCache::load().unwrap();
+        let user = User::new("Ada", "ada@example.com", "user", "");
+        let token = IDToken::new(&user).sign(&keys).unwrap();
+
+        let old = Keyring::load().unwrap().active().unwrap().kid.clone();
+        Keyring::rotate().unwrap();
+        keys.reload().unwrap();
+        assert_ne!(keys.signing_key().unwrap().kid, old);
+        assert!(IDToken::verify(&keys, &token).is_ok());
+
+        let retires_at = Keyring::load().unwrap().find(&old).unwrap().retires_at.unwrap();
+        run_schedule_at(retires_at);
+        keys.reload().unwrap();
+        assert!(matches!(
+            IDToken::verify(&keys, &token),
+            Err(Error::SignatureVerificationError { .. })
+        ));
+    }
+}
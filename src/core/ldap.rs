@@ -0,0 +1,249 @@
+use crate::{
+    core::{federation::Federation, ldap_connection::LdapConnection, user::User},
+    errors::Result,
+    traits::{ldap_directory::LdapDirectory, store::Store},
+    utils::ldap_utils::{bind_and_search, escape_dn, escape_filter, fill_template, Ldap3Directory, LdapSearch},
+};
+
+pub struct Ldap;
+
+impl Ldap {
+    // Checks the password by binding to the directory as the user. The user is
+    // created on their first sign in, and as the directory decides the role,
+    // it is updated to the mapped role on every sign in when there is a
+    // mapping. Returns None when the directory refused the password.
+    pub async fn authenticate(
+        store: &dyn Store,
+        connection: &LdapConnection,
+        email: &str,
+        password: &str,
+        user: Option<User>,
+    ) -> Result<Option<User>> {
+        Self::authenticate_with(store, &Ldap3Directory, connection, email, password, user).await
+    }
+
+    pub async fn authenticate_with(
+        store: &dyn Store,
+        directory: &dyn LdapDirectory,
+        connection: &LdapConnection,
+        email: &str,
+        password: &str,
+        user: Option<User>,
+    ) -> Result<Option<User>> {
+        let bind_dn = fill_template(&connection.bind_dn, email, escape_dn);
+        let filter = fill_template(&connection.user_filter, email, escape_filter);
+        let search = LdapSearch {
+            base: &connection.search_base,
+            filter: &filter,
+            attributes: vec![&connection.name_attribute, &connection.group_attribute],
+        };
+        let entry = match bind_and_search(
+            directory,
+            &connection.url,
+            connection.start_tls,
+            &bind_dn,
+            password,
+            &search,
+        )
+        .await
+        {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let role = connection.role(&entry);
+
+        match user {
+            Some(user) if connection.role_mapping.is_empty() || user.role == role => Ok(Some(user)),
+            Some(user) => match User::update_role(store, &user.email, &role).await {
+                Ok(role) => Ok(Some(User { role, ..user })),
+                Err(e) => Err(e),
+            },
+            None => {
+                // the company runs the directory of its domain, so the email is verified
+                let name = entry.attribute(&connection.name_attribute);
+                let uid = match Federation::create_user(store, email, name, &role, true).await {
+                    Ok(uid) => uid,
+                    Err(e) => return Err(e),
+                };
+                match User::get_from_uid(store, &uid).await {
+                    Ok(user) => Ok(Some(user)),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env, sync::Mutex};
+
+    use async_trait::async_trait;
+    use bson::{oid::ObjectId, DateTime};
+
+    use super::*;
+    use crate::{
+        core::ldap_connection::LdapRoleMapping,
+        errors::Error,
+        store::memory_store::MemoryStore,
+        traits::store::UserStore,
+        utils::ldap_utils::LdapEntry,
+    };
+
+    fn set_keys() {
+        env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+    }
+
+    // answers every bind with the same entries and records what it was asked
+    struct StubDirectory {
+        // None refuses the password
+        entries: Option<Vec<LdapEntry>>,
+        // the bind DN and filter of every bind
+        binds: Mutex<Vec<(String, String)>>,
+    }
+
+    impl StubDirectory {
+        fn new(entries: Option<Vec<LdapEntry>>) -> Self {
+            Self {
+                entries,
+                binds: Mutex::new(vec![]),
+            }
+        }
+
+        fn binds(&self) -> Vec<(String, String)> {
+            self.binds.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl LdapDirectory for StubDirectory {
+        async fn bind_and_search(
+            &self,
+            _url: &str,
+            _start_tls: bool,
+            bind_dn: &str,
+            _password: &str,
+            search: &LdapSearch<'_>,
+        ) -> Result<Option<Vec<LdapEntry>>> {
+            self.binds
+                .lock()
+                .unwrap()
+                .push((bind_dn.to_string(), search.filter.to_string()));
+            Ok(self.entries.clone())
+        }
+    }
+
+    fn entry(name: &str, groups: &[&str]) -> LdapEntry {
+        LdapEntry {
+            attributes: HashMap::from([
+                ("cn".to_string(), vec![name.to_string()]),
+                ("memberof".to_string(), groups.iter().map(|group| group.to_string()).collect()),
+            ]),
+        }
+    }
+
+    fn connection() -> LdapConnection {
+        LdapConnection {
+            _id: ObjectId::new(),
+            connection_id: "corp".to_string(),
+            name: "Corp".to_string(),
+            url: "ldap://ldap.example.com".to_string(),
+            start_tls: true,
+            bind_dn: "uid={username},ou=people,dc=example,dc=com".to_string(),
+            search_base: "ou=people,dc=example,dc=com".to_string(),
+            user_filter: "(mail={email})".to_string(),
+            domains: vec!["example.com".to_string()],
+            name_attribute: "cn".to_string(),
+            group_attribute: "memberOf".to_string(),
+            role_mapping: vec![LdapRoleMapping {
+                group: "cn=admins,ou=groups,dc=example,dc=com".to_string(),
+                role: "admin".to_string(),
+            }],
+            default_role: "user".to_string(),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn creates_the_user_with_the_mapped_role() {
+        set_keys();
+        let store = MemoryStore::new();
+        let directory = StubDirectory::new(Some(vec![entry("Ada", &["CN=Admins,OU=Groups,DC=example,DC=com"])]));
+
+        let user = Ldap::authenticate_with(&store, &directory, &connection(), "ada@example.com", "secret", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.name, "Ada");
+        assert_eq!(user.role, "admin");
+        assert!(user.email_verified);
+        assert_eq!(
+            directory.binds(),
+            vec![(
+                "uid=ada,ou=people,dc=example,dc=com".to_string(),
+                "(mail=ada@example.com)".to_string()
+            )]
+        );
+
+        // the directory decides the role on every sign in
+        let directory = StubDirectory::new(Some(vec![entry("Ada", &[])]));
+        let user = Ldap::authenticate_with(&store, &directory, &connection(), "ada@example.com", "secret", Some(user))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.role, "user");
+    }
+
+    #[tokio::test]
+    async fn escapes_the_email_in_the_bind_dn_and_filter() {
+        let store = MemoryStore::new();
+        let directory = StubDirectory::new(None);
+        Ldap::authenticate_with(&store, &directory, &connection(), "a,b*)(uid=*@example.com", "secret", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            directory.binds(),
+            vec![(
+                "uid=a\\2cb*)(uid\\3d*,ou=people,dc=example,dc=com".to_string(),
+                "(mail=a,b\\2a\\29\\28uid=\\2a@example.com)".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_an_empty_password_without_binding() {
+        let store = MemoryStore::new();
+        let directory = StubDirectory::new(Some(vec![entry("Ada", &[])]));
+        let user = Ldap::authenticate_with(&store, &directory, &connection(), "ada@example.com", "", None)
+            .await
+            .unwrap();
+        assert!(user.is_none());
+        assert!(directory.binds().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_refused_password() {
+        let store = MemoryStore::new();
+        let directory = StubDirectory::new(None);
+        let user = Ldap::authenticate_with(&store, &directory, &connection(), "ada@example.com", "wrong", None)
+            .await
+            .unwrap();
+        assert!(user.is_none());
+    }
+
+    #[tokio::test]
+    async fn needs_exactly_one_entry() {
+        let store = MemoryStore::new();
+        for entries in [vec![], vec![entry("Ada", &[]), entry("Grace", &[])]] {
+            let directory = StubDirectory::new(Some(entries));
+            assert!(matches!(
+                Ldap::authenticate_with(&store, &directory, &connection(), "ada@example.com", "secret", None).await,
+                Err(Error::LdapFailed { .. })
+            ));
+        }
+        assert!(store.get_users().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    models::ldap_model::{CreateLdapConnectionPayload, LdapConnectionResponse},
+    traits::store::Store,
+    utils::ldap_utils::LdapEntry,
+};
+
+// a group DN and the role its members get
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LdapRoleMapping {
+    pub group: String,
+    pub role: String,
+}
+
+// An LDAP or Active Directory server the users of some email domains sign in
+// against. Their password is checked by binding to the directory as them, so
+// it never gets stored here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LdapConnection {
+    pub _id: ObjectId,
+    pub connection_id: String,
+    pub name: String,
+    pub url: String,
+    pub start_tls: bool,
+    pub bind_dn: String,
+    // the entry of the bind DN is read when empty
+    pub search_base: String,
+    pub user_filter: String,
+    pub domains: Vec<String>,
+    pub name_attribute: String,
+    pub group_attribute: String,
+    pub role_mapping: Vec<LdapRoleMapping>,
+    pub default_role: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+fn has_placeholder(template: &str) -> bool {
+    template.contains("{username}") || template.contains("{email}")
+}
+
+fn or_default(value: &Option<String>, default: &str) -> String {
+    match value {
+        Some(value) if !value.trim().is_empty() => value.trim().to_string(),
+        _ => default.to_string(),
+    }
+}
+
+impl LdapConnection {
+    pub async fn create(store: &dyn Store, payload: &CreateLdapConnectionPayload) -> Result<LdapConnectionResponse> {
+        let is_slug = payload
+            .connection_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if payload.connection_id.is_empty() || !is_slug {
+            return Err(Error::InvalidPayload {
+                message: "The connection id can only contain lowercase letters, digits and -".to_string(),
+            });
+        }
+
+        let url = payload.url.trim();
+        if !url.starts_with("ldap://") && !url.starts_with("ldaps://") {
+            return Err(Error::InvalidPayload {
+                message: "The url has to start with ldap:// or ldaps://".to_string(),
+            });
+        }
+        if payload.start_tls && url.starts_with("ldaps://") {
+            return Err(Error::InvalidPayload {
+                message: "StartTLS is only for ldap:// urls".to_string(),
+            });
+        }
+
+        // the DN has to depend on the user, else everyone would bind as the same entry
+        if !has_placeholder(&payload.bind_dn) {
+            return Err(Error::InvalidPayload {
+                message: "The bind DN needs {username} or {email}".to_string(),
+            });
+        }
+        let search_base = or_default(&payload.search_base, "");
+        let user_filter = or_default(&payload.user_filter, "(mail={email})");
+        if !search_base.is_empty() && !has_placeholder(&user_filter) {
+            return Err(Error::InvalidPayload {
+                message: "The user filter needs {username} or {email}".to_string(),
+            });
+        }
+
+        let domains: Vec<String> = payload
+            .domains
+            .iter()
+            .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+            .collect();
+        if domains.is_empty() || domains.iter().any(|domain| !domain.contains('.') || domain.contains('@')) {
+            return Err(Error::InvalidPayload {
+                message: "The connection needs the email domains of its users".to_string(),
+            });
+        }
+        if payload
+            .role_mapping
+            .iter()
+            .any(|mapping| mapping.group.trim().is_empty() || mapping.role.trim().is_empty())
+        {
+            return Err(Error::InvalidPayload {
+                message: "A role mapping needs a group and a role".to_string(),
+            });
+        }
+
+        // a domain can only sign in against one directory
+        let connections = match store.get_ldap_connections().await {
+            Ok(connections) => connections,
+            Err(e) => return Err(e),
+        };
+        if connections.iter().any(|c| c.connection_id == payload.connection_id) {
+            return Err(Error::InvalidPayload {
+                message: "An LDAP connection with this id already exists".to_string(),
+            });
+        }
+        if connections
+            .iter()
+            .any(|c| c.domains.iter().any(|domain| domains.contains(domain)))
+        {
+            return Err(Error::InvalidPayload {
+                message: "Another LDAP connection already has one of these domains".to_string(),
+            });
+        }
+
+        let connection = Self {
+            _id: ObjectId::new(),
+            connection_id: payload.connection_id.clone(),
+            name: payload.name.trim().to_string(),
+            url: url.to_string(),
+            start_tls: payload.start_tls,
+            bind_dn: payload.bind_dn.trim().to_string(),
+            search_base,
+            user_filter,
+            domains,
+            name_attribute: or_default(&payload.name_attribute, "cn"),
+            group_attribute: or_default(&payload.group_attribute, "memberOf"),
+            role_mapping: payload
+                .role_mapping
+                .iter()
+                .map(|mapping| LdapRoleMapping {
+                    group: mapping.group.trim().to_string(),
+                    role: mapping.role.trim().to_string(),
+                })
+                .collect(),
+            default_role: or_default(&payload.default_role, "user"),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        };
+        match store.insert_ldap_connection(&connection).await {
+            Ok(_) => Ok(connection.to_response()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // the connection the users of the domain of the email sign in with, if any
+    pub async fn for_email(store: &dyn Store, email: &str) -> Result<Option<Self>> {
+        let domain = match email.rsplit_once('@') {
+            Some((_, domain)) => domain.to_lowercase(),
+            None => return Ok(None),
+        };
+        match store.get_ldap_connections().await {
+            Ok(connections) => Ok(connections.into_iter().find(|c| c.domains.contains(&domain))),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_all(store: &dyn Store) -> Result<Vec<LdapConnectionResponse>> {
+        let mut connections = match store.get_ldap_connections().await {
+            Ok(connections) => connections,
+            Err(e) => return Err(e),
+        };
+        connections.sort_by_key(|c| c.created_at);
+        Ok(connections.iter().map(|c| c.to_response()).collect())
+    }
+
+    pub async fn delete(store: &dyn Store, connection_id: &str) -> Result<()> {
+        match store.delete_ldap_connection(connection_id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::LdapConnectionNotFound {
+                message: "LDAP connection not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // the role of the first mapping the user is a member of, DNs are case insensitive
+    pub fn role(&self, entry: &LdapEntry) -> String {
+        let groups: Vec<String> = entry
+            .attribute_values(&self.group_attribute)
+            .iter()
+            .map(|group| group.to_lowercase())
+            .collect();
+        match self
+            .role_mapping
+            .iter()
+            .find(|mapping| groups.contains(&mapping.group.to_lowercase()))
+        {
+            Some(mapping) => mapping.role.clone(),
+            None => self.default_role.clone(),
+        }
+    }
+
+    fn to_response(&self) -> LdapConnectionResponse {
+        LdapConnectionResponse {
+            connection_id: self.connection_id.clone(),
+            name: self.name.clone(),
+            url: self.url.clone(),
+            start_tls: self.start_tls,
+            bind_dn: self.bind_dn.clone(),
+            search_base: self.search_base.clone(),
+            user_filter: self.user_filter.clone(),
+            domains: self.domains.clone(),
+            name_attribute: self.name_attribute.clone(),
+            group_attribute: self.group_attribute.clone(),
+            role_mapping: self.role_mapping.clone(),
+            default_role: self.default_role.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::user::User,
+    errors::{Error, Result},
+    traits::store::Store,
+    utils::{
+        email_utils::Email,
+        oauth_utils::{generate_token, hash_token},
+    },
+};
+
+// the link in the email works for 15 minutes
+pub const MAGIC_LINK_TTL_SECS: i64 = 900;
+
+// A pending passwordless sign in. The link is mailed to the user and signs
+// them in once, from the same client that asked for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLink {
+    pub _id: ObjectId,
+    // only the hash of the token is stored, the token itself is only in the email
+    pub token_hash: String,
+    pub uid: String,
+    pub user_agent: String,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+impl MagicLink {
+    // Mails the user a link to redirect_url with the token in its query. The
+    // app behind redirect_url redeems the token for a session. Nothing is sent
+    // for an email without a user.
+    pub async fn request(store: &dyn Store, email: &str, redirect_url: &str, user_agent: &str) -> Result<()> {
+        if !redirect_url.starts_with("https://") && !redirect_url.starts_with("http://") {
+            return Err(Error::InvalidPayload {
+                message: "Invalid redirect url".to_string(),
+            });
+        }
+
+        // an unknown email gets the same answer, so nobody can find out who has an account
+        let user = match User::get_from_email(store, email).await {
+            Ok(user) => user,
+            // there is no DEK for an email without a user
+            Err(Error::KeyNotFound { .. }) | Err(Error::UserNotFound { .. }) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let token = generate_token(32);
+        let magic_link = Self {
+            _id: ObjectId::new(),
+            token_hash: hash_token(&token),
+            uid: user.uid.clone(),
+            user_agent: user_agent.to_string(),
+            expires_at: DateTime::from_millis(
+                DateTime::now().timestamp_millis() + MAGIC_LINK_TTL_SECS * 1000,
+            ),
+            created_at: DateTime::now(),
+        };
+
+        match store.insert_magic_link(&magic_link).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        // nothing else cleans up after the unused ones
+        match store.delete_expired_magic_links().await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let separator = if redirect_url.contains('?') { "&" } else { "?" };
+        Email::new(
+            &user.name,
+            &user.email,
+            "Sign in",
+            &format!("Please click on the link to sign in: {}{}token={} . The link works once and expires in 15 minutes. If it was not you, you can ignore this email.", redirect_url, separator, token),
+        ).send().await;
+
+        Ok(())
+    }
+
+    // uses up the link of the token and returns the uid it was issued for
+    pub async fn redeem(store: &dyn Store, token: &str, user_agent: &str) -> Result<String> {
+        // taken out right away, so a link can't be used twice even at the same time
+        let magic_link = match store.take_magic_link(&hash_token(token)).await {
+            Ok(Some(magic_link)) => magic_link,
+            Ok(None) => {
+                return Err(Error::InvalidToken {
+                    message: "Invalid sign in link. Please request a new link.".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        if magic_link.expires_at.timestamp_millis() < DateTime::now().timestamp_millis() {
+            return Err(Error::InvalidToken {
+                message: "The link has expired. Please request a new link.".to_string(),
+            });
+        }
+        if magic_link.user_agent != user_agent {
+            return Err(Error::InvalidToken {
+                message: "The link has to be opened on the device it was requested from.".to_string(),
+            });
+        }
+
+        Ok(magic_link.uid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::dek::Dek, store::memory_store::MemoryStore, traits::store::SessionStore, utils::email_utils,
+    };
+    use std::env;
+
+    fn set_keys() {
+        env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+    }
+
+    async fn add_user(store: &dyn Store, email: &str) -> User {
+        let dek = Dek::generate();
+        let user = User::new("Ada", email, "user", "secret")
+            .encrypt_and_add(store, &dek)
+            .await
+            .unwrap();
+        Dek::new(&user.uid, &user.email, &dek)
+            .encrypt_and_add(store)
+            .await
+            .unwrap();
+        user
+    }
+
+    // the token of the last link mailed to the address
+    fn mailed_token(email: &str) -> String {
+        let body = email_utils::sent_to(email).pop().unwrap();
+        let start = body.find("token=").unwrap() + "token=".len();
+        body[start..].split_whitespace().next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn signs_in_once() {
+        set_keys();
+        let store = MemoryStore::new();
+        let user = add_user(&store, "link-once@example.com").await;
+
+        MagicLink::request(&store, "link-once@example.com", "https://app.example.com/signin", "curl")
+            .await
+            .unwrap();
+        let token = mailed_token("link-once@example.com");
+
+        assert_eq!(MagicLink::redeem(&store, &token, "curl").await.unwrap(), user.uid);
+        assert!(matches!(
+            MagicLink::redeem(&store, &token, "curl").await,
+            Err(Error::InvalidToken { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn sends_nothing_for_an_unknown_email() {
+        set_keys();
+        let store = MemoryStore::new();
+
+        assert!(MagicLink::request(&store, "link-nobody@example.com", "https://app.example.com", "curl")
+            .await
+            .is_ok());
+        assert!(email_utils::sent_to("link-nobody@example.com").is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_an_expired_link() {
+        let store = MemoryStore::new();
+        let magic_link = MagicLink {
+            _id: ObjectId::new(),
+            token_hash: hash_token("expired"),
+            uid: "u1".to_string(),
+            user_agent: "curl".to_string(),
+            expires_at: DateTime::from_millis(DateTime::now().timestamp_millis() - 1000),
+            created_at: DateTime::now(),
+        };
+        store.insert_magic_link(&magic_link).await.unwrap();
+
+        assert!(matches!(
+            MagicLink::redeem(&store, "expired", "curl").await,
+            Err(Error::InvalidToken { .. })
+        ));
+    }
+
+    // opened on another device, the link is used up all the same
+    #[tokio::test]
+    async fn rejects_and_uses_up_a_link_from_another_client() {
+        set_keys();
+        let store = MemoryStore::new();
+        add_user(&store, "link-agent@example.com").await;
+
+        MagicLink::request(&store, "link-agent@example.com", "https://app.example.com?next=/", "curl")
+            .await
+            .unwrap();
+        let token = mailed_token("link-agent@example.com");
+
+        assert!(matches!(
+            MagicLink::redeem(&store, &token, "Mozilla/5.0").await,
+            Err(Error::InvalidToken { .. })
+        ));
+        assert!(MagicLink::redeem(&store, &token, "curl").await.is_err());
+    }
+}
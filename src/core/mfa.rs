@@ -0,0 +1,463 @@
+use bson::DateTime;
+use openssl::rand::rand_bytes;
+
+use crate::{
+    core::{user::User, webauthn::Webauthn},
+    errors::{Error, Result},
+    models::webauthn_model::AssertionCredential,
+    traits::store::Store,
+    utils::{encryption_utils::Encryption, password_utils::Password, totp_utils::Totp},
+};
+
+// the name authenticator apps show next to the code
+const TOTP_ISSUER: &str = "FlexAuth";
+
+const RECOVERY_CODE_COUNT: usize = 10;
+// no 0/o and 1/l, the codes are typed in from paper
+const RECOVERY_CODE_ALPHABET: &[u8; 32] = b"23456789abcdefghijkmnpqrstuvwxyz";
+
+// Returns the codes to show to the user once and their hashes to store.
+// Every code is 10 characters (50 bits), shown as xxxxx-xxxxx.
+fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let mut codes = Vec::new();
+    let mut hashes = Vec::new();
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let mut bytes = [0; 10];
+        rand_bytes(&mut bytes).unwrap();
+        let code: String = bytes
+            .iter()
+            .map(|b| RECOVERY_CODE_ALPHABET[(b % 32) as usize] as char)
+            .collect();
+        hashes.push(Password::salt_and_hash(&code));
+        codes.push(format!("{}-{}", &code[..5], &code[5..]));
+    }
+    (codes, hashes)
+}
+
+// The first second factor of the user comes with recovery codes, the ones
+// enrolled after it keep them. Returns the codes to show if new ones were made.
+fn issue_recovery_codes(user: &mut User) -> Option<Vec<String>> {
+    if !user.recovery_codes.is_empty() {
+        return None;
+    }
+    let (recovery_codes, hashes) = generate_recovery_codes();
+    user.recovery_codes = hashes;
+    Some(recovery_codes)
+}
+
+// users may type the code with or without the dash and in any case
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// what the user answered the second step of a sign in with
+pub enum MfaProof<'a> {
+    Totp(&'a str),
+    RecoveryCode(&'a str),
+    Webauthn(&'a AssertionCredential),
+}
+
+pub struct Mfa;
+
+impl Mfa {
+    // the second factors the user can sign in with, empty if there is none
+    pub async fn methods(store: &dyn Store, user: &User) -> Result<Vec<String>> {
+        let has_passkeys = match Webauthn::has_credentials(store, &user.uid).await {
+            Ok(has_passkeys) => has_passkeys,
+            Err(e) => return Err(e),
+        };
+        let mut methods = Vec::new();
+        if user.totp_enabled {
+            methods.push("totp".to_string());
+        }
+        // the recovery codes stand in for whichever second factor the user has
+        if (user.totp_enabled || has_passkeys) && !user.recovery_codes.is_empty() {
+            methods.push("recovery_code".to_string());
+        }
+        if has_passkeys {
+            methods.push("webauthn".to_string());
+        }
+        Ok(methods)
+    }
+
+    pub async fn has_mfa(store: &dyn Store, user: &User) -> Result<bool> {
+        match Self::methods(store, user).await {
+            Ok(methods) => Ok(!methods.is_empty()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn require_mfa(store: &dyn Store, user: &User) -> Result<()> {
+        match Self::has_mfa(store, user).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::MfaNotEnabled {
+                message: "No second factor is enabled".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Called once a passkey was registered. Returns the recovery codes to show if
+    // it is the first second factor of the user.
+    pub async fn passkey_registered(store: &dyn Store, uid: &str) -> Result<Option<Vec<String>>> {
+        let (mut user, dek_data) = match User::get_stored(store, uid).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+        let recovery_codes = match issue_recovery_codes(&mut user) {
+            Some(recovery_codes) => recovery_codes,
+            None => return Ok(None),
+        };
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => Ok(Some(recovery_codes)),
+            Ok(false) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Called once a passkey was deleted. The recovery codes go with the last
+    // second factor, the next first one comes with new ones.
+    pub async fn passkey_deleted(store: &dyn Store, uid: &str) -> Result<()> {
+        let (mut user, dek_data) = match User::get_stored(store, uid).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+        match Self::has_mfa(store, &user).await {
+            Ok(false) if !user.recovery_codes.is_empty() => {}
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        user.recovery_codes = Vec::new();
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn verify(store: &dyn Store, uid: &str, proof: MfaProof<'_>) -> Result<()> {
+        match proof {
+            MfaProof::Totp(code) => Self::verify_totp(store, uid, code).await,
+            MfaProof::RecoveryCode(code) => Self::use_recovery_code(store, uid, code).await,
+            MfaProof::Webauthn(credential) => {
+                match Webauthn::verify_assertion(store, Some(uid), credential).await {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    // Starts the TOTP enrollment of the user. The secret is stored right away
+    // but only used for sign in after it was confirmed with a first code.
+    // Returns the secret and the otpauth uri to show as a QR code.
+    pub async fn enroll_totp(store: &dyn Store, email: &str) -> Result<(String, String)> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        if user.totp_enabled {
+            return Err(Error::MfaAlreadyEnabled {
+                message: "TOTP is already enabled".to_string(),
+            });
+        }
+
+        let secret = Totp::generate_secret();
+        user.totp_secret = Some(Encryption::encrypt_data(&secret, &dek_data.dek));
+        user.totp_last_step = None;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+
+        let uri = Totp::provisioning_uri(&secret, TOTP_ISSUER, email);
+        Ok((secret, uri))
+    }
+
+    // Enables TOTP once the user proved the authenticator app has the secret.
+    // Returns the recovery codes if it is the first second factor of the user,
+    // this is the only time they are shown.
+    pub async fn confirm_totp(store: &dyn Store, email: &str, code: &str) -> Result<Vec<String>> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        if user.totp_enabled {
+            return Err(Error::MfaAlreadyEnabled {
+                message: "TOTP is already enabled".to_string(),
+            });
+        }
+        let secret = match &user.totp_secret {
+            Some(secret) => Encryption::decrypt_data(secret, &dek_data.dek),
+            None => {
+                return Err(Error::MfaNotEnabled {
+                    message: "TOTP enrollment was not started".to_string(),
+                })
+            }
+        };
+
+        let step = match Totp::verify(&secret, code, Totp::current_step()) {
+            Some(step) => step,
+            None => {
+                return Err(Error::InvalidMfaCode {
+                    message: "Invalid TOTP code".to_string(),
+                })
+            }
+        };
+
+        let recovery_codes = issue_recovery_codes(&mut user).unwrap_or_default();
+        user.totp_enabled = true;
+        user.totp_last_step = Some(step as i64);
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => Ok(recovery_codes),
+            Ok(false) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn disable_totp(store: &dyn Store, email: &str) -> Result<()> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        if !user.totp_enabled && user.totp_secret.is_none() {
+            return Err(Error::MfaNotEnabled {
+                message: "TOTP is not enabled".to_string(),
+            });
+        }
+
+        // the recovery codes stay for the passkeys of the user, if there are any
+        match Webauthn::has_credentials(store, &user.uid).await {
+            Ok(true) => {}
+            Ok(false) => user.recovery_codes = Vec::new(),
+            Err(e) => return Err(e),
+        }
+        user.totp_secret = None;
+        user.totp_enabled = false;
+        user.totp_last_step = None;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // checks a code of an enabled TOTP, every code is only accepted once
+    pub async fn verify_totp(store: &dyn Store, uid: &str, code: &str) -> Result<()> {
+        Self::verify_totp_at(store, uid, code, Totp::current_step()).await
+    }
+
+    async fn verify_totp_at(store: &dyn Store, uid: &str, code: &str, current_step: u64) -> Result<()> {
+        let (user, dek_data) = match User::get_stored(store, uid).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        let secret = match (&user.totp_secret, user.totp_enabled) {
+            (Some(secret), true) => Encryption::decrypt_data(secret, &dek_data.dek),
+            _ => {
+                return Err(Error::MfaNotEnabled {
+                    message: "TOTP is not enabled".to_string(),
+                })
+            }
+        };
+
+        let step = match Totp::verify(&secret, code, current_step) {
+            Some(step) => step as i64,
+            None => {
+                return Err(Error::InvalidMfaCode {
+                    message: "Invalid TOTP code".to_string(),
+                })
+            }
+        };
+        if user.totp_last_step.is_some_and(|last_step| step <= last_step) {
+            return Err(Error::InvalidMfaCode {
+                message: "TOTP code was already used".to_string(),
+            });
+        }
+
+        // two sign ins with the same code can both get here, only one moves the step on
+        match store.set_totp_last_step(&user.uid, step).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::InvalidMfaCode {
+                message: "TOTP code was already used".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // replaces all recovery codes of the user, the old ones stop working
+    pub async fn regenerate_recovery_codes(store: &dyn Store, email: &str) -> Result<Vec<String>> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        match Self::require_mfa(store, &user).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let (recovery_codes, hashes) = generate_recovery_codes();
+        user.recovery_codes = hashes;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => Ok(recovery_codes),
+            Ok(false) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn recovery_codes_remaining(store: &dyn Store, email: &str) -> Result<usize> {
+        let (user, _) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        match Self::require_mfa(store, &user).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(user.recovery_codes.len())
+    }
+
+    // checks a recovery code and uses it up
+    pub async fn use_recovery_code(store: &dyn Store, uid: &str, code: &str) -> Result<()> {
+        let (user, _) = match User::get_stored(store, uid).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        match Self::require_mfa(store, &user).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let code = normalize_recovery_code(code);
+        let hash = match user
+            .recovery_codes
+            .iter()
+            .find(|hash| Password::verify_hash(&code, hash))
+        {
+            Some(hash) => hash,
+            None => {
+                return Err(Error::InvalidMfaCode {
+                    message: "Invalid recovery code".to_string(),
+                })
+            }
+        };
+
+        // only one of two sign ins with the same code gets to remove it
+        match store.remove_recovery_code(&user.uid, hash).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::InvalidMfaCode {
+                message: "Invalid recovery code".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::{core::dek::Dek, store::memory_store::MemoryStore};
+
+    fn set_keys() {
+        env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+    }
+
+    // a user with TOTP enabled, returns the uid and the secret
+    async fn totp_user(store: &dyn Store) -> (String, String) {
+        set_keys();
+        let dek = Dek::generate();
+        let user = User::new("Ada", "ada@example.com", "user", "secret")
+            .encrypt_and_add(store, &dek)
+            .await
+            .unwrap();
+        Dek::new(&user.uid, &user.email, &dek)
+            .encrypt_and_add(store)
+            .await
+            .unwrap();
+        let (secret, _) = Mfa::enroll_totp(store, &user.email).await.unwrap();
+        let code = Totp::code(&secret, Totp::current_step()).unwrap();
+        Mfa::confirm_totp(store, &user.email, &code).await.unwrap();
+        (user.uid, secret)
+    }
+
+    #[tokio::test]
+    async fn accepts_codes_one_step_off() {
+        let store = MemoryStore::new();
+        let (uid, secret) = totp_user(&store).await;
+        let now = Totp::current_step() + 100;
+
+        Mfa::verify_totp_at(&store, &uid, &Totp::code(&secret, now - 1).unwrap(), now)
+            .await
+            .unwrap();
+        Mfa::verify_totp_at(&store, &uid, &Totp::code(&secret, now + 1).unwrap(), now)
+            .await
+            .unwrap();
+        for step in [now - 2, now + 2] {
+            assert!(matches!(
+                Mfa::verify_totp_at(&store, &uid, &Totp::code(&secret, step).unwrap(), now).await,
+                Err(Error::InvalidMfaCode { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_a_replayed_step() {
+        let store = MemoryStore::new();
+        let (uid, secret) = totp_user(&store).await;
+        let now = Totp::current_step() + 100;
+
+        let code = Totp::code(&secret, now).unwrap();
+        Mfa::verify_totp_at(&store, &uid, &code, now).await.unwrap();
+        // the same code, and the one of the step before, within the drift window
+        for code in [code, Totp::code(&secret, now - 1).unwrap()] {
+            assert!(matches!(
+                Mfa::verify_totp_at(&store, &uid, &code, now).await,
+                Err(Error::InvalidMfaCode { .. })
+            ));
+        }
+        Mfa::verify_totp_at(&store, &uid, &Totp::code(&secret, now + 1).unwrap(), now + 1)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    traits::store::Store,
+    utils::oauth_utils::{generate_token, hash_token},
+};
+
+// the user has to type the code right after the password
+pub const CHALLENGE_TTL_SECS: i64 = 300;
+// wrong codes allowed before the challenge is thrown away
+const MAX_ATTEMPTS: i32 = 5;
+
+// Handed out by a sign in with the right password when the user has a second
+// factor. The session is only created once the challenge is completed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub _id: ObjectId,
+    // only the hash of the token is stored, the token itself is handed to the client once
+    pub token_hash: String,
+    pub uid: String,
+    // the challenge has to be completed from the same client that started it
+    pub user_agent: String,
+    pub attempts: i32,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+impl MfaChallenge {
+    // stores a new challenge and returns the plain token to hand to the client
+    pub async fn issue(store: &dyn Store, uid: &str, user_agent: &str) -> Result<String> {
+        let token = generate_token(32);
+        let challenge = Self {
+            _id: ObjectId::new(),
+            token_hash: hash_token(&token),
+            uid: uid.to_string(),
+            user_agent: user_agent.to_string(),
+            attempts: 0,
+            expires_at: DateTime::from_millis(
+                DateTime::now().timestamp_millis() + CHALLENGE_TTL_SECS * 1000,
+            ),
+            created_at: DateTime::now(),
+        };
+
+        match store.insert_mfa_challenge(&challenge).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        // nothing else cleans up after the abandoned ones
+        match store.delete_expired_mfa_challenges().await {
+            Ok(_) => Ok(token),
+            Err(e) => Err(e),
+        }
+    }
+
+    // returns the pending challenge of the token
+    pub async fn get(store: &dyn Store, token: &str, user_agent: &str) -> Result<Self> {
+        let challenge = match store.get_mfa_challenge(&hash_token(token)).await {
+            Ok(Some(challenge)) => challenge,
+            Ok(None) => {
+                return Err(Error::InvalidToken {
+                    message: "Invalid MFA token".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        if challenge.expires_at.timestamp_millis() < DateTime::now().timestamp_millis() {
+            return Err(Error::InvalidToken {
+                message: "MFA token expired".to_string(),
+            });
+        }
+        if challenge.user_agent != user_agent {
+            return Err(Error::InvalidToken {
+                message: "MFA token was issued to another client".to_string(),
+            });
+        }
+
+        Ok(challenge)
+    }
+
+    // Counts an attempt at the second factor. It is counted before the code is
+    // checked, so guesses made at the same time can't get past the limit. The
+    // challenge is gone after too many of them.
+    pub async fn count_attempt(&self, store: &dyn Store) -> Result<()> {
+        match store.count_mfa_challenge_attempt(&self.token_hash, MAX_ATTEMPTS).await {
+            Ok(true) => Ok(()),
+            Ok(false) => match store.delete_mfa_challenge(&self.token_hash).await {
+                Ok(_) => Err(Error::InvalidToken {
+                    message: "Too many attempts, please sign in again".to_string(),
+                }),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    // a completed challenge can't be used again
+    pub async fn complete(&self, store: &dyn Store) -> Result<()> {
+        match store.delete_mfa_challenge(&self.token_hash).await {
+            Ok(true) => Ok(()),
+            // someone else completed it in the meantime
+            Ok(false) => Err(Error::InvalidToken {
+                message: "Invalid MFA token".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::join_all;
+
+    use super::*;
+    use crate::store::memory_store::MemoryStore;
+
+    #[tokio::test]
+    async fn attempts_made_at_the_same_time_stay_within_the_limit() {
+        let store = MemoryStore::new();
+        let token = MfaChallenge::issue(&store, "u1", "curl").await.unwrap();
+        let challenge = MfaChallenge::get(&store, &token, "curl").await.unwrap();
+
+        let attempts = join_all((0..10).map(|_| challenge.count_attempt(&store))).await;
+        assert_eq!(attempts.iter().filter(|attempt| attempt.is_ok()).count(), MAX_ATTEMPTS as usize);
+        assert!(matches!(
+            MfaChallenge::get(&store, &token, "curl").await,
+            Err(Error::InvalidToken { .. })
+        ));
+    }
+}
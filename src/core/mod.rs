@@ -1,4 +1,30 @@
 pub mod auth;
+pub mod authorization_code;
 pub mod dek;
+pub mod dek_rotation;
+pub mod federation;
+pub mod identity_provider;
+pub mod kek;
+pub mod key_cache;
+pub mod key_provider;
+pub mod keyfile;
+pub mod keyring;
+pub mod ldap;
+pub mod ldap_connection;
+pub mod magic_link;
+pub mod mfa;
+pub mod mfa_challenge;
+pub mod oauth;
+pub mod oauth_client;
+pub mod otp;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+pub mod reencryption;
+pub mod rotated_refresh_token;
+pub mod saml;
+pub mod saml_connection;
+pub mod service_account;
 pub mod session;
 pub mod user;
+pub mod user_identity;
+pub mod webauthn;
@@ -0,0 +1,328 @@
+use crate::{
+    core::{
+        authorization_code::AuthorizationCode, dek::Dek, key_cache::KeyCache,
+        oauth_client::OAuthClient, service_account::ServiceAccount, session::Session, user::User,
+    },
+    errors::{Error, Result},
+    models::oauth_model::{AuthorizeParams, IntrospectionResponse, TokenResponse},
+    traits::store::Store,
+    utils::{
+        oauth_utils::{is_valid_code_verifier, redirect_url},
+        session_utils::{AccessToken, IDToken, RefreshToken},
+    },
+};
+
+pub const SUPPORTED_SCOPES: [&str; 4] = ["openid", "profile", "email", "offline_access"];
+const DEFAULT_SCOPE: &str = "openid";
+// lifetime of the ID token used as access token
+const ACCESS_TOKEN_TTL_SECS: u64 = 3600;
+// service account tokens can't be refreshed or revoked, so they are kept short
+const SERVICE_ACCESS_TOKEN_TTL_SECS: u64 = 900;
+
+// An error of an authorization request that can be sent back to the client on
+// its redirect uri (RFC 6749 section 4.1.2.1).
+pub struct AuthorizeError {
+    pub error: &'static str,
+    pub description: String,
+}
+
+pub struct OAuth;
+
+impl OAuth {
+    // The client and redirect uri have to be checked before anything else. If
+    // they are wrong we must not redirect anywhere.
+    pub async fn get_client(store: &dyn Store, client_id: &str, redirect_uri: &str) -> Result<OAuthClient> {
+        let client = match OAuthClient::get(store, client_id).await {
+            Ok(client) => client,
+            Err(e) => return Err(e),
+        };
+        if !client.has_redirect_uri(redirect_uri) {
+            return Err(Error::InvalidPayload {
+                message: "The redirect uri is not registered for this client".to_string(),
+            });
+        }
+        Ok(client)
+    }
+
+    // checks the rest of the authorization request, returns the scope to grant
+    pub fn validate_request(params: &AuthorizeParams) -> std::result::Result<String, AuthorizeError> {
+        if params.response_type != "code" {
+            return Err(AuthorizeError {
+                error: "unsupported_response_type",
+                description: "Only the code response type is supported".to_string(),
+            });
+        }
+        // PKCE is required for every client and only with S256, plain would leak the verifier.
+        // A S256 challenge is always 43 base64url characters.
+        let is_valid_challenge = params.code_challenge.len() == 43
+            && params
+                .code_challenge
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if params.code_challenge_method != "S256" || !is_valid_challenge {
+            return Err(AuthorizeError {
+                error: "invalid_request",
+                description: "A S256 code_challenge is required".to_string(),
+            });
+        }
+
+        let scope = if params.scope.trim().is_empty() {
+            DEFAULT_SCOPE.to_string()
+        } else {
+            params.scope.split_whitespace().collect::<Vec<&str>>().join(" ")
+        };
+        if let Some(unknown) = scope.split(' ').find(|s| !SUPPORTED_SCOPES.contains(s)) {
+            return Err(AuthorizeError {
+                error: "invalid_scope",
+                description: format!("Unsupported scope: {}", unknown),
+            });
+        }
+        Ok(scope)
+    }
+
+    pub fn error_redirect(params: &AuthorizeParams, error: &AuthorizeError) -> String {
+        redirect_url(
+            &params.redirect_uri,
+            &[
+                ("error", error.error),
+                ("error_description", &error.description),
+                ("state", &params.state),
+            ],
+        )
+    }
+
+    // Called once the user signed in on the hosted login page. Returns the url
+    // to redirect the browser to, carrying the authorization code.
+    pub async fn authorize(
+        store: &dyn Store,
+        params: &AuthorizeParams,
+        scope: &str,
+        uid: &str,
+    ) -> Result<String> {
+        let nonce = if params.nonce.is_empty() {
+            None
+        } else {
+            Some(params.nonce.as_str())
+        };
+        let code = match AuthorizationCode::issue(
+            store,
+            &params.client_id,
+            uid,
+            &params.redirect_uri,
+            &params.code_challenge,
+            scope,
+            nonce,
+        )
+        .await
+        {
+            Ok(code) => code,
+            Err(e) => return Err(e),
+        };
+
+        Ok(redirect_url(
+            &params.redirect_uri,
+            &[("code", &code), ("state", &params.state)],
+        ))
+    }
+
+    // exchanges an authorization code for a new session of the user
+    pub async fn exchange_code(
+        store: &dyn Store,
+        keys: &KeyCache,
+        code: &str,
+        client_id: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+        user_agent: &str,
+    ) -> Result<TokenResponse> {
+        if code.is_empty() || client_id.is_empty() || !is_valid_code_verifier(code_verifier) {
+            return Err(Error::InvalidPayload {
+                message: "code, client_id, redirect_uri and code_verifier are required".to_string(),
+            });
+        }
+
+        let authorization_code =
+            match AuthorizationCode::redeem(store, code, client_id, redirect_uri, code_verifier).await {
+                Ok(authorization_code) => authorization_code,
+                Err(e) => return Err(e),
+            };
+
+        let user = match User::get_from_uid(store, &authorization_code.uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+        let dek_data = match Dek::get(store, &user.uid).await {
+            Ok(dek_data) => dek_data,
+            Err(e) => return Err(e),
+        };
+
+        let id_token = IDToken::new(&user).for_client(
+            client_id,
+            authorization_code.nonce.as_deref(),
+            &authorization_code.scope,
+        );
+        let session = match Session::with_id_token(keys, &user, id_token, user_agent)
+            .encrypt_add(store, &dek_data)
+            .await
+        {
+            Ok(session) => session,
+            Err(e) => return Err(e),
+        };
+
+        let refresh_token = if authorization_code.scope.split(' ').any(|s| s == "offline_access") {
+            Some(session.refresh_token)
+        } else {
+            None
+        };
+        Ok(TokenResponse {
+            access_token: session.id_token.clone(),
+            token_type: "Bearer".to_string(),
+            expires_in: ACCESS_TOKEN_TTL_SECS,
+            id_token: Some(session.id_token),
+            refresh_token,
+            scope: authorization_code.scope,
+        })
+    }
+
+    // client_credentials grant, issues an access token to a service account
+    pub async fn client_credentials(
+        store: &dyn Store,
+        keys: &KeyCache,
+        client_id: &str,
+        client_secret: &str,
+        scope: &str,
+    ) -> Result<TokenResponse> {
+        let account = match ServiceAccount::authenticate(store, client_id, client_secret).await {
+            Ok(account) => account,
+            Err(e) => return Err(e),
+        };
+
+        // without a requested scope the account gets every scope it is allowed
+        let allowed = account.scopes();
+        let scope = if scope.trim().is_empty() {
+            allowed.join(" ")
+        } else {
+            let requested: Vec<&str> = scope.split_whitespace().collect();
+            if let Some(unknown) = requested.iter().find(|s| !allowed.iter().any(|a| a == *s)) {
+                return Err(Error::InvalidScope {
+                    message: format!("Scope not allowed for this client: {}", unknown),
+                });
+            }
+            requested.join(" ")
+        };
+
+        let access_token =
+            match AccessToken::new(&account.uid, &scope, SERVICE_ACCESS_TOKEN_TTL_SECS as usize).sign(keys) {
+                Ok(token) => token,
+                Err(e) => return Err(e),
+            };
+
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: SERVICE_ACCESS_TOKEN_TTL_SECS,
+            id_token: None,
+            refresh_token: None,
+            scope,
+        })
+    }
+
+    // RFC 7662, anything that is not a valid and live token is just inactive
+    pub async fn introspect(store: &dyn Store, keys: &KeyCache, token: &str) -> Result<IntrospectionResponse> {
+        let inactive = IntrospectionResponse::default();
+
+        // the token_type claim tells ID tokens and refresh tokens apart
+        if IDToken::verify(keys, token).is_ok() {
+            let claims = match Session::verify(store, keys, token).await {
+                Ok((claims, true)) => claims,
+                Ok((_, false)) => return Ok(inactive),
+                Err(Error::InvalidToken { .. }) | Err(Error::KeyNotFound { .. }) => return Ok(inactive),
+                Err(e) => return Err(e),
+            };
+            let session = match Session::get_from_token(store, &claims.uid, token).await {
+                Ok(Some(session)) => session,
+                Ok(None) => return Ok(inactive),
+                Err(e) => return Err(e),
+            };
+            return Ok(IntrospectionResponse {
+                active: true,
+                sub: Some(claims.uid),
+                client_id: claims.aud,
+                scope: claims.scope,
+                token_type: Some("id_token".to_string()),
+                exp: Some(claims.exp),
+                iat: Some(claims.iat),
+                sid: Some(session.session_id),
+            });
+        }
+
+        if let Ok(claims) = RefreshToken::verify(keys, token) {
+            let session = match Session::get_from_token(store, &claims.uid, token).await {
+                Ok(Some(session)) if !session.is_revoked => session,
+                Ok(_) | Err(Error::KeyNotFound { .. }) => return Ok(inactive),
+                Err(e) => return Err(e),
+            };
+            return Ok(IntrospectionResponse {
+                active: true,
+                sub: Some(claims.uid),
+                token_type: Some("refresh_token".to_string()),
+                exp: Some(claims.exp),
+                iat: Some(claims.iat),
+                sid: Some(session.session_id),
+                ..Default::default()
+            });
+        }
+
+        if let Ok(claims) = AccessToken::verify(keys, token) {
+            // the token dies with its service account
+            match ServiceAccount::get(store, &claims.client_id).await {
+                Ok(_) => {}
+                Err(Error::ServiceAccountNotFound { .. }) => return Ok(inactive),
+                Err(e) => return Err(e),
+            }
+            return Ok(IntrospectionResponse {
+                active: true,
+                sub: Some(claims.sub),
+                client_id: Some(claims.client_id),
+                scope: Some(claims.scope),
+                token_type: Some("access_token".to_string()),
+                exp: Some(claims.exp),
+                iat: Some(claims.iat),
+                sid: None,
+            });
+        }
+
+        Ok(inactive)
+    }
+
+    // RFC 7009, revokes the session an ID token or refresh token belongs to.
+    // Unknown and invalid tokens are ignored, there is nothing to revoke.
+    pub async fn revoke(store: &dyn Store, keys: &KeyCache, token: &str) -> Result<()> {
+        // an expired ID token still identifies its session, which can still be refreshed
+        let uid = match IDToken::verify(keys, token) {
+            Ok((claims, _)) => Some(claims.uid),
+            Err(_) => match RefreshToken::verify(keys, token) {
+                Ok(claims) => Some(claims.uid),
+                Err(_) => None,
+            },
+        };
+
+        let uid = match uid {
+            Some(uid) => uid,
+            None => {
+                if AccessToken::verify(keys, token).is_ok() {
+                    return Err(Error::UnsupportedTokenType {
+                        message: "Service account tokens can't be revoked, they expire on their own".to_string(),
+                    });
+                }
+                return Ok(());
+            }
+        };
+
+        match Session::get_from_token(store, &uid, token).await {
+            Ok(Some(session)) => Session::revoke(store, &session.session_id, &session.uid).await,
+            Ok(None) | Err(Error::KeyNotFound { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
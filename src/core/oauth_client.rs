@@ -0,0 +1,85 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    traits::store::Store,
+    utils::oauth_utils::generate_token,
+};
+
+// An application that signs its users in through the hosted login page. These
+// are public clients (SPAs, mobile apps) so they have no secret and every
+// authorization has to be bound to a PKCE challenge instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthClient {
+    pub _id: ObjectId,
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl OAuthClient {
+    pub fn new(name: &str, redirect_uris: &[String]) -> Self {
+        Self {
+            _id: ObjectId::new(),
+            client_id: generate_token(16),
+            name: name.to_string(),
+            redirect_uris: redirect_uris.to_vec(),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    pub async fn create(store: &dyn Store, name: &str, redirect_uris: &[String]) -> Result<Self> {
+        // redirect uris are matched exactly, so they have to be absolute and without a fragment
+        for uri in redirect_uris {
+            let is_http = uri.starts_with("https://") || uri.starts_with("http://");
+            if !is_http || uri.contains('#') {
+                return Err(Error::InvalidPayload {
+                    message: format!("Invalid redirect uri: {}", uri),
+                });
+            }
+        }
+
+        let client = Self::new(name, redirect_uris);
+        match store.insert_client(&client).await {
+            Ok(_) => Ok(client),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get(store: &dyn Store, client_id: &str) -> Result<Self> {
+        match store.get_client(client_id).await {
+            Ok(Some(client)) => Ok(client),
+            Ok(None) => Err(Error::OAuthClientNotFound {
+                message: "OAuth client not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_all(store: &dyn Store) -> Result<Vec<Self>> {
+        let mut clients = match store.get_clients().await {
+            Ok(clients) => clients,
+            Err(e) => return Err(e),
+        };
+        clients.sort_by_key(|c| c.created_at);
+        Ok(clients)
+    }
+
+    pub async fn delete(store: &dyn Store, client_id: &str) -> Result<()> {
+        match store.delete_client(client_id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::OAuthClientNotFound {
+                message: "OAuth client not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn has_redirect_uri(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|uri| uri == redirect_uri)
+    }
+}
@@ -0,0 +1,216 @@
+use bson::{oid::ObjectId, DateTime};
+use openssl::rand::rand_bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    traits::store::Store,
+    utils::password_utils::Password,
+};
+
+// the code has to be typed in within 10 minutes
+pub const OTP_TTL_SECS: i64 = 600;
+// wrong codes allowed before the code is thrown away
+const MAX_ATTEMPTS: i32 = 5;
+
+pub const CHANNEL_EMAIL: &str = "email";
+pub const CHANNEL_SMS: &str = "sms";
+// proves a new phone number belongs to the user
+pub const CHANNEL_PHONE_VERIFICATION: &str = "phone_verification";
+
+// A one-time passcode sent to the user. A user has at most one pending code
+// per channel, asking for a new one replaces the old one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtpCode {
+    pub _id: ObjectId,
+    pub uid: String,
+    pub channel: String,
+    // salted and hashed like a password
+    pub code_hash: String,
+    // the code has to be entered on the same client that asked for it
+    pub user_agent: String,
+    pub attempts: i32,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+impl OtpCode {
+    // stores a new code for the user and returns the plain code to send
+    pub async fn issue(store: &dyn Store, uid: &str, channel: &str, user_agent: &str) -> Result<String> {
+        let mut bytes = [0; 4];
+        rand_bytes(&mut bytes).unwrap();
+        let code = format!("{:06}", u32::from_be_bytes(bytes) % 1_000_000);
+
+        let otp = Self {
+            _id: ObjectId::new(),
+            uid: uid.to_string(),
+            channel: channel.to_string(),
+            code_hash: Password::salt_and_hash(&code),
+            user_agent: user_agent.to_string(),
+            attempts: 0,
+            expires_at: DateTime::from_millis(DateTime::now().timestamp_millis() + OTP_TTL_SECS * 1000),
+            created_at: DateTime::now(),
+        };
+
+        match store.delete_otp_code(uid, channel).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        match store.insert_otp_code(&otp).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        // nothing else cleans up after the unused ones
+        match store.delete_expired_otp_codes().await {
+            Ok(_) => Ok(code),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Checks the code and uses it up. Every try counts as an attempt, the code
+    // is gone after too many of them.
+    pub async fn verify(store: &dyn Store, uid: &str, channel: &str, code: &str, user_agent: &str) -> Result<()> {
+        let otp = match store.get_otp_code(uid, channel).await {
+            Ok(Some(otp)) => otp,
+            Ok(None) => {
+                return Err(Error::InvalidOtp {
+                    message: "No code was requested. Please request a new code.".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        if otp.expires_at.timestamp_millis() < DateTime::now().timestamp_millis() {
+            return Err(Error::InvalidOtp {
+                message: "The code has expired. Please request a new code.".to_string(),
+            });
+        }
+        if otp.user_agent != user_agent {
+            return Err(Error::InvalidOtp {
+                message: "The code was requested from another client".to_string(),
+            });
+        }
+
+        // counted before the code is checked, so guesses made at the same time
+        // can't get past the limit
+        match store.count_otp_attempt(&otp, MAX_ATTEMPTS).await {
+            Ok(true) => {}
+            // it stays used up until it expires or a new one is requested
+            Ok(false) => {
+                return Err(Error::InvalidOtp {
+                    message: "Too many attempts. Please request a new code.".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+        if !Password::verify_hash(code.trim(), &otp.code_hash) {
+            return Err(Error::InvalidOtp {
+                message: "Invalid code".to_string(),
+            });
+        }
+
+        match store.delete_otp_code(uid, channel).await {
+            Ok(true) => Ok(()),
+            // someone else used it in the meantime
+            Ok(false) => Err(Error::InvalidOtp {
+                message: "Invalid code".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::memory_store::MemoryStore, traits::store::SessionStore};
+    use futures::future::join_all;
+
+    #[tokio::test]
+    async fn accepts_a_code_once() {
+        let store = MemoryStore::new();
+        for channel in [CHANNEL_EMAIL, CHANNEL_SMS] {
+            let code = OtpCode::issue(&store, "u1", channel, "curl").await.unwrap();
+            assert!(OtpCode::verify(&store, "u1", channel, &code, "curl").await.is_ok());
+            assert!(matches!(
+                OtpCode::verify(&store, "u1", channel, &code, "curl").await,
+                Err(Error::InvalidOtp { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_channels_apart() {
+        let store = MemoryStore::new();
+        let email_code = OtpCode::issue(&store, "u1", CHANNEL_EMAIL, "curl").await.unwrap();
+        let sms_code = OtpCode::issue(&store, "u1", CHANNEL_SMS, "curl").await.unwrap();
+
+        // a new code replaces the pending one of its channel only
+        OtpCode::issue(&store, "u1", CHANNEL_SMS, "curl").await.unwrap();
+        assert!(OtpCode::verify(&store, "u1", CHANNEL_EMAIL, &email_code, "curl").await.is_ok());
+        assert!(matches!(
+            OtpCode::verify(&store, "u1", CHANNEL_EMAIL, &sms_code, "curl").await,
+            Err(Error::InvalidOtp { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_an_expired_code() {
+        let store = MemoryStore::new();
+        for channel in [CHANNEL_EMAIL, CHANNEL_SMS] {
+            let otp = OtpCode {
+                _id: ObjectId::new(),
+                uid: "u1".to_string(),
+                channel: channel.to_string(),
+                code_hash: Password::salt_and_hash("123456"),
+                user_agent: "curl".to_string(),
+                attempts: 0,
+                expires_at: DateTime::from_millis(DateTime::now().timestamp_millis() - 1000),
+                created_at: DateTime::now(),
+            };
+            store.insert_otp_code(&otp).await.unwrap();
+
+            assert!(matches!(
+                OtpCode::verify(&store, "u1", channel, "123456", "curl").await,
+                Err(Error::InvalidOtp { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_a_code_from_another_client() {
+        let store = MemoryStore::new();
+        for channel in [CHANNEL_EMAIL, CHANNEL_SMS] {
+            let code = OtpCode::issue(&store, "u1", channel, "curl").await.unwrap();
+            assert!(matches!(
+                OtpCode::verify(&store, "u1", channel, &code, "Mozilla/5.0").await,
+                Err(Error::InvalidOtp { .. })
+            ));
+            assert!(OtpCode::verify(&store, "u1", channel, &code, "curl").await.is_ok());
+        }
+    }
+
+    // guesses made at the same time are counted one by one, and once the
+    // attempts are used up not even the right code gets in
+    #[tokio::test]
+    async fn caps_the_attempts_made_at_the_same_time() {
+        let store = MemoryStore::new();
+        let code = OtpCode::issue(&store, "u1", CHANNEL_SMS, "curl").await.unwrap();
+        let wrong = format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000);
+
+        let results = join_all(
+            (0..2 * MAX_ATTEMPTS).map(|_| OtpCode::verify(&store, "u1", CHANNEL_SMS, &wrong, "curl")),
+        )
+        .await;
+        let checked = results
+            .iter()
+            .filter(|result| matches!(result, Err(Error::InvalidOtp { message }) if message == "Invalid code"))
+            .count();
+        assert_eq!(checked, MAX_ATTEMPTS as usize);
+
+        assert!(matches!(
+            OtpCode::verify(&store, "u1", CHANNEL_SMS, &code, "curl").await,
+            Err(Error::InvalidOtp { .. })
+        ));
+    }
+}
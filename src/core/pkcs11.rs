@@ -0,0 +1,281 @@
+use std::{env, sync::Mutex};
+
+use aes_gcm::{aead::OsRng, AeadCore, Aes256Gcm};
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    mechanism::{aead::GcmParams, Mechanism},
+    object::{Attribute, KeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+
+use crate::{
+    errors::{Error, Result},
+    traits::key_provider::KeyProvider,
+    utils::encryption_utils::Encryption,
+};
+
+const TAG_BITS: u64 = 128;
+
+// KEY_PROVIDER=pkcs11 wraps with an AES-256 key that never leaves the HSM.
+// PKCS11_MODULE is the library of the HSM, PKCS11_TOKEN_LABEL the token (default:
+// the first one) and PKCS11_PIN the PIN of its user. PKCS11_KEY_LABEL is the
+// label of the current key, the ones it replaced go in PKCS11_PREVIOUS_KEY_LABELS.
+pub struct Pkcs11KeyProvider {
+    // one session for as long as the server runs, the calls on it take turns
+    session: Mutex<Session>,
+    // the id and handle of every key, the current one first
+    keys: Vec<(String, ObjectHandle)>,
+}
+
+fn pkcs11_error(message: String) -> Error {
+    Error::KeyProviderFailed { message }
+}
+
+fn required_var(name: &str) -> Result<String> {
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => Ok(value),
+        _ => Err(pkcs11_error(format!("{} must be set", name))),
+    }
+}
+
+// the labels are set by whoever creates the keys, so they make the ids
+fn key_id(label: &str) -> String {
+    format!("pkcs11:{}", label)
+}
+
+pub struct Pkcs11Config {
+    pub module: String,
+    // the first token when not set
+    pub token_label: Option<String>,
+    pub pin: String,
+    // the label of the current key first
+    pub key_labels: Vec<String>,
+}
+
+impl Pkcs11Config {
+    pub fn from_env() -> Result<Self> {
+        let mut key_labels = vec![required_var("PKCS11_KEY_LABEL")?];
+        key_labels.extend(
+            env::var("PKCS11_PREVIOUS_KEY_LABELS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|label| label.trim().to_string())
+                .filter(|label| !label.is_empty()),
+        );
+        Ok(Self {
+            module: required_var("PKCS11_MODULE")?,
+            token_label: env::var("PKCS11_TOKEN_LABEL").ok(),
+            pin: required_var("PKCS11_PIN")?,
+            key_labels,
+        })
+    }
+}
+
+// a logged in read-write session on the configured token
+fn open_session(config: &Pkcs11Config) -> Result<Session> {
+    let pkcs11 = Pkcs11::new(&config.module).map_err(|err| pkcs11_error(err.to_string()))?;
+    pkcs11
+        .initialize(CInitializeArgs::OsThreads)
+        .map_err(|err| pkcs11_error(err.to_string()))?;
+
+    let slots = pkcs11
+        .get_slots_with_token()
+        .map_err(|err| pkcs11_error(err.to_string()))?;
+    let slot = slots.into_iter().find(|slot| match &config.token_label {
+        Some(label) => pkcs11
+            .get_token_info(*slot)
+            .is_ok_and(|info| info.label() == label),
+        None => true,
+    });
+    let slot = match slot {
+        Some(slot) => slot,
+        None => return Err(pkcs11_error("No PKCS#11 token found".to_string())),
+    };
+
+    let session = pkcs11
+        .open_rw_session(slot)
+        .map_err(|err| pkcs11_error(err.to_string()))?;
+    let pin = AuthPin::new(config.pin.clone());
+    session
+        .login(UserType::User, Some(&pin))
+        .map_err(|err| pkcs11_error(err.to_string()))?;
+    Ok(session)
+}
+
+fn find_key(session: &Session, label: &str) -> Result<Option<ObjectHandle>> {
+    let template = [
+        Attribute::Class(ObjectClass::SECRET_KEY),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ];
+    match session.find_objects(&template) {
+        Ok(handles) => Ok(handles.first().copied()),
+        Err(err) => Err(pkcs11_error(err.to_string())),
+    }
+}
+
+impl Pkcs11KeyProvider {
+    pub fn open() -> Result<Self> {
+        Self::with_config(&Pkcs11Config::from_env()?)
+    }
+
+    pub fn with_config(config: &Pkcs11Config) -> Result<Self> {
+        let session = open_session(config)?;
+        let mut keys = vec![];
+        for (i, label) in config.key_labels.iter().enumerate() {
+            match find_key(&session, label)? {
+                Some(handle) => keys.push((key_id(label), handle)),
+                // nothing can be wrapped without the current key
+                None if i == 0 => {
+                    return Err(pkcs11_error(format!("No PKCS#11 key labeled {}", label)))
+                }
+                None => eprintln!(">> No PKCS#11 key labeled {}, skipping it", label),
+            }
+        }
+        Ok(Self {
+            session: Mutex::new(session),
+            keys,
+        })
+    }
+
+    pub fn create_key() -> Result<String> {
+        Self::create_key_with(&Pkcs11Config::from_env()?)
+    }
+
+    // Generates the current key of the config in the token. It is created sensitive
+    // and not extractable, so it can only be used through the HSM.
+    pub fn create_key_with(config: &Pkcs11Config) -> Result<String> {
+        let session = open_session(config)?;
+        let label = match config.key_labels.first() {
+            Some(label) => label,
+            None => return Err(pkcs11_error("No PKCS#11 key label set".to_string())),
+        };
+        if find_key(&session, label)?.is_some() {
+            return Err(pkcs11_error(format!("A PKCS#11 key labeled {} already exists", label)));
+        }
+        let template = [
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::KeyType(KeyType::AES),
+            Attribute::ValueLen(32.into()),
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Encrypt(true),
+            Attribute::Decrypt(true),
+            Attribute::Label(label.as_bytes().to_vec()),
+        ];
+        match session.generate_key(&Mechanism::AesKeyGen, &template) {
+            Ok(_) => Ok(key_id(label)),
+            Err(err) => Err(pkcs11_error(err.to_string())),
+        }
+    }
+}
+
+impl KeyProvider for Pkcs11KeyProvider {
+    fn key_id(&self) -> String {
+        self.keys[0].0.clone()
+    }
+
+    fn wrap(&self, data: &str) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mechanism = Mechanism::AesGcm(GcmParams::new(&nonce, &[], TAG_BITS.into()));
+        let session = match self.session.lock() {
+            Ok(session) => session,
+            Err(err) => return Err(pkcs11_error(err.to_string())),
+        };
+        match session.encrypt(&mechanism, self.keys[0].1, data.as_bytes()) {
+            Ok(cipher_text) => Ok(Encryption::seal(&nonce, &cipher_text)),
+            Err(err) => Err(pkcs11_error(err.to_string())),
+        }
+    }
+
+    fn unwrap(&self, wrapped: &str, key_id: Option<&str>) -> Option<String> {
+        let (nonce, cipher_text) = Encryption::open(wrapped)?;
+        let mechanism = Mechanism::AesGcm(GcmParams::new(&nonce, &[], TAG_BITS.into()));
+        let session = self.session.lock().ok()?;
+        self.keys
+            .iter()
+            .filter(|(id, _)| key_id.is_none_or(|key_id| id == key_id))
+            .find_map(|(_, handle)| session.decrypt(&mechanism, *handle, &cipher_text).ok())
+            .and_then(|data| String::from_utf8(data).ok())
+    }
+}
+
+// Runs against SoftHSM when it is installed: SOFTHSM2_MODULE is its library
+// (default: the Debian path) and softhsm2-util must be on the PATH. A token is
+// made in a directory of its own, so the tokens of the machine are left alone.
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, process::Command};
+
+    use super::*;
+
+    const PIN: &str = "1234";
+
+    fn softhsm_module() -> Option<String> {
+        let module = env::var("SOFTHSM2_MODULE")
+            .unwrap_or("/usr/lib/softhsm/libsofthsm2.so".to_string());
+        Path::new(&module).exists().then_some(module)
+    }
+
+    fn init_token(module: &str) -> Option<Pkcs11Config> {
+        let dir = env::temp_dir().join(format!("softhsm-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("tokens")).ok()?;
+        let conf = dir.join("softhsm2.conf");
+        fs::write(&conf, format!("directories.tokendir = {}\n", dir.join("tokens").display())).ok()?;
+        env::set_var("SOFTHSM2_CONF", &conf);
+        let status = Command::new("softhsm2-util")
+            .args(["--init-token", "--free", "--label", "test", "--so-pin", "5678", "--pin", PIN])
+            .status()
+            .ok()?;
+        status.success().then(|| Pkcs11Config {
+            module: module.to_string(),
+            token_label: Some("test".to_string()),
+            pin: PIN.to_string(),
+            key_labels: vec!["current".to_string()],
+        })
+    }
+
+    // one test, as the token and the library are shared by the whole process
+    #[test]
+    fn wraps_and_unwraps_with_softhsm() {
+        let module = match softhsm_module() {
+            Some(module) => module,
+            None => return eprintln!(">> SoftHSM not installed, skipping"),
+        };
+        let mut config = match init_token(&module) {
+            Some(config) => config,
+            None => return eprintln!(">> Could not make a SoftHSM token, skipping"),
+        };
+
+        config.key_labels = vec!["previous".to_string()];
+        assert_eq!(Pkcs11KeyProvider::create_key_with(&config).unwrap(), "pkcs11:previous");
+        assert!(Pkcs11KeyProvider::create_key_with(&config).is_err());
+        let wrapped_before = {
+            let provider = Pkcs11KeyProvider::with_config(&config).unwrap();
+            provider.wrap("old dek").unwrap()
+        };
+
+        config.key_labels = vec!["current".to_string()];
+        Pkcs11KeyProvider::create_key_with(&config).unwrap();
+        config.key_labels = vec!["current".to_string(), "previous".to_string()];
+        let provider = Pkcs11KeyProvider::with_config(&config).unwrap();
+        assert_eq!(provider.key_id(), "pkcs11:current");
+
+        let wrapped = provider.wrap("dek").unwrap();
+        assert_ne!(wrapped, provider.wrap("dek").unwrap());
+        assert_eq!(provider.unwrap(&wrapped, Some("pkcs11:current")).as_deref(), Some("dek"));
+        assert_eq!(provider.unwrap(&wrapped, None).as_deref(), Some("dek"));
+        assert_eq!(provider.unwrap(&wrapped, Some("pkcs11:previous")), None);
+        assert_eq!(
+            provider.unwrap(&wrapped_before, Some("pkcs11:previous")).as_deref(),
+            Some("old dek")
+        );
+
+        let mut tampered = Encryption::open(&wrapped).unwrap();
+        *tampered.1.last_mut().unwrap() ^= 1;
+        let tampered = Encryption::seal(&tampered.0, &tampered.1);
+        assert_eq!(provider.unwrap(&tampered, None), None);
+    }
+}
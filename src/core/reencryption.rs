@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    core::{dek::Dek, key_provider::key_provider},
+    errors::Result,
+    traits::store::Store,
+    utils::encryption_utils::Encryption,
+};
+
+// how many records were moved to the current format, and how many could not be
+#[derive(Debug, Default)]
+pub struct ReencryptionReport {
+    pub deks: u64,
+    pub users: u64,
+    pub sessions: u64,
+    pub failed: u64,
+}
+
+// the top level fields of a record that are still in the legacy format
+fn legacy_fields<T: Serialize>(record: &T, skip: &[&str]) -> Vec<(String, String)> {
+    let value = match serde_json::to_value(record) {
+        Ok(value) => value,
+        Err(_) => return vec![],
+    };
+    match value {
+        Value::Object(map) => map
+            .into_iter()
+            .filter(|(field, _)| field != "uid" && !skip.contains(&field.as_str()))
+            .filter_map(|(field, value)| match value {
+                Value::String(s) if Encryption::is_legacy(&s) => Some((field, s)),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// the value encrypted again in the current format, None when it does not decrypt with the key
+fn reencrypt(cipher_text: &str, key: &str) -> Option<String> {
+    Encryption::try_decrypt_data(cipher_text, key).map(|data| Encryption::encrypt_data(&data, key))
+}
+
+// the record a field is swapped in, by its uid or session_id
+enum Record<'a> {
+    User(&'a str),
+    Session(&'a str),
+}
+
+pub struct Reencryption;
+
+impl Reencryption {
+    // Moves the users, sessions and deks still encrypted in the legacy format
+    // to the current one. Every field is swapped only if it did not change in
+    // the meantime, so a write made while this runs is never overwritten.
+    pub async fn run(store: &dyn Store) -> Result<ReencryptionReport> {
+        let mut report = ReencryptionReport::default();
+
+        let deks = match store.get_deks().await {
+            Ok(deks) => deks,
+            Err(e) => return Err(e),
+        };
+        for dek in deks {
+            // the email index and kek id are not encrypted
+            let fields = legacy_fields(&dek, &["email_index", "kek_id"]);
+            if fields.is_empty() {
+                continue;
+            }
+            // wrapped again by the key provider, which always uses the current format
+            match Dek::rewrap(store, &dek, key_provider()).await {
+                Ok(true) => report.deks += 1,
+                // changed since it was read, so it was wrapped in the current format
+                Ok(false) => {}
+                Err(_) => report.failed += 1,
+            }
+        }
+
+        let mut keys: HashMap<String, Option<String>> = HashMap::new();
+
+        let users = match store.get_users().await {
+            Ok(users) => users,
+            Err(e) => return Err(e),
+        };
+        for user in users {
+            let fields = legacy_fields(&user, &[]);
+            if fields.is_empty() {
+                continue;
+            }
+            let dek = match Self::dek(store, &mut keys, &user.uid).await {
+                Some(dek) => dek,
+                None => {
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            if Self::swap(store, Record::User(&user.uid), &fields, &dek, &mut report).await? {
+                report.users += 1;
+            }
+        }
+
+        let sessions = match store.get_sessions().await {
+            Ok(sessions) => sessions,
+            Err(e) => return Err(e),
+        };
+        for session in sessions {
+            let mut fields = legacy_fields(&session, &[]);
+            if fields.is_empty() {
+                continue;
+            }
+            let dek = match Self::dek(store, &mut keys, &session.uid).await {
+                Some(dek) => dek,
+                None => {
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            // the session is matched by its session_id, so that one goes last
+            fields.sort_by_key(|(field, _)| field == "session_id");
+            if Self::swap(store, Record::Session(&session.session_id), &fields, &dek, &mut report).await? {
+                report.sessions += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Gives the deks from before the email index existed one, they can't be
+    // found by email without it. Returns how many got one.
+    pub async fn index_emails(store: &dyn Store) -> Result<u64> {
+        let deks = match store.get_deks().await {
+            Ok(deks) => deks,
+            Err(e) => return Err(e),
+        };
+        let mut indexed = 0;
+        for dek in deks.iter().filter(|dek| dek.email_index.is_none()) {
+            let email = match key_provider().unwrap(&dek.email, dek.kek_id.as_deref()) {
+                Some(email) => email,
+                None => {
+                    eprintln!(">> Error decrypting the email of the DEK of {}", dek.uid);
+                    continue;
+                }
+            };
+            let email_index = match Dek::email_index(&email) {
+                Ok(email_index) => email_index,
+                Err(e) => return Err(e),
+            };
+            match store.set_dek_email_index(&dek.uid, &email_index).await {
+                Ok(true) => indexed += 1,
+                Ok(false) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(indexed)
+    }
+
+    // the decrypted DEK of the user, looked up once per user
+    async fn dek(store: &dyn Store, keys: &mut HashMap<String, Option<String>>, uid: &str) -> Option<String> {
+        if let Some(dek) = keys.get(uid) {
+            return dek.clone();
+        }
+        let dek = match Dek::get(store, uid).await {
+            Ok(dek) => Some(dek.dek),
+            Err(e) => {
+                eprintln!(">> Error getting the DEK of {}: {:?}", uid, e);
+                None
+            }
+        };
+        keys.insert(uid.to_string(), dek.clone());
+        dek
+    }
+
+    // re-encrypts the fields one by one, returns true if any of them was swapped
+    async fn swap(
+        store: &dyn Store,
+        record: Record<'_>,
+        fields: &[(String, String)],
+        key: &str,
+        report: &mut ReencryptionReport,
+    ) -> Result<bool> {
+        let mut swapped = false;
+        for (field, current) in fields {
+            let value = match reencrypt(current, key) {
+                Some(value) => value,
+                None => {
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            let replaced = match record {
+                Record::User(uid) => store.replace_user_field(uid, field, current, &value).await,
+                Record::Session(session_id) => {
+                    store.replace_session_field(session_id, field, current, &value).await
+                }
+            };
+            match replaced {
+                Ok(true) => swapped = true,
+                // changed since it was read, so it was written in the current format
+                Ok(false) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(swapped)
+    }
+}
@@ -0,0 +1,65 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::dek::Dek,
+    errors::Result,
+    traits::store::Store,
+    utils::{encryption_utils::Encryption, oauth_utils::hash_token},
+};
+
+// A refresh token that was already exchanged for a new one. Every session is a
+// token family, if one of its rotated tokens shows up again someone is
+// replaying a stolen copy and the whole family gets revoked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotatedRefreshToken {
+    pub _id: ObjectId,
+    pub token_hash: String,
+    pub uid: String,
+    // encrypted with the DEK of the user, the same way the session stores it
+    pub session_id: String,
+    // after this the token is rejected for being expired, so the record can go
+    pub expires_at: DateTime,
+    pub rotated_at: DateTime,
+}
+
+impl RotatedRefreshToken {
+    pub async fn record(
+        store: &dyn Store,
+        dek: &Dek,
+        uid: &str,
+        session_id: &str,
+        refresh_token: &str,
+        expires_at: usize,
+    ) -> Result<()> {
+        let rotated = Self {
+            _id: ObjectId::new(),
+            token_hash: hash_token(refresh_token),
+            uid: uid.to_string(),
+            session_id: Encryption::encrypt_data(session_id, &dek.dek),
+            expires_at: DateTime::from_millis(expires_at as i64 * 1000),
+            rotated_at: DateTime::now(),
+        };
+        match store.insert_rotated_refresh_token(&rotated, dek.version).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        // nothing else cleans up after the expired ones
+        match store.delete_expired_rotated_refresh_tokens().await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // returns the record with the session id decrypted if the token was already rotated
+    pub async fn find(store: &dyn Store, dek: &str, refresh_token: &str) -> Result<Option<Self>> {
+        match store.get_rotated_refresh_token(&hash_token(refresh_token)).await {
+            Ok(Some(mut rotated)) => {
+                rotated.session_id = Encryption::decrypt_data(&rotated.session_id, dek);
+                Ok(Some(rotated))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    core::{
+        federation::{Federation, FederationState},
+        saml_connection::SamlConnection,
+        user::User,
+        user_identity::UserIdentity,
+    },
+    errors::{Error, Result},
+    traits::store::Store,
+    utils::{
+        oauth_utils::generate_token,
+        saml_utils::{authn_request_url, verify_response, ExpectedResponse, SamlAssertion, NAME_ID_TRANSIENT},
+        validation_utils::Validation,
+    },
+};
+
+// SAML sign ins go through the same pending sign ins as the OpenID Connect
+// ones: the id of the AuthnRequest is the nonce, and the app redeems the
+// one-time token at the end the same way.
+pub struct Saml;
+
+impl Saml {
+    // Starts a sign in at the identity provider of the connection and returns
+    // the url to send the browser to. The identity provider posts the response
+    // to the acs url, which sends the browser on to redirect_url.
+    pub async fn start(store: &dyn Store, connection_id: &str, redirect_url: &str, user_agent: &str) -> Result<String> {
+        match Federation::check_redirect_url(redirect_url) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let connection = match SamlConnection::get(store, connection_id).await {
+            Ok(connection) => connection,
+            Err(e) => return Err(e),
+        };
+
+        // an xml ID can't start with a digit or -
+        let request_id = format!("_{}", generate_token(20));
+        let relay_state = match Federation::insert_pending(
+            store,
+            &connection.connection_id,
+            &request_id,
+            "",
+            redirect_url,
+            user_agent,
+            None,
+        )
+        .await
+        {
+            Ok(state) => state,
+            Err(e) => return Err(e),
+        };
+
+        Ok(authn_request_url(
+            &connection.sso_url,
+            &request_id,
+            &connection.sp_entity_id(),
+            &connection.acs_url(),
+            &relay_state,
+        ))
+    }
+
+    // Checks the response the identity provider posted and signs in the user
+    // it is about. Returns the one-time token for the app, or None when the
+    // identity provider did not sign the user in.
+    pub async fn complete(
+        store: &dyn Store,
+        connection_id: &str,
+        pending: &FederationState,
+        saml_response: &str,
+    ) -> Result<Option<String>> {
+        if pending.provider_id != connection_id {
+            return Err(Error::InvalidToken {
+                message: "The response was posted for another connection".to_string(),
+            });
+        }
+        let connection = match SamlConnection::get(store, connection_id).await {
+            Ok(connection) => connection,
+            Err(e) => return Err(e),
+        };
+
+        let sp_entity_id = connection.sp_entity_id();
+        let acs_url = connection.acs_url();
+        let expected = ExpectedResponse {
+            idp_entity_id: &connection.idp_entity_id,
+            certificates: &connection.certificates,
+            sp_entity_id: &sp_entity_id,
+            acs_url: &acs_url,
+            request_id: &pending.nonce,
+        };
+        let assertion = match verify_response(saml_response, &expected) {
+            Ok(Some(assertion)) => assertion,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let uid = match Self::find_or_create_user(store, &connection, &assertion).await {
+            Ok(uid) => uid,
+            Err(e) => return Err(e),
+        };
+
+        match Federation::issue_signin_token(store, pending, &uid).await {
+            Ok(token) => Ok(Some(token)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // The user the NameID is linked to. The connection is set up by an admin
+    // for the email domains of a company, so its emails are trusted: unknown
+    // NameIDs are linked to the user with the email, or a new user is
+    // provisioned from the attributes.
+    async fn find_or_create_user(store: &dyn Store, connection: &SamlConnection, assertion: &SamlAssertion) -> Result<String> {
+        let email = if connection.email_attribute.is_empty() {
+            Some(assertion.name_id.as_str())
+        } else {
+            assertion.attribute(&connection.email_attribute)
+        };
+        let email = match email {
+            Some(email) if Validation::email(email) => email.to_string(),
+            _ => {
+                return Err(Error::InvalidToken {
+                    message: "The identity provider did not share a valid email".to_string(),
+                })
+            }
+        };
+        if !connection.allows_email(&email) {
+            return Err(Error::InvalidToken {
+                message: "The email is not at a domain of the connection".to_string(),
+            });
+        }
+
+        // transient NameIDs change with every sign in, the email is what stays the same
+        let subject = if assertion.name_id_format == NAME_ID_TRANSIENT {
+            email.clone()
+        } else {
+            assertion.name_id.clone()
+        };
+        match UserIdentity::find_uid(store, &connection.connection_id, &subject).await {
+            Ok(Some(uid)) => return Ok(uid),
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+
+        let uid = match User::get_from_email(store, &email).await {
+            Ok(user) => user.uid,
+            // there is no DEK for an email without a user
+            Err(Error::KeyNotFound { .. }) => {
+                let name = if connection.name_attribute.is_empty() {
+                    None
+                } else {
+                    assertion.attribute(&connection.name_attribute)
+                };
+                match Federation::create_user(store, &email, name, &connection.role(assertion), true).await {
+                    Ok(uid) => uid,
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+
+        match UserIdentity::link(store, &uid, &connection.connection_id, &subject, Some(&email)).await {
+            Ok(_) => Ok(uid),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    models::saml_model::{CreateSamlConnectionPayload, SamlConnectionResponse},
+    traits::store::Store,
+    utils::saml_utils::{fetch_metadata, parse_metadata, SamlAssertion},
+};
+
+// a value of the role attribute and the role users with it get
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SamlRoleMapping {
+    pub value: String,
+    pub role: String,
+}
+
+// An enterprise identity provider (Okta, Entra ID, ADFS...) users of some
+// email domains sign in with over SAML 2.0. flexauth is the service provider
+// of the connection, the identity provider is set up from its metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SamlConnection {
+    pub _id: ObjectId,
+    // short name used in the api and the urls, e.g. "acme"
+    pub connection_id: String,
+    pub name: String,
+    pub idp_entity_id: String,
+    pub sso_url: String,
+    // base64 DER, responses signed with any of them are accepted
+    pub certificates: Vec<String>,
+    pub domains: Vec<String>,
+    // names of the attributes the user is read from, the email is the NameID when empty
+    pub email_attribute: String,
+    pub name_attribute: String,
+    pub role_attribute: String,
+    pub role_mapping: Vec<SamlRoleMapping>,
+    pub default_role: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+fn server_url() -> String {
+    let server_url = dotenv::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+    server_url.trim_end_matches('/').to_string()
+}
+
+impl SamlConnection {
+    pub async fn create(store: &dyn Store, payload: &CreateSamlConnectionPayload) -> Result<SamlConnectionResponse> {
+        let is_slug = payload
+            .connection_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if payload.connection_id.is_empty() || !is_slug {
+            return Err(Error::InvalidPayload {
+                message: "The connection id can only contain lowercase letters, digits and -".to_string(),
+            });
+        }
+
+        let domains: Vec<String> = payload
+            .domains
+            .iter()
+            .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+            .collect();
+        if domains.is_empty() || domains.iter().any(|domain| !domain.contains('.') || domain.contains('@')) {
+            return Err(Error::InvalidPayload {
+                message: "The connection needs the email domains of its users".to_string(),
+            });
+        }
+        if payload
+            .role_mapping
+            .iter()
+            .any(|mapping| mapping.value.is_empty() || mapping.role.trim().is_empty())
+        {
+            return Err(Error::InvalidPayload {
+                message: "A role mapping needs a value and a role".to_string(),
+            });
+        }
+
+        // the connection id is the provider id of the identities linked through it
+        match store.get_saml_connection(&payload.connection_id).await {
+            Ok(Some(_)) => {
+                return Err(Error::InvalidPayload {
+                    message: "A SAML connection with this id already exists".to_string(),
+                })
+            }
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+        match store.get_identity_provider(&payload.connection_id).await {
+            Ok(Some(_)) => {
+                return Err(Error::InvalidPayload {
+                    message: "An identity provider with this id already exists".to_string(),
+                })
+            }
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+
+        let metadata_xml = match (&payload.metadata_xml, &payload.metadata_url) {
+            (Some(xml), _) if !xml.trim().is_empty() => xml.clone(),
+            (_, Some(url)) if !url.is_empty() => match fetch_metadata(url).await {
+                Ok(xml) => xml,
+                Err(e) => return Err(e),
+            },
+            _ => {
+                return Err(Error::InvalidPayload {
+                    message: "The metadata of the identity provider is missing".to_string(),
+                })
+            }
+        };
+        let metadata = match parse_metadata(&metadata_xml) {
+            Ok(metadata) => metadata,
+            Err(e) => return Err(e),
+        };
+
+        let default_role = match &payload.default_role {
+            Some(role) if !role.trim().is_empty() => role.trim().to_string(),
+            _ => "user".to_string(),
+        };
+        let connection = Self {
+            _id: ObjectId::new(),
+            connection_id: payload.connection_id.clone(),
+            name: payload.name.trim().to_string(),
+            idp_entity_id: metadata.entity_id,
+            sso_url: metadata.sso_url,
+            certificates: metadata.certificates,
+            domains,
+            email_attribute: payload.email_attribute.clone().unwrap_or_default(),
+            name_attribute: payload.name_attribute.clone().unwrap_or_default(),
+            role_attribute: payload.role_attribute.clone().unwrap_or_default(),
+            role_mapping: payload
+                .role_mapping
+                .iter()
+                .map(|mapping| SamlRoleMapping {
+                    value: mapping.value.clone(),
+                    role: mapping.role.trim().to_string(),
+                })
+                .collect(),
+            default_role,
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        };
+        match store.insert_saml_connection(&connection).await {
+            Ok(_) => Ok(connection.to_response()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get(store: &dyn Store, connection_id: &str) -> Result<Self> {
+        match store.get_saml_connection(connection_id).await {
+            Ok(Some(connection)) => Ok(connection),
+            Ok(None) => Err(Error::SamlConnectionNotFound {
+                message: "SAML connection not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_all(store: &dyn Store) -> Result<Vec<SamlConnectionResponse>> {
+        let mut connections = match store.get_saml_connections().await {
+            Ok(connections) => connections,
+            Err(e) => return Err(e),
+        };
+        connections.sort_by_key(|c| c.created_at);
+        Ok(connections.iter().map(|c| c.to_response()).collect())
+    }
+
+    pub async fn delete(store: &dyn Store, connection_id: &str) -> Result<()> {
+        match store.delete_saml_connection(connection_id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::SamlConnectionNotFound {
+                message: "SAML connection not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    // the id the identity provider knows us by, it is also where our metadata is
+    pub fn sp_entity_id(&self) -> String {
+        format!("{}/saml/metadata/{}", server_url(), self.connection_id)
+    }
+
+    // where the identity provider posts its responses to
+    pub fn acs_url(&self) -> String {
+        format!("{}/saml/acs/{}", server_url(), self.connection_id)
+    }
+
+    pub fn allows_email(&self, email: &str) -> bool {
+        match email.rsplit_once('@') {
+            Some((_, domain)) => self.domains.contains(&domain.to_lowercase()),
+            None => false,
+        }
+    }
+
+    // the role of the first mapping a value of the role attribute matches
+    pub fn role(&self, assertion: &SamlAssertion) -> String {
+        let values = assertion.attribute_values(&self.role_attribute);
+        match self
+            .role_mapping
+            .iter()
+            .find(|mapping| values.contains(&mapping.value))
+        {
+            Some(mapping) => mapping.role.clone(),
+            None => self.default_role.clone(),
+        }
+    }
+
+    fn to_response(&self) -> SamlConnectionResponse {
+        SamlConnectionResponse {
+            connection_id: self.connection_id.clone(),
+            name: self.name.clone(),
+            idp_entity_id: self.idp_entity_id.clone(),
+            sso_url: self.sso_url.clone(),
+            sp_entity_id: self.sp_entity_id(),
+            acs_url: self.acs_url(),
+            domains: self.domains.clone(),
+            email_attribute: self.email_attribute.clone(),
+            name_attribute: self.name_attribute.clone(),
+            role_attribute: self.role_attribute.clone(),
+            role_mapping: self.role_mapping.clone(),
+            default_role: self.default_role.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+}
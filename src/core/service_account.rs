@@ -0,0 +1,182 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    models::service_account_model::ServiceAccountResponse,
+    traits::{decryption::Decrypt, encryption::Encrypt, store::Store},
+    utils::{
+        encryption_utils::Encryption,
+        oauth_utils::{generate_token, is_valid_scope_token},
+        password_utils::Password,
+    },
+};
+
+use super::dek::Dek;
+
+// A machine identity for backend services using the client_credentials grant.
+// It is stored like a user: encrypted with its own DEK, with the uid (the
+// client_id) left in plaintext and the secret salted and hashed like a password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceAccount {
+    pub _id: ObjectId,
+    pub uid: String,
+    pub name: String,
+    pub secret: String,
+    // space separated, the scopes the account may request
+    pub scope: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl ServiceAccount {
+    pub fn new(name: &str, scope: &str, secret: &str) -> Self {
+        Self {
+            _id: ObjectId::new(),
+            uid: generate_token(16),
+            name: name.to_string(),
+            secret: Password::salt_and_hash(secret),
+            scope: scope.to_string(),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        self.scope.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    pub fn to_response(&self) -> ServiceAccountResponse {
+        ServiceAccountResponse {
+            client_id: self.uid.clone(),
+            name: self.name.clone(),
+            scopes: self.scopes(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+
+    // creates the account and returns it along with its secret
+    pub async fn create(store: &dyn Store, name: &str, scopes: &[String]) -> Result<(Self, String)> {
+        if let Some(invalid) = scopes.iter().find(|s| !is_valid_scope_token(s)) {
+            return Err(Error::InvalidPayload {
+                message: format!("Invalid scope: {:?}", invalid),
+            });
+        }
+
+        let secret = generate_token(32);
+        let account = ServiceAccount::new(name, &scopes.join(" "), &secret);
+
+        // service accounts have no email, the uid keeps the email index of the deks unique
+        let dek = Dek::generate();
+        match Dek::new(&account.uid, &account.uid, &dek)
+            .encrypt_and_add(store)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        match store.insert_service_account(&account.encrypt(&dek)).await {
+            Ok(_) => Ok((account, secret)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // returns the account as it is stored (encrypted) along with its decrypted dek
+    async fn get_stored(store: &dyn Store, client_id: &str) -> Result<(Self, Dek)> {
+        let not_found = Error::ServiceAccountNotFound {
+            message: "Service account not found".to_string(),
+        };
+        let dek_data = match Dek::get(store, client_id).await {
+            Ok(dek) => dek,
+            Err(Error::KeyNotFound { .. }) => return Err(not_found),
+            Err(e) => return Err(e),
+        };
+        match store.get_service_account(client_id).await {
+            Ok(Some(account)) => Ok((account, dek_data)),
+            Ok(None) => Err(not_found),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get(store: &dyn Store, client_id: &str) -> Result<Self> {
+        match ServiceAccount::get_stored(store, client_id).await {
+            Ok((account, dek_data)) => Ok(account.decrypt(&dek_data.dek)),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_all(store: &dyn Store) -> Result<Vec<ServiceAccountResponse>> {
+        let accounts_data = match store.get_service_accounts().await {
+            Ok(accounts) => accounts,
+            Err(e) => return Err(e),
+        };
+
+        let mut accounts = Vec::new();
+        for account_data in accounts_data {
+            let dek_data = match Dek::get(store, &account_data.uid).await {
+                Ok(dek) => dek,
+                Err(e) => return Err(e),
+            };
+            accounts.push(account_data.decrypt(&dek_data.dek).to_response());
+        }
+
+        accounts.sort_by_key(|a| a.created_at);
+        Ok(accounts)
+    }
+
+    // replaces the secret, the old one stops working right away
+    pub async fn rotate_secret(store: &dyn Store, client_id: &str) -> Result<(Self, String)> {
+        let (mut account, dek_data) = match ServiceAccount::get_stored(store, client_id).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        let secret = generate_token(32);
+        account.secret = Encryption::encrypt_data(&Password::salt_and_hash(&secret), &dek_data.dek);
+        account.updated_at = DateTime::now();
+
+        match store.update_service_account(&account).await {
+            Ok(true) => Ok((account.decrypt(&dek_data.dek), secret)),
+            Ok(false) => Err(Error::ServiceAccountNotFound {
+                message: "Service account not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn delete(store: &dyn Store, client_id: &str) -> Result<()> {
+        match store.delete_service_account(client_id).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(Error::ServiceAccountNotFound {
+                    message: "Service account not found".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+        match store.delete_dek(client_id).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // checks the client credentials, unknown ids and wrong secrets look the same
+    pub async fn authenticate(store: &dyn Store, client_id: &str, client_secret: &str) -> Result<Self> {
+        let invalid_client = Error::InvalidClient {
+            message: "Invalid client credentials".to_string(),
+        };
+        if client_id.is_empty() || client_secret.is_empty() {
+            return Err(invalid_client);
+        }
+        let account = match ServiceAccount::get(store, client_id).await {
+            Ok(account) => account,
+            Err(Error::ServiceAccountNotFound { .. }) => return Err(invalid_client),
+            Err(e) => return Err(e),
+        };
+        if !Password::verify_hash(client_secret, &account.secret) {
+            return Err(invalid_client);
+        }
+        Ok(account)
+    }
+}
@@ -1,19 +1,19 @@
 use crate::{
     errors::{Error, Result},
     models::session_model::SessionResponse,
-    traits::{decryption::Decrypt, encryption::Encrypt},
+    traits::{decryption::Decrypt, encryption::Encrypt, store::Store},
     utils::{
         email_utils::Email, encryption_utils::Encryption, session_utils::{IDToken, RefreshToken}
     },
 };
-use bson::{doc, DateTime};
+use bson::DateTime;
 use woothee::parser::Parser;
-use futures::StreamExt;
-use mongodb::{Client, Collection};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::{dek::Dek, user::User};
+use super::{
+    dek::Dek, key_cache::KeyCache, rotated_refresh_token::RotatedRefreshToken, user::User,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
@@ -35,13 +35,17 @@ pub struct Session {
 }
 
 impl Session {
-    pub fn new(user: &User, user_agent: &str) -> Self {
-        let id_token = match IDToken::new(user).sign() {
+    pub fn new(keys: &KeyCache, user: &User, user_agent: &str) -> Self {
+        Self::with_id_token(keys, user, IDToken::new(user), user_agent)
+    }
+
+    pub fn with_id_token(keys: &KeyCache, user: &User, id_token: IDToken, user_agent: &str) -> Self {
+        let id_token = match id_token.sign(keys) {
             Ok(token) => token,
             Err(_) => "".to_string(),
         };
 
-        let refresh_token = match RefreshToken::new(&user.uid).sign() {
+        let refresh_token = match RefreshToken::new(&user.uid).sign(keys) {
             Ok(token) => token,
             Err(_) => "".to_string(),
         };
@@ -92,62 +96,75 @@ impl Session {
         }
     }
 
-    pub async fn encrypt_add(&self, mongo_client: &Client, key: &str) -> Result<Self> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
+    pub async fn encrypt_add(&self, store: &dyn Store, dek: &Dek) -> Result<Self> {
+        let encrypted_session = self.encrypt(&dek.dek);
 
-        let encrypted_session = self.encrypt(key);
-
-        match collection_session.insert_one(encrypted_session, None).await {
+        match store.insert_session(&encrypted_session, dek.version).await {
             Ok(_) => Ok(self.clone()),
-            Err(e) => Err(Error::ServerError {
-                message: e.to_string(),
-            }),
+            Err(e) => Err(e),
         }
     }
 
-    pub async fn verify(mongo_client: &Client, id_token: &str) -> Result<(IDToken, bool)> {
-        let token_data = match IDToken::verify(&id_token) {
+    // finds the stored (encrypted) session of the user with the given plain session_id
+    async fn get_stored(store: &dyn Store, uid: &str, session_id: &str, dek: &str) -> Result<Option<Session>> {
+        let sessions = match store.get_sessions_by_uid(uid).await {
+            Ok(sessions) => sessions,
+            Err(e) => return Err(e),
+        };
+
+        Ok(sessions
+            .into_iter()
+            .find(|session| Encryption::decrypt_data(&session.session_id, dek) == session_id))
+    }
+
+    // finds the session of the user holding the given ID token or refresh token
+    pub async fn get_from_token(store: &dyn Store, uid: &str, token: &str) -> Result<Option<Session>> {
+        let dek_data = match Dek::get(store, uid).await {
+            Ok(dek) => dek,
+            Err(e) => return Err(e),
+        };
+
+        let sessions = match store.get_sessions_by_uid(uid).await {
+            Ok(sessions) => sessions,
+            Err(e) => return Err(e),
+        };
+
+        Ok(sessions
+            .into_iter()
+            .find(|session| {
+                Encryption::decrypt_data(&session.id_token, &dek_data.dek) == token
+                    || Encryption::decrypt_data(&session.refresh_token, &dek_data.dek) == token
+            })
+            .map(|session| session.decrypt(&dek_data.dek)))
+    }
+
+    pub async fn verify(store: &dyn Store, keys: &KeyCache, id_token: &str) -> Result<(IDToken, bool)> {
+        let token_data = match IDToken::verify(keys, &id_token) {
             Ok(token_verify_result) => {
                 //  check if the session is expired using the boolean
                 if !token_verify_result.1 {
                     return Ok(token_verify_result);
                 }
-                let db = mongo_client.database("auth");
-                let collection_session: Collection<Session> = db.collection("sessions");
 
-                let dek_data = match Dek::get(mongo_client, &token_verify_result.0.uid).await {
+                let dek_data = match Dek::get(store, &token_verify_result.0.uid).await {
                     Ok(dek) => dek,
                     Err(e) => return Err(e),
                 };
 
-                let encrypted_id =
-                    Encryption::encrypt_data(&token_verify_result.0.uid, &dek_data.dek);
-                let encrypted_id_token = Encryption::encrypt_data(&id_token, &dek_data.dek);
-
-                let session = match collection_session
-                    .count_documents(
-                        doc! {
-                            "uid": encrypted_id,
-                            "id_token": encrypted_id_token,
-                            "is_revoked": false,
-                        },
-                        None,
-                    )
-                    .await
-                {
-                    Ok(count) => {
-                        if count == 1 {
-                            Ok(())
-                        } else {
-                            Err(Error::SessionExpired {
+                let session = match store.get_sessions_by_uid(&token_verify_result.0.uid).await {
+                    Ok(sessions) => {
+                        let active_session = sessions.iter().find(|session| {
+                            !session.is_revoked
+                                && Encryption::decrypt_data(&session.id_token, &dek_data.dek) == id_token
+                        });
+                        match active_session {
+                            Some(_) => Ok(()),
+                            None => Err(Error::SessionExpired {
                                 message: "Invalid token".to_string(),
-                            })
+                            }),
                         }
                     }
-                    Err(e) => Err(Error::ServerError {
-                        message: e.to_string(),
-                    }),
+                    Err(e) => Err(e),
                 };
                 if session.is_err() {
                     return Err(Error::InvalidToken {
@@ -163,7 +180,8 @@ impl Session {
     }
 
     pub async fn refresh(
-        mongo_client: &Client,
+        store: &dyn Store,
+        keys: &KeyCache,
         uid: &str,
         session_id: &str,
         id_token: &str,
@@ -171,49 +189,41 @@ impl Session {
         user_agent: &str,
     ) -> Result<(String, String)> {
         // verify refresh token 
-        match RefreshToken::verify(&refresh_token) {
-            Ok(_) => {}
+        let refresh_token_data = match RefreshToken::verify(keys, &refresh_token) {
+            Ok(token_data) => token_data,
             Err(e) => {
-                match Self::revoke(&mongo_client, &session_id, &uid).await {
+                match Self::revoke(store, &session_id, &uid).await {
                     Ok(_) => return Err(e),
                     Err(err) => return Err(err),
                 }
             },
+        };
+        // A refresh token that was already rotated is replayed, so someone holds a stolen
+        // copy. We can't tell who, so the whole session (token family) is revoked.
+        let dek_data = match Dek::get(store, &refresh_token_data.uid).await {
+            Ok(dek) => dek,
+            Err(e) => return Err(e),
+        };
+        match RotatedRefreshToken::find(store, &dek_data.dek, &refresh_token).await {
+            Ok(Some(rotated)) => return Self::revoke_family(store, &rotated.uid, &rotated.session_id).await,
+            Ok(None) => {}
+            Err(e) => return Err(e),
         }
-        match Self::verify(&mongo_client, &id_token).await {
+        match Self::verify(store, keys, &id_token).await {
             Ok(token_verify_result) => {
                 if !token_verify_result.1 {
-                    let db = mongo_client.database("auth");
-                    let collection_session: Collection<Session> = db.collection("sessions");
-
-                    let dek_data = match Dek::get(mongo_client, &token_verify_result.0.uid).await {
+                    let dek_data = match Dek::get(store, &token_verify_result.0.uid).await {
                         Ok(dek) => dek,
                         Err(e) => return Err(e),
                     };
 
-                    let encrypted_uid = Encryption::encrypt_data(&token_verify_result.0.uid, &dek_data.dek);
-                    let encrypted_id_token = Encryption::encrypt_data(&id_token, &dek_data.dek);
-                    let encrypted_refresh_token =
-                        Encryption::encrypt_data(&refresh_token, &dek_data.dek);
-                    let encrypted_session_id = Encryption::encrypt_data(&session_id, &dek_data.dek);
-
-                    match collection_session
-                        .find_one(
-                            doc! {
-                                "uid": &encrypted_uid,
-                                "session_id": &encrypted_session_id,
-                                "is_revoked": false,
-                            },
-                            None,
-                        )
-                        .await
-                    {
+                    match Self::get_stored(store, &token_verify_result.0.uid, &session_id, &dek_data.dek).await {
                         Ok(session) => {
                             match session {
-                                Some(data) => {
+                                Some(mut data) if !data.is_revoked => {
                                     let decrypted_session = data.decrypt(&dek_data.dek);
                                     if decrypted_session.user_agent != user_agent {
-                                        let user =  User::get_from_email(mongo_client, &decrypted_session.email).await.unwrap();
+                                        let user =  User::get_from_email(store, &decrypted_session.email).await.unwrap();
                                         Email::new(
                                             &user.name,
                                              &user.email,
@@ -236,50 +246,50 @@ impl Session {
                                         && decrypted_session.refresh_token == refresh_token
                                     {
                                         // generate a new id token and refresh token
-                                        let user = match User::get_from_uid(&mongo_client, &token_verify_result.0.uid).await {
+                                        let user = match User::get_from_uid(store, &token_verify_result.0.uid).await {
                                             Ok(user) => user,
                                             Err(e) => return Err(e),
                                         };
-                                        let new_id_token = match IDToken::new(&user).sign() {
+                                        let new_id_token = match IDToken::new(&user).sign(keys) {
                                             Ok(token) => token,
                                             Err(_) => "".to_string(),
                                         };
 
-                                        let new_refresh_token = match RefreshToken::new(&token_verify_result.0.uid).sign() {
+                                        let new_refresh_token = match RefreshToken::new(&token_verify_result.0.uid).sign(keys) {
                                             Ok(token) => token,
                                             Err(_) => "".to_string(),
                                         };
 
                                         // encrypt the new tokens
-                                        let new_id_token_encrypted = Encryption::encrypt_data(&new_id_token, &dek_data.dek);
-                                        let new_refresh_token_encrypted = Encryption::encrypt_data(&new_refresh_token, &dek_data.dek);
-
-                                        match collection_session
-                                            .update_one(
-                                                doc! {
-                                                    "uid": encrypted_uid,
-                                                    "id_token": encrypted_id_token,
-                                                    "refresh_token": encrypted_refresh_token,
-                                                    "is_revoked": false,
-                                                },
-                                                doc! {
-                                                    "$set": {
-                                                        "id_token": new_id_token_encrypted,
-                                                        "refresh_token": new_refresh_token_encrypted,
-                                                        "updated_at": DateTime::now(),
-                                                    }
-                                                },
-                                                None,
-                                            )
-                                            .await
+                                        let current_refresh_token = data.refresh_token.clone();
+                                        data.id_token = Encryption::encrypt_data(&new_id_token, &dek_data.dek);
+                                        data.refresh_token = Encryption::encrypt_data(&new_refresh_token, &dek_data.dek);
+                                        data.updated_at = DateTime::now();
+
+                                        // only one refresh can swap out the refresh token it was made with
+                                        match store.refresh_session(&data, &current_refresh_token, dek_data.version).await {
+                                            Ok(true) => {}
+                                            Ok(false) => {
+                                                return Self::lost_refresh(store, &token_verify_result.0.uid, session_id, &dek_data, refresh_token).await
+                                            }
+                                            Err(e) => return Err(e),
+                                        };
+                                        // remember the old refresh token to catch it if it is replayed
+                                        match RotatedRefreshToken::record(
+                                            store,
+                                            &dek_data,
+                                            &token_verify_result.0.uid,
+                                            &session_id,
+                                            &refresh_token,
+                                            refresh_token_data.exp,
+                                        )
+                                        .await
                                         {
                                             Ok(_) => return Ok((new_id_token, new_refresh_token)),
-                                            Err(e) => return Err(Error::ServerError { 
-                                                message: e.to_string(),
-                                            }),
+                                            Err(e) => return Err(e),
                                         };
                                     } else {
-                                        match Self::revoke(&mongo_client, &session_id, &uid).await {
+                                        match Self::revoke(store, &session_id, &uid).await {
                                             Ok(_) => return Err(Error::InvalidToken {
                                                 message: "Invalid token".to_string(),
                                             }),
@@ -287,16 +297,14 @@ impl Session {
                                         }
                                     }
                                 }
-                                None => {
+                                _ => {
                                     return Err(Error::SessionExpired {
                                         message: "Invalid token".to_string(),
                                     });
                                 }
                             }
                         }
-                        Err(e) => return Err(Error::ServerError {
-                            message: e.to_string(),
-                        }),
+                        Err(e) => return Err(e),
                     };
                 } else {
                     return Err(Error::ActiveSessionExists {
@@ -305,7 +313,7 @@ impl Session {
                 }
             } 
             Err(e) => {
-                match Self::revoke(&mongo_client, &session_id, &uid).await {
+                match Self::revoke(store, &session_id, &uid).await {
                     Ok(_) => return Err(e),
                     Err(err) => return Err(err),
                 }
@@ -313,94 +321,104 @@ impl Session {
         };
     }
 
-    pub async fn get_all(mongo_client: &Client) -> Result<Vec<SessionResponse>> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
+    // Another refresh swapped out the refresh token between reading and writing the
+    // session, so the same token was used twice. Unless the session was only
+    // re-encrypted in the meantime, that is a replay like any other.
+    async fn lost_refresh(
+        store: &dyn Store,
+        uid: &str,
+        session_id: &str,
+        dek_data: &Dek,
+        refresh_token: &str,
+    ) -> Result<(String, String)> {
+        match Self::get_stored(store, uid, session_id, &dek_data.dek).await {
+            Ok(Some(session))
+                if !session.is_revoked
+                    && session.decrypt(&dek_data.dek).refresh_token == refresh_token =>
+            {
+                Err(Error::SessionNotFound {
+                    message: "Session changed, please try again".to_string(),
+                })
+            }
+            Ok(_) => Self::revoke_family(store, uid, session_id).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn revoke_family(store: &dyn Store, uid: &str, session_id: &str) -> Result<(String, String)> {
+        eprintln!(
+            ">> SECURITY EVENT: refresh token reuse detected, revoking session {} of user {}",
+            session_id, uid
+        );
+        match Self::revoke(store, session_id, uid).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let user = match User::get_from_uid(store, uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+        Email::new(
+            &user.name,
+            &user.email,
+            &"Suspicious Session Activity Detected",
+            &"An old sign-in token of your account was used again after it had already been replaced. This usually means someone copied it from one of your devices. For your security, we have signed that device out.
+
+            If you just signed in again on that device, you can ignore this message. Otherwise we recommend taking the following steps:
+
+            Immediately change your password to a strong, unique one.
+            Review your account activity for any suspicious activity.
+            Sign out from all your devices.
+
+            Stay safe and secure,
+            FlexAuth Team",
+        )
+        .send()
+        .await;
+
+        Err(Error::InvalidToken {
+            message: "Refresh token reuse detected".to_string(),
+        })
+    }
 
+    pub async fn get_all(store: &dyn Store) -> Result<Vec<SessionResponse>> {
         // get all the sessions
-        let mut cursor = collection_session.find(None, None).await.unwrap();
+        let sessions_data = match store.get_sessions().await {
+            Ok(sessions) => sessions,
+            Err(_) => {
+                return Err(Error::ServerError {
+                    message: "Failed to get session".to_string(),
+                });
+            }
+        };
 
         let mut sessions = Vec::new();
 
-        while let Some(session) = cursor.next().await {
-            match session {
-                Ok(data) => {
-                    let dek_data = match Dek::get(mongo_client, &data.uid).await {
-                        Ok(dek) => dek,
-                        Err(e) => return Err(e),
-                    };
-
-                    let decrypted_session = data.decrypt(&dek_data.dek);
-
-                    sessions.push(SessionResponse {
-                        uid: decrypted_session.uid,
-                        session_id: decrypted_session.session_id,
-                        email: decrypted_session.email,
-                        user_agent: decrypted_session.user_agent,
-                        os: decrypted_session.os,
-                        os_version: decrypted_session.os_version,
-                        vendor: decrypted_session.vendor,
-                        device: decrypted_session.device,
-                        browser: decrypted_session.browser,
-                        browser_version: decrypted_session.browser_version,
-                        is_revoked: decrypted_session.is_revoked,
-                        created_at: decrypted_session.created_at,
-                        updated_at: decrypted_session.updated_at,
-                    });
-                }
-                Err(_) => {
-                    return Err(Error::ServerError {
-                        message: "Failed to get session".to_string(),
-                    });
-                }
-            }
+        for data in sessions_data {
+            let dek_data = match Dek::get(store, &data.uid).await {
+                Ok(dek) => dek,
+                Err(e) => return Err(e),
+            };
+
+            let decrypted_session = data.decrypt(&dek_data.dek);
+
+            sessions.push(SessionResponse {
+                uid: decrypted_session.uid,
+                session_id: decrypted_session.session_id,
+                email: decrypted_session.email,
+                user_agent: decrypted_session.user_agent,
+                os: decrypted_session.os,
+                os_version: decrypted_session.os_version,
+                vendor: decrypted_session.vendor,
+                device: decrypted_session.device,
+                browser: decrypted_session.browser,
+                browser_version: decrypted_session.browser_version,
+                is_revoked: decrypted_session.is_revoked,
+                created_at: decrypted_session.created_at,
+                updated_at: decrypted_session.updated_at,
+            });
         }
-        // let collection_dek: Collection<Dek> = db.collection("deks");
-
-        // let mut cursor_dek = collection_dek.find(None, None).await.unwrap();
-
-        // let mut sessions = Vec::new();
-        // let kek = env::var("SERVER_KEK").expect("Server Kek must be set.");
-
-        // // iterate over the sessions and decrypt the data
-        // while let Some(dek) = cursor_dek.next().await {
-        //     let dek_data: Dek = match dek {
-        //         Ok(data) => data.decrypt(&kek),
-        //         Err(_) => {
-        //             return Err(Error::ServerError {
-        //                 message: "Failed to get DEK".to_string(),
-        //             });
-        //         }
-        //     };
-
-        //     // find the session in the sessions collection using the encrypted email to iterate over the sessions
-        //     let cursor_session = collection_session
-        //         .find_one(
-        //             Some(doc! {
-        //                 "uid": &dek_data.uid,
-        //             }),
-        //             None,
-        //         )
-        //         .await
-        //         .unwrap();
-
-        //     match cursor_session {
-        //         Some(session) => {
-        //             let session_data = session.decrypt(&dek_data.dek);
-
-        //             sessions.push(SessionResponse {
-        //                 uid: session_data.uid,
-        //                 session_id: session_data.session_id,
-        //                 email: session_data.email,
-        //                 user_agent: session_data.user_agent,
-        //                 is_revoked: session_data.is_revoked,
-        //                 created_at: session_data.created_at,
-        //                 updated_at: session_data.updated_at,
-        //             });
-        //         }
-        //         None => {()}
-        //     }
-        // }
 
         // sort the sessions by created_at
         sessions.sort_by(|a, b| a.created_at.cmp(&b.created_at));
@@ -408,79 +426,55 @@ impl Session {
     }
 
     pub async fn get_all_from_uid(
-        mongo_client: &Client,
+        store: &dyn Store,
+        keys: &KeyCache,
         uid: &str,
     ) -> Result<Vec<SessionResponse>> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
-
-        let dek_data = match Dek::get(mongo_client, uid).await {
+        let dek_data = match Dek::get(store, uid).await {
             Ok(dek) => dek,
             Err(e) => return Err(e),
         };
 
-        let mut cursor = collection_session
-            .find(
-                doc! {
-                    "uid": uid,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let sessions = match store.get_sessions_by_uid(uid).await {
+            Ok(sessions) => sessions,
+            Err(e) => return Err(e),
+        };
 
         let mut sessions_res: Vec<SessionResponse> = Vec::new();
-        while let Some(session) = cursor.next().await {
-            match session {
-                Ok(data) => {
-                    let decrypted_session = data.decrypt(&dek_data.dek);
-                    match IDToken::verify(&decrypted_session.id_token) {
-                        Ok(token) => {
-                            println!("{:?}", token);
-                            sessions_res.push(SessionResponse {
-                                uid: decrypted_session.uid,
-                                session_id: decrypted_session.session_id,
-                                email: decrypted_session.email,
-                                user_agent: decrypted_session.user_agent,
-                                os: decrypted_session.os,
-                                os_version: decrypted_session.os_version,
-                                vendor: decrypted_session.vendor,
-                                device: decrypted_session.device,
-                                browser: decrypted_session.browser,
-                                browser_version: decrypted_session.browser_version,
-                                is_revoked: decrypted_session.is_revoked,
-                                created_at: decrypted_session.created_at,
-                                updated_at: decrypted_session.updated_at,
-                            });
-                        }
-                        Err(_) => continue,
-                    }
-                }
-                Err(e) => {
-                    return Err(Error::ServerError {
-                        message: e.to_string(),
-                    })
+        for data in sessions {
+            let decrypted_session = data.decrypt(&dek_data.dek);
+            match IDToken::verify(keys, &decrypted_session.id_token) {
+                Ok(token) => {
+                    println!("{:?}", token);
+                    sessions_res.push(SessionResponse {
+                        uid: decrypted_session.uid,
+                        session_id: decrypted_session.session_id,
+                        email: decrypted_session.email,
+                        user_agent: decrypted_session.user_agent,
+                        os: decrypted_session.os,
+                        os_version: decrypted_session.os_version,
+                        vendor: decrypted_session.vendor,
+                        device: decrypted_session.device,
+                        browser: decrypted_session.browser,
+                        browser_version: decrypted_session.browser_version,
+                        is_revoked: decrypted_session.is_revoked,
+                        created_at: decrypted_session.created_at,
+                        updated_at: decrypted_session.updated_at,
+                    });
                 }
+                Err(_) => continue,
             }
         }
         Ok(sessions_res)
     }
 
-    pub async fn get_details(mongo_client: &Client, uid: &str, session_id: &str) -> Result<SessionResponse> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
-
-        let dek_data = match Dek::get(mongo_client, uid).await {
+    pub async fn get_details(store: &dyn Store, uid: &str, session_id: &str) -> Result<SessionResponse> {
+        let dek_data = match Dek::get(store, uid).await {
             Ok(dek) => dek,
             Err(e) => return Err(e),
         };
 
-        let encrypted_session_id = Encryption::encrypt_data(session_id, &dek_data.dek);
-
-        let session = match collection_session
-            .find_one(doc! {"uid": &uid, "session_id": encrypted_session_id}, None)
-            .await
-        {
+        let session = match Self::get_stored(store, uid, session_id, &dek_data.dek).await {
             Ok(session) => {
                 match session {
                     Some(data) => {
@@ -492,9 +486,7 @@ impl Session {
                     }),
                 }
             }
-            Err(e) => Err(Error::ServerError {
-                message: e.to_string(),
-            }),
+            Err(e) => Err(e),
         };
 
         match session {
@@ -519,84 +511,148 @@ impl Session {
         }
     } 
 
-    pub async fn revoke_all(mongo_client: &Client, uid: &str) -> Result<()> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
-
-        match collection_session
-            .update_many(doc! {"uid": &uid }, doc! {"$set": {"is_revoked": true}}, None)
-            .await
-        {
+    pub async fn revoke_all(store: &dyn Store, uid: &str) -> Result<()> {
+        match store.revoke_sessions_by_uid(uid).await {
             Ok(_) => Ok(()),
-            Err(e) => Err(Error::ServerError {
-                message: e.to_string(),
-            }),
+            Err(e) => Err(e),
         }
     }
 
-    pub async fn revoke(mongo_client: &Client, session_id: &str, uid: &str) -> Result<()> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
+    pub async fn revoke(store: &dyn Store, session_id: &str, uid: &str) -> Result<()> {
+        // the stored session changes when it is re-encrypted or the DEK is rotated,
+        // so look it up again with the current DEK if it changed between reading and writing
+        for _ in 0..2 {
+            let dek_data = match Dek::get(store, uid).await {
+                Ok(dek) => dek,
+                Err(e) => return Err(e),
+            };
+            match Self::get_stored(store, uid, session_id, &dek_data.dek).await {
+                Ok(Some(mut session)) => {
+                    session.is_revoked = true;
+                    match store.update_session(&session, dek_data.version).await {
+                        Ok(true) => return Ok(()),
+                        Ok(false) | Err(Error::DekRotated { .. }) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::ServerError {
+            message: "The session could not be revoked".to_string(),
+        })
+    }
 
-        let dek_data = match Dek::get(mongo_client, uid).await {
+    pub async fn delete(store: &dyn Store, session_id: &str, uid: &str) -> Result<()> {
+        let dek_data = match Dek::get(store, uid).await {
             Ok(dek) => dek,
             Err(e) => return Err(e),
         };
 
-        let encrypted_session_id = Encryption::encrypt_data(session_id, &dek_data.dek);
+        match Self::get_stored(store, uid, session_id, &dek_data.dek).await {
+            Ok(Some(session)) => match store.delete_session(&session.session_id).await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            },
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
 
-        match collection_session
-            .update_one(
-                doc! {"session_id": encrypted_session_id},
-                doc! {"$set": {"is_revoked": true}},
-                None,
-            )
-            .await
-        {
+    pub async fn delete_all(store: &dyn Store, uid: &str) -> Result<()> {
+        match store.delete_sessions_by_uid(uid).await {
             Ok(_) => Ok(()),
-            Err(e) => Err(Error::ServerError {
-                message: e.to_string(),
-            }),
+            Err(e) => Err(e),
         }
     }
+}
 
-    pub async fn delete(mongo_client: &Client, session_id: &str, uid: &str) -> Result<()> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::memory_store::MemoryStore, utils::email_utils};
+    use std::env;
 
-        let dek_data = match Dek::get(mongo_client, uid).await {
-            Ok(dek) => dek,
-            Err(e) => return Err(e),
-        };
+    fn set_keys() {
+        env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+    }
 
-        let encrypted_session_id = Encryption::encrypt_data(session_id, &dek_data.dek);
+    // A refresh token is replayed after it was rotated: the session it belongs to is
+    // revoked, together with the tokens the rotation gave out.
+    #[tokio::test]
+    async fn replayed_refresh_token_revokes_its_session() {
+        set_keys();
+        let store = MemoryStore::new();
+        let keys = KeyCache::for_tests();
+        let dek = Dek::generate();
+        let user = User::new("Ada", "reuse@example.com", "user", "secret")
+            .encrypt_and_add(&store, &dek)
+            .await
+            .unwrap();
+        let dek_data = Dek::new(&user.uid, &user.email, &dek)
+            .encrypt_and_add(&store)
+            .await
+            .unwrap();
 
-        match collection_session
-            .delete_one(
-                doc! { "session_id": encrypted_session_id },
-                None,
-            )
+        // a session whose ID token has expired, so it may be refreshed
+        let mut expired = IDToken::new(&user);
+        expired.iat -= 7200;
+        expired.exp = expired.iat + 60;
+        let session = Session::with_id_token(&keys, &user, expired, "curl")
+            .encrypt_add(&store, &dek_data)
             .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Error::ServerError {
-                message: e.to_string(),
-            }),
-        }
-    }
+            .unwrap();
 
-    pub async fn delete_all(mongo_client: &Client, uid: &str) -> Result<()> {
-        let db = mongo_client.database("auth");
-        let collection_session: Collection<Session> = db.collection("sessions");
+        let (id_token, refresh_token) = Session::refresh(
+            &store,
+            &keys,
+            &user.uid,
+            &session.session_id,
+            &session.id_token,
+            &session.refresh_token,
+            "curl",
+        )
+        .await
+        .unwrap();
+        assert!(Session::verify(&store, &keys, &id_token).await.unwrap().1);
+
+        // the rotated refresh token is used again
+        assert!(matches!(
+            Session::refresh(
+                &store,
+                &keys,
+                &user.uid,
+                &session.session_id,
+                &session.id_token,
+                &session.refresh_token,
+                "curl",
+            )
+            .await,
+            Err(Error::InvalidToken { .. })
+        ));
 
-        match collection_session
-            .delete_many(doc! {"uid": &uid }, None)
+        let stored = Session::get_from_token(&store, &user.uid, &refresh_token)
             .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Error::ServerError {
-                message: e.to_string(),
-            }),
-        }
+            .unwrap()
+            .unwrap();
+        assert!(stored.is_revoked);
+        assert!(matches!(
+            Session::verify(&store, &keys, &id_token).await,
+            Err(Error::InvalidToken { .. })
+        ));
+        assert!(Session::refresh(
+            &store,
+            &keys,
+            &user.uid,
+            &session.session_id,
+            &id_token,
+            &refresh_token,
+            "curl",
+        )
+        .await
+        .is_err());
+        assert_eq!(email_utils::sent_to("reuse@example.com").len(), 1);
     }
 }
@@ -1,19 +1,18 @@
-use std::env;
-
 use crate::{
     errors::{Error, Result},
     models::{password_model::ForgetPasswordRequest, user_model::{EmailVerificationRequest, UserBlockRequest, UserResponse}},
-    traits::{decryption::Decrypt, encryption::Encrypt},
+    traits::{decryption::Decrypt, encryption::Encrypt, store::Store},
     utils::{
-        email_utils::Email, encryption_utils::Encryption, password_utils::Password
+        email_utils::Email, encryption_utils::Encryption, password_utils::Password, sms_utils::Sms
     },
 };
-use bson::{doc, oid::ObjectId, uuid, DateTime};
-use futures::StreamExt;
-use mongodb::{options::FindOptions, Client, Collection};
+use bson::{oid::ObjectId, uuid, DateTime};
 use serde::{Deserialize, Serialize};
 
-use super::{dek::Dek, session::Session};
+use super::{
+    dek::Dek,
+    otp::{OtpCode, CHANNEL_PHONE_VERIFICATION, OTP_TTL_SECS},
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct User {
@@ -27,10 +26,36 @@ pub struct User {
     pub is_active: bool,
     pub failed_login_attempts: i32,
     pub blocked_until: Option<DateTime>,
+    // base32 TOTP secret, encrypted with the DEK like the other fields
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    // the secret is only used for sign in once the user confirmed a first code
+    #[serde(default)]
+    pub totp_enabled: bool,
+    // time step of the last accepted code, so a code can't be used twice
+    #[serde(default)]
+    pub totp_last_step: Option<i64>,
+    // one-time codes for when the authenticator is lost, hashed like a password
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    // E.164, encrypted with the DEK like the other fields
+    #[serde(default)]
+    pub phone: Option<String>,
+    // codes are only sent by SMS once the user proved the number is theirs
+    #[serde(default)]
+    pub phone_verified: bool,
+    // false for users created through an identity provider, until they reset the password
+    #[serde(default = "has_password_default")]
+    pub has_password: bool,
     pub created_at: Option<DateTime>,
     pub updated_at: Option<DateTime>,
 }
 
+// users stored before has_password existed all chose a password
+fn has_password_default() -> bool {
+    true
+}
+
 impl User {
     pub fn new(name: &str, email: &str, role: &str, password: &str) -> Self {
         Self {
@@ -44,75 +69,54 @@ impl User {
             is_active: true,
             failed_login_attempts: 0,
             blocked_until: None,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: None,
+            recovery_codes: Vec::new(),
+            phone: None,
+            phone_verified: false,
+            has_password: true,
             created_at: Some(DateTime::now()),
             updated_at: Some(DateTime::now()),
         }
     }
-    pub async fn encrypt_and_add(&self, mongo_client: &Client, dek: &str) -> Result<Self> {
-        let db = mongo_client.database("auth");
+    pub async fn encrypt_and_add(&self, store: &dyn Store, dek: &str) -> Result<Self> {
         let mut user = self.clone();
         user.password = Password::salt_and_hash(user.password.as_str());
-        let collection: Collection<User> = db.collection("users");
-        match collection.insert_one(user.encrypt(&dek), None).await {
+        match store.insert_user(&user.encrypt(&dek)).await {
             Ok(_) => return Ok(user),
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to Insert User".to_string(),
-                });
-            }
+            Err(e) => return Err(e),
         }
     }
-    pub async fn get_from_email(mongo_client: &Client, email: &str) -> Result<User> {
-                let user_collection: Collection<User> =
-                    mongo_client.database("auth").collection("users");
-                let dek_data = match Dek::get(&mongo_client, email).await {
-                    Ok(dek) => dek,
-                    Err(e) => {
-                        return Err(e);
-                    }
-                };
-
-                println!("Dek Data {:?}", dek_data);
-
-                match user_collection
-                    .find_one(
-                        doc! {
-                            "uid": dek_data.uid,
-                        },
-                        None,
-                    )
-                    .await
-                {
-                    Ok(Some(user)) => {
-                        let decrypted_user = user.decrypt(&dek_data.dek);
-                        return Ok(decrypted_user);
-                    }
-                    Ok(None) => Err(Error::UserNotFound {
-                        message: "User not found".to_string(),
-                    }),
-                    Err(_) => Err(Error::ServerError {
-                        message: "Failed to get User".to_string(),
-                    }),
-                }
+    pub async fn get_from_email(store: &dyn Store, email: &str) -> Result<User> {
+        let dek_data = match Dek::get(store, email).await {
+            Ok(dek) => dek,
+            Err(e) => {
+                return Err(e);
+            }
+        };
+
+        match store.get_user(&dek_data.uid).await {
+            Ok(Some(user)) => {
+                let decrypted_user = user.decrypt(&dek_data.dek);
+                return Ok(decrypted_user);
+            }
+            Ok(None) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to get User".to_string(),
+            }),
+        }
     }
-    pub async fn get_from_uid(mongo_client: &Client, uid: &str) -> Result<User> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-        let dek_data = match Dek::get(&mongo_client, uid).await {
+    pub async fn get_from_uid(store: &dyn Store, uid: &str) -> Result<User> {
+        let dek_data = match Dek::get(store, uid).await {
             Ok(dek) => dek,
             Err(e) => {
                 return Err(e);
             }
         };
-        match collection
-            .find_one(
-                doc! {
-                    "uid": &uid,
-                },
-                None,
-            )
-            .await
-        {
+        match store.get_user(uid).await {
             Ok(Some(user)) => {
                 let decrypted_user = user.decrypt(&dek_data.dek);
                 return Ok(decrypted_user);
@@ -125,27 +129,41 @@ impl User {
             }),
         }
     }
-    pub async fn get_all(mongo_client: &Client) -> Result<Vec<UserResponse>> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
+    // returns the user as it is stored (encrypted) along with the decrypted dek of the user
+    pub async fn get_stored(store: &dyn Store, identifier: &str) -> Result<(User, Dek)> {
+        let dek_data = match Dek::get(store, identifier).await {
+            Ok(dek) => dek,
+            Err(e) => {
+                return Err(e);
+            }
+        };
 
+        match store.get_user(&dek_data.uid).await {
+            Ok(Some(user)) => Ok((user, dek_data)),
+            Ok(None) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to get User".to_string(),
+            }),
+        }
+    }
+    pub async fn get_all(store: &dyn Store) -> Result<Vec<UserResponse>> {
         // get all the users
-        let mut cursor_user = collection.find(None, None).await.unwrap();
+        let users_data = match store.get_users().await {
+            Ok(users) => users,
+            Err(_) => {
+                return Err(Error::ServerError {
+                    message: "Failed to get User".to_string(),
+                });
+            }
+        };
 
         let mut users = Vec::new();
 
         // iterate over the users and decrypt the data
-        while let Some(user) = cursor_user.next().await {
-            let user_data = match user {
-                Ok(data) => data,
-                Err(_) => {
-                    return Err(Error::ServerError {
-                        message: "Failed to get User".to_string(),
-                    });
-                }
-            };
-
-            let dek_data = match Dek::get(&mongo_client, &user_data.uid).await {
+        for user_data in users_data {
+            let dek_data = match Dek::get(store, &user_data.uid).await {
                 Ok(dek) => dek,
                 Err(e) => {
                     return Err(e);
@@ -163,6 +181,8 @@ impl User {
                 email_verified: decrypted_user.email_verified,
                 blocked_until: decrypted_user.blocked_until,
                 is_active: decrypted_user.is_active,
+                phone: decrypted_user.phone,
+                phone_verified: decrypted_user.phone_verified,
                 uid: decrypted_user.uid,
             });
         }
@@ -172,424 +192,237 @@ impl User {
 
         Ok(users)
     }
-    pub async fn get_recent(mongo_client: &Client, limit: i64) -> Result<Vec<UserResponse>> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-
-        // get recent users from the users collection till the limit provided sort by created_at
-
-        let find_options = FindOptions::builder()
-            .sort(doc! { "created_at": -1 })
-            .limit(limit)
-            .build();
-        
-        let mut cursor = collection
-            .find(None, find_options)
-            .await
-            .unwrap();
-        
-
-        let mut users = Vec::new();
-
-        // iterate over the users and decrypt the data
-        while let Some(user) = cursor.next().await {
-            let user_data = match user {
-                Ok(data) => data,
-                Err(_) => {
-                    return Err(Error::ServerError {
-                        message: "Failed to get User".to_string(),
-                    });
-                }
-            };
-
-            let dek_data = match Dek::get(&mongo_client, &user_data.uid).await {
-                Ok(dek) => dek,
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-
-            let decrypted_user = user_data.decrypt(&dek_data.dek);
-
-            users.push(UserResponse {
-                name: decrypted_user.name,
-                email: decrypted_user.email,
-                role: decrypted_user.role,
-                blocked_until: decrypted_user.blocked_until,
-                created_at: decrypted_user.created_at,
-                updated_at: decrypted_user.updated_at,
-                email_verified: decrypted_user.email_verified,
-                is_active: decrypted_user.is_active,
-                uid: decrypted_user.uid,
-            });
-        }
-
-        // sort the users by created_at
-        users.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    pub async fn get_recent(store: &dyn Store, limit: i64) -> Result<Vec<UserResponse>> {
+        let users = match User::get_all(store).await {
+            Ok(users) => users,
+            Err(e) => return Err(e),
+        };
 
         // get the recent users
         let recent_users = users.iter().rev().take(limit as usize).cloned().collect();
 
         Ok(recent_users)
     }
-    pub async fn update_role(mongo_client: &Client,email: &str,role: &str) -> Result<String> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-
-        let dek_data = match Dek::get(&mongo_client, email).await {
-            Ok(dek) => dek,
-            Err(e) => {
-                return Err(e);
-            }
+    pub async fn update_name(store: &dyn Store, email: &str, name: &str) -> Result<String> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
         };
 
-        // find the user in the users collection using the uid
-        match collection
-            .update_one(
-                doc! {
-                    "uid": &dek_data.uid,
-                },
-                doc! {
-                    "$set": {
-                        "role": Encryption::encrypt_data(&role, &dek_data.dek),
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-        {
-            Ok(cursor) => {
-                let modified_count = cursor.modified_count;
-
-                // Return Error if User is not there
-                if modified_count == 0 {
-                    // send back a 404 to
-                    return Err(Error::UserNotFound {
-                        message: "User not found".to_string(),
-                    });
-                }
-                return Ok(role.to_string());
-            }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to update User".to_string(),
+        user.name = Encryption::encrypt_data(&name, &dek_data.dek);
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => return Ok(name.to_string()),
+            Ok(false) => {
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
                 })
             }
+            Err(e) => return Err(e),
         }
     }
-    pub async fn toggle_account_activation(mongo_client: &Client, email: &str, is_active: &bool) -> Result<bool> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-        let dek_data = match Dek::get(&mongo_client, email).await {
-            Ok(dek) => dek,
-            Err(e) => {
-                return Err(e);
-            }
+    // sets a new, unverified phone number and texts it a code to verify it with
+    pub async fn update_phone(store: &dyn Store, email: &str, phone: &str, user_agent: &str) -> Result<()> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
         };
 
-        // find the user in the users collection using the uid
-        match collection
-            .update_one(
-                doc! {
-                    "uid": &dek_data.uid,
-                },
-                doc! {
-                    "$set": {
-                        "is_active": is_active,
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-        {
-            Ok(cursor) => {
-                let modified_count = cursor.modified_count;
-
-                // Return Error if User is not there
-                if modified_count == 0 {
-                    // send back a 404 to
-                    return Err(Error::UserNotFound {
-                        message: "User not found".to_string(),
-                    });
-                }
-                return Ok(is_active.to_owned());
-            }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to update User".to_string(),
+        user.phone = Some(Encryption::encrypt_data(phone, &dek_data.dek));
+        user.phone_verified = false;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
                 })
             }
+            Err(e) => return Err(e),
         }
+
+        let code = match OtpCode::issue(store, &user.uid, CHANNEL_PHONE_VERIFICATION, user_agent).await {
+            Ok(code) => code,
+            Err(e) => return Err(e),
+        };
+
+        Sms::new(
+            phone,
+            &format!("Your FlexAuth verification code is {}. It expires in {} minutes.", code, OTP_TTL_SECS / 60),
+        ).send().await
     }
-    pub async fn increase_failed_login_attempt(mongo_client: &Client, email: &str) -> Result<i32> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-        let dek_data = match Dek::get(&mongo_client, email).await {
-            Ok(dek) => dek,
-            Err(e) => {
-                return Err(e);
-            }
+    pub async fn verify_phone(store: &dyn Store, email: &str, code: &str, user_agent: &str) -> Result<()> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
         };
 
-        // find the user in the users collection using the uid
-        match collection
-            .update_one(
-                doc! {
-                    "uid": &dek_data.uid,
-                },
-                doc! {
-                    "$inc": {
-                        "failed_login_attempts": 1
-                    },
-                    "$set": {
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-        {
-            Ok(cursor) => {
-                let modified_count = cursor.modified_count;
-
-                // Return Error if User is not there
-                if modified_count == 0 {
-                    // send back a 404 to
-                    return Err(Error::UserNotFound {
-                        message: "User not found".to_string(),
-                    });
-                }
+        if user.phone.is_none() {
+            return Err(Error::PhoneNotVerified {
+                message: "The user has no phone number".to_string(),
+            });
+        }
 
-                // check if the failed login attempts is greater than 5 then add_block_user_until 180 seconds and if it is 10 then for 10 minutes if its 15 then 1hr
-                let user = match User::get_from_email(&mongo_client, email).await {
-                    Ok(user) => user,
-                    Err(e) => {
-                        return Err(e);
-                    }
-                };
-
-                if user.failed_login_attempts == 5 {
-                    let blocked_until = DateTime::now().timestamp_millis() + 180000;
-
-                    // send a email to the user to notify multiple login attempts detected
-                    Email::new(
-                        &user.name,
-                        &user.email,
-                        &"Multiple login Attempts detected",
-                        &("We have detected an multiple unauthorized login attempt associated with your account. For your security, we have taken action to protect your account.
-
-                            If you attempted to log in, please disregard this message. However, if you did not attempt to log in, we recommend taking the following steps:
-
-                            Immediately change your password to a strong, unique one.
-                            Review your account activity for any suspicious activity.
-                            If you have any concerns or questions, please don't hesitate to contact our support team.
-
-                            Stay safe and secure,
-                            FlexAuth Team"),
-                    ).send().await;
-
-                    match collection
-                        .update_one(
-                            doc! {
-                                "uid": &dek_data.uid,
-                            },
-                            doc! {
-                                "$set": {
-                                    "blocked_until": DateTime::from_millis(blocked_until),
-                                    "updated_at": DateTime::now(),
-                                }
-                            },
-                            None,
-                        )
-                        .await
-                    {
-                        Ok(_) => {
-                            return Ok(5);
-                        }
-                        Err(_) => {
-                            return Err(Error::ServerError {
-                                message: "Failed to update User".to_string(),
-                            });
-                        }
-                    }
-                } else if user.failed_login_attempts == 10 {
-                    let blocked_until = DateTime::now().timestamp_millis() + 600000;
-
-                    // send a email to the user to notify multiple login attempts detected
-                    Email::new(
-                        &user.name,
-                        &user.email,
-                        &"Multiple login Attempts detected",
-                        &("We have detected an multiple unauthorized login attempt associated with your account. For your security, we have taken action to protect your account.
-
-                            If you attempted to log in, please disregard this message. However, if you did not attempt to log in, we recommend taking the following steps:
-
-                            Immediately change your password to a strong, unique one.
-                            Review your account activity for any suspicious activity.
-                            If you have any concerns or questions, please don't hesitate to contact our support team.
-
-                            Stay safe and secure,
-                            FlexAuth Team"),
-                    ).send().await;
-
-                    match collection
-                        .update_one(
-                            doc! {
-                                "uid": &dek_data.uid,
-                            },
-                            doc! {
-                                "$set": {
-                                    "blocked_until": DateTime::from_millis(blocked_until),
-                                    "updated_at": DateTime::now(),
-                                }
-                            },
-                            None,
-                        )
-                        .await
-                    {
-                        Ok(_) => {
-                            return Ok(10);
-                        }
-                        Err(_) => {
-                            return Err(Error::ServerError {
-                                message: "Failed to update User".to_string(),
-                            });
-                        }
-                    }
-                } else if user.failed_login_attempts == 15 {
-                    let blocked_until = DateTime::now().timestamp_millis() + 3600000;
-
-                    // send a email to the user to notify multiple login attempts detected
-                    Email::new(
-                        &user.name,
-                        &user.email,
-                        &"Multiple login Attempts detected",
-                        &("We have detected an multiple unauthorized login attempt associated with your account. For your security, we have taken action to protect your account.
-
-                            If you attempted to log in, please disregard this message. However, if you did not attempt to log in, we recommend taking the following steps:
-
-                            Immediately change your password to a strong, unique one.
-                            Review your account activity for any suspicious activity.
-                            If you have any concerns or questions, please don't hesitate to contact our support team.
-
-                            Stay safe and secure,
-                            FlexAuth Team"),
-                    ).send().await;
-
-                    match collection
-                        .update_one(
-                            doc! {
-                                "uid": &dek_data.uid,
-                            },
-                            doc! {
-                                "$set": {
-                                    "blocked_until": DateTime::from_millis(blocked_until),
-                                    "updated_at": DateTime::now(),
-                                }
-                            },
-                            None,
-                        )
-                        .await
-                    {
-                        Ok(_) => {
-                            return Ok(15);
-                        }
-                        Err(_) => {
-                            return Err(Error::ServerError {
-                                message: "Failed to update User".to_string(),
-                            });
-                        }
-                    }
-                } else {
-                    return Ok(user.failed_login_attempts);
-                }
-            }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to update User".to_string(),
-                })
-            }
+        match OtpCode::verify(store, &user.uid, CHANNEL_PHONE_VERIFICATION, code, user_agent).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        user.phone_verified = true;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(e) => Err(e),
         }
     }
-    pub async fn reset_failed_login_attempt(mongo_client: &Client,email: &str ) -> Result<String> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-        let dek_data = match Dek::get(&mongo_client, email).await {
-            Ok(dek) => dek,
-            Err(e) => {
-                return Err(e);
-            }
+    pub async fn remove_phone(store: &dyn Store, email: &str) -> Result<()> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
         };
 
-        // find the user in the users collection using the uid
-        match collection
-            .update_one(
-                doc! {
-                    "uid": &dek_data.uid,
-                },
-                doc! {
-                    "$set": {
-                        "failed_login_attempts": 0,
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-        {
-            Ok(cursor) => {
-                let modified_count = cursor.modified_count;
-
-                // Return Error if User is not there
-                if modified_count == 0 {
-                    // send back a 404 to
-                    return Err(Error::UserNotFound {
-                        message: "User not found".to_string(),
-                    });
-                }
-                return Ok("Failed login attempts reset".to_string());
-            }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to update User".to_string(),
-                })
+        user.phone = None;
+        user.phone_verified = false;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::UserNotFound {
+                message: "User not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+    pub async fn update_role(store: &dyn Store, email: &str, role: &str) -> Result<String> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        user.role = Encryption::encrypt_data(&role, &dek_data.dek);
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => return Ok(role.to_string()),
+            Ok(false) => {
+                // send back a 404 to
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                });
             }
+            Err(e) => return Err(e),
         }
     }
-    pub async fn change_password(mongo_client: &Client, email: &str, old_password: &str, new_password: &str) -> Result<String> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-        let dek_data = match Dek::get(&mongo_client, email).await {
-            Ok(dek) => dek,
-            Err(e) => {
-                return Err(e);
+    pub async fn toggle_account_activation(store: &dyn Store, email: &str, is_active: &bool) -> Result<bool> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        user.is_active = *is_active;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => return Ok(is_active.to_owned()),
+            Ok(false) => {
+                // send back a 404 to
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                });
             }
+            Err(e) => return Err(e),
+        }
+    }
+    pub async fn increase_failed_login_attempt(store: &dyn Store, email: &str) -> Result<i32> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
         };
-        // get user
-        let user = match collection
-            .find_one(
-                doc! { "email": Encryption::encrypt_data(&email, &dek_data.dek) },
-                None,
-            )
-            .await
-        {
-            Ok(Some(user)) => user,
-            Ok(None) => {
+
+        user.failed_login_attempts += 1;
+        user.updated_at = Some(DateTime::now());
+
+        // check if the failed login attempts is greater than 5 then add_block_user_until 180 seconds and if it is 10 then for 10 minutes if its 15 then 1hr
+        let block_for_millis = match user.failed_login_attempts {
+            5 => Some(180000),
+            10 => Some(600000),
+            15 => Some(3600000),
+            _ => None,
+        };
+
+        if let Some(block_for_millis) = block_for_millis {
+            let blocked_until = DateTime::now().timestamp_millis() + block_for_millis;
+            user.blocked_until = Some(DateTime::from_millis(blocked_until));
+        }
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => {}
+            Ok(false) => {
+                // send back a 404 to
                 return Err(Error::UserNotFound {
                     message: "User not found".to_string(),
                 });
             }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to get User".to_string(),
+            Err(e) => return Err(e),
+        }
+
+        if block_for_millis.is_some() {
+            let user = user.decrypt(&dek_data.dek);
+            // send a email to the user to notify multiple login attempts detected
+            Email::new(
+                &user.name,
+                &user.email,
+                &"Multiple login Attempts detected",
+                &("We have detected an multiple unauthorized login attempt associated with your account. For your security, we have taken action to protect your account.
+
+                    If you attempted to log in, please disregard this message. However, if you did not attempt to log in, we recommend taking the following steps:
+
+                    Immediately change your password to a strong, unique one.
+                    Review your account activity for any suspicious activity.
+                    If you have any concerns or questions, please don't hesitate to contact our support team.
+
+                    Stay safe and secure,
+                    FlexAuth Team"),
+            ).send().await;
+        }
+
+        Ok(user.failed_login_attempts)
+    }
+    pub async fn reset_failed_login_attempt(store: &dyn Store, email: &str) -> Result<String> {
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
+
+        user.failed_login_attempts = 0;
+        user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&user, dek_data.version).await {
+            Ok(true) => return Ok("Failed login attempts reset".to_string()),
+            Ok(false) => {
+                // send back a 404 to
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
                 });
             }
+            Err(e) => return Err(e),
+        }
+    }
+    pub async fn change_password(store: &dyn Store, email: &str, old_password: &str, new_password: &str) -> Result<String> {
+        // get user
+        let (mut user, dek_data) = match User::get_stored(store, email).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
         };
 
         let decrypted_user = user.decrypt(&dek_data.dek);
-        
+
         if !Password::verify_hash(&old_password, &decrypted_user.password) {
             return Err(Error::InvalidPassword {
                 message: "New password cannot be the same as the old password".to_string(),
@@ -598,39 +431,23 @@ impl User {
         // hash and salt the new password
         let hashed_and_salted_pass = Password::salt_and_hash(&new_password);
         // encrypt the new password
-        let encrypted_password = Encryption::encrypt_data(&hashed_and_salted_pass, &dek_data.dek);
+        user.password = Encryption::encrypt_data(&hashed_and_salted_pass, &dek_data.dek);
+        user.updated_at = Some(DateTime::now());
 
         // update the user with the new password
-        match collection
-            .find_one_and_update(
-                doc! { "email": Encryption::encrypt_data(&email, &dek_data.dek) },
-                doc! {
-                    "$set": {
-                        "password": encrypted_password,
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-        {
-            Ok(_) => { return Ok("Password updated successfully".to_string())}
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to update User".to_string(),
-                });
-            }
+        match store.update_user(&user, dek_data.version).await {
+            Ok(_) => return Ok("Password updated successfully".to_string()),
+            Err(e) => return Err(e),
         }
     }
-    pub async fn forget_password_request(mongo_client: &Client, email: &str) -> Result<String> {
+    pub async fn forget_password_request(store: &dyn Store, email: &str) -> Result<String> {
         // check if the user exists
-        let db = mongo_client.database("auth");
-        let dek_data = match Dek::get(&mongo_client, &email).await {
+        let dek_data = match Dek::get(store, &email).await {
             Ok(dek) => dek,
             Err(e) => return Err(e),
         };
 
-        let user = match User::get_from_email(&mongo_client, &email).await {
+        let user = match User::get_from_email(store, &email).await {
             Ok(user) => user,
             Err(e) => return Err(e)
         };
@@ -638,9 +455,10 @@ impl User {
         // get a time 10 minutes from now
         let ten_minutes_from_now_millis = DateTime::now().timestamp_millis() + 600000;
         let ten_minutes_from_now = DateTime::from_millis(ten_minutes_from_now_millis);
-        // create a new doc in forget_password_requests collection
+        // create a new forget password request
         let new_doc = ForgetPasswordRequest {
             _id: ObjectId::new(),
+            uid: dek_data.uid.clone(),
             req_id: uuid::Uuid::new().to_string(),
             email: Encryption::encrypt_data(&email, &dek_data.dek),
             is_used: false,
@@ -649,10 +467,10 @@ impl User {
             updated_at: DateTime::now(),
         };
 
-        db.collection("forget_password_requests")
-            .insert_one(new_doc.clone(), None)
-            .await
-            .unwrap();
+        match store.insert_forget_password_request(&new_doc, dek_data.version).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
 
         // send a email to the user with the link having id of the new doc
         Email::new(
@@ -664,29 +482,18 @@ impl User {
 
         Ok("Forget password request sent to email successfully".to_string())
     }
-    pub async fn forget_password_reset(mongo_client: &Client,req_id: &str, email: &str,new_password: &str) -> Result<String> {
-        let db = mongo_client.database("auth");
-        let user_collection: Collection<User> = db.collection("users");
-        let forget_password_requests_collection: Collection<ForgetPasswordRequest> =
-            db.collection("forget_password_requests");
-
-        // find the dek with the email
-        let dek_data = match Dek::get(&mongo_client, &email).await {
-            Ok(dek) => dek,
-            Err(e) => {
-                return Err(e);
+    pub async fn forget_password_reset(store: &dyn Store, req_id: &str, email: &str, new_password: &str) -> Result<String> {
+        // check if forget password request exists
+        let mut forget_password_request = match store.get_forget_password_request(&req_id).await {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                return Err(Error::UserNotFound {
+                    message: "Forget password request not found. Please request a new link.".to_string(),
+                });
             }
+            Err(e) => return Err(e),
         };
 
-        // check if forget password request exists
-        let forget_password_request = forget_password_requests_collection
-            .find_one(doc! { "req_id": &req_id }, None)
-            .await
-            .unwrap()
-            .unwrap();
-
-        println!("Forget Password Request {:?}", forget_password_request);
-
         if forget_password_request.is_used {
             return Err(Error::ResetPasswordLinkExpired {
                 message: "The link has already been used. Please request a new link.".to_string(),
@@ -708,49 +515,38 @@ impl User {
         }
 
         // check if the user exists
-        let user = match User::get_from_email(&mongo_client, &email).await {
-            Ok(user) => user,
+        let (mut stored_user, dek_data) = match User::get_stored(store, &email).await {
+            Ok(data) => data,
             Err(e) => {
                 return Err(e);
             }
         };
+        let user = stored_user.decrypt(&dek_data.dek);
 
         // hash and salt the new password
         let hashed_and_salted_pass = Password::salt_and_hash(&new_password);
         // encrypt the new password
-        let encrypted_password = Encryption::encrypt_data(&hashed_and_salted_pass, &dek_data.dek);
+        stored_user.password = Encryption::encrypt_data(&hashed_and_salted_pass, &dek_data.dek);
+        stored_user.has_password = true;
+        stored_user.updated_at = Some(DateTime::now());
 
         // update the user with the new password
-        user_collection
-            .find_one_and_update(
-                doc! { "email": Encryption::encrypt_data(&email, &dek_data.dek) },
-                doc! {
-                    "$set": {
-                        "password": encrypted_password,
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        match store.update_user(&stored_user, dek_data.version).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
 
         // update the forget password request as used
-        forget_password_requests_collection
-            .find_one_and_update(
-                doc! { "req_id": &req_id },
-                doc! {
-                    "$set": {
-                        "is_used": true,
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-            .unwrap();
-        
-        let block_req_id = match User::block_request(&mongo_client, &email, &user.uid).await {
+        forget_password_request.is_used = true;
+        forget_password_request.updated_at = DateTime::now();
+        // the requests from before they recorded whose they are
+        forget_password_request.uid = stored_user.uid.clone();
+        match store.update_forget_password_request(&forget_password_request, dek_data.version).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let block_req_id = match User::block_request(store, &email, &user.uid).await {
             Ok(req_id) => req_id,
             Err(e) => {
                 return Err(e);
@@ -767,12 +563,9 @@ impl User {
 
         Ok("Password updated successfully".to_string())
     }
-    pub async fn verify_email_request(mongo_client: &Client, email: &str) -> Result<EmailVerificationRequest> {
-        // make a new request in the email_verification_requests collection
-        let db = mongo_client.database("auth");
-        let collection: Collection<EmailVerificationRequest> = db.collection("email_verification_requests");
-
-        let dek_data = match Dek::get(&mongo_client, email).await {
+    pub async fn verify_email_request(store: &dyn Store, email: &str) -> Result<EmailVerificationRequest> {
+        // make a new email verification request
+        let dek_data = match Dek::get(store, email).await {
             Ok(dek) => dek,
             Err(e) => {
                 return Err(e);
@@ -785,7 +578,7 @@ impl User {
 
         let new_doc = EmailVerificationRequest {
             _id: ObjectId::new(),
-            uid: dek_data.uid,
+            uid: dek_data.uid.clone(),
             req_id: uuid::Uuid::new().to_string(),
             email: Encryption::encrypt_data(&email, &dek_data.dek),
             // expires in 24 hours
@@ -794,7 +587,10 @@ impl User {
             updated_at: Some(DateTime::now()),
         };
 
-        collection.insert_one(&new_doc, None).await.unwrap();
+        match store.insert_email_verification_request(&new_doc, dek_data.version).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
 
         // send a email to the user with the link having id of the new doc
         Email::new(
@@ -806,21 +602,16 @@ impl User {
 
         Ok(new_doc)
     }
-    pub async fn verify_email(mongo_client: &Client, req_id: &str) -> Result<String> {
+    pub async fn verify_email(store: &dyn Store, req_id: &str) -> Result<String> {
         // check if the email_verification_request exists
-        let db = mongo_client.database("auth");
-        let collection: Collection<EmailVerificationRequest> = db.collection("email_verification_requests");
-
-        let email_verification_request = match collection
-            .find_one(doc! { "req_id": req_id }, None)
-            .await
-            .unwrap() {
-            Some(data) => data,
-            None => {
+        let email_verification_request = match store.get_email_verification_request(req_id).await {
+            Ok(Some(data)) => data,
+            Ok(None) => {
                 return Err(Error::UserNotFound {
                     message: "Email verification request not found. Please request a new link.".to_string(),
                 });
             }
+            Err(e) => return Err(e),
         };
 
         // check if the request exists
@@ -837,34 +628,24 @@ impl User {
         }
 
         // update the user with verified email
-        let user_collection: Collection<User> = db.collection("users");
-
-        user_collection
-            .find_one_and_update(
-                doc! { "uid": &email_verification_request.uid },
-                doc! {
-                    "$set": {
-                        "email_verified": true,
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (mut user, dek_data) = match User::get_stored(store, &email_verification_request.uid).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
 
-        // delete the email_verification_request
-        collection
-            .delete_one(doc! { "req_id": req_id }, None)
-            .await
-            .unwrap();
+        user.email_verified = true;
+        user.updated_at = Some(DateTime::now());
 
-        let dek_data = match Dek::get(&mongo_client, &email_verification_request.uid).await {
-            Ok(dek) => dek,
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        match store.update_user(&user, dek_data.version).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        // delete the email_verification_request
+        match store.delete_email_verification_request(req_id).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
 
         let decrypted_email = Encryption::decrypt_data(&email_verification_request.email, &dek_data.dek);
 
@@ -877,11 +658,8 @@ impl User {
         ).send().await;
         Ok(req_id.to_string())
     }
-    pub async fn block_request(mongo_client: &Client, email: &str, uid: &str) -> Result<String> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<UserBlockRequest> = db.collection("users_block_requests");
-
-        let dek_data = match Dek::get(&mongo_client, uid).await {
+    pub async fn block_request(store: &dyn Store, email: &str, uid: &str) -> Result<String> {
+        let dek_data = match Dek::get(store, uid).await {
             Ok(dek) => dek,
             Err(e) => {
                 return Err(e);
@@ -904,33 +682,21 @@ impl User {
             updated_at: Some(DateTime::now()),
         };
 
-        match collection.insert_one(&block_request, None).await {
-            Ok(_) => {
-                Ok(req_id)
-            }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to insert block request".to_string(),
-                });
-            }
+        match store.insert_block_request(&block_request, dek_data.version).await {
+            Ok(_) => Ok(req_id),
+            Err(e) => return Err(e),
         }
     }
-    pub async fn block(mongo_client: &Client, req_id: &str) -> Result<String> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-        let collection_block_requests: Collection<UserBlockRequest> = db.collection("users_block_requests");
-
+    pub async fn block(store: &dyn Store, req_id: &str) -> Result<String> {
         // check if the block request exists
-        let block_request = match collection_block_requests
-            .find_one(doc! { "req_id": req_id }, None)
-            .await
-            .unwrap() {
-            Some(data) => data,
-            None => {
+        let mut block_request = match store.get_block_request(req_id).await {
+            Ok(Some(data)) => data,
+            Ok(None) => {
                 return Err(Error::UserNotFound {
                     message: "Block request not found. Please request a new link.".to_string(),
                 });
             }
+            Err(e) => return Err(e),
         };
 
         // check if the request is expired
@@ -947,130 +713,94 @@ impl User {
             });
         }
 
-        // find the user in the users collection using the uid
-        match collection
-            .update_one(
-                doc! {
-                    "uid": &block_request.uid,
-                },
-                doc! {
-                    "$set": {
-                        "is_active": false,
-                        "updated_at": DateTime::now(),
-                    }
-                },
-                None,
-            )
-            .await
-        {
-            Ok(cursor) => {
-                let modified_count = cursor.modified_count;
-
-                // Return Error if User is not there
-                if modified_count == 0 {
-                    // send back a 404 to
-                    return Err(Error::UserNotFound {
-                        message: "User not found".to_string(),
-                    });
-                }
+        // find the user using the uid
+        let (mut stored_user, dek_data) = match User::get_stored(store, &block_request.uid).await {
+            Ok(data) => data,
+            Err(e) => return Err(e),
+        };
 
-                // Send a email to the user that the account has been blocked
-                let user = match User::get_from_uid(&mongo_client, &block_request.uid).await {
-                    Ok(user) => user,
-                    Err(e) => {
-                        return Err(e);
-                    }
-                };
-
-                Email::new(
-                    &user.name,
-                    &user.email,
-                    &"Account Blocked",
-                    &("Your account has been blocked. If it was not you please take action as soon as possible. If you want to re-activate your account then please contact us by simply replying to this email."),
-                ).send().await;
-                return Ok("User blocked successfully".to_string());
-            }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to update User".to_string(),
-                })
+        stored_user.is_active = false;
+        stored_user.updated_at = Some(DateTime::now());
+
+        match store.update_user(&stored_user, dek_data.version).await {
+            Ok(true) => {}
+            Ok(false) => {
+                // send back a 404 to
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                });
             }
+            Err(e) => return Err(e),
         }
-    }
-    pub async fn delete(mongo_client: &Client, email: &str) -> Result<String> {
-        let db = mongo_client.database("auth");
-        let collection: Collection<User> = db.collection("users");
-        let collection_dek: Collection<Dek> = db.collection("deks");
 
-        let dek_data = match Dek::get(&mongo_client, email).await {
+        // mark the block request as used
+        block_request.is_used = true;
+        block_request.updated_at = Some(DateTime::now());
+        match store.update_block_request(&block_request, dek_data.version).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        // Send a email to the user that the account has been blocked
+        let user = stored_user.decrypt(&dek_data.dek);
+
+        Email::new(
+            &user.name,
+            &user.email,
+            &"Account Blocked",
+            &("Your account has been blocked. If it was not you please take action as soon as possible. If you want to re-activate your account then please contact us by simply replying to this email."),
+        ).send().await;
+        return Ok("User blocked successfully".to_string());
+    }
+    pub async fn delete(store: &dyn Store, email: &str) -> Result<String> {
+        let dek_data = match Dek::get(store, email).await {
             Ok(dek) => dek,
             Err(e) => {
                 return Err(e);
             }
         };
 
-        match collection
-            .delete_one(
-                doc! {
-                    "uid": &dek_data.uid,
-                },
-                None,
-            )
-            .await
-        {
-            Ok(cursor) => {
-                if cursor.deleted_count == 0 {
-                    // send back a 404 to
-                    return Err(Error::UserNotFound {
-                        message: "User not found".to_string(),
-                    });
-                }
-
-                let kek = env::var("SERVER_KEK").expect("Server Kek must be set.");
-
-                // delete the dek from the deks collection
-                match collection_dek
-                    .delete_one(
-                        doc! {
-                            "uid": Encryption::encrypt_data(&dek_data.uid, &kek),
-                        },
-                        None,
-                    )
-                    .await
-                {
-                    Ok(cursor_dek) => {
-                        // delete all sessions associated with the user
-                        let session_collection: Collection<Session> = db.collection("sessions");
-                        session_collection
-                            .delete_many(
-                                doc! {
-                                    "uid": Encryption::encrypt_data(&dek_data.uid, &kek),
-                                },
-                                None,
-                            )
-                            .await
-                            .unwrap();
-                        
-                        if cursor_dek.deleted_count == 0 {
-                            // send back a 404 to
-                            return Err(Error::UserNotFound {
-                                message: "DEK not found".to_string(),
-                            });
-                        }
-                        Ok(dek_data.uid)
-                    }
-                    Err(_) => {
-                        return Err(Error::ServerError {
-                            message: "Failed to delete DEK".to_string(),
-                        });
-                    }
-                }
-            }
-            Err(_) => {
-                return Err(Error::ServerError {
-                    message: "Failed to delete User".to_string(),
-                })
+        match store.delete_user(&dek_data.uid).await {
+            Ok(true) => {}
+            Ok(false) => {
+                // send back a 404 to
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                });
             }
+            Err(e) => return Err(e),
+        }
+
+        // delete the dek of the user
+        let dek_deleted = match store.delete_dek(&dek_data.uid).await {
+            Ok(deleted) => deleted,
+            Err(e) => return Err(e),
+        };
+
+        // delete all sessions associated with the user
+        match store.delete_sessions_by_uid(&dek_data.uid).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        // the passkeys can't be decrypted without the dek anyway
+        match store.delete_webauthn_credentials_by_uid(&dek_data.uid).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        // and so can't the linked provider accounts
+        match store.delete_user_identities_by_uid(&dek_data.uid).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        if !dek_deleted {
+            // send back a 404 to
+            return Err(Error::UserNotFound {
+                message: "DEK not found".to_string(),
+            });
         }
+        Ok(dek_data.uid)
     }
 }
@@ -0,0 +1,151 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{dek::Dek, user::User},
+    errors::{Error, Result},
+    models::federation_model::{LoginMethodsResponse, UserIdentityResponse},
+    traits::store::Store,
+    utils::{encryption_utils::Encryption, oauth_utils::hash_token},
+};
+
+// Links an account of an upstream identity provider to a user. The subject is
+// encrypted with the DEK of the user, so accounts are looked up by its hash.
+// A user has at most one account per provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserIdentity {
+    pub _id: ObjectId,
+    pub uid: String,
+    pub provider_id: String,
+    pub subject_hash: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime,
+}
+
+// subjects are only unique per provider
+pub fn subject_hash(provider_id: &str, subject: &str) -> String {
+    hash_token(&format!("{}:{}", provider_id, subject))
+}
+
+impl UserIdentity {
+    pub async fn link(
+        store: &dyn Store,
+        uid: &str,
+        provider_id: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<Self> {
+        match Self::find_uid(store, provider_id, subject).await {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                return Err(Error::IdentityAlreadyLinked {
+                    message: "This account is already linked to a user".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        }
+
+        let identities = match store.get_user_identities(uid).await {
+            Ok(identities) => identities,
+            Err(e) => return Err(e),
+        };
+        if identities.iter().any(|i| i.provider_id == provider_id) {
+            return Err(Error::IdentityAlreadyLinked {
+                message: "Another account of this provider is already linked".to_string(),
+            });
+        }
+
+        let dek_data = match Dek::get(store, uid).await {
+            Ok(dek) => dek,
+            Err(e) => return Err(e),
+        };
+
+        let identity = Self {
+            _id: ObjectId::new(),
+            uid: uid.to_string(),
+            provider_id: provider_id.to_string(),
+            subject_hash: subject_hash(provider_id, subject),
+            subject: Encryption::encrypt_data(subject, &dek_data.dek),
+            email: email.map(|email| Encryption::encrypt_data(email, &dek_data.dek)),
+            created_at: DateTime::now(),
+        };
+        match store.insert_user_identity(&identity, dek_data.version).await {
+            Ok(_) => Ok(identity),
+            Err(e) => Err(e),
+        }
+    }
+
+    // the uid of the user the provider account is linked to, if any
+    pub async fn find_uid(store: &dyn Store, provider_id: &str, subject: &str) -> Result<Option<String>> {
+        match store
+            .get_user_identity(&subject_hash(provider_id, subject))
+            .await
+        {
+            Ok(identity) => Ok(identity.map(|i| i.uid)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // everything the user can sign in with besides the email
+    pub async fn login_methods(store: &dyn Store, uid: &str) -> Result<LoginMethodsResponse> {
+        let user = match User::get_from_uid(store, uid).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+        let dek_data = match Dek::get(store, uid).await {
+            Ok(dek) => dek,
+            Err(e) => return Err(e),
+        };
+        let passkeys = match store.get_webauthn_credentials(uid).await {
+            Ok(credentials) => credentials.len(),
+            Err(e) => return Err(e),
+        };
+        let mut identities = match store.get_user_identities(uid).await {
+            Ok(identities) => identities,
+            Err(e) => return Err(e),
+        };
+        identities.sort_by_key(|i| i.created_at);
+
+        Ok(LoginMethodsResponse {
+            password: user.has_password,
+            passkeys,
+            identities: identities
+                .iter()
+                .map(|i| UserIdentityResponse {
+                    provider_id: i.provider_id.clone(),
+                    subject: Encryption::decrypt_data(&i.subject, &dek_data.dek),
+                    email: i
+                        .email
+                        .as_ref()
+                        .map(|email| Encryption::decrypt_data(email, &dek_data.dek)),
+                    created_at: i.created_at,
+                })
+                .collect(),
+        })
+    }
+
+    // the user must keep at least one way to sign in
+    pub async fn unlink(store: &dyn Store, uid: &str, provider_id: &str) -> Result<()> {
+        match store.unlink_user_identity(uid, provider_id).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => return Err(e),
+        }
+
+        // nothing was deleted, either there is nothing to delete or it is all that is left
+        let identities = match store.get_user_identities(uid).await {
+            Ok(identities) => identities,
+            Err(e) => return Err(e),
+        };
+        if identities.iter().any(|i| i.provider_id == provider_id) {
+            Err(Error::LastLoginMethod {
+                message: "This is the only way left to sign in. Set a password or add a passkey first.".to_string(),
+            })
+        } else {
+            Err(Error::IdentityNotFound {
+                message: "No account of this provider is linked".to_string(),
+            })
+        }
+    }
+}
@@ -0,0 +1,684 @@
+use bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{dek::Dek, mfa::Mfa, user::User},
+    errors::{Error, Result},
+    models::webauthn_model::{
+        AssertionCredential, AuthenticatorSelection, CreationOptionsResponse,
+        CredentialDescriptor, CredentialParameter, PublicKeyCredentialCreationOptions,
+        PublicKeyCredentialRequestOptions, RegistrationCredential, RelyingParty,
+        RequestOptionsResponse, WebauthnCredentialResponse, WebauthnUser,
+    },
+    traits::{decryption::Decrypt, encryption::Encrypt, store::Store},
+    utils::{
+        encryption_utils::Encryption,
+        oauth_utils::{generate_token, hash_token},
+        webauthn_utils::{
+            decode_base64url, encode_base64url, parse_attestation_object,
+            parse_authenticator_data, parse_client_data, rp_id_hash, verify_signature, ClientData,
+            ALG_ES256, ALG_RS256, FLAG_USER_PRESENT, FLAG_USER_VERIFIED,
+        },
+    },
+};
+
+// the ceremony has to be finished within this time
+const CHALLENGE_TTL_SECS: i64 = 300;
+const RP_NAME: &str = "FlexAuth";
+
+const PURPOSE_REGISTRATION: &str = "registration";
+const PURPOSE_AUTHENTICATION: &str = "authentication";
+
+// A passkey or security key of a user. Everything but the counters is
+// encrypted with the DEK of the user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebauthnCredential {
+    pub _id: ObjectId,
+    pub uid: String,
+    // base64url, as the browser reports it
+    pub credential_id: String,
+    // base64url of the DER public key
+    pub public_key: String,
+    pub alg: i64,
+    // signature counter of the authenticator, it only goes up unless the key was cloned
+    pub sign_count: i64,
+    pub name: String,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+impl WebauthnCredential {
+    pub fn to_response(&self) -> WebauthnCredentialResponse {
+        WebauthnCredentialResponse {
+            credential_id: self.credential_id.clone(),
+            name: self.name.clone(),
+            sign_count: self.sign_count,
+            created_at: self.created_at,
+            last_used_at: self.last_used_at,
+        }
+    }
+}
+
+// A challenge handed out for a ceremony. Only its hash is stored and it can
+// only be used once.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebauthnChallenge {
+    pub _id: ObjectId,
+    pub challenge_hash: String,
+    // None for a passwordless sign in, the user is only known from the response
+    pub uid: Option<String>,
+    pub purpose: String,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+fn server_url() -> String {
+    dotenv::var("SERVER_URL")
+        .unwrap_or_else(|_| "http://localhost:8080".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+// the domain passkeys are bound to, the host of SERVER_URL by default
+fn rp_id() -> String {
+    match dotenv::var("WEBAUTHN_RP_ID") {
+        Ok(rp_id) => rp_id,
+        Err(_) => {
+            let url = server_url();
+            let host = url.split("://").nth(1).unwrap_or(&url);
+            let host = host.split('/').next().unwrap_or(host);
+            host.split(':').next().unwrap_or(host).to_string()
+        }
+    }
+}
+
+// the pages the ceremonies may run on, comma separated in WEBAUTHN_ORIGINS
+fn allowed_origins() -> Vec<String> {
+    match dotenv::var("WEBAUTHN_ORIGINS") {
+        Ok(origins) => origins
+            .split(',')
+            .map(|o| o.trim().trim_end_matches('/').to_string())
+            .filter(|o| !o.is_empty())
+            .collect(),
+        Err(_) => vec![server_url()],
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidWebauthnResponse {
+        message: message.to_string(),
+    }
+}
+
+pub struct Webauthn;
+
+impl Webauthn {
+    async fn issue_challenge(store: &dyn Store, uid: Option<&str>, purpose: &str) -> Result<String> {
+        let challenge = generate_token(32);
+        let record = WebauthnChallenge {
+            _id: ObjectId::new(),
+            challenge_hash: hash_token(&challenge),
+            uid: uid.map(|uid| uid.to_string()),
+            purpose: purpose.to_string(),
+            expires_at: DateTime::from_millis(
+                DateTime::now().timestamp_millis() + CHALLENGE_TTL_SECS * 1000,
+            ),
+            created_at: DateTime::now(),
+        };
+
+        match store.insert_webauthn_challenge(&record).await {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        // nothing else cleans up after the abandoned ones
+        match store.delete_expired_webauthn_challenges().await {
+            Ok(_) => Ok(challenge),
+            Err(e) => Err(e),
+        }
+    }
+
+    // checks the client data and uses up the challenge it was signed for
+    async fn take_challenge(
+        store: &dyn Store,
+        client_data: &ClientData,
+        ceremony: &str,
+        purpose: &str,
+    ) -> Result<WebauthnChallenge> {
+        if client_data.type_ != ceremony {
+            return Err(invalid("Wrong ceremony type"));
+        }
+        if !allowed_origins().contains(&client_data.origin) {
+            return Err(invalid("Origin is not allowed"));
+        }
+
+        let challenge = match store
+            .take_webauthn_challenge(&hash_token(&client_data.challenge))
+            .await
+        {
+            Ok(Some(challenge)) => challenge,
+            Ok(None) => return Err(invalid("Unknown challenge")),
+            Err(e) => return Err(e),
+        };
+        if challenge.expires_at.timestamp_millis() < DateTime::now().timestamp_millis() {
+            return Err(invalid("Challenge expired"));
+        }
+        if challenge.purpose != purpose {
+            return Err(invalid("Challenge was issued for another ceremony"));
+        }
+
+        Ok(challenge)
+    }
+
+    // the decrypted credentials of the user
+    async fn get_credentials(store: &dyn Store, dek: &str, uid: &str) -> Result<Vec<WebauthnCredential>> {
+        match store.get_webauthn_credentials(uid).await {
+            Ok(credentials) => Ok(credentials.iter().map(|c| c.decrypt(dek)).collect()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // the stored (encrypted) credential of the user with the given plain credential_id,
+    // the store matches credentials on what is stored
+    async fn get_stored(store: &dyn Store, dek: &str, uid: &str, credential_id: &str) -> Result<Option<WebauthnCredential>> {
+        match store.get_webauthn_credentials(uid).await {
+            Ok(credentials) => Ok(credentials
+                .into_iter()
+                .find(|c| Encryption::decrypt_data(&c.credential_id, dek) == credential_id)),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn has_credentials(store: &dyn Store, uid: &str) -> Result<bool> {
+        match store.get_webauthn_credentials(uid).await {
+            Ok(credentials) => Ok(!credentials.is_empty()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn registration_options(store: &dyn Store, email: &str) -> Result<CreationOptionsResponse> {
+        let user = match User::get_from_email(store, email).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+        let dek_data = match Dek::get(store, &user.uid).await {
+            Ok(dek_data) => dek_data,
+            Err(e) => return Err(e),
+        };
+        let credentials = match Self::get_credentials(store, &dek_data.dek, &user.uid).await {
+            Ok(credentials) => credentials,
+            Err(e) => return Err(e),
+        };
+
+        let challenge = match Self::issue_challenge(store, Some(&user.uid), PURPOSE_REGISTRATION).await {
+            Ok(challenge) => challenge,
+            Err(e) => return Err(e),
+        };
+
+        Ok(CreationOptionsResponse {
+            public_key: PublicKeyCredentialCreationOptions {
+                rp: RelyingParty {
+                    id: rp_id(),
+                    name: RP_NAME.to_string(),
+                },
+                user: WebauthnUser {
+                    // comes back as the user handle on a passwordless sign in
+                    id: encode_base64url(user.uid.as_bytes()),
+                    name: user.email,
+                    display_name: user.name,
+                },
+                challenge,
+                pub_key_cred_params: [ALG_ES256, ALG_RS256]
+                    .iter()
+                    .map(|alg| CredentialParameter {
+                        type_: "public-key".to_string(),
+                        alg: *alg,
+                    })
+                    .collect(),
+                timeout: CHALLENGE_TTL_SECS * 1000,
+                attestation: "none".to_string(),
+                // don't register the same authenticator twice
+                exclude_credentials: credentials
+                    .iter()
+                    .map(|c| CredentialDescriptor {
+                        type_: "public-key".to_string(),
+                        id: c.credential_id.clone(),
+                    })
+                    .collect(),
+                authenticator_selection: AuthenticatorSelection {
+                    resident_key: "preferred".to_string(),
+                    user_verification: "preferred".to_string(),
+                },
+            },
+        })
+    }
+
+    // Returns the new credential, with the recovery codes of the user if it is
+    // their first second factor.
+    pub async fn register(
+        store: &dyn Store,
+        email: &str,
+        name: &str,
+        credential: &RegistrationCredential,
+    ) -> Result<(WebauthnCredential, Option<Vec<String>>)> {
+        let user = match User::get_from_email(store, email).await {
+            Ok(user) => user,
+            Err(e) => return Err(e),
+        };
+        let dek_data = match Dek::get(store, &user.uid).await {
+            Ok(dek_data) => dek_data,
+            Err(e) => return Err(e),
+        };
+
+        let client_data_json = match decode_base64url(&credential.response.client_data_json) {
+            Some(client_data_json) => client_data_json,
+            None => return Err(invalid("Invalid clientDataJSON")),
+        };
+        let client_data = match parse_client_data(&client_data_json) {
+            Some(client_data) => client_data,
+            None => return Err(invalid("Invalid clientDataJSON")),
+        };
+        let challenge = match Self::take_challenge(store, &client_data, "webauthn.create", PURPOSE_REGISTRATION).await {
+            Ok(challenge) => challenge,
+            Err(e) => return Err(e),
+        };
+        if challenge.uid.as_deref() != Some(user.uid.as_str()) {
+            return Err(invalid("Challenge was issued to another user"));
+        }
+
+        let (fmt, auth_data) = match decode_base64url(&credential.response.attestation_object)
+            .and_then(|attestation_object| parse_attestation_object(&attestation_object))
+        {
+            Some(attestation) => attestation,
+            None => return Err(invalid("Invalid attestationObject")),
+        };
+        // we ask for no attestation, so the statement is never checked
+        if fmt != "none" {
+            return Err(invalid("Only the none attestation format is supported"));
+        }
+        let auth_data = match parse_authenticator_data(&auth_data) {
+            Some(auth_data) => auth_data,
+            None => return Err(invalid("Invalid authenticator data")),
+        };
+        if auth_data.rp_id_hash != rp_id_hash(&rp_id()) {
+            return Err(invalid("Credential was created for another relying party"));
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            return Err(invalid("User was not present"));
+        }
+        let attested = match auth_data.attested_credential {
+            Some(attested) => attested,
+            None => return Err(invalid("No credential in the authenticator data")),
+        };
+
+        let credential_id = encode_base64url(&attested.credential_id);
+        if credential_id != credential.id.trim_end_matches('=') {
+            return Err(invalid("Credential id does not match"));
+        }
+        let existing = match Self::get_credentials(store, &dek_data.dek, &user.uid).await {
+            Ok(credentials) => credentials,
+            Err(e) => return Err(e),
+        };
+        if existing.iter().any(|c| c.credential_id == credential_id) {
+            return Err(invalid("Credential is already registered"));
+        }
+
+        let webauthn_credential = WebauthnCredential {
+            _id: ObjectId::new(),
+            uid: user.uid.clone(),
+            credential_id,
+            public_key: encode_base64url(&attested.public_key),
+            alg: attested.alg,
+            sign_count: auth_data.sign_count as i64,
+            name: if name.trim().is_empty() {
+                "Passkey".to_string()
+            } else {
+                name.trim().to_string()
+            },
+            created_at: DateTime::now(),
+            last_used_at: None,
+        };
+
+        match store
+            .insert_webauthn_credential(&webauthn_credential.encrypt(&dek_data.dek), dek_data.version)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        match Mfa::passkey_registered(store, &user.uid).await {
+            Ok(recovery_codes) => Ok((webauthn_credential, recovery_codes)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Options for navigator.credentials.get(). With a uid only the credentials
+    // of that user are allowed (second factor), without one the browser offers
+    // every passkey it has for us (passwordless sign in).
+    pub async fn authentication_options(store: &dyn Store, uid: Option<&str>) -> Result<RequestOptionsResponse> {
+        let allow_credentials = match uid {
+            Some(uid) => {
+                let dek_data = match Dek::get(store, uid).await {
+                    Ok(dek_data) => dek_data,
+                    Err(e) => return Err(e),
+                };
+                match Self::get_credentials(store, &dek_data.dek, uid).await {
+                    Ok(credentials) => credentials
+                        .iter()
+                        .map(|c| CredentialDescriptor {
+                            type_: "public-key".to_string(),
+                            id: c.credential_id.clone(),
+                        })
+                        .collect(),
+                    Err(e) => return Err(e),
+                }
+            }
+            None => Vec::new(),
+        };
+
+        let challenge = match Self::issue_challenge(store, uid, PURPOSE_AUTHENTICATION).await {
+            Ok(challenge) => challenge,
+            Err(e) => return Err(e),
+        };
+
+        Ok(RequestOptionsResponse {
+            public_key: PublicKeyCredentialRequestOptions {
+                challenge,
+                rp_id: rp_id(),
+                allow_credentials,
+                timeout: CHALLENGE_TTL_SECS * 1000,
+                // a passkey alone replaces the password, so it has to verify the user
+                user_verification: if uid.is_some() { "preferred" } else { "required" }.to_string(),
+            },
+        })
+    }
+
+    // Checks an assertion and returns the uid of the user it belongs to. Pass
+    // the uid when the user is already known from the first factor.
+    pub async fn verify_assertion(
+        store: &dyn Store,
+        uid: Option<&str>,
+        credential: &AssertionCredential,
+    ) -> Result<String> {
+        let client_data_json = match decode_base64url(&credential.response.client_data_json) {
+            Some(client_data_json) => client_data_json,
+            None => return Err(invalid("Invalid clientDataJSON")),
+        };
+        let client_data = match parse_client_data(&client_data_json) {
+            Some(client_data) => client_data,
+            None => return Err(invalid("Invalid clientDataJSON")),
+        };
+        let challenge = match Self::take_challenge(store, &client_data, "webauthn.get", PURPOSE_AUTHENTICATION).await {
+            Ok(challenge) => challenge,
+            Err(e) => return Err(e),
+        };
+        if challenge.uid.as_deref() != uid {
+            return Err(invalid("Challenge was issued to another user"));
+        }
+
+        let uid = match uid {
+            Some(uid) => uid.to_string(),
+            None => match credential
+                .response
+                .user_handle
+                .as_deref()
+                .and_then(decode_base64url)
+                .and_then(|user_handle| String::from_utf8(user_handle).ok())
+            {
+                Some(uid) => uid,
+                None => return Err(invalid("Missing user handle")),
+            },
+        };
+        // an unknown user looks the same as an unknown credential
+        let dek_data = match Dek::get(store, &uid).await {
+            Ok(dek_data) => dek_data,
+            Err(Error::ServerError { message }) => return Err(Error::ServerError { message }),
+            Err(_) => return Err(invalid("Unknown credential")),
+        };
+        let stored_credential =
+            match Self::get_stored(store, &dek_data.dek, &uid, credential.id.trim_end_matches('=')).await {
+                Ok(Some(stored_credential)) => stored_credential,
+                Ok(None) => return Err(invalid("Unknown credential")),
+                Err(e) => return Err(e),
+            };
+        let mut stored = stored_credential.decrypt(&dek_data.dek);
+
+        let auth_data_bytes = match decode_base64url(&credential.response.authenticator_data) {
+            Some(auth_data_bytes) => auth_data_bytes,
+            None => return Err(invalid("Invalid authenticator data")),
+        };
+        let auth_data = match parse_authenticator_data(&auth_data_bytes) {
+            Some(auth_data) => auth_data,
+            None => return Err(invalid("Invalid authenticator data")),
+        };
+        if auth_data.rp_id_hash != rp_id_hash(&rp_id()) {
+            return Err(invalid("Assertion was made for another relying party"));
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            return Err(invalid("User was not present"));
+        }
+        if challenge.uid.is_none() && auth_data.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(invalid("User was not verified"));
+        }
+
+        let public_key = match decode_base64url(&stored.public_key) {
+            Some(public_key) => public_key,
+            None => {
+                return Err(Error::ServerError {
+                    message: "Invalid stored public key".to_string(),
+                })
+            }
+        };
+        let signature = match decode_base64url(&credential.response.signature) {
+            Some(signature) => signature,
+            None => return Err(invalid("Invalid signature")),
+        };
+        if !verify_signature(&public_key, &auth_data_bytes, &client_data_json, &signature) {
+            return Err(invalid("Invalid signature"));
+        }
+
+        // authenticators without a counter always report 0
+        let sign_count = auth_data.sign_count as i64;
+        if (sign_count > 0 || stored.sign_count > 0) && sign_count <= stored.sign_count {
+            eprintln!(
+                ">> SECURITY EVENT: WebAuthn sign count did not increase for uid {}, the authenticator may be cloned",
+                uid
+            );
+            return Err(invalid("Sign count did not increase"));
+        }
+
+        stored.sign_count = sign_count;
+        stored.last_used_at = Some(DateTime::now());
+        let updated = WebauthnCredential {
+            credential_id: stored_credential.credential_id,
+            ..stored.encrypt(&dek_data.dek)
+        };
+        match store.update_webauthn_credential(&updated, dek_data.version).await {
+            Ok(_) => Ok(uid),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn list(store: &dyn Store, email: &str) -> Result<Vec<WebauthnCredentialResponse>> {
+        let dek_data = match Dek::get(store, email).await {
+            Ok(dek_data) => dek_data,
+            Err(e) => return Err(e),
+        };
+        match Self::get_credentials(store, &dek_data.dek, &dek_data.uid).await {
+            Ok(credentials) => {
+                let mut credentials: Vec<WebauthnCredentialResponse> =
+                    credentials.iter().map(|c| c.to_response()).collect();
+                credentials.sort_by_key(|c| c.created_at);
+                Ok(credentials)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn delete(store: &dyn Store, email: &str, credential_id: &str) -> Result<()> {
+        let dek_data = match Dek::get(store, email).await {
+            Ok(dek_data) => dek_data,
+            Err(e) => return Err(e),
+        };
+        let stored = match Self::get_stored(store, &dek_data.dek, &dek_data.uid, credential_id).await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => {
+                return Err(Error::WebauthnCredentialNotFound {
+                    message: "Credential not found".to_string(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        match store.delete_webauthn_credential(&dek_data.uid, &stored.credential_id).await {
+            Ok(true) => Mfa::passkey_deleted(store, &dek_data.uid).await,
+            Ok(false) => Err(Error::WebauthnCredentialNotFound {
+                message: "Credential not found".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::store::memory_store::MemoryStore;
+
+    // made by tests/fixtures/webauthn/generate.py for localhost
+    const REGISTRATION: &str = include_str!("../../tests/fixtures/webauthn/registration.json");
+    const ASSERTION: &str = include_str!("../../tests/fixtures/webauthn/assertion.json");
+
+    fn set_keys() {
+        env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+        env::set_var("EMAIL_INDEX_KEY", "abc");
+    }
+
+    fn registration() -> RegistrationCredential {
+        serde_json::from_str(REGISTRATION).unwrap()
+    }
+
+    fn assertion() -> AssertionCredential {
+        serde_json::from_str(ASSERTION).unwrap()
+    }
+
+    async fn add_user(store: &dyn Store) -> User {
+        set_keys();
+        let dek = Dek::generate();
+        let user = User::new("Ada", "ada@example.com", "user", "secret")
+            .encrypt_and_add(store, &dek)
+            .await
+            .unwrap();
+        Dek::new(&user.uid, &user.email, &dek)
+            .encrypt_and_add(store)
+            .await
+            .unwrap();
+        user
+    }
+
+    // hands out the challenge the client data of the fixture was made for
+    async fn issue_challenge(store: &dyn Store, uid: &str, client_data_json: &str, purpose: &str) {
+        let client_data = parse_client_data(&decode_base64url(client_data_json).unwrap()).unwrap();
+        store
+            .insert_webauthn_challenge(&WebauthnChallenge {
+                _id: ObjectId::new(),
+                challenge_hash: hash_token(&client_data.challenge),
+                uid: Some(uid.to_string()),
+                purpose: purpose.to_string(),
+                expires_at: DateTime::from_millis(DateTime::now().timestamp_millis() + 60 * 1000),
+                created_at: DateTime::now(),
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn register(store: &dyn Store, user: &User, credential: &RegistrationCredential) -> Result<WebauthnCredential> {
+        issue_challenge(store, &user.uid, &credential.response.client_data_json, PURPOSE_REGISTRATION).await;
+        Webauthn::register(store, &user.email, "YubiKey", credential)
+            .await
+            .map(|(credential, _)| credential)
+    }
+
+    async fn sign_in(store: &dyn Store, user: &User, credential: &AssertionCredential) -> Result<String> {
+        issue_challenge(store, &user.uid, &credential.response.client_data_json, PURPOSE_AUTHENTICATION).await;
+        Webauthn::verify_assertion(store, Some(&user.uid), credential).await
+    }
+
+    fn assert_invalid<T: std::fmt::Debug>(res: Result<T>, message: &str) {
+        match res {
+            Err(Error::InvalidWebauthnResponse { message: m }) => assert_eq!(m, message),
+            other => panic!("expected InvalidWebauthnResponse, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn registers_and_signs_in_with_a_passkey() {
+        let store = MemoryStore::new();
+        let user = add_user(&store).await;
+
+        let credential = register(&store, &user, &registration()).await.unwrap();
+        assert_eq!(credential.credential_id, registration().id);
+        assert_eq!(credential.alg, ALG_ES256);
+        assert_eq!(credential.sign_count, 0);
+
+        assert_eq!(sign_in(&store, &user, &assertion()).await.unwrap(), user.uid);
+        let stored = Webauthn::list(&store, &user.email).await.unwrap();
+        assert_eq!(stored[0].sign_count, 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_another_relying_party() {
+        let store = MemoryStore::new();
+        let user = add_user(&store).await;
+
+        // the rp id hash starts the authenticator data inside the attestation object
+        let mut credential = registration();
+        let mut attestation_object = decode_base64url(&credential.response.attestation_object).unwrap();
+        let (_, auth_data) = parse_attestation_object(&attestation_object).unwrap();
+        let start = attestation_object
+            .windows(auth_data.len())
+            .position(|window| window == auth_data)
+            .unwrap();
+        attestation_object[start] ^= 1;
+        credential.response.attestation_object = encode_base64url(&attestation_object);
+        assert_invalid(
+            register(&store, &user, &credential).await,
+            "Credential was created for another relying party",
+        );
+
+        register(&store, &user, &registration()).await.unwrap();
+        let mut credential = assertion();
+        let mut auth_data = decode_base64url(&credential.response.authenticator_data).unwrap();
+        auth_data[0] ^= 1;
+        credential.response.authenticator_data = encode_base64url(&auth_data);
+        assert_invalid(
+            sign_in(&store, &user, &credential).await,
+            "Assertion was made for another relying party",
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_a_bad_signature() {
+        let store = MemoryStore::new();
+        let user = add_user(&store).await;
+        register(&store, &user, &registration()).await.unwrap();
+
+        let mut credential = assertion();
+        let mut signature = decode_base64url(&credential.response.signature).unwrap();
+        let last = signature.len() - 1;
+        signature[last] ^= 1;
+        credential.response.signature = encode_base64url(&signature);
+        assert_invalid(sign_in(&store, &user, &credential).await, "Invalid signature");
+    }
+
+    // the same assertion again, as a cloned authenticator would send it
+    #[tokio::test]
+    async fn rejects_a_sign_count_that_does_not_increase() {
+        let store = MemoryStore::new();
+        let user = add_user(&store).await;
+        register(&store, &user, &registration()).await.unwrap();
+
+        sign_in(&store, &user, &assertion()).await.unwrap();
+        assert_invalid(sign_in(&store, &user, &assertion()).await, "Sign count did not increase");
+    }
+}
@@ -1,6 +1,7 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
 use serde::Serialize;
 
@@ -22,12 +23,23 @@ pub enum Error {
     InvalidPassword { message: String },
     ResetPasswordLinkExpired { message: String },
 
+    // -- MFA Errors
+    InvalidMfaCode { message: String },
+    MfaAlreadyEnabled { message: String },
+    MfaNotEnabled { message: String },
+    InvalidWebauthnResponse { message: String },
+    WebauthnCredentialNotFound { message: String },
+    InvalidOtp { message: String },
+    PhoneNotVerified { message: String },
+
     // -- Session Errors
     InvalidToken { message: String },
     RefreshTokenCreationError { message: String },
     IdTokenCreationError { message: String },
+    AccessTokenCreationError { message: String },
     PublicKeyLoadError { message: String },
     PrivateKeyLoadError { message: String },
+    SigningKeyError { message: String },
     SignatureVerificationError { message: String },
     ExpiredSignature { message: String },
     SessionExpired { message: String },
@@ -42,8 +54,30 @@ pub enum Error {
     InvalidEmail { message: String },
     InvalidUserAgent { message: String },
 
+    // -- OAuth Errors
+    OAuthClientNotFound { message: String },
+    InvalidGrant { message: String },
+    InvalidClient { message: String },
+    InvalidScope { message: String },
+    ServiceAccountNotFound { message: String },
+    UnsupportedTokenType { message: String },
+
+    // -- Federation Errors
+    IdentityProviderNotFound { message: String },
+    FederationFailed { message: String },
+    IdentityAlreadyLinked { message: String },
+    IdentityNotFound { message: String },
+    LastLoginMethod { message: String },
+    ReauthenticationRequired { message: String },
+    SamlConnectionNotFound { message: String },
+    LdapConnectionNotFound { message: String },
+    LdapFailed { message: String },
+
     // -- Encryption Errors
     KeyNotFound { message: String },
+    KeyProviderFailed { message: String },
+    // the user got a new DEK while the request was running
+    DekRotated { message: String },
 
     ServerError { message: String },
 }
@@ -107,11 +141,50 @@ impl Error {
                 ClientError::SERVICE_ERROR,
             ),
 
+            Self::KeyProviderFailed { message: _ } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ClientError::SERVICE_ERROR,
+            ),
+
+            // nothing was written, the same request goes through once retried
+            Self::DekRotated { message: _ } => {
+                (StatusCode::CONFLICT, ClientError::DEK_CHANGED)
+            }
+
             Self::ResetPasswordLinkExpired { message: _ } => (
                 StatusCode::UNAUTHORIZED,
                 ClientError::RESET_PASSWORD_LINK_EXPIRED,
             ),
 
+            // -- MFA Errors
+            Self::InvalidMfaCode { message: _ } => {
+                (StatusCode::UNAUTHORIZED, ClientError::INVALID_MFA_CODE)
+            }
+
+            Self::MfaAlreadyEnabled { message: _ } => {
+                (StatusCode::CONFLICT, ClientError::MFA_ALREADY_ENABLED)
+            }
+
+            Self::MfaNotEnabled { message: _ } => {
+                (StatusCode::BAD_REQUEST, ClientError::MFA_NOT_ENABLED)
+            }
+
+            Self::InvalidWebauthnResponse { message: _ } => {
+                (StatusCode::UNAUTHORIZED, ClientError::INVALID_WEBAUTHN_RESPONSE)
+            }
+
+            Self::WebauthnCredentialNotFound { message: _ } => {
+                (StatusCode::NOT_FOUND, ClientError::WEBAUTHN_CREDENTIAL_NOT_FOUND)
+            }
+
+            Self::InvalidOtp { message: _ } => {
+                (StatusCode::UNAUTHORIZED, ClientError::INVALID_OTP)
+            }
+
+            Self::PhoneNotVerified { message: _ } => {
+                (StatusCode::BAD_REQUEST, ClientError::PHONE_NOT_VERIFIED)
+            }
+
             // -- Session Errors
             Self::PublicKeyLoadError { message: _ } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -123,6 +196,11 @@ impl Error {
                 ClientError::SERVICE_ERROR,
             ),
 
+            Self::SigningKeyError { message: _ } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ClientError::SERVICE_ERROR,
+            ),
+
             Self::SignatureVerificationError { message: _ } => (
                 StatusCode::UNAUTHORIZED,
                 ClientError::SIGNATURE_VERIFICATION_ERROR,
@@ -140,6 +218,11 @@ impl Error {
                 ClientError::SERVICE_ERROR,
             ),
 
+            Self::AccessTokenCreationError { message: _ } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ClientError::SERVICE_ERROR,
+            ),
+
             Self::RefreshTokenCreationError { message: _ } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 ClientError::SERVICE_ERROR,
@@ -171,6 +254,70 @@ impl Error {
                 (StatusCode::UNAUTHORIZED, ClientError::BLOCK_REQUEST_LINK_EXPIRED)
             }
 
+            // -- OAuth errors
+            Self::OAuthClientNotFound { message: _ } => {
+                (StatusCode::NOT_FOUND, ClientError::OAUTH_CLIENT_NOT_FOUND)
+            }
+
+            Self::InvalidGrant { message: _ } => {
+                (StatusCode::BAD_REQUEST, ClientError::INVALID_GRANT)
+            }
+
+            Self::InvalidClient { message: _ } => {
+                (StatusCode::UNAUTHORIZED, ClientError::INVALID_CLIENT)
+            }
+
+            Self::InvalidScope { message: _ } => {
+                (StatusCode::BAD_REQUEST, ClientError::INVALID_SCOPE)
+            }
+
+            Self::ServiceAccountNotFound { message: _ } => {
+                (StatusCode::NOT_FOUND, ClientError::SERVICE_ACCOUNT_NOT_FOUND)
+            }
+
+            Self::UnsupportedTokenType { message: _ } => {
+                (StatusCode::BAD_REQUEST, ClientError::UNSUPPORTED_TOKEN_TYPE)
+            }
+
+            // -- Federation errors
+            Self::IdentityProviderNotFound { message: _ } => {
+                (StatusCode::NOT_FOUND, ClientError::IDENTITY_PROVIDER_NOT_FOUND)
+            }
+
+            // the upstream provider failed or could not be reached
+            Self::FederationFailed { message: _ } => {
+                (StatusCode::BAD_GATEWAY, ClientError::FEDERATION_FAILED)
+            }
+
+            Self::IdentityAlreadyLinked { message: _ } => {
+                (StatusCode::CONFLICT, ClientError::IDENTITY_ALREADY_LINKED)
+            }
+
+            Self::IdentityNotFound { message: _ } => {
+                (StatusCode::NOT_FOUND, ClientError::IDENTITY_NOT_FOUND)
+            }
+
+            Self::LastLoginMethod { message: _ } => {
+                (StatusCode::CONFLICT, ClientError::LAST_LOGIN_METHOD)
+            }
+
+            Self::ReauthenticationRequired { message: _ } => {
+                (StatusCode::UNAUTHORIZED, ClientError::REAUTHENTICATION_REQUIRED)
+            }
+
+            Self::SamlConnectionNotFound { message: _ } => {
+                (StatusCode::NOT_FOUND, ClientError::SAML_CONNECTION_NOT_FOUND)
+            }
+
+            Self::LdapConnectionNotFound { message: _ } => {
+                (StatusCode::NOT_FOUND, ClientError::LDAP_CONNECTION_NOT_FOUND)
+            }
+
+            // the directory failed or could not be reached
+            Self::LdapFailed { message: _ } => {
+                (StatusCode::BAD_GATEWAY, ClientError::LDAP_FAILED)
+            }
+
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 ClientError::SERVICE_ERROR,
@@ -198,6 +345,88 @@ pub enum ClientError {
     SESSION_NOT_FOUND,
     EMAIL_VERIFICATION_LINK_EXPIRED,
     BLOCK_REQUEST_LINK_EXPIRED,
+    OAUTH_CLIENT_NOT_FOUND,
+    INVALID_GRANT,
+    INVALID_CLIENT,
+    INVALID_SCOPE,
+    SERVICE_ACCOUNT_NOT_FOUND,
+    UNSUPPORTED_TOKEN_TYPE,
+    INVALID_MFA_CODE,
+    MFA_ALREADY_ENABLED,
+    MFA_NOT_ENABLED,
+    INVALID_WEBAUTHN_RESPONSE,
+    WEBAUTHN_CREDENTIAL_NOT_FOUND,
+    INVALID_OTP,
+    PHONE_NOT_VERIFIED,
+    IDENTITY_PROVIDER_NOT_FOUND,
+    FEDERATION_FAILED,
+    IDENTITY_ALREADY_LINKED,
+    IDENTITY_NOT_FOUND,
+    LAST_LOGIN_METHOD,
+    REAUTHENTICATION_REQUIRED,
+    SAML_CONNECTION_NOT_FOUND,
+    LDAP_CONNECTION_NOT_FOUND,
+    LDAP_FAILED,
+    DEK_CHANGED,
+}
+
+// Errors of the OAuth token endpoints. OAuth clients expect the RFC 6749
+// (section 5.2) body instead of our usual error body.
+#[derive(Debug, Serialize)]
+pub struct OAuthError {
+    pub error: String,
+    pub error_description: String,
+}
+
+impl OAuthError {
+    pub fn new(error: &str, error_description: &str) -> Self {
+        Self {
+            error: error.to_string(),
+            error_description: error_description.to_string(),
+        }
+    }
+}
+
+impl From<Error> for OAuthError {
+    fn from(e: Error) -> Self {
+        println!("{:?}", e);
+        match e {
+            Error::InvalidGrant { message }
+            | Error::WrongCredentials { message }
+            | Error::UserNotFound { message } => Self::new("invalid_grant", &message),
+            Error::InvalidPayload { message } => Self::new("invalid_request", &message),
+            Error::OAuthClientNotFound { message }
+            | Error::InvalidClient { message }
+            | Error::ServiceAccountNotFound { message } => Self::new("invalid_client", &message),
+            Error::InvalidScope { message } => Self::new("invalid_scope", &message),
+            Error::UnsupportedTokenType { message } => Self::new("unsupported_token_type", &message),
+            _ => Self::new("server_error", "Internal server error"),
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let status = match self.error.as_str() {
+            "invalid_client" => StatusCode::UNAUTHORIZED,
+            "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let mut response = (
+            status,
+            [(header::CACHE_CONTROL, "no-store")],
+            Json(self),
+        )
+            .into_response();
+        // a 401 has to tell the client how to authenticate
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Basic realm=\"oauth\""),
+            );
+        }
+        response
+    }
 }
 
 // region:    --- Error Boilerplate
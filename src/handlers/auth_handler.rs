@@ -4,13 +4,22 @@ use axum::{
     Json,
 };
 use axum_macros::debug_handler;
+use serde_json::{json, Value};
 
 use crate::{
-    core::{auth::Auth, session::Session, user::User},
+    core::{
+        auth::Auth, magic_link::MagicLink, mfa::MfaProof, session::Session, user::User,
+        webauthn::Webauthn,
+    },
     errors::{Error, Result},
     models::{
-        auth_model::{SignInOrSignUpResponse, SignInPayload, SignUpPayload},
+        auth_model::{
+            OtpSignInPayload, MagicLinkRequestPayload, MagicLinkSignInPayload, MfaSignInPayload, MfaTokenPayload,
+            SignInOrSignUpResponse, SignInPayload, SignInResponse, SignUpPayload,
+        },
         session_model::{RevokeSessionsPayload, RevokeSessionsResult},
+        user_model::UserEmailPayload,
+        webauthn_model::{PasskeySignInPayload, RequestOptionsResponse},
     },
     utils::validation_utils::Validation,
     AppState,
@@ -41,7 +50,7 @@ pub async fn signup_handler(
         });
     }
 
-    let user = User::get_from_email(&state.mongo_client, &payload.email).await;
+    let user = User::get_from_email(state.store.as_ref(), &payload.email).await;
     if user.is_ok() {
         return Err(Error::UserAlreadyExists {
             message: "User already exists".to_string(),
@@ -67,7 +76,8 @@ pub async fn signup_handler(
     }
 
     match Auth::sign_up(
-        &state.mongo_client,
+        state.store.as_ref(),
+        &state.keys,
         &payload.name,
         &payload.email,
         &payload.role,
@@ -85,7 +95,7 @@ pub async fn signin_handler(
     State(state): State<AppState>,
     header: HeaderMap,
     payload: Json<SignInPayload>,
-) -> Result<Json<SignInOrSignUpResponse>> {
+) -> Result<Json<SignInResponse>> {
     println!(">> HANDLER: signin_handler called");
     // check if the payload is empty
     if payload.email.is_empty() || payload.password.is_empty() {
@@ -113,7 +123,8 @@ pub async fn signin_handler(
     }
 
     match Auth::sign_in(
-        &state.mongo_client,
+        state.store.as_ref(),
+        &state.keys,
         &payload.email,
         &payload.password,
         &user_agent,
@@ -125,6 +136,337 @@ pub async fn signin_handler(
     }
 }
 
+// second step of a sign in for users with MFA, takes the mfa_token of the first step
+pub async fn signin_mfa_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<MfaSignInPayload>,
+) -> Result<Json<SignInOrSignUpResponse>> {
+    println!(">> HANDLER: signin_mfa_handler called");
+
+    let proof = match (&payload.webauthn, payload.recovery_code.is_empty(), payload.code.is_empty()) {
+        (Some(credential), _, _) => MfaProof::Webauthn(credential),
+        (None, false, _) => MfaProof::RecoveryCode(&payload.recovery_code),
+        (None, true, false) => MfaProof::Totp(&payload.code),
+        (None, true, true) => {
+            return Err(Error::InvalidPayload {
+                message: "Invalid payload".to_string(),
+            })
+        }
+    };
+
+    if payload.mfa_token.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    if user_agent.is_empty() {
+        return Err(Error::InvalidUserAgent {
+            message: "Invalid User Agent, Can't let random user to signin".to_string(),
+        });
+    }
+
+    match Auth::sign_in_with_mfa(
+        state.store.as_ref(),
+        &state.keys,
+        &payload.mfa_token,
+        proof,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn magic_link_request_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<MagicLinkRequestPayload>,
+) -> Result<Json<Value>> {
+    println!(">> HANDLER: magic_link_request_handler called");
+
+    if payload.email.is_empty() || payload.redirect_url.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    if user_agent.is_empty() {
+        return Err(Error::InvalidUserAgent {
+            message: "Invalid User Agent, Can't let random user to signin".to_string(),
+        });
+    }
+
+    match MagicLink::request(
+        state.store.as_ref(),
+        &payload.email,
+        &payload.redirect_url,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(_) => Ok(Json(json!({
+            "message": "If the email has an account, a sign in link was sent to it"
+        }))),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn magic_link_signin_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<MagicLinkSignInPayload>,
+) -> Result<Json<SignInResponse>> {
+    println!(">> HANDLER: magic_link_signin_handler called");
+
+    if payload.token.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    match Auth::sign_in_with_magic_link(
+        state.store.as_ref(),
+        &state.keys,
+        &payload.token,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn email_otp_request_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<Value>> {
+    println!(">> HANDLER: email_otp_request_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    if user_agent.is_empty() {
+        return Err(Error::InvalidUserAgent {
+            message: "Invalid User Agent, Can't let random user to signin".to_string(),
+        });
+    }
+
+    match Auth::request_email_otp(state.store.as_ref(), &payload.email, &user_agent).await {
+        Ok(_) => Ok(Json(json!({
+            "message": "Sign in code sent to email successfully"
+        }))),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn email_otp_signin_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<OtpSignInPayload>,
+) -> Result<Json<SignInResponse>> {
+    println!(">> HANDLER: email_otp_signin_handler called");
+
+    if payload.email.is_empty() || payload.code.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    match Auth::sign_in_with_email_otp(
+        state.store.as_ref(),
+        &state.keys,
+        &payload.email,
+        &payload.code,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn sms_otp_request_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<Value>> {
+    println!(">> HANDLER: sms_otp_request_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    if user_agent.is_empty() {
+        return Err(Error::InvalidUserAgent {
+            message: "Invalid User Agent, Can't let random user to signin".to_string(),
+        });
+    }
+
+    match Auth::request_sms_otp(state.store.as_ref(), &payload.email, &user_agent).await {
+        Ok(_) => Ok(Json(json!({
+            "message": "Sign in code sent to phone successfully"
+        }))),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn sms_otp_signin_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<OtpSignInPayload>,
+) -> Result<Json<SignInResponse>> {
+    println!(">> HANDLER: sms_otp_signin_handler called");
+
+    if payload.email.is_empty() || payload.code.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    match Auth::sign_in_with_sms_otp(
+        state.store.as_ref(),
+        &state.keys,
+        &payload.email,
+        &payload.code,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+// WebAuthn options for answering the second step with a passkey
+pub async fn signin_mfa_webauthn_options_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<MfaTokenPayload>,
+) -> Result<Json<RequestOptionsResponse>> {
+    println!(">> HANDLER: signin_mfa_webauthn_options_handler called");
+
+    if payload.mfa_token.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    match Auth::mfa_webauthn_options(state.store.as_ref(), &payload.mfa_token, &user_agent).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+// WebAuthn options for a passwordless sign in, any passkey of any user may answer
+pub async fn passkey_options_handler(
+    State(state): State<AppState>,
+) -> Result<Json<RequestOptionsResponse>> {
+    println!(">> HANDLER: passkey_options_handler called");
+
+    match Webauthn::authentication_options(state.store.as_ref(), None).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn passkey_signin_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<PasskeySignInPayload>,
+) -> Result<Json<SignInOrSignUpResponse>> {
+    println!(">> HANDLER: passkey_signin_handler called");
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    if user_agent.is_empty() {
+        return Err(Error::InvalidUserAgent {
+            message: "Invalid User Agent, Can't let random user to signin".to_string(),
+        });
+    }
+
+    match Auth::sign_in_with_passkey(
+        state.store.as_ref(),
+        &state.keys,
+        &payload.credential,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
 pub async fn signout_handler(
     State(state): State<AppState>,
     payload: Json<RevokeSessionsPayload>,
@@ -137,7 +479,7 @@ pub async fn signout_handler(
         });
     }
 
-    match Session::revoke(&state.mongo_client, &payload.session_id, &payload.uid).await {
+    match Session::revoke(state.store.as_ref(), &payload.session_id, &payload.uid).await {
         Ok(_) => Ok(Json(RevokeSessionsResult {
             message: "Session revoked successfully".to_string(),
         })),
@@ -0,0 +1,145 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::{auth::Auth, federation::Federation},
+    errors::{Error, Result},
+    handlers::oauth_handler::page,
+    models::{
+        auth_model::SignInResponse,
+        federation_model::{
+            FederationCallbackParams, FederationSignInPayload, FederationStartPayload,
+            FederationStartResponse,
+        },
+    },
+    utils::oauth_utils::redirect_url,
+    AppState,
+};
+
+#[debug_handler]
+pub async fn federation_start_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<FederationStartPayload>,
+) -> Result<Json<FederationStartResponse>> {
+    println!(">> HANDLER: federation_start_handler called");
+
+    if payload.provider_id.is_empty() || payload.redirect_url.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    if user_agent.is_empty() {
+        return Err(Error::InvalidUserAgent {
+            message: "Invalid User Agent, Can't let random user to signin".to_string(),
+        });
+    }
+
+    match Federation::start(
+        state.store.as_ref(),
+        &payload.provider_id,
+        &payload.redirect_url,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(authorization_url) => Ok(Json(FederationStartResponse { authorization_url })),
+        Err(e) => Err(e),
+    }
+}
+
+// The provider sends the browser back here. It always ends up at the
+// redirect url of the app, with a token to redeem (or linked=provider_id
+// for a link) or an error code.
+pub async fn federation_callback_handler(
+    State(state): State<AppState>,
+    Query(params): Query<FederationCallbackParams>,
+) -> Response {
+    println!(">> HANDLER: federation_callback_handler called");
+
+    // without a known state there is no redirect url to go back to
+    let pending = match Federation::take_pending(state.store.as_ref(), &params.state).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            println!("{:?}", e);
+            return page(
+                StatusCode::BAD_REQUEST,
+                "Sign in",
+                "<h2>Sign in failed</h2><p class='error'>This sign in is unknown or has expired. Please start over.</p>",
+            );
+        }
+    };
+
+    // the user cancelled or the provider refused to sign them in
+    if !params.error.is_empty() || params.code.is_empty() {
+        return Redirect::to(&redirect_url(&pending.redirect_url, &[("error", "access_denied")]))
+            .into_response();
+    }
+
+    let result = match &pending.link_uid {
+        Some(uid) => match Federation::complete_link(state.store.as_ref(), &pending, uid, &params.code).await {
+            Ok(_) => Ok(("linked", pending.provider_id.clone())),
+            Err(e) => Err(e),
+        },
+        None => match Federation::complete(state.store.as_ref(), &pending, &params.code).await {
+            Ok(token) => Ok(("token", token)),
+            Err(e) => Err(e),
+        },
+    };
+
+    match result {
+        Ok((key, value)) => Redirect::to(&redirect_url(&pending.redirect_url, &[(key, &value)])).into_response(),
+        Err(e) => {
+            // the app gets the same error code the api would have answered with
+            let (_, client_error) = e.client_status_and_error();
+            println!("{:?}", e);
+            Redirect::to(&redirect_url(&pending.redirect_url, &[("error", client_error.as_ref())]))
+                .into_response()
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn federation_signin_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<FederationSignInPayload>,
+) -> Result<Json<SignInResponse>> {
+    println!(">> HANDLER: federation_signin_handler called");
+
+    if payload.token.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    match Auth::sign_in_with_federation(
+        state.store.as_ref(),
+        &state.keys,
+        &payload.token,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
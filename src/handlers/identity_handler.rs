@@ -0,0 +1,112 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    Json,
+};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::{auth::Auth, federation::Federation, session::Session, user_identity::UserIdentity},
+    errors::{Error, Result},
+    models::federation_model::{
+        FederationStartResponse, IdentityLinkPayload, IdentityListPayload, IdentityResult,
+        IdentityUnlinkPayload, LoginMethodsResponse,
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn list_identities_handler(
+    State(state): State<AppState>,
+    payload: Json<IdentityListPayload>,
+) -> Result<Json<LoginMethodsResponse>> {
+    println!(">> HANDLER: list_identities_handler called");
+
+    if payload.id_token.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    let uid = match Session::verify(state.store.as_ref(), &state.keys, &payload.id_token).await {
+        Ok((token, true)) => token.uid,
+        Ok(_) => {
+            return Err(Error::SessionExpired {
+                message: "Session expired".to_string(),
+            })
+        }
+        Err(e) => return Err(e),
+    };
+
+    match UserIdentity::login_methods(state.store.as_ref(), &uid).await {
+        Ok(methods) => Ok(Json(methods)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn link_identity_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<IdentityLinkPayload>,
+) -> Result<Json<FederationStartResponse>> {
+    println!(">> HANDLER: link_identity_handler called");
+
+    if payload.id_token.is_empty() || payload.provider_id.is_empty() || payload.redirect_url.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    let password = payload.password.as_deref().filter(|p| !p.is_empty());
+    let user = match Auth::reauthenticate(state.store.as_ref(), &state.keys, &payload.id_token, password).await {
+        Ok(user) => user,
+        Err(e) => return Err(e),
+    };
+
+    match Federation::start_link(
+        state.store.as_ref(),
+        &user.uid,
+        &payload.provider_id,
+        &payload.redirect_url,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(authorization_url) => Ok(Json(FederationStartResponse { authorization_url })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn unlink_identity_handler(
+    State(state): State<AppState>,
+    payload: Json<IdentityUnlinkPayload>,
+) -> Result<Json<IdentityResult>> {
+    println!(">> HANDLER: unlink_identity_handler called");
+
+    if payload.id_token.is_empty() || payload.provider_id.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    let password = payload.password.as_deref().filter(|p| !p.is_empty());
+    let user = match Auth::reauthenticate(state.store.as_ref(), &state.keys, &payload.id_token, password).await {
+        Ok(user) => user,
+        Err(e) => return Err(e),
+    };
+
+    match UserIdentity::unlink(state.store.as_ref(), &user.uid, &payload.provider_id).await {
+        Ok(_) => Ok(Json(IdentityResult {
+            message: "Identity unlinked".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
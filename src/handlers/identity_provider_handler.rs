@@ -0,0 +1,81 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::identity_provider::IdentityProvider,
+    errors::{Error, Result},
+    models::federation_model::{
+        CreateIdentityProviderPayload, CreateIdentityProviderResult, DeleteIdentityProviderResult,
+        IdentityProviderIdPayload, IdentityProviderResponse,
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn create_identity_provider_handler(
+    State(state): State<AppState>,
+    payload: Json<CreateIdentityProviderPayload>,
+) -> Result<Json<CreateIdentityProviderResult>> {
+    println!(">> HANDLER: create_identity_provider_handler called");
+
+    if payload.provider_id.is_empty()
+        || payload.name.trim().is_empty()
+        || payload.issuer.is_empty()
+        || payload.client_id.is_empty()
+        || payload.client_secret.is_empty()
+    {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    let scope = match &payload.scope {
+        Some(scope) => scope.trim().to_string(),
+        None => "openid email profile".to_string(),
+    };
+
+    match IdentityProvider::create(
+        state.store.as_ref(),
+        &payload.provider_id,
+        payload.name.trim(),
+        &payload.issuer,
+        &payload.client_id,
+        &payload.client_secret,
+        &scope,
+    )
+    .await
+    {
+        Ok(provider) => Ok(Json(CreateIdentityProviderResult {
+            message: "Identity provider created".to_string(),
+            provider,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn get_all_identity_providers_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<IdentityProviderResponse>>> {
+    println!(">> HANDLER: get_all_identity_providers_handler called");
+
+    match IdentityProvider::get_all(state.store.as_ref()).await {
+        Ok(providers) => Ok(Json(providers)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn delete_identity_provider_handler(
+    State(state): State<AppState>,
+    payload: Json<IdentityProviderIdPayload>,
+) -> Result<Json<DeleteIdentityProviderResult>> {
+    println!(">> HANDLER: delete_identity_provider_handler called");
+
+    match IdentityProvider::delete(state.store.as_ref(), &payload.provider_id).await {
+        Ok(_) => Ok(Json(DeleteIdentityProviderResult {
+            message: "Identity provider deleted".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
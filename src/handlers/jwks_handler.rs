@@ -0,0 +1,11 @@
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+
+use crate::{errors::Result, AppState};
+
+pub async fn jwks_handler(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    // let downstream services cache the keys for a while instead of fetching them per token
+    Ok((
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(state.keys.jwks()),
+    ))
+}
@@ -0,0 +1,66 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::ldap_connection::LdapConnection,
+    errors::{Error, Result},
+    models::ldap_model::{
+        CreateLdapConnectionPayload, CreateLdapConnectionResult, DeleteLdapConnectionResult,
+        LdapConnectionIdPayload, LdapConnectionResponse,
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn create_ldap_connection_handler(
+    State(state): State<AppState>,
+    payload: Json<CreateLdapConnectionPayload>,
+) -> Result<Json<CreateLdapConnectionResult>> {
+    println!(">> HANDLER: create_ldap_connection_handler called");
+
+    if payload.connection_id.is_empty()
+        || payload.name.trim().is_empty()
+        || payload.url.is_empty()
+        || payload.bind_dn.is_empty()
+        || payload.domains.is_empty()
+    {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match LdapConnection::create(state.store.as_ref(), &payload).await {
+        Ok(connection) => Ok(Json(CreateLdapConnectionResult {
+            message: "LDAP connection created".to_string(),
+            connection,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn get_all_ldap_connections_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<LdapConnectionResponse>>> {
+    println!(">> HANDLER: get_all_ldap_connections_handler called");
+
+    match LdapConnection::get_all(state.store.as_ref()).await {
+        Ok(connections) => Ok(Json(connections)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn delete_ldap_connection_handler(
+    State(state): State<AppState>,
+    payload: Json<LdapConnectionIdPayload>,
+) -> Result<Json<DeleteLdapConnectionResult>> {
+    println!(">> HANDLER: delete_ldap_connection_handler called");
+
+    match LdapConnection::delete(state.store.as_ref(), &payload.connection_id).await {
+        Ok(_) => Ok(Json(DeleteLdapConnectionResult {
+            message: "LDAP connection deleted".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
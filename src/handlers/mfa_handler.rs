@@ -0,0 +1,122 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::mfa::Mfa,
+    errors::{Error, Result},
+    models::{
+        mfa_model::{
+            MfaResult, RecoveryCodesRemainingResponse, RecoveryCodesResponse, TotpCodePayload,
+            TotpEnrollResponse,
+        },
+        user_model::UserEmailPayload,
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn enroll_totp_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<TotpEnrollResponse>> {
+    println!(">> HANDLER: enroll_totp_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Mfa::enroll_totp(state.store.as_ref(), &payload.email).await {
+        Ok((secret, otpauth_uri)) => Ok(Json(TotpEnrollResponse {
+            message: "Scan the QR code and confirm with a code to enable TOTP".to_string(),
+            secret,
+            otpauth_uri,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn confirm_totp_handler(
+    State(state): State<AppState>,
+    payload: Json<TotpCodePayload>,
+) -> Result<Json<RecoveryCodesResponse>> {
+    println!(">> HANDLER: confirm_totp_handler called");
+
+    if payload.email.is_empty() || payload.code.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Mfa::confirm_totp(state.store.as_ref(), &payload.email, &payload.code).await {
+        Ok(recovery_codes) => Ok(Json(RecoveryCodesResponse {
+            message: "TOTP enabled".to_string(),
+            recovery_codes,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn disable_totp_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<MfaResult>> {
+    println!(">> HANDLER: disable_totp_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Mfa::disable_totp(state.store.as_ref(), &payload.email).await {
+        Ok(_) => Ok(Json(MfaResult {
+            message: "TOTP disabled".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn regenerate_recovery_codes_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<RecoveryCodesResponse>> {
+    println!(">> HANDLER: regenerate_recovery_codes_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Mfa::regenerate_recovery_codes(state.store.as_ref(), &payload.email).await {
+        Ok(recovery_codes) => Ok(Json(RecoveryCodesResponse {
+            message: "Recovery codes regenerated".to_string(),
+            recovery_codes,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn recovery_codes_remaining_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<RecoveryCodesRemainingResponse>> {
+    println!(">> HANDLER: recovery_codes_remaining_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Mfa::recovery_codes_remaining(state.store.as_ref(), &payload.email).await {
+        Ok(remaining) => Ok(Json(RecoveryCodesRemainingResponse { remaining })),
+        Err(e) => Err(e),
+    }
+}
@@ -1,6 +1,20 @@
 pub mod auth_handler;
+pub mod federation_handler;
 pub mod health_check_handler;
+pub mod identity_handler;
+pub mod identity_provider_handler;
+pub mod jwks_handler;
+pub mod ldap_connection_handler;
+pub mod mfa_handler;
+pub mod oauth_client_handler;
+pub mod oauth_handler;
+pub mod oidc_handler;
 pub mod overview_handler;
 pub mod password_handler;
+pub mod saml_connection_handler;
+pub mod saml_handler;
+pub mod service_account_handler;
 pub mod session_handler;
+pub mod signing_key_handler;
 pub mod user_handler;
+pub mod webauthn_handler;
@@ -0,0 +1,61 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::oauth_client::OAuthClient,
+    errors::{Error, Result},
+    models::oauth_model::{
+        CreateOAuthClientPayload, CreateOAuthClientResult, DeleteOAuthClientResult,
+        OAuthClientIdPayload,
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn create_oauth_client_handler(
+    State(state): State<AppState>,
+    payload: Json<CreateOAuthClientPayload>,
+) -> Result<Json<CreateOAuthClientResult>> {
+    println!(">> HANDLER: create_oauth_client_handler called");
+
+    if payload.name.trim().is_empty() || payload.redirect_uris.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match OAuthClient::create(state.store.as_ref(), payload.name.trim(), &payload.redirect_uris).await {
+        Ok(client) => Ok(Json(CreateOAuthClientResult {
+            message: "OAuth client created".to_string(),
+            client,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn get_all_oauth_clients_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<OAuthClient>>> {
+    println!(">> HANDLER: get_all_oauth_clients_handler called");
+
+    match OAuthClient::get_all(state.store.as_ref()).await {
+        Ok(clients) => Ok(Json(clients)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn delete_oauth_client_handler(
+    State(state): State<AppState>,
+    payload: Json<OAuthClientIdPayload>,
+) -> Result<Json<DeleteOAuthClientResult>> {
+    println!(">> HANDLER: delete_oauth_client_handler called");
+
+    match OAuthClient::delete(state.store.as_ref(), &payload.client_id).await {
+        Ok(_) => Ok(Json(DeleteOAuthClientResult {
+            message: "OAuth client deleted".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
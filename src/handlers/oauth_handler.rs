@@ -0,0 +1,544 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form, Json,
+};
+
+use crate::{
+    core::{
+        auth::Auth,
+        mfa::{Mfa, MfaProof},
+        mfa_challenge::MfaChallenge,
+        oauth::OAuth,
+        service_account::ServiceAccount,
+        user::User,
+    },
+    errors::{self, Error, OAuthError},
+    models::{
+        auth_model::MfaTokenPayload,
+        oauth_model::{
+            AuthorizeForm, AuthorizeMfaForm, AuthorizeParams, IntrospectPayload, RevokePayload,
+            TokenPayload, TokenResponse,
+        },
+        webauthn_model::{AssertionCredential, RequestOptionsResponse},
+    },
+    utils::oauth_utils::basic_credentials,
+    AppState,
+};
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+pub fn page(status: StatusCode, title: &str, body: &str) -> Response {
+    let html = format!(r#"
+    <!DOCTYPE html>
+    <html lang="en">
+    <head>
+        <meta charset="UTF-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1.0">
+        <title>{title}</title>
+        <style>
+            body {{ font-family: Arial, sans-serif; margin: 0; background-color: #060A13; }}
+            .navbar {{ background-color: #060A13; overflow: hidden; border-bottom: 0.5px solid #1E293B; }}
+            .navbar h1 {{ color: #f2f2f2; text-align: center; padding: 14px 0px; }}
+            .form-div {{ margin: 0 auto; display: flex; justify-content: center; align-items: center; }}
+            form {{ margin-top: 20px; display: flex; flex-direction: column; align-items: left; width: 40%; }}
+            label {{ display: block; margin-top: 10px; text-align: left; color: #f2f2f2; }}
+            input {{ width: 100%; padding: 8px; margin-top: 5px; margin-bottom: 10px; border: 1px solid #ccc; border-radius: 4px; box-sizing: border-box; }}
+            button {{ padding: 10px 20px; background-color: #3B81F6; color: #f2f2f2; border: none; cursor: pointer; border-radius: 5px; width: 100%; }}
+            h2 {{ text-align: center; color: #f2f2f2; }}
+            p {{ text-align: center; color: #f2f2f2; }}
+            .error {{ color: #F87171; }}
+        </style>
+    </head>
+    <body>
+        <div class='navbar'>
+            <h1>FlexAuth</h1>
+        </div>
+        {body}
+    </body>
+    </html>
+    "#);
+    // the login page must never be framed by another site
+    (status, [(header::X_FRAME_OPTIONS, "DENY")], Html(html)).into_response()
+}
+
+// the authorization request is carried through the forms as hidden fields
+fn hidden_fields(params: &AuthorizeParams) -> String {
+    [
+        ("response_type", &params.response_type),
+        ("client_id", &params.client_id),
+        ("redirect_uri", &params.redirect_uri),
+        ("scope", &params.scope),
+        ("state", &params.state),
+        ("code_challenge", &params.code_challenge),
+        ("code_challenge_method", &params.code_challenge_method),
+        ("nonce", &params.nonce),
+    ]
+    .iter()
+    .map(|(name, value)| {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            name,
+            escape_html(value)
+        )
+    })
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+fn error_message(error: Option<&str>) -> String {
+    match error {
+        Some(error) => format!(r#"<p class="error">{}</p>"#, escape_html(error)),
+        None => "".to_string(),
+    }
+}
+
+fn login_page(params: &AuthorizeParams, client_name: &str, error: Option<&str>) -> Response {
+    let hidden_fields = hidden_fields(params);
+    let error = error_message(error);
+
+    let body = format!(r#"
+        <h2>Sign in to {client_name}</h2>
+        {error}
+        <div class='form-div'>
+            <form method="post" action="/oauth/authorize">
+                {hidden_fields}
+                <label for="email">Email:</label>
+                <input type="email" id="email" name="email" required placeholder="Enter email">
+                <label for="password">Password:</label>
+                <input type="password" id="password" name="password" required placeholder="Enter password">
+                <br />
+                <button type="submit">Sign in</button>
+            </form>
+        </div>
+    "#, client_name = escape_html(client_name));
+
+    let status = if error.is_empty() { StatusCode::OK } else { StatusCode::UNAUTHORIZED };
+    page(status, "Sign in", &body)
+}
+
+// asks the browser for an assertion and posts it with the form as JSON
+const PASSKEY_SCRIPT: &str = r#"
+    <script>
+        const toBuffer = (value) => Uint8Array.from(atob(value.replace(/-/g, '+').replace(/_/g, '/')), (c) => c.charCodeAt(0));
+        const toBase64url = (buffer) => btoa(String.fromCharCode(...new Uint8Array(buffer))).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+        async function usePasskey() {
+            const form = document.getElementById('mfa-form');
+            const response = await fetch('/oauth/authorize/webauthn-options', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/x-www-form-urlencoded' },
+                body: new URLSearchParams({ mfa_token: form.mfa_token.value }),
+            });
+            if (!response.ok) { form.submit(); return; }
+            const { publicKey } = await response.json();
+            publicKey.challenge = toBuffer(publicKey.challenge);
+            publicKey.allowCredentials = publicKey.allowCredentials.map((c) => ({ ...c, id: toBuffer(c.id) }));
+            const credential = await navigator.credentials.get({ publicKey });
+            form.webauthn.value = JSON.stringify({
+                id: credential.id,
+                type: credential.type,
+                response: {
+                    clientDataJSON: toBase64url(credential.response.clientDataJSON),
+                    authenticatorData: toBase64url(credential.response.authenticatorData),
+                    signature: toBase64url(credential.response.signature),
+                },
+            });
+            form.submit();
+        }
+    </script>
+"#;
+
+fn mfa_page(
+    params: &AuthorizeParams,
+    client_name: &str,
+    mfa_token: &str,
+    methods: &[String],
+    error: Option<&str>,
+) -> Response {
+    let hidden_fields = hidden_fields(params);
+    let error = error_message(error);
+    let has = |method: &str| methods.iter().any(|m| m == method);
+
+    let mut fields = String::new();
+    if has("totp") {
+        fields.push_str(r#"
+                <label for="code">Code from your authenticator app:</label>
+                <input type="text" id="code" name="code" autocomplete="one-time-code" inputmode="numeric" placeholder="Enter code">
+        "#);
+    }
+    if has("recovery_code") {
+        fields.push_str(r#"
+                <label for="recovery_code">Lost your device? Use a recovery code instead:</label>
+                <input type="text" id="recovery_code" name="recovery_code" autocomplete="off" placeholder="xxxxx-xxxxx">
+        "#);
+    }
+    if has("totp") {
+        fields.push_str(r#"
+                <br />
+                <button type="submit">Verify</button>
+        "#);
+    }
+    let mut script = "";
+    if has("webauthn") {
+        fields.push_str(r#"
+                <br />
+                <button type="button" onclick="usePasskey()">Use a passkey</button>
+        "#);
+        script = PASSKEY_SCRIPT;
+    }
+
+    let body = format!(r#"
+        <h2>Sign in to {client_name}</h2>
+        <p>Verify it's you.</p>
+        {error}
+        <div class='form-div'>
+            <form id="mfa-form" method="post" action="/oauth/authorize/mfa">
+                {hidden_fields}
+                <input type="hidden" name="mfa_token" value="{mfa_token}">
+                <input type="hidden" name="webauthn" value="">
+                {fields}
+            </form>
+        </div>
+        {script}
+    "#, client_name = escape_html(client_name), mfa_token = escape_html(mfa_token));
+
+    let status = if error.is_empty() { StatusCode::OK } else { StatusCode::UNAUTHORIZED };
+    page(status, "Verify", &body)
+}
+
+fn user_agent(header: &HeaderMap) -> String {
+    match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap_or_default().to_string(),
+        None => "".to_string(),
+    }
+}
+
+// the second factors of the user the challenge was issued for
+async fn challenge_methods(state: &AppState, mfa_token: &str, user_agent: &str) -> errors::Result<Vec<String>> {
+    let challenge = match MfaChallenge::get(state.store.as_ref(), mfa_token, user_agent).await {
+        Ok(challenge) => challenge,
+        Err(e) => return Err(e),
+    };
+    let user = match User::get_from_uid(state.store.as_ref(), &challenge.uid).await {
+        Ok(user) => user,
+        Err(e) => return Err(e),
+    };
+    Mfa::methods(state.store.as_ref(), &user).await
+}
+
+// shows the verify page again with the factors the user has
+async fn retry_mfa_page(
+    state: &AppState,
+    form: &AuthorizeMfaForm,
+    client_name: &str,
+    user_agent: &str,
+    message: &str,
+) -> Response {
+    match challenge_methods(state, &form.mfa_token, user_agent).await {
+        Ok(methods) => mfa_page(&form.params, client_name, &form.mfa_token, &methods, Some(message)),
+        Err(e) => {
+            println!("{:?}", e);
+            login_page(&form.params, client_name, Some("Your sign in expired. Please sign in again"))
+        }
+    }
+}
+
+// redirects back to the client with a new authorization code
+async fn issue_code(state: &AppState, params: &AuthorizeParams, client_name: &str, scope: &str, uid: &str) -> Response {
+    match OAuth::authorize(state.store.as_ref(), params, scope, uid).await {
+        Ok(url) => Redirect::to(&url).into_response(),
+        Err(e) => {
+            println!("{:?}", e);
+            login_page(params, client_name, Some("Something went wrong. Please try again"))
+        }
+    }
+}
+
+// Checks the authorization request. A bad client or redirect uri is shown to
+// the user, everything else is sent back to the client on its redirect uri.
+async fn check_request(state: &AppState, params: &AuthorizeParams) -> Result<(String, String), Response> {
+    let client = match OAuth::get_client(state.store.as_ref(), &params.client_id, &params.redirect_uri).await {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{:?}", e);
+            return Err(page(
+                StatusCode::BAD_REQUEST,
+                "Invalid request",
+                "<h2>Invalid request</h2><p>The application sent an invalid client or redirect uri.</p>",
+            ));
+        }
+    };
+    match OAuth::validate_request(params) {
+        Ok(scope) => Ok((client.name, scope)),
+        Err(error) => Err(Redirect::to(&OAuth::error_redirect(params, &error)).into_response()),
+    }
+}
+
+pub async fn authorize_page_handler(
+    State(state): State<AppState>,
+    Query(params): Query<AuthorizeParams>,
+) -> Response {
+    println!(">> HANDLER: authorize_page_handler called");
+
+    match check_request(&state, &params).await {
+        Ok((client_name, _)) => login_page(&params, &client_name, None),
+        Err(response) => response,
+    }
+}
+
+pub async fn authorize_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    Form(form): Form<AuthorizeForm>,
+) -> Response {
+    println!(">> HANDLER: authorize_handler called");
+
+    let (client_name, scope) = match check_request(&state, &form.params).await {
+        Ok(checked) => checked,
+        Err(response) => return response,
+    };
+
+    let user = match Auth::verify_credentials(state.store.as_ref(), &form.email, &form.password).await {
+        Ok(user) => user,
+        Err(e) => {
+            println!("{:?}", e);
+            let message = match e {
+                // don't tell which one of the two was wrong
+                Error::WrongCredentials { .. } | Error::UserNotFound { .. } | Error::KeyNotFound { .. } => {
+                    "Invalid email or password"
+                }
+                Error::UserBlocked { .. } => "Too many failed attempts. Please try again later",
+                _ => "Something went wrong. Please try again",
+            };
+            return login_page(&form.params, &client_name, Some(message));
+        }
+    };
+
+    let methods = match Mfa::methods(state.store.as_ref(), &user).await {
+        Ok(methods) => methods,
+        Err(e) => {
+            println!("{:?}", e);
+            return login_page(&form.params, &client_name, Some("Something went wrong. Please try again"));
+        }
+    };
+    if methods.is_empty() {
+        return issue_code(&state, &form.params, &client_name, &scope, &user.uid).await;
+    }
+
+    // the code is only issued once the second factor was passed too
+    match MfaChallenge::issue(state.store.as_ref(), &user.uid, &user_agent(&header)).await {
+        Ok(mfa_token) => mfa_page(&form.params, &client_name, &mfa_token, &methods, None),
+        Err(e) => {
+            println!("{:?}", e);
+            login_page(&form.params, &client_name, Some("Something went wrong. Please try again"))
+        }
+    }
+}
+
+pub async fn authorize_mfa_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    Form(form): Form<AuthorizeMfaForm>,
+) -> Response {
+    println!(">> HANDLER: authorize_mfa_handler called");
+
+    let (client_name, scope) = match check_request(&state, &form.params).await {
+        Ok(checked) => checked,
+        Err(response) => return response,
+    };
+
+    let user_agent = user_agent(&header);
+
+    // a passkey assertion, a recovery code or a TOTP code, in that order
+    let webauthn: Option<AssertionCredential> = match form.webauthn.is_empty() {
+        true => None,
+        false => serde_json::from_str(&form.webauthn).ok(),
+    };
+    let proof = match (&webauthn, form.recovery_code.is_empty(), form.code.is_empty()) {
+        (Some(credential), _, _) => MfaProof::Webauthn(credential),
+        (None, false, _) => MfaProof::RecoveryCode(&form.recovery_code),
+        (None, true, false) => MfaProof::Totp(&form.code),
+        (None, true, true) => {
+            let message = match form.webauthn.is_empty() {
+                true => "Enter a code",
+                false => "The passkey could not be verified",
+            };
+            return retry_mfa_page(&state, &form, &client_name, &user_agent, message).await;
+        }
+    };
+
+    let message = match Auth::verify_mfa_challenge(state.store.as_ref(), &form.mfa_token, proof, &user_agent).await {
+        Ok(user) => return issue_code(&state, &form.params, &client_name, &scope, &user.uid).await,
+        Err(e) => {
+            println!("{:?}", e);
+            match e {
+                Error::InvalidMfaCode { .. } => "Invalid code",
+                Error::InvalidWebauthnResponse { .. } => "The passkey could not be verified",
+                Error::UserBlocked { .. } => {
+                    return login_page(
+                        &form.params,
+                        &client_name,
+                        Some("Too many failed attempts. Please try again later"),
+                    )
+                }
+                // expired or used up, start over
+                Error::InvalidToken { .. } => {
+                    return login_page(
+                        &form.params,
+                        &client_name,
+                        Some("Your sign in expired. Please sign in again"),
+                    )
+                }
+                _ => return login_page(&form.params, &client_name, Some("Something went wrong. Please try again")),
+            }
+        }
+    };
+    retry_mfa_page(&state, &form, &client_name, &user_agent, message).await
+}
+
+// WebAuthn options for the passkey button of the verify page
+pub async fn authorize_webauthn_options_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    Form(form): Form<MfaTokenPayload>,
+) -> errors::Result<Json<RequestOptionsResponse>> {
+    println!(">> HANDLER: authorize_webauthn_options_handler called");
+
+    match Auth::mfa_webauthn_options(state.store.as_ref(), &form.mfa_token, &user_agent(&header)).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+// client_secret_basic, or client_secret_post when there is no Authorization header
+fn client_credentials(
+    header: &HeaderMap,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<(String, String), OAuthError> {
+    match header.get(header::AUTHORIZATION) {
+        Some(value) => match value.to_str().ok().and_then(basic_credentials) {
+            Some(credentials) => Ok(credentials),
+            None => Err(OAuthError::new(
+                "invalid_client",
+                "Invalid Authorization header",
+            )),
+        },
+        None => Ok((client_id.to_string(), client_secret.to_string())),
+    }
+}
+
+pub async fn token_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    Form(payload): Form<TokenPayload>,
+) -> Result<impl IntoResponse, OAuthError> {
+    println!(">> HANDLER: token_handler called");
+
+    let tokens: TokenResponse = match payload.grant_type.as_str() {
+        "authorization_code" => {
+            let user_agent = match header.get(header::USER_AGENT) {
+                Some(ua) => ua.to_str().unwrap_or_default().to_string(),
+                None => "".to_string(),
+            };
+            match OAuth::exchange_code(
+                state.store.as_ref(),
+                &state.keys,
+                &payload.code,
+                &payload.client_id,
+                &payload.redirect_uri,
+                &payload.code_verifier,
+                &user_agent,
+            )
+            .await
+            {
+                Ok(tokens) => tokens,
+                Err(e) => return Err(OAuthError::from(e)),
+            }
+        }
+        "client_credentials" => {
+            let (client_id, client_secret) =
+                match client_credentials(&header, &payload.client_id, &payload.client_secret) {
+                    Ok(credentials) => credentials,
+                    Err(e) => return Err(e),
+                };
+            match OAuth::client_credentials(
+                state.store.as_ref(),
+                &state.keys,
+                &client_id,
+                &client_secret,
+                &payload.scope,
+            )
+            .await
+            {
+                Ok(tokens) => tokens,
+                Err(e) => return Err(OAuthError::from(e)),
+            }
+        }
+        _ => {
+            return Err(OAuthError::new(
+                "unsupported_grant_type",
+                "Only the authorization_code and client_credentials grants are supported",
+            ))
+        }
+    };
+
+    // tokens must never end up in a cache
+    Ok((
+        [(header::CACHE_CONTROL, "no-store"), (header::PRAGMA, "no-cache")],
+        Json(tokens),
+    ))
+}
+
+// only service accounts may look into tokens, e.g. an API gateway
+pub async fn introspect_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    Form(payload): Form<IntrospectPayload>,
+) -> Result<impl IntoResponse, OAuthError> {
+    println!(">> HANDLER: introspect_handler called");
+
+    let (client_id, client_secret) =
+        match client_credentials(&header, &payload.client_id, &payload.client_secret) {
+            Ok(credentials) => credentials,
+            Err(e) => return Err(e),
+        };
+    match ServiceAccount::authenticate(state.store.as_ref(), &client_id, &client_secret).await {
+        Ok(_) => {}
+        Err(e) => return Err(OAuthError::from(e)),
+    }
+
+    if payload.token.is_empty() {
+        return Err(OAuthError::new("invalid_request", "token is required"));
+    }
+
+    match OAuth::introspect(state.store.as_ref(), &state.keys, &payload.token).await {
+        Ok(response) => Ok(([(header::CACHE_CONTROL, "no-store")], Json(response))),
+        Err(e) => Err(OAuthError::from(e)),
+    }
+}
+
+// holding a token is enough to revoke it, so apps can sign their users out
+pub async fn revoke_handler(
+    State(state): State<AppState>,
+    Form(payload): Form<RevokePayload>,
+) -> Result<StatusCode, OAuthError> {
+    println!(">> HANDLER: revoke_handler called");
+
+    if payload.token.is_empty() {
+        return Err(OAuthError::new("invalid_request", "token is required"));
+    }
+
+    match OAuth::revoke(state.store.as_ref(), &state.keys, &payload.token).await {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err(OAuthError::from(e)),
+    }
+}
@@ -0,0 +1,120 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::env;
+
+use crate::{
+    core::{oauth::SUPPORTED_SCOPES, session::Session, user::User},
+    errors::Error,
+    models::oidc_model::{OpenIdConfiguration, UserInfoResponse},
+    AppState,
+};
+
+// Token errors on /userinfo carry a WWW-Authenticate challenge as required by
+// RFC 6750, the response mapper keeps the header when it builds the error body.
+pub enum BearerError {
+    Missing,
+    Rejected(Error),
+}
+
+impl IntoResponse for BearerError {
+    fn into_response(self) -> Response {
+        let (error, challenge) = match self {
+            BearerError::Missing => (
+                Error::InvalidToken {
+                    message: "Missing bearer token".to_string(),
+                },
+                "Bearer",
+            ),
+            BearerError::Rejected(error) => match error {
+                Error::InvalidToken { .. }
+                | Error::SessionExpired { .. }
+                | Error::ExpiredSignature { .. }
+                | Error::SignatureVerificationError { .. } => {
+                    (error, "Bearer error=\"invalid_token\"")
+                }
+                _ => (error, "Bearer"),
+            },
+        };
+        let mut response = error.into_response();
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+        response
+    }
+}
+
+fn strs(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+pub async fn openid_configuration_handler() -> Json<OpenIdConfiguration> {
+    let server_url = env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+
+    Json(OpenIdConfiguration {
+        issuer: server_url.clone(),
+        authorization_endpoint: format!("{}/oauth/authorize", server_url),
+        token_endpoint: format!("{}/oauth/token", server_url),
+        introspection_endpoint: format!("{}/oauth/introspect", server_url),
+        revocation_endpoint: format!("{}/oauth/revoke", server_url),
+        jwks_uri: format!("{}/.well-known/jwks.json", server_url),
+        userinfo_endpoint: format!("{}/userinfo", server_url),
+        response_types_supported: strs(&["code"]),
+        grant_types_supported: strs(&["authorization_code", "client_credentials"]),
+        code_challenge_methods_supported: strs(&["S256"]),
+        // apps are public clients that use PKCE, service accounts have a secret
+        token_endpoint_auth_methods_supported: strs(&[
+            "none",
+            "client_secret_basic",
+            "client_secret_post",
+        ]),
+        subject_types_supported: strs(&["public"]),
+        id_token_signing_alg_values_supported: strs(&["RS256"]),
+        scopes_supported: strs(&SUPPORTED_SCOPES),
+        claims_supported: strs(&[
+            "sub", "iss", "aud", "iat", "exp", "nonce", "scope", "name", "email", "email_verified",
+        ]),
+    })
+}
+
+pub async fn userinfo_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+) -> Result<Json<UserInfoResponse>, BearerError> {
+    println!(">> HANDLER: userinfo_handler called");
+
+    // get the bearer token from the authorization header
+    let token = match header
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(token) if !token.trim().is_empty() => token.trim().to_string(),
+        _ => return Err(BearerError::Missing),
+    };
+
+    let token_data = match Session::verify(state.store.as_ref(), &state.keys, &token).await {
+        Ok((token_data, true)) => token_data,
+        Ok((_, false)) => {
+            return Err(BearerError::Rejected(Error::SessionExpired {
+                message: "Session expired".to_string(),
+            }))
+        }
+        Err(e) => return Err(BearerError::Rejected(e)),
+    };
+
+    let user = match User::get_from_uid(state.store.as_ref(), &token_data.uid).await {
+        Ok(user) => user,
+        Err(e) => return Err(BearerError::Rejected(e)),
+    };
+
+    Ok(Json(UserInfoResponse {
+        sub: user.uid,
+        name: user.name,
+        email: user.email,
+        email_verified: user.email_verified,
+    }))
+}
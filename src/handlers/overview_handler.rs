@@ -14,7 +14,7 @@ pub async fn get_all_overview_handler(
 ) -> Result<Json<OverviewResponse>> {
     println!(">> HANDLER: get_all_overview_handler called");
 
-    let users = User::get_all(&state.mongo_client).await.unwrap();
+    let users = User::get_all(state.store.as_ref()).await.unwrap();
     let user_count = users.len();
     let active_user_count = users.iter().filter(|u| u.is_active).count();
     let inactive_user_count = users.iter().filter(|u| !u.is_active).count();
@@ -23,7 +23,7 @@ pub async fn get_all_overview_handler(
         .filter(|u| u.blocked_until.map_or(false, |time| time > DateTime::now()))
         .count();
 
-    let all_sessions = Session::get_all(&state.mongo_client).await.unwrap();
+    let all_sessions = Session::get_all(state.store.as_ref()).await.unwrap();
     println!(">> all_sessions Length: {:?}", all_sessions.len());
 
     let active_session_count = all_sessions.iter().filter(|s| !s.is_revoked).count();
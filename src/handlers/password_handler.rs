@@ -35,7 +35,7 @@ pub async fn reset_password_handler(
     }
 
     match User::change_password(
-        &state.mongo_client,
+        state.store.as_ref(),
         &payload.email,
         &payload.old_password,
         &payload.new_password,
@@ -63,7 +63,7 @@ pub async fn forget_password_request_handler(
         });
     }
 
-    match User::forget_password_request(&state.mongo_client, &payload.email).await {
+    match User::forget_password_request(state.store.as_ref(), &payload.email).await {
         Ok(_) => {
             return Ok(Json(json!({
                 "message": "Password reset request sent successfully. Please check your email."
@@ -85,7 +85,7 @@ pub async fn forget_password_reset_handler(
             message: "Invalid Payload".to_string(),
         });
     }
-    match User::forget_password_reset(&state.mongo_client, &id, &payload.email, &payload.password)
+    match User::forget_password_reset(state.store.as_ref(), &id, &payload.email, &payload.password)
         .await
     {
         Ok(_) => {
@@ -0,0 +1,61 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::saml_connection::SamlConnection,
+    errors::{Error, Result},
+    models::saml_model::{
+        CreateSamlConnectionPayload, CreateSamlConnectionResult, DeleteSamlConnectionResult,
+        SamlConnectionIdPayload, SamlConnectionResponse,
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn create_saml_connection_handler(
+    State(state): State<AppState>,
+    payload: Json<CreateSamlConnectionPayload>,
+) -> Result<Json<CreateSamlConnectionResult>> {
+    println!(">> HANDLER: create_saml_connection_handler called");
+
+    if payload.connection_id.is_empty() || payload.name.trim().is_empty() || payload.domains.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match SamlConnection::create(state.store.as_ref(), &payload).await {
+        Ok(connection) => Ok(Json(CreateSamlConnectionResult {
+            message: "SAML connection created".to_string(),
+            connection,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn get_all_saml_connections_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<SamlConnectionResponse>>> {
+    println!(">> HANDLER: get_all_saml_connections_handler called");
+
+    match SamlConnection::get_all(state.store.as_ref()).await {
+        Ok(connections) => Ok(Json(connections)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn delete_saml_connection_handler(
+    State(state): State<AppState>,
+    payload: Json<SamlConnectionIdPayload>,
+) -> Result<Json<DeleteSamlConnectionResult>> {
+    println!(">> HANDLER: delete_saml_connection_handler called");
+
+    match SamlConnection::delete(state.store.as_ref(), &payload.connection_id).await {
+        Ok(_) => Ok(Json(DeleteSamlConnectionResult {
+            message: "SAML connection deleted".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,112 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Form, Json,
+};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::{federation::Federation, saml::Saml, saml_connection::SamlConnection},
+    errors::{Error, Result},
+    handlers::oauth_handler::page,
+    models::{
+        federation_model::FederationStartResponse,
+        saml_model::{SamlAcsForm, SamlStartPayload},
+    },
+    utils::{oauth_utils::redirect_url, saml_utils::sp_metadata},
+    AppState,
+};
+
+#[debug_handler]
+pub async fn saml_start_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<SamlStartPayload>,
+) -> Result<Json<FederationStartResponse>> {
+    println!(">> HANDLER: saml_start_handler called");
+
+    if payload.connection_id.is_empty() || payload.redirect_url.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    if user_agent.is_empty() {
+        return Err(Error::InvalidUserAgent {
+            message: "Invalid User Agent, Can't let random user to signin".to_string(),
+        });
+    }
+
+    match Saml::start(
+        state.store.as_ref(),
+        &payload.connection_id,
+        &payload.redirect_url,
+        &user_agent,
+    )
+    .await
+    {
+        Ok(authorization_url) => Ok(Json(FederationStartResponse { authorization_url })),
+        Err(e) => Err(e),
+    }
+}
+
+// The identity provider posts its response here from the browser. Like the
+// federation callback it always ends up at the redirect url of the app, with a
+// token to redeem at /api/federation/signin or an error code.
+pub async fn saml_acs_handler(
+    State(state): State<AppState>,
+    Path(connection_id): Path<String>,
+    Form(form): Form<SamlAcsForm>,
+) -> Response {
+    println!(">> HANDLER: saml_acs_handler called");
+
+    // without a known relay state there is no redirect url to go back to,
+    // that is also where sign ins started at the identity provider end up
+    let pending = match Federation::take_pending(state.store.as_ref(), &form.relay_state).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            println!("{:?}", e);
+            return page(
+                StatusCode::BAD_REQUEST,
+                "Sign in",
+                "<h2>Sign in failed</h2><p class='error'>This sign in is unknown or has expired. Please start over.</p>",
+            );
+        }
+    };
+
+    match Saml::complete(state.store.as_ref(), &connection_id, &pending, &form.saml_response).await {
+        Ok(Some(token)) => Redirect::to(&redirect_url(&pending.redirect_url, &[("token", &token)])).into_response(),
+        // the user cancelled or the identity provider refused to sign them in
+        Ok(None) => Redirect::to(&redirect_url(&pending.redirect_url, &[("error", "access_denied")])).into_response(),
+        Err(e) => {
+            let (_, client_error) = e.client_status_and_error();
+            println!("{:?}", e);
+            Redirect::to(&redirect_url(&pending.redirect_url, &[("error", client_error.as_ref())]))
+                .into_response()
+        }
+    }
+}
+
+// the service provider metadata the identity provider is set up with
+pub async fn saml_metadata_handler(
+    State(state): State<AppState>,
+    Path(connection_id): Path<String>,
+) -> Result<Response> {
+    println!(">> HANDLER: saml_metadata_handler called");
+
+    match SamlConnection::get(state.store.as_ref(), &connection_id).await {
+        Ok(connection) => Ok((
+            [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+            sp_metadata(&connection.sp_entity_id(), &connection.acs_url()),
+        )
+            .into_response()),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,79 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::service_account::ServiceAccount,
+    errors::{Error, Result},
+    models::service_account_model::{
+        CreateServiceAccountPayload, DeleteServiceAccountResult, ServiceAccountIdPayload,
+        ServiceAccountResponse, ServiceAccountSecretResult,
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn create_service_account_handler(
+    State(state): State<AppState>,
+    payload: Json<CreateServiceAccountPayload>,
+) -> Result<Json<ServiceAccountSecretResult>> {
+    println!(">> HANDLER: create_service_account_handler called");
+
+    if payload.name.trim().is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match ServiceAccount::create(state.store.as_ref(), payload.name.trim(), &payload.scopes).await {
+        Ok((account, client_secret)) => Ok(Json(ServiceAccountSecretResult {
+            message: "Service account created".to_string(),
+            service_account: account.to_response(),
+            client_secret,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn get_all_service_accounts_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ServiceAccountResponse>>> {
+    println!(">> HANDLER: get_all_service_accounts_handler called");
+
+    match ServiceAccount::get_all(state.store.as_ref()).await {
+        Ok(accounts) => Ok(Json(accounts)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn rotate_service_account_secret_handler(
+    State(state): State<AppState>,
+    payload: Json<ServiceAccountIdPayload>,
+) -> Result<Json<ServiceAccountSecretResult>> {
+    println!(">> HANDLER: rotate_service_account_secret_handler called");
+
+    match ServiceAccount::rotate_secret(state.store.as_ref(), &payload.client_id).await {
+        Ok((account, client_secret)) => Ok(Json(ServiceAccountSecretResult {
+            message: "Service account secret rotated".to_string(),
+            service_account: account.to_response(),
+            client_secret,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn delete_service_account_handler(
+    State(state): State<AppState>,
+    payload: Json<ServiceAccountIdPayload>,
+) -> Result<Json<DeleteServiceAccountResult>> {
+    println!(">> HANDLER: delete_service_account_handler called");
+
+    match ServiceAccount::delete(state.store.as_ref(), &payload.client_id).await {
+        Ok(_) => Ok(Json(DeleteServiceAccountResult {
+            message: "Service account deleted".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
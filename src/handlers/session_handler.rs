@@ -31,7 +31,7 @@ pub async fn verify_session_handler(
     }
 
     // verify the token
-    match Session::verify(&state.mongo_client, &payload.token).await {
+    match Session::verify(state.store.as_ref(), &state.keys, &payload.token).await {
         Ok(data) => {
             return {
                 if data.1 {
@@ -52,7 +52,7 @@ pub async fn get_all_handler(
     State(state): State<AppState>,
 ) -> Result<Json<Vec<SessionResponse>>> {
     // verify the token
-    match Session::get_all(&state.mongo_client).await {
+    match Session::get_all(state.store.as_ref()).await {
         Ok(data) => {
             return Ok(Json(data));
         }
@@ -73,7 +73,7 @@ pub async fn get_all_from_uid_handler(
     }
 
     // verify the token
-    match Session::get_all_from_uid(&state.mongo_client, &payload.uid).await {
+    match Session::get_all_from_uid(state.store.as_ref(), &state.keys, &payload.uid).await {
         Ok(data) => {
             return Ok(Json(data));
         }
@@ -93,7 +93,7 @@ pub async fn get_details_handler(
         });
     }
 
-    match Session::get_details(&state.mongo_client, &payload.uid, &payload.session_id).await {
+    match Session::get_details(state.store.as_ref(), &payload.uid, &payload.session_id).await {
         Ok(data) => {
             return Ok(Json(data));
         }
@@ -132,7 +132,8 @@ pub async fn refresh_session_handler(
 
     // verify the token
     match Session::refresh(
-        &state.mongo_client,
+        state.store.as_ref(),
+        &state.keys,
         &payload.uid,
         &payload.session_id,
         &payload.id_token,
@@ -166,7 +167,7 @@ pub async fn revoke_handler(
     }
 
     // revoke the session
-    match Session::revoke(&state.mongo_client, &payload.session_id, &payload.uid).await {
+    match Session::revoke(state.store.as_ref(), &payload.session_id, &payload.uid).await {
         Ok(_) => {
             return Ok(Json(RevokeSessionsResult {
                 message: "Session revoked successfully".to_string(),
@@ -182,7 +183,7 @@ pub async fn revoke_all_handler(
     payload: Json<RevokeAllSessionsPayload>,
 ) -> Result<Json<RevokeAllSessionsResult>> {
     // revoke all the sessions
-    match Session::revoke_all(&state.mongo_client, &payload.uid).await {
+    match Session::revoke_all(state.store.as_ref(), &payload.uid).await {
         Ok(_) => {
             return Ok(Json(RevokeAllSessionsResult {
                 message: "All sessions revoked successfully".to_string(),
@@ -205,7 +206,7 @@ pub async fn delete_handler(
         });
     }
 
-    match Session::delete(&state.mongo_client, &payload.session_id, &payload.uid).await {
+    match Session::delete(state.store.as_ref(), &payload.session_id, &payload.uid).await {
         Ok(_) => {
             return Ok(Json(DeleteSessionsResult {
                 message: "Session deleted successfully".to_string(),
@@ -227,7 +228,7 @@ pub async fn delete_all_handler(
         });
     }
 
-    match Session::delete_all(&state.mongo_client, &payload.uid).await {
+    match Session::delete_all(state.store.as_ref(), &payload.uid).await {
         Ok(_) => {
             return Ok(Json(DeleteAllSessionsResult {
                 message: "All sessions deleted successfully".to_string(),
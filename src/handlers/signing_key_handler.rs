@@ -0,0 +1,39 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::keyring::{Keyring, SigningKey},
+    errors::Result,
+    models::signing_key_model::RotateSigningKeyResult,
+    AppState,
+};
+
+#[debug_handler]
+pub async fn get_all_signing_keys_handler() -> Result<Json<Vec<SigningKey>>> {
+    println!(">> HANDLER: get_all_signing_keys_handler called");
+
+    match Keyring::load() {
+        Ok(keyring) => Ok(Json(keyring.keys)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn rotate_signing_key_handler(
+    State(state): State<AppState>,
+) -> Result<Json<RotateSigningKeyResult>> {
+    println!(">> HANDLER: rotate_signing_key_handler called");
+
+    let key = match Keyring::rotate() {
+        Ok(key) => key,
+        Err(e) => return Err(e),
+    };
+    // sign with the new key right away instead of waiting for the watcher
+    match state.keys.reload() {
+        Ok(_) => Ok(Json(RotateSigningKeyResult {
+            message: "Signing key rotated successfully".to_string(),
+            key,
+        })),
+        Err(e) => Err(e),
+    }
+}
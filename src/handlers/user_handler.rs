@@ -1,27 +1,27 @@
 use crate::{
-    core::{dek::Dek, session::Session, user::User},
+    core::{dek_rotation::DekRotation, session::Session, user::User},
     errors::{Error, Result},
     models::user_model::{
-        BlockUserResponse, EmailVerificationResponse, RecentUserPayload, ToggleUserActivationStatusPayload, ToggleUserActivationStatusResponse, UpdateUserPayload, UpdateUserResponse, UpdateUserRolePayload, UpdateUserRoleResponse, UserEmailPayload, UserEmailResponse, UserIdPayload, UserResponse
+        BlockUserResponse, EmailVerificationResponse, RecentUserPayload, ToggleUserActivationStatusPayload, ToggleUserActivationStatusResponse, UpdatePhonePayload, UpdateUserPayload, UpdateUserResponse, UpdateUserRolePayload, UpdateUserRoleResponse, UserEmailPayload, UserEmailResponse, UserIdPayload, UserResponse, VerifyPhonePayload
     },
-    utils::{encryption_utils::Encryption, validation_utils::Validation},
+    utils::validation_utils::Validation,
     AppState,
 };
 use axum::{
     extract::{Path, State},
+    http::{header, HeaderMap},
     response::{Html, IntoResponse},
     Json,
 };
 use axum_macros::debug_handler;
-use bson::{doc, DateTime};
-use mongodb::Collection;
+use serde_json::{json, Value};
 
 pub async fn get_all_users_handler(
     State(state): State<AppState>,
 ) -> Result<Json<Vec<UserResponse>>> {
     println!(">> HANDLER: get_user_handler called");
 
-    match User::get_all(&state.mongo_client).await {
+    match User::get_all(state.store.as_ref()).await {
         Ok(users) => Ok(Json(users)),
         Err(e) => Err(e),
     }
@@ -33,7 +33,7 @@ pub async fn get_recent_users_handler(
 ) -> Result<Json<Vec<UserResponse>>> {
     println!(">> HANDLER: get_recent_users_handler called");
 
-    match User::get_recent(&state.mongo_client, payload.limit).await {
+    match User::get_recent(state.store.as_ref(), payload.limit).await {
         Ok(users) => Ok(Json(users)),
         Err(e) => Err(e),
     }
@@ -58,44 +58,92 @@ pub async fn update_user_handler(
         });
     }
 
-    let db = state.mongo_client.database("auth");
-    let collection: Collection<User> = db.collection("users");
-    let dek_data = match Dek::get(&state.mongo_client, &payload.email).await {
-        Ok(dek) => dek,
-        Err(e) => return Err(e),
+    match User::update_name(state.store.as_ref(), &payload.email, &payload.name).await {
+        Ok(name) => Ok(Json(UpdateUserResponse {
+            email: payload.email.to_owned(),
+            name,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn update_phone_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<UpdatePhonePayload>,
+) -> Result<Json<Value>> {
+    println!(">> HANDLER: update_phone_handler called");
+
+    if payload.email.is_empty() || payload.phone.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    if !Validation::phone(&payload.phone) {
+        return Err(Error::InvalidPayload {
+            message: "Invalid phone number, use the E.164 format like +14155552671".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
     };
-    // find the user in the users collection using the uid
-    match collection
-        .update_one(
-            doc! {
-                "uid": &dek_data.uid,
-            },
-            doc! {
-                "$set": {
-                    "name": Encryption::encrypt_data(&payload.name, &dek_data.dek),
-                    "updated_at": DateTime::now(),
-                }
-            },
-            None,
-        )
-        .await
-    {
-        Ok(res) => {
-            if res.modified_count == 0 {
-                return Err(Error::UserNotFound {
-                    message: "User not found".to_string(),
-                });
-            }
-            Ok(Json(UpdateUserResponse {
-                email: payload.email.to_owned(),
-                name: payload.name.to_owned(),
-            }))
-        }
-        Err(e) => {
-            return Err(Error::ServerError {
-                message: e.to_string(),
-            })
-        }
+
+    match User::update_phone(state.store.as_ref(), &payload.email, &payload.phone, &user_agent).await {
+        Ok(_) => Ok(Json(json!({
+            "message": "Verification code sent to the phone number"
+        }))),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn verify_phone_handler(
+    State(state): State<AppState>,
+    header: HeaderMap,
+    payload: Json<VerifyPhonePayload>,
+) -> Result<Json<Value>> {
+    println!(">> HANDLER: verify_phone_handler called");
+
+    if payload.email.is_empty() || payload.code.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    // get user-agent form the header
+    let user_agent = match header.get(header::USER_AGENT) {
+        Some(ua) => ua.to_str().unwrap().to_string(),
+        None => "".to_string(),
+    };
+
+    match User::verify_phone(state.store.as_ref(), &payload.email, &payload.code, &user_agent).await {
+        Ok(_) => Ok(Json(json!({
+            "message": "Phone number verified"
+        }))),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn remove_phone_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<Value>> {
+    println!(">> HANDLER: remove_phone_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match User::remove_phone(state.store.as_ref(), &payload.email).await {
+        Ok(_) => Ok(Json(json!({
+            "message": "Phone number removed"
+        }))),
+        Err(e) => Err(e),
     }
 }
 
@@ -118,7 +166,7 @@ pub async fn update_user_role_handler(
         });
     }
 
-    match User::update_role(&State(state).mongo_client, &payload.email, &payload.role).await {
+    match User::update_role(state.store.as_ref(), &payload.email, &payload.role).await {
         Ok(role) => {
             return Ok(Json(UpdateUserRoleResponse {
                 message: "User role updated".to_string(),
@@ -152,7 +200,7 @@ pub async fn toggle_user_activation_status(
     }
 
     match User::toggle_account_activation(
-        &State(state).mongo_client,
+        state.store.as_ref(),
         &payload.email,
         &payload.is_active.unwrap(),
     )
@@ -182,7 +230,7 @@ pub async fn get_user_email_handler(
         });
     }
 
-    match User::get_from_email(&state.mongo_client, &payload.email).await {
+    match User::get_from_email(state.store.as_ref(), &payload.email).await {
         Ok(user) => {
             return Ok(Json(UserResponse {
                 uid: user.uid,
@@ -192,6 +240,8 @@ pub async fn get_user_email_handler(
                 is_active: user.is_active,
                 email_verified: user.email_verified,
                 blocked_until: user.blocked_until,
+                phone: user.phone,
+                phone_verified: user.phone_verified,
                 created_at: user.created_at,
                 updated_at: user.updated_at,
             }))
@@ -212,7 +262,7 @@ pub async fn get_user_id_handler(
         });
     }
 
-    match User::get_from_uid(&state.mongo_client, &payload.uid).await {
+    match User::get_from_uid(state.store.as_ref(), &payload.uid).await {
         Ok(user) => {
             return Ok(Json(UserResponse {
                 uid: user.uid,
@@ -222,6 +272,8 @@ pub async fn get_user_id_handler(
                 is_active: user.is_active,
                 email_verified: user.email_verified,
                 blocked_until: user.blocked_until,
+                phone: user.phone,
+                phone_verified: user.phone_verified,
                 created_at: user.created_at,
                 updated_at: user.updated_at,
             }))
@@ -243,7 +295,7 @@ pub async fn verify_email_request_handler(
         });
     }
 
-    match User::verify_email_request(&State(&state).mongo_client, &payload.email).await {
+    match User::verify_email_request(state.store.as_ref(), &payload.email).await {
         Ok(_) => {
             return Ok(Json(UserEmailResponse {
                 message: "Verification email sent".to_string(),
@@ -261,7 +313,7 @@ pub async fn verify_email_handler(
 ) -> Result<Json<EmailVerificationResponse>> {
     println!(">> HANDLER: verify_email_handler called");
 
-    match User::verify_email(&State(&state).mongo_client, &id).await {
+    match User::verify_email(state.store.as_ref(), &id).await {
         Ok(req_id) => {
             return Ok(Json(EmailVerificationResponse {
                 message: "Email verified successfully".to_string(),
@@ -284,9 +336,9 @@ pub async fn delete_user_handler(
         });
     }
 
-    match User::delete(&State(&state).mongo_client, &payload.email).await {
+    match User::delete(state.store.as_ref(), &payload.email).await {
         Ok(uid) => {
-            match Session::delete_all(&State(&state).mongo_client, &uid).await {
+            match Session::delete_all(state.store.as_ref(), &uid).await {
                 Ok(_) => {}
                 Err(e) => return Err(e),
             }
@@ -299,6 +351,27 @@ pub async fn delete_user_handler(
     }
 }
 
+pub async fn rotate_dek_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<UserEmailResponse>> {
+    println!(">> HANDLER: rotate_dek_handler called");
+
+    if !Validation::email(&payload.email) {
+        return Err(Error::InvalidPayload {
+            message: "Invalid Email".to_string(),
+        });
+    }
+
+    match DekRotation::rotate(state.store.as_ref(), &payload.email).await {
+        Ok(_) => Ok(Json(UserEmailResponse {
+            message: "DEK rotated".to_string(),
+            email: payload.email.to_owned(),
+        })),
+        Err(e) => Err(e),
+    }
+}
+
 #[debug_handler]
 pub async fn block_user_handler(
     Path(id): Path<String>,
@@ -306,7 +379,7 @@ pub async fn block_user_handler(
 ) -> Result<Json<BlockUserResponse>> {
     println!(">> HANDLER: block_request called");
 
-    match User::block(&State(&state).mongo_client, &id).await {
+    match User::block(state.store.as_ref(), &id).await {
         Ok(_) => {
             return Ok(Json(BlockUserResponse {
                 message: "User blocked".to_string(),
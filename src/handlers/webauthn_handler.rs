@@ -0,0 +1,104 @@
+use axum::{extract::State, Json};
+use axum_macros::debug_handler;
+
+use crate::{
+    core::webauthn::Webauthn,
+    errors::{Error, Result},
+    models::{
+        user_model::UserEmailPayload,
+        webauthn_model::{
+            CreationOptionsResponse, WebauthnCredentialIdPayload, WebauthnCredentialResponse,
+            WebauthnRegisterPayload, WebauthnRegisterResult, WebauthnResult,
+        },
+    },
+    AppState,
+};
+
+#[debug_handler]
+pub async fn registration_options_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<CreationOptionsResponse>> {
+    println!(">> HANDLER: registration_options_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Webauthn::registration_options(state.store.as_ref(), &payload.email).await {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn register_handler(
+    State(state): State<AppState>,
+    payload: Json<WebauthnRegisterPayload>,
+) -> Result<Json<WebauthnRegisterResult>> {
+    println!(">> HANDLER: register_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Webauthn::register(
+        state.store.as_ref(),
+        &payload.email,
+        &payload.name,
+        &payload.credential,
+    )
+    .await
+    {
+        Ok((credential, recovery_codes)) => Ok(Json(WebauthnRegisterResult {
+            message: "Passkey registered".to_string(),
+            credential: credential.to_response(),
+            recovery_codes,
+        })),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn list_credentials_handler(
+    State(state): State<AppState>,
+    payload: Json<UserEmailPayload>,
+) -> Result<Json<Vec<WebauthnCredentialResponse>>> {
+    println!(">> HANDLER: list_credentials_handler called");
+
+    if payload.email.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Webauthn::list(state.store.as_ref(), &payload.email).await {
+        Ok(credentials) => Ok(Json(credentials)),
+        Err(e) => Err(e),
+    }
+}
+
+#[debug_handler]
+pub async fn delete_credential_handler(
+    State(state): State<AppState>,
+    payload: Json<WebauthnCredentialIdPayload>,
+) -> Result<Json<WebauthnResult>> {
+    println!(">> HANDLER: delete_credential_handler called");
+
+    if payload.email.is_empty() || payload.credential_id.is_empty() {
+        return Err(Error::InvalidPayload {
+            message: "Invalid payload".to_string(),
+        });
+    }
+
+    match Webauthn::delete(state.store.as_ref(), &payload.email, &payload.credential_id).await {
+        Ok(_) => Ok(Json(WebauthnResult {
+            message: "Passkey deleted".to_string(),
+        })),
+        Err(e) => Err(e),
+    }
+}
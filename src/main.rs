@@ -1,5 +1,6 @@
 use axum::extract::State;
 use axum::response::Html;
+use axum::http::{header, Method};
 use axum::routing::get;
 use axum::{middleware, Router};
 use dotenv::dotenv;
@@ -7,9 +8,14 @@ use handlers::password_handler::forget_password_form;
 use handlers::user_handler::{show_block_user_page, show_verification_page_email};
 use middlewares::res_log::main_response_mapper;
 use middlewares::with_api_key::with_api_key;
-use mongodb::Client;
+use core::{key_cache::KeyCache, keyring::Keyring};
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::{Any, CorsLayer};
+use traits::store::Store;
 
+mod commands;
 mod config;
 mod core;
 mod errors;
@@ -17,22 +23,87 @@ mod handlers;
 mod middlewares;
 mod models;
 mod routes;
+mod store;
 mod traits;
 mod utils;
 
 #[derive(Clone)]
 struct AppState {
-    mongo_client: Client,
+    store: Arc<dyn Store>,
+    keys: KeyCache,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
-    let mongo_client = config::db_connection_handler::connect().await?;
+    // run an admin command instead of the server when one is passed
+    if let Some(command) = std::env::args().nth(1) {
+        return commands::run(&command).await;
+    }
+
+    let keys = KeyCache::load()?;
+    println!(">> Signing key {} is active", keys.signing_key()?.kid);
+    keys.watch();
+    // move the signing keys through pending -> active -> retiring -> retired
+    let scheduled_keys = keys.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match Keyring::run_schedule() {
+                Ok(true) => {
+                    if let Err(e) = scheduled_keys.reload() {
+                        eprintln!(">> Error reloading the signing keys: {:?}", e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!(">> Error running the signing key schedule: {:?}", e),
+            }
+        }
+    });
+
+    let key_provider = core::key_provider::init()?;
+    println!(">> Wrapping the DEKs with KEK {}", key_provider.key_id());
+    core::dek::init_email_index_key()?;
+
+    let store = config::db_connection_handler::connect_store().await?;
+    // deks are looked up by the email index, so the ones without it get it before serving
+    match core::reencryption::Reencryption::index_emails(store.as_ref()).await {
+        Ok(0) => {}
+        Ok(indexed) => println!(">> Indexed the email of {} deks", indexed),
+        Err(e) => eprintln!(">> Error indexing the emails of the deks: {:?}", e),
+    }
     // init users if not exists
-    config::init::init_users(mongo_client.clone()).await;
+    config::init::init_users(store.as_ref()).await;
+    // move the data still encrypted in the legacy format to the current one
+    let reencryption_store = store.clone();
+    tokio::spawn(async move {
+        match core::reencryption::Reencryption::run(reencryption_store.as_ref()).await {
+            Ok(report) => println!(
+                ">> Re-encrypted {} deks, {} users and {} sessions, {} values failed",
+                report.deks, report.users, report.sessions, report.failed
+            ),
+            Err(e) => eprintln!(">> Error re-encrypting the legacy data: {:?}", e),
+        }
+    });
 
-    let app_state = AppState { mongo_client };
+    // give the users new DEKs once theirs are older than DEK_ROTATION_DAYS
+    if let Some(days) = core::dek_rotation::rotation_days() {
+        let rotation_store = store.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                match core::dek_rotation::DekRotation::run_policy(rotation_store.as_ref(), days).await {
+                    Ok(0) => {}
+                    Ok(rotated) => println!(">> Rotated the DEKs of {} users", rotated),
+                    Err(e) => eprintln!(">> Error running the DEK rotation policy: {:?}", e),
+                }
+            }
+        });
+    }
+
+    let app_state = AppState { store, keys };
     // Define routes where middleware is applied
     let protected_routes = Router::new()
         .merge(routes::auth_routes::routes(State(app_state.clone())))
@@ -40,9 +111,32 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .merge(routes::password_routes::routes(State(app_state.clone())))
         .merge(routes::session_routes::routes(State(app_state.clone())))
         .merge(routes::overview_routes::routes(State(app_state.clone())))
+        .merge(routes::signing_key_routes::routes(State(app_state.clone())))
+        .merge(routes::oauth_client_routes::routes(State(app_state.clone())))
+        .merge(routes::service_account_routes::routes(State(app_state.clone())))
+        .merge(routes::mfa_routes::routes(State(app_state.clone())))
+        .merge(routes::webauthn_routes::routes(State(app_state.clone())))
+        .merge(routes::identity_provider_routes::routes(State(app_state.clone())))
+        .merge(routes::federation_routes::routes(State(app_state.clone())))
+        .merge(routes::identity_routes::routes(State(app_state.clone())))
+        .merge(routes::saml_connection_routes::routes(State(app_state.clone())))
+        .merge(routes::saml_routes::routes(State(app_state.clone())))
+        .merge(routes::ldap_connection_routes::routes(State(app_state.clone())))
         .layer(middleware::map_response(main_response_mapper))
         .layer(middleware::from_fn(with_api_key));
 
+    // endpoints called from the browser by single page apps of other origins
+    let oauth_routes = Router::new()
+        .merge(routes::jwks_routes::routes(State(app_state.clone())))
+        .merge(routes::oidc_routes::routes(State(app_state.clone())))
+        .merge(routes::oauth_routes::routes(State(app_state.clone())))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(Any)
+                .allow_methods([Method::GET, Method::POST])
+                .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]),
+        );
+
     // Define routes where middleware is not applied
     let public_routes = Router::new()
         .route("/", get(root_handler))
@@ -50,6 +144,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .route("/verify-email/:id", get(show_verification_page_email))
         .route("/block-account/:id", get(show_block_user_page))
         .merge(routes::health_check_routes::routes())
+        .merge(routes::federation_routes::callback_routes(State(app_state.clone())))
+        .merge(routes::saml_routes::public_routes(State(app_state.clone())))
+        .merge(oauth_routes)
         .layer(middleware::map_response(main_response_mapper));
 
     // Combine public and protected routes
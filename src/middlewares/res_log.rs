@@ -1,7 +1,7 @@
 use std::time::SystemTime;
 
 use crate::errors::{ClientError, Error};
-use axum::{http::{Method, Uri}, response::IntoResponse, Json};
+use axum::{http::{header, Method, Uri}, response::IntoResponse, Json};
 use serde::Serialize;
 use serde_json::{json, Value};
 use serde_with::skip_serializing_none;
@@ -79,7 +79,14 @@ pub async fn main_response_mapper(
         });
 
         println!(">> Client Error: {:?}", client_error_body);
-        (*status, Json(client_error_body)).into_response()
+        let mut error_response = (*status, Json(client_error_body)).into_response();
+        // keep the auth challenge set by the handler e.g. on /userinfo
+        if let Some(challenge) = res.headers().get(header::WWW_AUTHENTICATE) {
+            error_response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, challenge.clone());
+        }
+        error_response
     });
 
     println!(">> Server Log line - {uuid} - Error: {error:?}", uuid = uuid, error = client_status_error);
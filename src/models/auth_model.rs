@@ -1,6 +1,8 @@
 use bson::DateTime;
 use serde::{Deserialize, Serialize};
 
+use crate::models::webauthn_model::AssertionCredential;
+
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct SignUpPayload {
     pub name: String,
@@ -15,6 +17,24 @@ pub struct SignInPayload {
     pub password: String,
 }
 
+// the link in the email goes to redirect_url?token=...
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MagicLinkRequestPayload {
+    pub email: String,
+    pub redirect_url: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MagicLinkSignInPayload {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OtpSignInPayload {
+    pub email: String,
+    pub code: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 
 pub struct SessionResponseForSignInOrSignUp {
@@ -36,3 +56,37 @@ pub struct SignInOrSignUpResponse {
     pub is_active: bool,
     pub session: SessionResponseForSignInOrSignUp,
 }
+
+// returned by a sign in with the right password when the user has a second factor
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MfaChallengeResponse {
+    pub message: String,
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub methods: Vec<String>,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SignInResponse {
+    Session(SignInOrSignUpResponse),
+    MfaRequired(MfaChallengeResponse),
+}
+
+// one of the TOTP code, a recovery code or a WebAuthn assertion
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MfaSignInPayload {
+    pub mfa_token: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub recovery_code: String,
+    #[serde(default)]
+    pub webauthn: Option<AssertionCredential>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MfaTokenPayload {
+    pub mfa_token: String,
+}
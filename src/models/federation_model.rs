@@ -0,0 +1,108 @@
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateIdentityProviderPayload {
+    pub provider_id: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    // "openid email profile" when not given
+    pub scope: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdentityProviderIdPayload {
+    pub provider_id: String,
+}
+
+// what the admin api shows of a provider, never the secret
+#[derive(Serialize, Debug, Clone)]
+pub struct IdentityProviderResponse {
+    pub provider_id: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub scope: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CreateIdentityProviderResult {
+    pub message: String,
+    pub provider: IdentityProviderResponse,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeleteIdentityProviderResult {
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FederationStartPayload {
+    pub provider_id: String,
+    pub redirect_url: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FederationStartResponse {
+    pub authorization_url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FederationSignInPayload {
+    pub token: String,
+}
+
+// the query the provider redirects back to GET /federation/callback with
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FederationCallbackParams {
+    pub code: String,
+    pub state: String,
+    pub error: String,
+}
+
+// re-authenticates with the password, or without it right after signing in
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdentityLinkPayload {
+    pub id_token: String,
+    pub password: Option<String>,
+    pub provider_id: String,
+    pub redirect_url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdentityUnlinkPayload {
+    pub id_token: String,
+    pub password: Option<String>,
+    pub provider_id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdentityListPayload {
+    pub id_token: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UserIdentityResponse {
+    pub provider_id: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime,
+}
+
+// magic links and email codes are left out, they work for every user
+#[derive(Serialize, Debug, Clone)]
+pub struct LoginMethodsResponse {
+    pub password: bool,
+    pub passkeys: usize,
+    pub identities: Vec<UserIdentityResponse>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IdentityResult {
+    pub message: String,
+}
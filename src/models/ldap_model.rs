@@ -0,0 +1,63 @@
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::core::ldap_connection::LdapRoleMapping;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateLdapConnectionPayload {
+    pub connection_id: String,
+    pub name: String,
+    // ldap://host:389 or ldaps://host:636
+    pub url: String,
+    #[serde(default)]
+    pub start_tls: bool,
+    // the DN to bind as, with {username} and {email} filled in, e.g. "uid={username},ou=people,dc=acme,dc=com"
+    pub bind_dn: String,
+    // where to search for the user after binding, the bind DN is read when not given
+    pub search_base: Option<String>,
+    // "(mail={email})" when not given
+    pub user_filter: Option<String>,
+    // users with an email at these domains sign in with their directory password
+    pub domains: Vec<String>,
+    // "cn" and "memberOf" when not given
+    pub name_attribute: Option<String>,
+    pub group_attribute: Option<String>,
+    #[serde(default)]
+    pub role_mapping: Vec<LdapRoleMapping>,
+    // "user" when not given
+    pub default_role: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LdapConnectionIdPayload {
+    pub connection_id: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LdapConnectionResponse {
+    pub connection_id: String,
+    pub name: String,
+    pub url: String,
+    pub start_tls: bool,
+    pub bind_dn: String,
+    pub search_base: String,
+    pub user_filter: String,
+    pub domains: Vec<String>,
+    pub name_attribute: String,
+    pub group_attribute: String,
+    pub role_mapping: Vec<LdapRoleMapping>,
+    pub default_role: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CreateLdapConnectionResult {
+    pub message: String,
+    pub connection: LdapConnectionResponse,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeleteLdapConnectionResult {
+    pub message: String,
+}
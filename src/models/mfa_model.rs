@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TotpCodePayload {
+    pub email: String,
+    pub code: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TotpEnrollResponse {
+    pub message: String,
+    // for users who can't scan the QR code
+    pub secret: String,
+    // render this as a QR code for the authenticator app
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RecoveryCodesResponse {
+    pub message: String,
+    // shown only this once, the user has to store them somewhere safe. Empty when
+    // the user already got them with another second factor.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RecoveryCodesRemainingResponse {
+    pub remaining: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MfaResult {
+    pub message: String,
+}
@@ -1,5 +1,14 @@
 pub mod auth_model;
+pub mod federation_model;
+pub mod ldap_model;
+pub mod mfa_model;
+pub mod oauth_model;
+pub mod oidc_model;
 pub mod overview_model;
 pub mod password_model;
+pub mod saml_model;
+pub mod service_account_model;
 pub mod session_model;
+pub mod signing_key_model;
 pub mod user_model;
+pub mod webauthn_model;
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::oauth_client::OAuthClient;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateOAuthClientPayload {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OAuthClientIdPayload {
+    pub client_id: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CreateOAuthClientResult {
+    pub message: String,
+    pub client: OAuthClient,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeleteOAuthClientResult {
+    pub message: String,
+}
+
+// the query of GET /oauth/authorize, it is carried through the login form as hidden fields
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AuthorizeParams {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub state: String,
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+    pub nonce: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AuthorizeForm {
+    #[serde(flatten)]
+    pub params: AuthorizeParams,
+    pub email: String,
+    pub password: String,
+}
+
+// the second step of the hosted login for users with MFA
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AuthorizeMfaForm {
+    #[serde(flatten)]
+    pub params: AuthorizeParams,
+    pub mfa_token: String,
+    pub code: String,
+    pub recovery_code: String,
+    // the JSON of a passkey assertion, see the verify page
+    pub webauthn: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TokenPayload {
+    pub grant_type: String,
+    pub code: String,
+    pub redirect_uri: String,
+    pub client_id: String,
+    pub code_verifier: String,
+    // client_credentials grant
+    pub client_secret: String,
+    pub scope: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TokenResponse {
+    // for users the ID token doubles as the access token for /userinfo and /session/verify
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    // not issued to service accounts, they have no user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    // only handed out when the offline_access scope was granted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct IntrospectPayload {
+    pub token: String,
+    // the service account calling the endpoint when it doesn't use basic auth
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RevokePayload {
+    pub token: String,
+}
+
+// RFC 7662 section 2.2, an inactive token only has `active: false`
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    // the session the token belongs to, service account tokens have none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+}
@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub jwks_uri: String,
+    pub userinfo_endpoint: String,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UserInfoResponse {
+    pub sub: String,
+    pub name: String,
+    pub email: String,
+    pub email_verified: bool,
+}
@@ -4,6 +4,9 @@ use serde::{Deserialize, Serialize};
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct ForgetPasswordRequest {
     pub _id: ObjectId,
+    // empty for the requests from before it was stored, they expired long ago
+    #[serde(default)]
+    pub uid: String,
     pub email: String,
     pub req_id: String,
     pub is_used: bool,
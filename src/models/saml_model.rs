@@ -0,0 +1,74 @@
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::core::saml_connection::SamlRoleMapping;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateSamlConnectionPayload {
+    pub connection_id: String,
+    pub name: String,
+    // the metadata of the identity provider, or the url to load it from
+    pub metadata_xml: Option<String>,
+    pub metadata_url: Option<String>,
+    // only users with an email at these domains can sign in through the connection
+    pub domains: Vec<String>,
+    // the NameID when not given
+    pub email_attribute: Option<String>,
+    pub name_attribute: Option<String>,
+    pub role_attribute: Option<String>,
+    #[serde(default)]
+    pub role_mapping: Vec<SamlRoleMapping>,
+    // "user" when not given
+    pub default_role: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SamlConnectionIdPayload {
+    pub connection_id: String,
+}
+
+// what the admin api shows of a connection, with what the identity provider has to be set up with
+#[derive(Serialize, Debug, Clone)]
+pub struct SamlConnectionResponse {
+    pub connection_id: String,
+    pub name: String,
+    pub idp_entity_id: String,
+    pub sso_url: String,
+    pub sp_entity_id: String,
+    pub acs_url: String,
+    pub domains: Vec<String>,
+    pub email_attribute: String,
+    pub name_attribute: String,
+    pub role_attribute: String,
+    pub role_mapping: Vec<SamlRoleMapping>,
+    pub default_role: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CreateSamlConnectionResult {
+    pub message: String,
+    pub connection: SamlConnectionResponse,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeleteSamlConnectionResult {
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SamlStartPayload {
+    pub connection_id: String,
+    pub redirect_url: String,
+}
+
+// the form the identity provider posts to POST /saml/acs/:connection_id
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SamlAcsForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+    #[serde(rename = "RelayState")]
+    pub relay_state: String,
+}
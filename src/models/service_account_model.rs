@@ -0,0 +1,35 @@
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateServiceAccountPayload {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServiceAccountIdPayload {
+    pub client_id: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ServiceAccountResponse {
+    pub client_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+// the secret is only ever shown here, right after it was created or rotated
+#[derive(Serialize, Debug, Clone)]
+pub struct ServiceAccountSecretResult {
+    pub message: String,
+    pub service_account: ServiceAccountResponse,
+    pub client_secret: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeleteServiceAccountResult {
+    pub message: String,
+}
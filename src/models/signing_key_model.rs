@@ -0,0 +1,9 @@
+use serde::Serialize;
+
+use crate::core::keyring::SigningKey;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RotateSigningKeyResult {
+    pub message: String,
+    pub key: SigningKey,
+}
@@ -37,6 +37,18 @@ pub struct UpdateUserPayload {
     pub email: String,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct UpdatePhonePayload {
+    pub email: String,
+    pub phone: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct VerifyPhonePayload {
+    pub email: String,
+    pub code: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UpdateUserResponse {
     pub email: String,
@@ -77,6 +89,8 @@ pub struct UserResponse {
     pub email_verified: bool,
     pub is_active: bool,
     pub blocked_until: Option<DateTime>,
+    pub phone: Option<String>,
+    pub phone_verified: bool,
     pub created_at: Option<DateTime>,
     pub updated_at: Option<DateTime>,
 }
@@ -0,0 +1,156 @@
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+// The options and credentials use the JSON form of the WebAuthn spec, binary
+// values are base64url. Browsers can pass them straight to
+// PublicKeyCredential.parseCreationOptionsFromJSON / toJSON().
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnUser {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub alg: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub id: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialCreationOptions {
+    pub rp: RelyingParty,
+    pub user: WebauthnUser,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameter>,
+    pub timeout: i64,
+    pub attestation: String,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialRequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub timeout: i64,
+    pub user_verification: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptionsResponse {
+    pub public_key: PublicKeyCredentialCreationOptions,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptionsResponse {
+    pub public_key: PublicKeyCredentialRequestOptions,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+// the result of navigator.credentials.create()
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RegistrationCredential {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+// the result of navigator.credentials.get()
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AssertionCredential {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebauthnRegisterPayload {
+    pub email: String,
+    // shown in the list of passkeys of the user, e.g. "YubiKey"
+    #[serde(default)]
+    pub name: String,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebauthnCredentialIdPayload {
+    pub email: String,
+    pub credential_id: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WebauthnCredentialResponse {
+    pub credential_id: String,
+    pub name: String,
+    pub sign_count: i64,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WebauthnRegisterResult {
+    pub message: String,
+    pub credential: WebauthnCredentialResponse,
+    // only with the first second factor of the user, shown this once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PasskeySignInPayload {
+    pub credential: AssertionCredential,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WebauthnResult {
+    pub message: String,
+}
@@ -1,13 +1,29 @@
 use axum::{extract::State, routing::post, Router};
 
 use crate::{
-    handlers::auth_handler::{signin_handler, signout_handler, signup_handler}, AppState
+    handlers::auth_handler::{
+        email_otp_request_handler, email_otp_signin_handler, magic_link_request_handler,
+        magic_link_signin_handler, passkey_options_handler, passkey_signin_handler,
+        signin_handler, signin_mfa_handler, signin_mfa_webauthn_options_handler,
+        signout_handler, signup_handler, sms_otp_request_handler, sms_otp_signin_handler,
+    },
+    AppState,
 };
 
 pub fn routes(State(state): State<AppState>) -> Router {
     let auth_routes = Router::new()
         .route("/signup", post(signup_handler))
         .route("/signin", post(signin_handler))
+        .route("/signin/mfa", post(signin_mfa_handler))
+        .route("/signin/mfa/webauthn-options", post(signin_mfa_webauthn_options_handler))
+        .route("/magic-link/request", post(magic_link_request_handler))
+        .route("/magic-link/signin", post(magic_link_signin_handler))
+        .route("/email-otp/request", post(email_otp_request_handler))
+        .route("/email-otp/signin", post(email_otp_signin_handler))
+        .route("/sms-otp/request", post(sms_otp_request_handler))
+        .route("/sms-otp/signin", post(sms_otp_signin_handler))
+        .route("/passkey/options", post(passkey_options_handler))
+        .route("/passkey/signin", post(passkey_signin_handler))
         .route("/signout", post(signout_handler));
 
     Router::new().nest("/auth", auth_routes).with_state(state)
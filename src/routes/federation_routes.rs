@@ -0,0 +1,29 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::federation_handler::{
+        federation_callback_handler, federation_signin_handler, federation_start_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let federation_routes = Router::new()
+        .route("/start", post(federation_start_handler))
+        .route("/signin", post(federation_signin_handler));
+
+    Router::new()
+        .nest("/federation", federation_routes)
+        .with_state(state)
+}
+
+// the redirect uri of the providers, it is opened by the browser so it has no api key
+pub fn callback_routes(State(state): State<AppState>) -> Router {
+    Router::new()
+        .route("/federation/callback", get(federation_callback_handler))
+        .with_state(state)
+}
@@ -0,0 +1,24 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::identity_provider_handler::{
+        create_identity_provider_handler, delete_identity_provider_handler,
+        get_all_identity_providers_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let identity_provider_routes = Router::new()
+        .route("/create", post(create_identity_provider_handler))
+        .route("/get-all", get(get_all_identity_providers_handler))
+        .route("/delete", post(delete_identity_provider_handler));
+
+    Router::new()
+        .nest("/identity-provider", identity_provider_routes)
+        .with_state(state)
+}
@@ -0,0 +1,17 @@
+use axum::{extract::State, routing::post, Router};
+
+use crate::{
+    handlers::identity_handler::{
+        link_identity_handler, list_identities_handler, unlink_identity_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let identity_routes = Router::new()
+        .route("/list", post(list_identities_handler))
+        .route("/link", post(link_identity_handler))
+        .route("/unlink", post(unlink_identity_handler));
+
+    Router::new().nest("/identity", identity_routes).with_state(state)
+}
@@ -0,0 +1,9 @@
+use axum::{extract::State, routing::get, Router};
+
+use crate::{handlers::jwks_handler::jwks_handler, AppState};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    Router::new()
+        .route("/.well-known/jwks.json", get(jwks_handler))
+        .with_state(state)
+}
@@ -0,0 +1,24 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::ldap_connection_handler::{
+        create_ldap_connection_handler, delete_ldap_connection_handler,
+        get_all_ldap_connections_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let ldap_connection_routes = Router::new()
+        .route("/create", post(create_ldap_connection_handler))
+        .route("/get-all", get(get_all_ldap_connections_handler))
+        .route("/delete", post(delete_ldap_connection_handler));
+
+    Router::new()
+        .nest("/ldap-connection", ldap_connection_routes)
+        .with_state(state)
+}
@@ -0,0 +1,20 @@
+use axum::{extract::State, routing::post, Router};
+
+use crate::{
+    handlers::mfa_handler::{
+        confirm_totp_handler, disable_totp_handler, enroll_totp_handler,
+        recovery_codes_remaining_handler, regenerate_recovery_codes_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let mfa_routes = Router::new()
+        .route("/totp/enroll", post(enroll_totp_handler))
+        .route("/totp/confirm", post(confirm_totp_handler))
+        .route("/totp/disable", post(disable_totp_handler))
+        .route("/recovery-codes/regenerate", post(regenerate_recovery_codes_handler))
+        .route("/recovery-codes/remaining", post(recovery_codes_remaining_handler));
+
+    Router::new().nest("/mfa", mfa_routes).with_state(state)
+}
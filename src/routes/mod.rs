@@ -1,6 +1,20 @@
 pub mod auth_routes;
+pub mod federation_routes;
 pub mod health_check_routes;
+pub mod identity_provider_routes;
+pub mod identity_routes;
+pub mod jwks_routes;
+pub mod ldap_connection_routes;
+pub mod mfa_routes;
+pub mod oauth_client_routes;
+pub mod oauth_routes;
+pub mod oidc_routes;
 pub mod overview_routes;
 pub mod password_routes;
+pub mod saml_connection_routes;
+pub mod saml_routes;
+pub mod service_account_routes;
 pub mod session_routes;
+pub mod signing_key_routes;
 pub mod user_routes;
+pub mod webauthn_routes;
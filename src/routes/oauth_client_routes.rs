@@ -0,0 +1,23 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::oauth_client_handler::{
+        create_oauth_client_handler, delete_oauth_client_handler, get_all_oauth_clients_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let oauth_client_routes = Router::new()
+        .route("/create", post(create_oauth_client_handler))
+        .route("/get-all", get(get_all_oauth_clients_handler))
+        .route("/delete", post(delete_oauth_client_handler));
+
+    Router::new()
+        .nest("/oauth-client", oauth_client_routes)
+        .with_state(state)
+}
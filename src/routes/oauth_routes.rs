@@ -0,0 +1,28 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::oauth_handler::{
+        authorize_handler, authorize_mfa_handler, authorize_page_handler,
+        authorize_webauthn_options_handler, introspect_handler, revoke_handler, token_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let oauth_routes = Router::new()
+        .route(
+            "/authorize",
+            get(authorize_page_handler).post(authorize_handler),
+        )
+        .route("/authorize/mfa", post(authorize_mfa_handler))
+        .route("/authorize/webauthn-options", post(authorize_webauthn_options_handler))
+        .route("/token", post(token_handler))
+        .route("/introspect", post(introspect_handler))
+        .route("/revoke", post(revoke_handler));
+
+    Router::new().nest("/oauth", oauth_routes).with_state(state)
+}
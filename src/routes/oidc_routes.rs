@@ -0,0 +1,16 @@
+use axum::{extract::State, routing::get, Router};
+
+use crate::{
+    handlers::oidc_handler::{openid_configuration_handler, userinfo_handler},
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(openid_configuration_handler),
+        )
+        .route("/userinfo", get(userinfo_handler).post(userinfo_handler))
+        .with_state(state)
+}
@@ -0,0 +1,24 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::saml_connection_handler::{
+        create_saml_connection_handler, delete_saml_connection_handler,
+        get_all_saml_connections_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let saml_connection_routes = Router::new()
+        .route("/create", post(create_saml_connection_handler))
+        .route("/get-all", get(get_all_saml_connections_handler))
+        .route("/delete", post(delete_saml_connection_handler));
+
+    Router::new()
+        .nest("/saml-connection", saml_connection_routes)
+        .with_state(state)
+}
@@ -0,0 +1,26 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::saml_handler::{saml_acs_handler, saml_metadata_handler, saml_start_handler},
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let saml_routes = Router::new().route("/start", post(saml_start_handler));
+
+    Router::new()
+        .nest("/saml", saml_routes)
+        .with_state(state)
+}
+
+// used by the identity providers and the browser, so they have no api key
+pub fn public_routes(State(state): State<AppState>) -> Router {
+    Router::new()
+        .route("/saml/acs/:connection_id", post(saml_acs_handler))
+        .route("/saml/metadata/:connection_id", get(saml_metadata_handler))
+        .with_state(state)
+}
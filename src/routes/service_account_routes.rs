@@ -0,0 +1,25 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::service_account_handler::{
+        create_service_account_handler, delete_service_account_handler,
+        get_all_service_accounts_handler, rotate_service_account_secret_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let service_account_routes = Router::new()
+        .route("/create", post(create_service_account_handler))
+        .route("/get-all", get(get_all_service_accounts_handler))
+        .route("/rotate-secret", post(rotate_service_account_secret_handler))
+        .route("/delete", post(delete_service_account_handler));
+
+    Router::new()
+        .nest("/service-account", service_account_routes)
+        .with_state(state)
+}
@@ -0,0 +1,20 @@
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    handlers::signing_key_handler::{get_all_signing_keys_handler, rotate_signing_key_handler},
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let signing_key_routes = Router::new()
+        .route("/get-all", get(get_all_signing_keys_handler))
+        .route("/rotate", post(rotate_signing_key_handler));
+
+    Router::new()
+        .nest("/signing-key", signing_key_routes)
+        .with_state(state)
+}
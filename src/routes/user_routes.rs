@@ -4,7 +4,7 @@ use axum::{
 
 use crate::{
     handlers::user_handler::{
-        block_user_handler, delete_user_handler, get_all_users_handler, get_recent_users_handler, get_user_email_handler, get_user_id_handler, toggle_user_activation_status, update_user_handler, update_user_role_handler, verify_email_handler, verify_email_request_handler
+        block_user_handler, delete_user_handler, get_all_users_handler, get_recent_users_handler, get_user_email_handler, get_user_id_handler, remove_phone_handler, rotate_dek_handler, toggle_user_activation_status, update_phone_handler, update_user_handler, update_user_role_handler, verify_email_handler, verify_email_request_handler, verify_phone_handler
     }, AppState
 };
 
@@ -23,6 +23,10 @@ pub fn routes(State(state): State<AppState>) -> Router {
             post(toggle_user_activation_status),
         )
         .route("/update-role", post(update_user_role_handler))
+        .route("/phone/update", post(update_phone_handler))
+        .route("/phone/verify", post(verify_phone_handler))
+        .route("/phone/remove", post(remove_phone_handler))
+        .route("/rotate-dek", post(rotate_dek_handler))
         .route("/delete", post(delete_user_handler));
 
     Router::new().nest("/user", user_routes).with_state(state)
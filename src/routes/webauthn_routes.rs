@@ -0,0 +1,19 @@
+use axum::{extract::State, routing::post, Router};
+
+use crate::{
+    handlers::webauthn_handler::{
+        delete_credential_handler, list_credentials_handler, register_handler,
+        registration_options_handler,
+    },
+    AppState,
+};
+
+pub fn routes(State(state): State<AppState>) -> Router {
+    let webauthn_routes = Router::new()
+        .route("/register/options", post(registration_options_handler))
+        .route("/register", post(register_handler))
+        .route("/credentials", post(list_credentials_handler))
+        .route("/credentials/delete", post(delete_credential_handler));
+
+    Router::new().nest("/webauthn", webauthn_routes).with_state(state)
+}
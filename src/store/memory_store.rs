@@ -0,0 +1,855 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use bson::DateTime;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    core::{
+        authorization_code::AuthorizationCode, dek::Dek,
+        dek_rotation::{DekRotation, UserRecords},
+        federation::FederationState,
+        identity_provider::IdentityProvider, ldap_connection::LdapConnection,
+        magic_link::MagicLink,
+        mfa_challenge::MfaChallenge, oauth_client::OAuthClient, otp::OtpCode,
+        rotated_refresh_token::RotatedRefreshToken, saml_connection::SamlConnection,
+        service_account::ServiceAccount, session::Session, user::User,
+        user_identity::UserIdentity,
+        webauthn::{WebauthnChallenge, WebauthnCredential},
+    },
+    errors::{Error, Result},
+    models::{
+        password_model::ForgetPasswordRequest,
+        user_model::{EmailVerificationRequest, UserBlockRequest},
+    },
+    traits::store::{KeyStore, OAuthStore, SessionStore, UserStore},
+};
+
+#[derive(Default)]
+struct Collections {
+    users: Vec<User>,
+    sessions: Vec<Session>,
+    deks: Vec<Dek>,
+    forget_password_requests: Vec<ForgetPasswordRequest>,
+    email_verification_requests: Vec<EmailVerificationRequest>,
+    block_requests: Vec<UserBlockRequest>,
+    oauth_clients: Vec<OAuthClient>,
+    authorization_codes: Vec<AuthorizationCode>,
+    service_accounts: Vec<ServiceAccount>,
+    rotated_refresh_tokens: Vec<RotatedRefreshToken>,
+    mfa_challenges: Vec<MfaChallenge>,
+    webauthn_credentials: Vec<WebauthnCredential>,
+    webauthn_challenges: Vec<WebauthnChallenge>,
+    magic_links: Vec<MagicLink>,
+    otp_codes: Vec<OtpCode>,
+    identity_providers: Vec<IdentityProvider>,
+    saml_connections: Vec<SamlConnection>,
+    ldap_connections: Vec<LdapConnection>,
+    user_identities: Vec<UserIdentity>,
+    federation_states: Vec<FederationState>,
+}
+
+// Keeps everything in process memory. Nothing survives a restart so this is
+// meant for tests and local experiments without a running MongoDB.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    data: Arc<RwLock<Collections>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// replaces the first item matching the predicate, returns false if nothing matched
+fn replace<T: Clone>(items: &mut [T], item: &T, matches: impl Fn(&T) -> bool) -> bool {
+    match items.iter_mut().find(|existing| matches(existing)) {
+        Some(existing) => {
+            *existing = item.clone();
+            true
+        }
+        None => false,
+    }
+}
+
+// sets a field of the first item matching the predicate if it still is current,
+// going through JSON the same way the encryption does
+fn replace_field<T: Serialize + DeserializeOwned>(
+    items: &mut [T],
+    field: &str,
+    current: &str,
+    value: &str,
+    matches: impl Fn(&T) -> bool,
+) -> Result<bool> {
+    let existing = match items.iter_mut().find(|existing| matches(existing)) {
+        Some(existing) => existing,
+        None => return Ok(false),
+    };
+    let mut json = serde_json::to_value(&*existing).map_err(|e| Error::ServerError {
+        message: e.to_string(),
+    })?;
+    match json.get_mut(field) {
+        Some(Value::String(stored)) if stored == current => *stored = value.to_string(),
+        _ => return Ok(false),
+    }
+    *existing = serde_json::from_value(json).map_err(|e| Error::ServerError {
+        message: e.to_string(),
+    })?;
+    Ok(true)
+}
+
+// removes all items matching the predicate and returns how many were removed
+fn remove<T>(items: &mut Vec<T>, matches: impl Fn(&T) -> bool) -> u64 {
+    let before = items.len();
+    items.retain(|item| !matches(item));
+    (before - items.len()) as u64
+}
+
+fn matching<T: Clone>(items: &[T], matches: impl Fn(&T) -> bool) -> Vec<T> {
+    items.iter().filter(|item| matches(item)).cloned().collect()
+}
+
+// the writes check it under the same write lock they write with
+fn check_dek_version(data: &Collections, uid: &str, dek_version: i64) -> Result<()> {
+    match data.deks.iter().find(|d| d.uid == uid) {
+        Some(dek) if dek.version == dek_version => Ok(()),
+        _ => Err(Error::DekRotated {
+            message: "The DEK of the user changed, please try again".to_string(),
+        }),
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn insert_user(&self, user: &User) -> Result<()> {
+        self.data.write().unwrap().users.push(user.clone());
+        Ok(())
+    }
+
+    async fn get_user(&self, uid: &str) -> Result<Option<User>> {
+        let data = self.data.read().unwrap();
+        Ok(data.users.iter().find(|u| u.uid == uid).cloned())
+    }
+
+    async fn get_users(&self) -> Result<Vec<User>> {
+        Ok(self.data.read().unwrap().users.clone())
+    }
+
+    async fn count_users(&self) -> Result<u64> {
+        Ok(self.data.read().unwrap().users.len() as u64)
+    }
+
+    async fn update_user(&self, user: &User, dek_version: i64) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &user.uid, dek_version)?;
+        Ok(replace(&mut data.users, user, |u| u.uid == user.uid))
+    }
+
+    async fn replace_user_field(&self, uid: &str, field: &str, current: &str, value: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        replace_field(&mut data.users, field, current, value, |u| u.uid == uid)
+    }
+
+    async fn set_totp_last_step(&self, uid: &str, step: i64) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        match data
+            .users
+            .iter_mut()
+            .find(|u| u.uid == uid && u.totp_last_step.is_none_or(|last_step| last_step < step))
+        {
+            Some(user) => {
+                user.totp_last_step = Some(step);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn remove_recovery_code(&self, uid: &str, hash: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        let user = match data.users.iter_mut().find(|u| u.uid == uid) {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+        match user.recovery_codes.iter().position(|h| h == hash) {
+            Some(index) => {
+                user.recovery_codes.remove(index);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_user(&self, uid: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.users, |u| u.uid == uid) > 0)
+    }
+
+    async fn insert_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &request.uid, dek_version)?;
+        data.forget_password_requests.push(request.clone());
+        Ok(())
+    }
+
+    async fn get_forget_password_request(&self, req_id: &str) -> Result<Option<ForgetPasswordRequest>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .forget_password_requests
+            .iter()
+            .find(|r| r.req_id == req_id)
+            .cloned())
+    }
+
+    async fn update_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &request.uid, dek_version)?;
+        Ok(replace(&mut data.forget_password_requests, request, |r| {
+            r.req_id == request.req_id
+        }))
+    }
+
+    async fn insert_email_verification_request(&self, request: &EmailVerificationRequest, dek_version: i64) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &request.uid, dek_version)?;
+        data.email_verification_requests.push(request.clone());
+        Ok(())
+    }
+
+    async fn get_email_verification_request(&self, req_id: &str) -> Result<Option<EmailVerificationRequest>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .email_verification_requests
+            .iter()
+            .find(|r| r.req_id == req_id)
+            .cloned())
+    }
+
+
+    async fn delete_email_verification_request(&self, req_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.email_verification_requests, |r| r.req_id == req_id) > 0)
+    }
+
+    async fn insert_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &request.uid, dek_version)?;
+        data.block_requests.push(request.clone());
+        Ok(())
+    }
+
+    async fn get_block_request(&self, req_id: &str) -> Result<Option<UserBlockRequest>> {
+        let data = self.data.read().unwrap();
+        Ok(data.block_requests.iter().find(|r| r.req_id == req_id).cloned())
+    }
+
+
+    async fn update_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &request.uid, dek_version)?;
+        Ok(replace(&mut data.block_requests, request, |r| {
+            r.req_id == request.req_id
+        }))
+    }
+
+    async fn insert_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &credential.uid, dek_version)?;
+        data.webauthn_credentials.push(credential.clone());
+        Ok(())
+    }
+
+    async fn get_webauthn_credentials(&self, uid: &str) -> Result<Vec<WebauthnCredential>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .webauthn_credentials
+            .iter()
+            .filter(|c| c.uid == uid)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &credential.uid, dek_version)?;
+        Ok(replace(&mut data.webauthn_credentials, credential, |c| {
+            c.uid == credential.uid && c.credential_id == credential.credential_id
+        }))
+    }
+
+    async fn delete_webauthn_credential(&self, uid: &str, credential_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.webauthn_credentials, |c| {
+            c.uid == uid && c.credential_id == credential_id
+        }) > 0)
+    }
+
+    async fn delete_webauthn_credentials_by_uid(&self, uid: &str) -> Result<u64> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.webauthn_credentials, |c| c.uid == uid))
+    }
+
+    async fn insert_user_identity(&self, identity: &UserIdentity, dek_version: i64) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &identity.uid, dek_version)?;
+        if data
+            .user_identities
+            .iter()
+            .any(|i| i.provider_id == identity.provider_id && i.subject_hash == identity.subject_hash)
+        {
+            return Err(Error::IdentityAlreadyLinked {
+                message: "This account is already linked to a user".to_string(),
+            });
+        }
+        data.user_identities.push(identity.clone());
+        Ok(())
+    }
+
+    async fn get_user_identity(&self, subject_hash: &str) -> Result<Option<UserIdentity>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .user_identities
+            .iter()
+            .find(|i| i.subject_hash == subject_hash)
+            .cloned())
+    }
+
+    async fn get_user_identities(&self, uid: &str) -> Result<Vec<UserIdentity>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .user_identities
+            .iter()
+            .filter(|i| i.uid == uid)
+            .cloned()
+            .collect())
+    }
+
+    async fn unlink_user_identity(&self, uid: &str, provider_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        let has_password = data.users.iter().any(|u| u.uid == uid && u.has_password);
+        let has_passkey = data.webauthn_credentials.iter().any(|c| c.uid == uid);
+        let identities = data.user_identities.iter().filter(|i| i.uid == uid).count();
+        if !has_password && !has_passkey && identities <= 1 {
+            return Ok(false);
+        }
+        Ok(remove(&mut data.user_identities, |i| {
+            i.uid == uid && i.provider_id == provider_id
+        }) > 0)
+    }
+
+    async fn delete_user_identities_by_uid(&self, uid: &str) -> Result<u64> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.user_identities, |i| i.uid == uid))
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn insert_session(&self, session: &Session, dek_version: i64) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &session.uid, dek_version)?;
+        data.sessions.push(session.clone());
+        Ok(())
+    }
+
+    async fn get_sessions(&self) -> Result<Vec<Session>> {
+        Ok(self.data.read().unwrap().sessions.clone())
+    }
+
+    async fn get_sessions_by_uid(&self, uid: &str) -> Result<Vec<Session>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .sessions
+            .iter()
+            .filter(|s| s.uid == uid)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_session(&self, session: &Session, dek_version: i64) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &session.uid, dek_version)?;
+        Ok(replace(&mut data.sessions, session, |s| {
+            s.session_id == session.session_id
+        }))
+    }
+
+    async fn refresh_session(&self, session: &Session, current_refresh_token: &str, dek_version: i64) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &session.uid, dek_version)?;
+        match data.sessions.iter_mut().find(|s| {
+            s.session_id == session.session_id && s.refresh_token == current_refresh_token && !s.is_revoked
+        }) {
+            Some(existing) => {
+                existing.id_token = session.id_token.clone();
+                existing.refresh_token = session.refresh_token.clone();
+                existing.updated_at = session.updated_at;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn replace_session_field(&self, session_id: &str, field: &str, current: &str, value: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        replace_field(&mut data.sessions, field, current, value, |s| s.session_id == session_id)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.sessions, |s| s.session_id == session_id) > 0)
+    }
+
+    async fn revoke_sessions_by_uid(&self, uid: &str) -> Result<u64> {
+        let mut data = self.data.write().unwrap();
+        let mut revoked = 0;
+        for session in data.sessions.iter_mut().filter(|s| s.uid == uid) {
+            session.is_revoked = true;
+            revoked += 1;
+        }
+        Ok(revoked)
+    }
+
+    async fn delete_sessions_by_uid(&self, uid: &str) -> Result<u64> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.sessions, |s| s.uid == uid))
+    }
+
+    async fn insert_rotated_refresh_token(&self, token: &RotatedRefreshToken, dek_version: i64) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        check_dek_version(&data, &token.uid, dek_version)?;
+        data.rotated_refresh_tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn get_rotated_refresh_token(&self, token_hash: &str) -> Result<Option<RotatedRefreshToken>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .rotated_refresh_tokens
+            .iter()
+            .find(|t| t.token_hash == token_hash)
+            .cloned())
+    }
+
+
+    async fn delete_expired_rotated_refresh_tokens(&self) -> Result<u64> {
+        let now = DateTime::now();
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.rotated_refresh_tokens, |t| t.expires_at < now))
+    }
+
+    async fn insert_mfa_challenge(&self, challenge: &MfaChallenge) -> Result<()> {
+        self.data.write().unwrap().mfa_challenges.push(challenge.clone());
+        Ok(())
+    }
+
+    async fn get_mfa_challenge(&self, token_hash: &str) -> Result<Option<MfaChallenge>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .mfa_challenges
+            .iter()
+            .find(|c| c.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn count_mfa_challenge_attempt(&self, token_hash: &str, max_attempts: i32) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        match data
+            .mfa_challenges
+            .iter_mut()
+            .find(|c| c.token_hash == token_hash && c.attempts < max_attempts)
+        {
+            Some(existing) => {
+                existing.attempts += 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_mfa_challenge(&self, token_hash: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.mfa_challenges, |c| c.token_hash == token_hash) > 0)
+    }
+
+    async fn delete_expired_mfa_challenges(&self) -> Result<u64> {
+        let now = DateTime::now();
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.mfa_challenges, |c| c.expires_at < now))
+    }
+
+    async fn insert_webauthn_challenge(&self, challenge: &WebauthnChallenge) -> Result<()> {
+        self.data.write().unwrap().webauthn_challenges.push(challenge.clone());
+        Ok(())
+    }
+
+    async fn take_webauthn_challenge(&self, challenge_hash: &str) -> Result<Option<WebauthnChallenge>> {
+        let mut data = self.data.write().unwrap();
+        let position = data
+            .webauthn_challenges
+            .iter()
+            .position(|c| c.challenge_hash == challenge_hash);
+        Ok(position.map(|i| data.webauthn_challenges.remove(i)))
+    }
+
+    async fn delete_expired_webauthn_challenges(&self) -> Result<u64> {
+        let now = DateTime::now();
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.webauthn_challenges, |c| c.expires_at < now))
+    }
+
+    async fn insert_magic_link(&self, magic_link: &MagicLink) -> Result<()> {
+        self.data.write().unwrap().magic_links.push(magic_link.clone());
+        Ok(())
+    }
+
+    async fn take_magic_link(&self, token_hash: &str) -> Result<Option<MagicLink>> {
+        let mut data = self.data.write().unwrap();
+        let position = data
+            .magic_links
+            .iter()
+            .position(|l| l.token_hash == token_hash);
+        Ok(position.map(|i| data.magic_links.remove(i)))
+    }
+
+    async fn delete_expired_magic_links(&self) -> Result<u64> {
+        let now = DateTime::now();
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.magic_links, |l| l.expires_at < now))
+    }
+
+    async fn insert_otp_code(&self, otp: &OtpCode) -> Result<()> {
+        self.data.write().unwrap().otp_codes.push(otp.clone());
+        Ok(())
+    }
+
+    async fn get_otp_code(&self, uid: &str, channel: &str) -> Result<Option<OtpCode>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .otp_codes
+            .iter()
+            .find(|o| o.uid == uid && o.channel == channel)
+            .cloned())
+    }
+
+    async fn count_otp_attempt(&self, otp: &OtpCode, max_attempts: i32) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        match data
+            .otp_codes
+            .iter_mut()
+            .find(|o| o._id == otp._id && o.attempts < max_attempts)
+        {
+            Some(existing) => {
+                existing.attempts += 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_otp_code(&self, uid: &str, channel: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.otp_codes, |o| o.uid == uid && o.channel == channel) > 0)
+    }
+
+    async fn delete_expired_otp_codes(&self) -> Result<u64> {
+        let now = DateTime::now();
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.otp_codes, |o| o.expires_at < now))
+    }
+
+    async fn insert_federation_state(&self, state: &FederationState) -> Result<()> {
+        self.data.write().unwrap().federation_states.push(state.clone());
+        Ok(())
+    }
+
+    async fn take_federation_state(&self, token_hash: &str) -> Result<Option<FederationState>> {
+        let mut data = self.data.write().unwrap();
+        let position = data
+            .federation_states
+            .iter()
+            .position(|s| s.token_hash == token_hash);
+        Ok(position.map(|i| data.federation_states.remove(i)))
+    }
+
+    async fn delete_expired_federation_states(&self) -> Result<u64> {
+        let now = DateTime::now();
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.federation_states, |s| s.expires_at < now))
+    }
+}
+
+#[async_trait]
+impl KeyStore for MemoryStore {
+    async fn insert_dek(&self, dek: &Dek) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        // the other stores have a unique index on it
+        if dek.email_index.is_some() && data.deks.iter().any(|d| d.email_index == dek.email_index) {
+            return Err(Error::ServerError {
+                message: "A DEK with this email index already exists".to_string(),
+            });
+        }
+        data.deks.push(dek.clone());
+        Ok(())
+    }
+
+    async fn get_dek(&self, uid: &str) -> Result<Option<Dek>> {
+        let data = self.data.read().unwrap();
+        Ok(data.deks.iter().find(|d| d.uid == uid).cloned())
+    }
+
+    async fn get_deks(&self) -> Result<Vec<Dek>> {
+        Ok(self.data.read().unwrap().deks.clone())
+    }
+
+    async fn get_deks_to_rewrap(&self, kek_id: &str, after_uid: &str, limit: i64) -> Result<Vec<Dek>> {
+        let data = self.data.read().unwrap();
+        let mut deks: Vec<Dek> = data
+            .deks
+            .iter()
+            .filter(|d| d.kek_id.as_deref() != Some(kek_id) && d.uid.as_str() > after_uid)
+            .cloned()
+            .collect();
+        deks.sort_by(|a, b| a.uid.cmp(&b.uid));
+        deks.truncate(limit.max(0) as usize);
+        Ok(deks)
+    }
+
+    async fn rewrap_dek(&self, dek: &Dek, current_kek_id: Option<&str>) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        match data
+            .deks
+            .iter_mut()
+            .find(|d| d.uid == dek.uid && d.version == dek.version && d.kek_id.as_deref() == current_kek_id)
+        {
+            Some(existing) => {
+                existing.email = dek.email.clone();
+                existing.dek = dek.dek.clone();
+                existing.kek_id = dek.kek_id.clone();
+                existing.updated_at = dek.updated_at;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn get_dek_by_email_index(&self, email_index: &str) -> Result<Option<Dek>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .deks
+            .iter()
+            .find(|d| d.email_index.as_deref() == Some(email_index))
+            .cloned())
+    }
+
+    async fn set_dek_email_index(&self, uid: &str, email_index: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        match data.deks.iter_mut().find(|d| d.uid == uid && d.email_index.is_none()) {
+            Some(dek) => {
+                dek.email_index = Some(email_index.to_string());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_dek(&self, uid: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.deks, |d| d.uid == uid) > 0)
+    }
+
+    async fn rotate_dek(&self, rotation: &DekRotation, current_version: i64) -> Result<bool> {
+        // everything happens under the one write lock, so nothing is written in between
+        let mut data = self.data.write().unwrap();
+        let uid = rotation.dek.uid.as_str();
+        let index = match data
+            .deks
+            .iter()
+            .position(|d| d.uid == uid && d.version == current_version)
+        {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let user = match data.users.iter().find(|u| u.uid == uid) {
+            Some(user) => user.clone(),
+            None => {
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                })
+            }
+        };
+        let records = UserRecords {
+            user,
+            sessions: matching(&data.sessions, |s| s.uid == uid),
+            rotated_refresh_tokens: matching(&data.rotated_refresh_tokens, |t| t.uid == uid),
+            webauthn_credentials: matching(&data.webauthn_credentials, |c| c.uid == uid),
+            identities: matching(&data.user_identities, |i| i.uid == uid),
+            forget_password_requests: matching(&data.forget_password_requests, |r| r.uid == uid),
+            email_verification_requests: matching(&data.email_verification_requests, |r| r.uid == uid),
+            block_requests: matching(&data.block_requests, |r| r.uid == uid),
+        };
+        let rotated = rotation.reencrypt(&records);
+
+        data.deks[index] = rotation.dek.clone();
+        replace(&mut data.users, &rotated.user, |u| u.uid == uid);
+        for (old, new) in records.sessions.iter().zip(&rotated.sessions) {
+            replace(&mut data.sessions, new, |s| s.session_id == old.session_id);
+        }
+        for (old, new) in records.rotated_refresh_tokens.iter().zip(&rotated.rotated_refresh_tokens) {
+            replace(&mut data.rotated_refresh_tokens, new, |t| t.token_hash == old.token_hash);
+        }
+        for (old, new) in records.webauthn_credentials.iter().zip(&rotated.webauthn_credentials) {
+            replace(&mut data.webauthn_credentials, new, |c| {
+                c.uid == uid && c.credential_id == old.credential_id
+            });
+        }
+        for (old, new) in records.identities.iter().zip(&rotated.identities) {
+            replace(&mut data.user_identities, new, |i| i.subject_hash == old.subject_hash);
+        }
+        for (old, new) in records.forget_password_requests.iter().zip(&rotated.forget_password_requests) {
+            replace(&mut data.forget_password_requests, new, |r| r.req_id == old.req_id);
+        }
+        for (old, new) in records.email_verification_requests.iter().zip(&rotated.email_verification_requests) {
+            replace(&mut data.email_verification_requests, new, |r| r.req_id == old.req_id);
+        }
+        for (old, new) in records.block_requests.iter().zip(&rotated.block_requests) {
+            replace(&mut data.block_requests, new, |r| r.req_id == old.req_id);
+        }
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl OAuthStore for MemoryStore {
+    async fn insert_client(&self, client: &OAuthClient) -> Result<()> {
+        self.data.write().unwrap().oauth_clients.push(client.clone());
+        Ok(())
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .oauth_clients
+            .iter()
+            .find(|c| c.client_id == client_id)
+            .cloned())
+    }
+
+    async fn get_clients(&self) -> Result<Vec<OAuthClient>> {
+        Ok(self.data.read().unwrap().oauth_clients.clone())
+    }
+
+    async fn delete_client(&self, client_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.oauth_clients, |c| c.client_id == client_id) > 0)
+    }
+
+    async fn insert_identity_provider(&self, provider: &IdentityProvider) -> Result<()> {
+        self.data.write().unwrap().identity_providers.push(provider.clone());
+        Ok(())
+    }
+
+    async fn get_identity_provider(&self, provider_id: &str) -> Result<Option<IdentityProvider>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .identity_providers
+            .iter()
+            .find(|p| p.provider_id == provider_id)
+            .cloned())
+    }
+
+    async fn get_identity_providers(&self) -> Result<Vec<IdentityProvider>> {
+        Ok(self.data.read().unwrap().identity_providers.clone())
+    }
+
+    async fn delete_identity_provider(&self, provider_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.identity_providers, |p| p.provider_id == provider_id) > 0)
+    }
+
+    async fn insert_saml_connection(&self, connection: &SamlConnection) -> Result<()> {
+        self.data.write().unwrap().saml_connections.push(connection.clone());
+        Ok(())
+    }
+
+    async fn get_saml_connection(&self, connection_id: &str) -> Result<Option<SamlConnection>> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .saml_connections
+            .iter()
+            .find(|c| c.connection_id == connection_id)
+            .cloned())
+    }
+
+    async fn get_saml_connections(&self) -> Result<Vec<SamlConnection>> {
+        Ok(self.data.read().unwrap().saml_connections.clone())
+    }
+
+    async fn delete_saml_connection(&self, connection_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.saml_connections, |c| c.connection_id == connection_id) > 0)
+    }
+
+    async fn insert_ldap_connection(&self, connection: &LdapConnection) -> Result<()> {
+        self.data.write().unwrap().ldap_connections.push(connection.clone());
+        Ok(())
+    }
+
+    async fn get_ldap_connections(&self) -> Result<Vec<LdapConnection>> {
+        Ok(self.data.read().unwrap().ldap_connections.clone())
+    }
+
+    async fn delete_ldap_connection(&self, connection_id: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.ldap_connections, |c| c.connection_id == connection_id) > 0)
+    }
+
+    async fn insert_authorization_code(&self, code: &AuthorizationCode) -> Result<()> {
+        self.data.write().unwrap().authorization_codes.push(code.clone());
+        Ok(())
+    }
+
+    async fn take_authorization_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>> {
+        let mut data = self.data.write().unwrap();
+        match data
+            .authorization_codes
+            .iter()
+            .position(|c| c.code_hash == code_hash)
+        {
+            Some(index) => Ok(Some(data.authorization_codes.remove(index))),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_expired_authorization_codes(&self) -> Result<u64> {
+        let now = DateTime::now();
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.authorization_codes, |c| c.expires_at < now))
+    }
+
+    async fn insert_service_account(&self, account: &ServiceAccount) -> Result<()> {
+        self.data.write().unwrap().service_accounts.push(account.clone());
+        Ok(())
+    }
+
+    async fn get_service_account(&self, uid: &str) -> Result<Option<ServiceAccount>> {
+        let data = self.data.read().unwrap();
+        Ok(data.service_accounts.iter().find(|a| a.uid == uid).cloned())
+    }
+
+    async fn get_service_accounts(&self) -> Result<Vec<ServiceAccount>> {
+        Ok(self.data.read().unwrap().service_accounts.clone())
+    }
+
+    async fn update_service_account(&self, account: &ServiceAccount) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(replace(&mut data.service_accounts, account, |a| a.uid == account.uid))
+    }
+
+    async fn delete_service_account(&self, uid: &str) -> Result<bool> {
+        let mut data = self.data.write().unwrap();
+        Ok(remove(&mut data.service_accounts, |a| a.uid == uid) > 0)
+    }
+}
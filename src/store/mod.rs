@@ -0,0 +1,7 @@
+pub mod memory_store;
+pub mod mongo_store;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub mod sql_store;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,1350 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bson::{doc, DateTime, Document};
+use futures::TryStreamExt;
+use mongodb::{
+    error::{ErrorKind, WriteFailure, TRANSIENT_TRANSACTION_ERROR},
+    options::{FindOptions, IndexOptions, TransactionOptions},
+    Client, ClientSession, Collection, Database, IndexModel,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    core::{
+        authorization_code::AuthorizationCode, dek::Dek,
+        dek_rotation::{DekRotation, UserRecords},
+        federation::FederationState,
+        identity_provider::IdentityProvider, ldap_connection::LdapConnection,
+        magic_link::MagicLink,
+        mfa_challenge::MfaChallenge, oauth_client::OAuthClient, otp::OtpCode,
+        rotated_refresh_token::RotatedRefreshToken, saml_connection::SamlConnection,
+        service_account::ServiceAccount, session::Session, user::User,
+        user_identity::UserIdentity,
+        webauthn::{WebauthnChallenge, WebauthnCredential},
+    },
+    errors::{Error, Result},
+    models::{
+        password_model::ForgetPasswordRequest,
+        user_model::{EmailVerificationRequest, UserBlockRequest},
+    },
+    traits::store::{KeyStore, OAuthStore, SessionStore, UserStore},
+};
+
+// how many times a guarded write is tried when its transaction conflicts with another one
+const GUARDED_WRITE_ATTEMPTS: u32 = 10;
+// the code of a write that broke a unique index
+const DUPLICATE_KEY: i32 = 11000;
+// A write that found no rotation recorded on the dek starts its commit within
+// this long, or not at all, and the commit takes at most as long again. A
+// rotation waits three times as long after recording itself, so those writes
+// are in before it reads the records.
+const UNGUARDED_WRITE_TIME: Duration = Duration::from_secs(2);
+const ROTATION_WAIT: Duration = Duration::from_secs(6);
+
+#[derive(Clone)]
+pub struct MongoStore {
+    client: Client,
+    // only a replica set or a sharded cluster has them, a standalone server does not
+    transactions: bool,
+}
+
+impl MongoStore {
+    pub async fn open(client: Client) -> Result<Self> {
+        let hello = client
+            .database("admin")
+            .run_command(doc! { "hello": 1 }, None)
+            .await
+            .map_err(server_error)?;
+        let transactions = hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid");
+        let store = Self { client, transactions };
+        store.create_indexes().await?;
+        Ok(store)
+    }
+
+    pub fn has_transactions(&self) -> bool {
+        self.transactions
+    }
+
+    // the indexes lookups rely on, creating one that already exists does nothing
+    async fn create_indexes(&self) -> Result<()> {
+        let email_index = IndexModel::builder()
+            .keys(doc! { "email_index": 1 })
+            .options(
+                IndexOptions::builder()
+                    .unique(true)
+                    // deks from before the index was added have none until they get one
+                    .partial_filter_expression(doc! { "email_index": { "$type": "string" } })
+                    .build(),
+            )
+            .build();
+        self.deks()
+            .create_index(email_index, None)
+            .await
+            .map_err(server_error)?;
+
+        // a provider account is linked to one user at most
+        let identity_index = IndexModel::builder()
+            .keys(doc! { "provider_id": 1, "subject_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.user_identities()
+            .create_index(identity_index, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    // Writes record to the collection, replacing the one matching filter or as a
+    // new one without a filter. It runs in a transaction that first reads the dek
+    // of uid, which has to still have dek_version. While a rotation is recorded on
+    // it, the dek is bumped as well. The rotation writes the dek too, so only one
+    // of them commits and the write made while the dek is rotated fails instead
+    // of landing next to the rotation. Outside of a rotation the writes of a user
+    // don't all write the dek and get in each other's way.
+    async fn write_guarded<T: Serialize + Send + Sync>(
+        &self,
+        collection: Collection<T>,
+        uid: &str,
+        dek_version: i64,
+        filter: Option<Document>,
+        record: &T,
+    ) -> Result<bool> {
+        self.write_guarded_unique(collection, uid, dek_version, filter, record, None)
+            .await
+    }
+
+    // write_guarded for a collection with a unique index, a write that breaks it
+    // fails with the duplicate error
+    async fn write_guarded_unique<T: Serialize + Send + Sync>(
+        &self,
+        collection: Collection<T>,
+        uid: &str,
+        dek_version: i64,
+        filter: Option<Document>,
+        record: &T,
+        duplicate: Option<Error>,
+    ) -> Result<bool> {
+        let write_error = |e: mongodb::error::Error| match &duplicate {
+            Some(duplicate) if is_duplicate_key(&e) => duplicate.clone(),
+            _ => server_error(e),
+        };
+
+        let mut session = self.client.start_session(None).await.map_err(server_error)?;
+        // without transactions nothing rotates the dek, see rotate_dek
+        if !self.transactions {
+            return write_record(&collection, filter, record, &mut session)
+                .await
+                .map_err(write_error);
+        }
+
+        let mut attempt = 1;
+        loop {
+            let options = TransactionOptions::builder().max_commit_time(UNGUARDED_WRITE_TIME).build();
+            session.start_transaction(options).await.map_err(server_error)?;
+            let started = Instant::now();
+            let res = match self
+                .deks()
+                .clone_with_type::<Document>()
+                .find_one_with_session(dek_version_filter(uid, dek_version), None, &mut session)
+                .await
+            {
+                Ok(None) => {
+                    let _ = session.abort_transaction().await;
+                    return Err(Error::DekRotated {
+                        message: "The DEK of the user changed, please try again".to_string(),
+                    });
+                }
+                Ok(Some(dek)) if dek.contains_key("rotation_started_at") => self
+                    .deks()
+                    .update_one_with_session(
+                        dek_version_filter(uid, dek_version),
+                        // a write to the dek, the update of a field to the value it has is not one
+                        doc! { "$inc": { "writes": 1 } },
+                        None,
+                        &mut session,
+                    )
+                    .await
+                    .map(|_| true),
+                Ok(Some(_)) => Ok(false),
+                Err(e) => Err(e),
+            };
+            let res = match res {
+                Ok(guarded) => write_record(&collection, filter.clone(), record, &mut session)
+                    .await
+                    .map(|written| (guarded, written)),
+                Err(e) => Err(e),
+            };
+            let res = match res {
+                // too late to be sure the rotation that may have started since sees it, tried again
+                Ok((false, _)) if started.elapsed() > UNGUARDED_WRITE_TIME && attempt < GUARDED_WRITE_ATTEMPTS => {
+                    let _ = session.abort_transaction().await;
+                    attempt += 1;
+                    continue;
+                }
+                Ok((false, _)) if started.elapsed() > UNGUARDED_WRITE_TIME => {
+                    let _ = session.abort_transaction().await;
+                    return Err(Error::ServerError {
+                        message: "The write took too long, please try again".to_string(),
+                    });
+                }
+                Ok((_, written)) => session.commit_transaction().await.map(|_| written),
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(written) => return Ok(written),
+                // another write of the user got to the dek first
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < GUARDED_WRITE_ATTEMPTS => {
+                    let _ = session.abort_transaction().await;
+                    tokio::time::sleep(Duration::from_millis(10 * attempt as u64)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let _ = session.abort_transaction().await;
+                    return Err(write_error(e));
+                }
+            }
+        }
+    }
+
+    // the writes of a dek rotation, returns false if the dek is not at current_version anymore
+    async fn write_rotation(
+        &self,
+        rotation: &DekRotation,
+        current_version: i64,
+        session: &mut ClientSession,
+    ) -> Result<bool> {
+        let uid = rotation.dek.uid.as_str();
+        let res = self
+            .deks()
+            .replace_one_with_session(dek_version_filter(uid, current_version), &rotation.dek, None, session)
+            .await
+            .map_err(server_error)?;
+        if res.matched_count == 0 {
+            return Ok(false);
+        }
+
+        // Read in the transaction, a write to any of them that commits before the
+        // rotation does makes it fail instead of being overwritten.
+        let user = match self
+            .users()
+            .find_one_with_session(doc! { "uid": uid }, None, session)
+            .await
+            .map_err(server_error)?
+        {
+            Some(user) => user,
+            None => {
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                })
+            }
+        };
+        let records = UserRecords {
+            user,
+            sessions: find_by_uid(self.sessions(), uid, session).await?,
+            rotated_refresh_tokens: find_by_uid(self.rotated_refresh_tokens(), uid, session).await?,
+            webauthn_credentials: find_by_uid(self.webauthn_credentials(), uid, session).await?,
+            identities: find_by_uid(self.user_identities(), uid, session).await?,
+            forget_password_requests: find_by_uid(self.forget_password_requests(), uid, session).await?,
+            email_verification_requests: find_by_uid(self.email_verification_requests(), uid, session).await?,
+            block_requests: find_by_uid(self.block_requests(), uid, session).await?,
+        };
+        let rotated = rotation.reencrypt(&records);
+
+        // each one replaced where it is, by what it was found with
+        replace_all(self.users(), vec![(doc! { "uid": uid }, &rotated.user)], session).await?;
+        replace_all(
+            self.sessions(),
+            records
+                .sessions
+                .iter()
+                .zip(&rotated.sessions)
+                .map(|(old, new)| (doc! { "session_id": &old.session_id }, new))
+                .collect(),
+            session,
+        )
+        .await?;
+        replace_all(
+            self.rotated_refresh_tokens(),
+            rotated
+                .rotated_refresh_tokens
+                .iter()
+                .map(|new| (doc! { "token_hash": &new.token_hash }, new))
+                .collect(),
+            session,
+        )
+        .await?;
+        replace_all(
+            self.webauthn_credentials(),
+            records
+                .webauthn_credentials
+                .iter()
+                .zip(&rotated.webauthn_credentials)
+                .map(|(old, new)| (doc! { "uid": uid, "credential_id": &old.credential_id }, new))
+                .collect(),
+            session,
+        )
+        .await?;
+        replace_all(
+            self.user_identities(),
+            rotated
+                .identities
+                .iter()
+                .map(|new| (doc! { "subject_hash": &new.subject_hash }, new))
+                .collect(),
+            session,
+        )
+        .await?;
+        replace_all(
+            self.forget_password_requests(),
+            rotated
+                .forget_password_requests
+                .iter()
+                .map(|new| (doc! { "req_id": &new.req_id }, new))
+                .collect(),
+            session,
+        )
+        .await?;
+        replace_all(
+            self.email_verification_requests(),
+            rotated
+                .email_verification_requests
+                .iter()
+                .map(|new| (doc! { "req_id": &new.req_id }, new))
+                .collect(),
+            session,
+        )
+        .await?;
+        replace_all(
+            self.block_requests(),
+            rotated
+                .block_requests
+                .iter()
+                .map(|new| (doc! { "req_id": &new.req_id }, new))
+                .collect(),
+            session,
+        )
+        .await?;
+        Ok(true)
+    }
+
+    fn db(&self) -> Database {
+        self.client.database("auth")
+    }
+
+    fn users(&self) -> Collection<User> {
+        self.db().collection("users")
+    }
+
+    fn sessions(&self) -> Collection<Session> {
+        self.db().collection("sessions")
+    }
+
+    fn deks(&self) -> Collection<Dek> {
+        self.db().collection("deks")
+    }
+
+    fn forget_password_requests(&self) -> Collection<ForgetPasswordRequest> {
+        self.db().collection("forget_password_requests")
+    }
+
+    fn email_verification_requests(&self) -> Collection<EmailVerificationRequest> {
+        self.db().collection("email_verification_requests")
+    }
+
+    fn block_requests(&self) -> Collection<UserBlockRequest> {
+        self.db().collection("users_block_requests")
+    }
+
+    fn oauth_clients(&self) -> Collection<OAuthClient> {
+        self.db().collection("oauth_clients")
+    }
+
+    fn authorization_codes(&self) -> Collection<AuthorizationCode> {
+        self.db().collection("authorization_codes")
+    }
+
+    fn rotated_refresh_tokens(&self) -> Collection<RotatedRefreshToken> {
+        self.db().collection("rotated_refresh_tokens")
+    }
+
+    fn mfa_challenges(&self) -> Collection<MfaChallenge> {
+        self.db().collection("mfa_challenges")
+    }
+
+    fn webauthn_credentials(&self) -> Collection<WebauthnCredential> {
+        self.db().collection("webauthn_credentials")
+    }
+
+    fn webauthn_challenges(&self) -> Collection<WebauthnChallenge> {
+        self.db().collection("webauthn_challenges")
+    }
+
+    fn magic_links(&self) -> Collection<MagicLink> {
+        self.db().collection("magic_links")
+    }
+
+    fn otp_codes(&self) -> Collection<OtpCode> {
+        self.db().collection("otp_codes")
+    }
+
+    fn identity_providers(&self) -> Collection<IdentityProvider> {
+        self.db().collection("identity_providers")
+    }
+
+    fn saml_connections(&self) -> Collection<SamlConnection> {
+        self.db().collection("saml_connections")
+    }
+
+    fn ldap_connections(&self) -> Collection<LdapConnection> {
+        self.db().collection("ldap_connections")
+    }
+
+    fn user_identities(&self) -> Collection<UserIdentity> {
+        self.db().collection("user_identities")
+    }
+
+    fn federation_states(&self) -> Collection<FederationState> {
+        self.db().collection("federation_states")
+    }
+
+    fn service_accounts(&self) -> Collection<ServiceAccount> {
+        self.db().collection("service_accounts")
+    }
+}
+
+fn server_error(e: mongodb::error::Error) -> Error {
+    Error::ServerError {
+        message: e.to_string(),
+    }
+}
+
+// the write broke a unique index
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::Command(e) => e.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+// The dek of uid if it has dek_version. The ones from before deks had a version
+// have none, they are at the first one.
+fn dek_version_filter(uid: &str, dek_version: i64) -> Document {
+    match dek_version {
+        0 => doc! { "uid": uid, "version": { "$in": [0, null] } },
+        _ => doc! { "uid": uid, "version": dek_version },
+    }
+}
+
+// if the user has a password, a passkey or more than one identity
+async fn has_other_login_method(store: &MongoStore, uid: &str, session: &mut ClientSession) -> Result<bool> {
+    // users from before has_password have one
+    let has_password = store
+        .users()
+        .count_documents_with_session(doc! { "uid": uid, "has_password": { "$ne": false } }, None, session)
+        .await
+        .map_err(server_error)?;
+    let passkeys = store
+        .webauthn_credentials()
+        .count_documents_with_session(doc! { "uid": uid }, None, session)
+        .await
+        .map_err(server_error)?;
+    let identities = store
+        .user_identities()
+        .count_documents_with_session(doc! { "uid": uid }, None, session)
+        .await
+        .map_err(server_error)?;
+    Ok(has_password > 0 || passkeys > 0 || identities > 1)
+}
+
+// replaces the record matching filter, or inserts it without one
+async fn write_record<T: Serialize + Send + Sync>(
+    collection: &Collection<T>,
+    filter: Option<Document>,
+    record: &T,
+    session: &mut ClientSession,
+) -> mongodb::error::Result<bool> {
+    match filter {
+        Some(filter) => collection
+            .replace_one_with_session(filter, record, None, session)
+            .await
+            .map(|res| res.matched_count > 0),
+        None => collection
+            .insert_one_with_session(record, None, session)
+            .await
+            .map(|_| true),
+    }
+}
+
+async fn find_by_uid<T: DeserializeOwned + Unpin + Send + Sync>(
+    collection: Collection<T>,
+    uid: &str,
+    session: &mut ClientSession,
+) -> Result<Vec<T>> {
+    let mut cursor = collection
+        .find_with_session(doc! { "uid": uid }, None, session)
+        .await
+        .map_err(server_error)?;
+    cursor.stream(session).try_collect().await.map_err(server_error)
+}
+
+async fn replace_all<T: Serialize + Send + Sync>(
+    collection: Collection<T>,
+    records: Vec<(Document, &T)>,
+    session: &mut ClientSession,
+) -> Result<()> {
+    for (filter, record) in records {
+        collection
+            .replace_one_with_session(filter, record, None, session)
+            .await
+            .map_err(server_error)?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl UserStore for MongoStore {
+    async fn insert_user(&self, user: &User) -> Result<()> {
+        match self.users().insert_one(user, None).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to Insert User".to_string(),
+            }),
+        }
+    }
+
+    async fn get_user(&self, uid: &str) -> Result<Option<User>> {
+        self.users()
+            .find_one(doc! { "uid": uid }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn get_users(&self) -> Result<Vec<User>> {
+        let cursor = self.users().find(None, None).await.map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn count_users(&self) -> Result<u64> {
+        self.users()
+            .count_documents(None, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn update_user(&self, user: &User, dek_version: i64) -> Result<bool> {
+        self.write_guarded(self.users(), &user.uid, dek_version, Some(doc! { "uid": &user.uid }), user)
+            .await
+    }
+
+    async fn replace_user_field(&self, uid: &str, field: &str, current: &str, value: &str) -> Result<bool> {
+        self.users()
+            .update_one(doc! { "uid": uid, field: current }, doc! { "$set": { field: value } }, None)
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn set_totp_last_step(&self, uid: &str, step: i64) -> Result<bool> {
+        // null also matches the users without the field
+        let filter = doc! {
+            "uid": uid,
+            "$or": [{ "totp_last_step": null }, { "totp_last_step": { "$lt": step } }],
+        };
+        self.users()
+            .update_one(filter, doc! { "$set": { "totp_last_step": step } }, None)
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn remove_recovery_code(&self, uid: &str, hash: &str) -> Result<bool> {
+        self.users()
+            .update_one(
+                doc! { "uid": uid, "recovery_codes": hash },
+                doc! { "$pull": { "recovery_codes": hash } },
+                None,
+            )
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_user(&self, uid: &str) -> Result<bool> {
+        match self.users().delete_one(doc! { "uid": uid }, None).await {
+            Ok(res) => Ok(res.deleted_count > 0),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to delete User".to_string(),
+            }),
+        }
+    }
+
+    async fn insert_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<()> {
+        self.write_guarded(self.forget_password_requests(), &request.uid, dek_version, None, request)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get_forget_password_request(&self, req_id: &str) -> Result<Option<ForgetPasswordRequest>> {
+        self.forget_password_requests()
+            .find_one(doc! { "req_id": req_id }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn update_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<bool> {
+        self.write_guarded(self.forget_password_requests(), &request.uid, dek_version, Some(doc! { "req_id": &request.req_id }), request)
+            .await
+    }
+
+    async fn insert_email_verification_request(&self, request: &EmailVerificationRequest, dek_version: i64) -> Result<()> {
+        self.write_guarded(self.email_verification_requests(), &request.uid, dek_version, None, request)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get_email_verification_request(&self, req_id: &str) -> Result<Option<EmailVerificationRequest>> {
+        self.email_verification_requests()
+            .find_one(doc! { "req_id": req_id }, None)
+            .await
+            .map_err(server_error)
+    }
+
+
+    async fn delete_email_verification_request(&self, req_id: &str) -> Result<bool> {
+        self.email_verification_requests()
+            .delete_one(doc! { "req_id": req_id }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<()> {
+        self.write_guarded(self.block_requests(), &request.uid, dek_version, None, request)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get_block_request(&self, req_id: &str) -> Result<Option<UserBlockRequest>> {
+        self.block_requests()
+            .find_one(doc! { "req_id": req_id }, None)
+            .await
+            .map_err(server_error)
+    }
+
+
+    async fn update_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<bool> {
+        self.write_guarded(self.block_requests(), &request.uid, dek_version, Some(doc! { "req_id": &request.req_id }), request)
+            .await
+    }
+
+    async fn insert_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<()> {
+        self.write_guarded(self.webauthn_credentials(), &credential.uid, dek_version, None, credential)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get_webauthn_credentials(&self, uid: &str) -> Result<Vec<WebauthnCredential>> {
+        let cursor = self
+            .webauthn_credentials()
+            .find(doc! { "uid": uid }, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn update_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<bool> {
+        let filter = doc! { "uid": &credential.uid, "credential_id": &credential.credential_id };
+        self.write_guarded(self.webauthn_credentials(), &credential.uid, dek_version, Some(filter), credential)
+            .await
+    }
+
+    async fn delete_webauthn_credential(&self, uid: &str, credential_id: &str) -> Result<bool> {
+        self.webauthn_credentials()
+            .delete_one(doc! { "uid": uid, "credential_id": credential_id }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_webauthn_credentials_by_uid(&self, uid: &str) -> Result<u64> {
+        self.webauthn_credentials()
+            .delete_many(doc! { "uid": uid }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_user_identity(&self, identity: &UserIdentity, dek_version: i64) -> Result<()> {
+        let duplicate = Error::IdentityAlreadyLinked {
+            message: "This account is already linked to a user".to_string(),
+        };
+        self.write_guarded_unique(
+            self.user_identities(),
+            &identity.uid,
+            dek_version,
+            None,
+            identity,
+            Some(duplicate),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    async fn get_user_identity(&self, subject_hash: &str) -> Result<Option<UserIdentity>> {
+        self.user_identities()
+            .find_one(doc! { "subject_hash": subject_hash }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn get_user_identities(&self, uid: &str) -> Result<Vec<UserIdentity>> {
+        let cursor = self
+            .user_identities()
+            .find(doc! { "uid": uid }, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn unlink_user_identity(&self, uid: &str, provider_id: &str) -> Result<bool> {
+        let mut session = self.client.start_session(None).await.map_err(server_error)?;
+        // without transactions the check and the delete can't happen together
+        if !self.transactions {
+            if !has_other_login_method(self, uid, &mut session).await? {
+                return Ok(false);
+            }
+            return self
+                .user_identities()
+                .delete_one_with_session(doc! { "uid": uid, "provider_id": provider_id }, None, &mut session)
+                .await
+                .map(|res| res.deleted_count > 0)
+                .map_err(server_error);
+        }
+
+        let mut attempt = 1;
+        loop {
+            session.start_transaction(None).await.map_err(server_error)?;
+            // a write to the dek of the user, so of two unlinks at the same time only one commits
+            let res = match self
+                .deks()
+                .update_one_with_session(doc! { "uid": uid }, doc! { "$inc": { "writes": 1 } }, None, &mut session)
+                .await
+            {
+                Ok(_) => has_other_login_method(self, uid, &mut session).await,
+                Err(e) => Err(server_error(e)),
+            };
+            let res = match res {
+                Ok(true) => self
+                    .user_identities()
+                    .delete_one_with_session(doc! { "uid": uid, "provider_id": provider_id }, None, &mut session)
+                    .await
+                    .map(|res| res.deleted_count > 0),
+                Ok(false) => {
+                    let _ = session.abort_transaction().await;
+                    return Ok(false);
+                }
+                Err(e) => {
+                    let _ = session.abort_transaction().await;
+                    return Err(e);
+                }
+            };
+            let res = match res {
+                Ok(deleted) => session.commit_transaction().await.map(|_| deleted),
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(deleted) => return Ok(deleted),
+                // the other unlink got to the dek first, check again with its delete in place
+                Err(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR) && attempt < GUARDED_WRITE_ATTEMPTS => {
+                    let _ = session.abort_transaction().await;
+                    tokio::time::sleep(Duration::from_millis(10 * attempt as u64)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let _ = session.abort_transaction().await;
+                    return Err(server_error(e));
+                }
+            }
+        }
+    }
+
+    async fn delete_user_identities_by_uid(&self, uid: &str) -> Result<u64> {
+        self.user_identities()
+            .delete_many(doc! { "uid": uid }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+}
+
+#[async_trait]
+impl SessionStore for MongoStore {
+    async fn insert_session(&self, session: &Session, dek_version: i64) -> Result<()> {
+        self.write_guarded(self.sessions(), &session.uid, dek_version, None, session)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get_sessions(&self) -> Result<Vec<Session>> {
+        let cursor = self.sessions().find(None, None).await.map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn get_sessions_by_uid(&self, uid: &str) -> Result<Vec<Session>> {
+        let cursor = self
+            .sessions()
+            .find(doc! { "uid": uid }, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn update_session(&self, session: &Session, dek_version: i64) -> Result<bool> {
+        self.write_guarded(self.sessions(), &session.uid, dek_version, Some(doc! { "session_id": &session.session_id }), session)
+            .await
+    }
+
+    async fn refresh_session(&self, session: &Session, current_refresh_token: &str, dek_version: i64) -> Result<bool> {
+        let filter = doc! {
+            "session_id": &session.session_id,
+            "refresh_token": current_refresh_token,
+            "is_revoked": false,
+        };
+        self.write_guarded(self.sessions(), &session.uid, dek_version, Some(filter), session)
+            .await
+    }
+
+    async fn replace_session_field(&self, session_id: &str, field: &str, current: &str, value: &str) -> Result<bool> {
+        // field can be session_id itself, then current is the same as session_id
+        self.sessions()
+            .update_one(
+                doc! { "session_id": session_id, field: current },
+                doc! { "$set": { field: value } },
+                None,
+            )
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<bool> {
+        self.sessions()
+            .delete_one(doc! { "session_id": session_id }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn revoke_sessions_by_uid(&self, uid: &str) -> Result<u64> {
+        self.sessions()
+            .update_many(doc! { "uid": uid }, doc! { "$set": { "is_revoked": true } }, None)
+            .await
+            .map(|res| res.modified_count)
+            .map_err(server_error)
+    }
+
+    async fn delete_sessions_by_uid(&self, uid: &str) -> Result<u64> {
+        self.sessions()
+            .delete_many(doc! { "uid": uid }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_rotated_refresh_token(&self, token: &RotatedRefreshToken, dek_version: i64) -> Result<()> {
+        self.write_guarded(self.rotated_refresh_tokens(), &token.uid, dek_version, None, token)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get_rotated_refresh_token(&self, token_hash: &str) -> Result<Option<RotatedRefreshToken>> {
+        self.rotated_refresh_tokens()
+            .find_one(doc! { "token_hash": token_hash }, None)
+            .await
+            .map_err(server_error)
+    }
+
+
+    async fn delete_expired_rotated_refresh_tokens(&self) -> Result<u64> {
+        self.rotated_refresh_tokens()
+            .delete_many(doc! { "expires_at": { "$lt": DateTime::now() } }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_mfa_challenge(&self, challenge: &MfaChallenge) -> Result<()> {
+        self.mfa_challenges()
+            .insert_one(challenge, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_mfa_challenge(&self, token_hash: &str) -> Result<Option<MfaChallenge>> {
+        self.mfa_challenges()
+            .find_one(doc! { "token_hash": token_hash }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn count_mfa_challenge_attempt(&self, token_hash: &str, max_attempts: i32) -> Result<bool> {
+        self.mfa_challenges()
+            .update_one(
+                doc! { "token_hash": token_hash, "attempts": { "$lt": max_attempts } },
+                doc! { "$inc": { "attempts": 1 } },
+                None,
+            )
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_mfa_challenge(&self, token_hash: &str) -> Result<bool> {
+        self.mfa_challenges()
+            .delete_one(doc! { "token_hash": token_hash }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_mfa_challenges(&self) -> Result<u64> {
+        self.mfa_challenges()
+            .delete_many(doc! { "expires_at": { "$lt": DateTime::now() } }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_webauthn_challenge(&self, challenge: &WebauthnChallenge) -> Result<()> {
+        self.webauthn_challenges()
+            .insert_one(challenge, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn take_webauthn_challenge(&self, challenge_hash: &str) -> Result<Option<WebauthnChallenge>> {
+        self.webauthn_challenges()
+            .find_one_and_delete(doc! { "challenge_hash": challenge_hash }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_webauthn_challenges(&self) -> Result<u64> {
+        self.webauthn_challenges()
+            .delete_many(doc! { "expires_at": { "$lt": DateTime::now() } }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_magic_link(&self, magic_link: &MagicLink) -> Result<()> {
+        self.magic_links()
+            .insert_one(magic_link, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn take_magic_link(&self, token_hash: &str) -> Result<Option<MagicLink>> {
+        self.magic_links()
+            .find_one_and_delete(doc! { "token_hash": token_hash }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_magic_links(&self) -> Result<u64> {
+        self.magic_links()
+            .delete_many(doc! { "expires_at": { "$lt": DateTime::now() } }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_otp_code(&self, otp: &OtpCode) -> Result<()> {
+        self.otp_codes()
+            .insert_one(otp, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_otp_code(&self, uid: &str, channel: &str) -> Result<Option<OtpCode>> {
+        self.otp_codes()
+            .find_one(doc! { "uid": uid, "channel": channel }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn count_otp_attempt(&self, otp: &OtpCode, max_attempts: i32) -> Result<bool> {
+        self.otp_codes()
+            .update_one(
+                doc! { "_id": otp._id, "attempts": { "$lt": max_attempts } },
+                doc! { "$inc": { "attempts": 1 } },
+                None,
+            )
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_otp_code(&self, uid: &str, channel: &str) -> Result<bool> {
+        self.otp_codes()
+            .delete_one(doc! { "uid": uid, "channel": channel }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_otp_codes(&self) -> Result<u64> {
+        self.otp_codes()
+            .delete_many(doc! { "expires_at": { "$lt": DateTime::now() } }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_federation_state(&self, state: &FederationState) -> Result<()> {
+        self.federation_states()
+            .insert_one(state, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn take_federation_state(&self, token_hash: &str) -> Result<Option<FederationState>> {
+        self.federation_states()
+            .find_one_and_delete(doc! { "token_hash": token_hash }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_federation_states(&self) -> Result<u64> {
+        self.federation_states()
+            .delete_many(doc! { "expires_at": { "$lt": DateTime::now() } }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+}
+
+#[async_trait]
+impl KeyStore for MongoStore {
+    async fn insert_dek(&self, dek: &Dek) -> Result<()> {
+        self.deks()
+            .insert_one(dek, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_dek(&self, uid: &str) -> Result<Option<Dek>> {
+        self.deks()
+            .find_one(doc! { "uid": uid }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn get_deks(&self) -> Result<Vec<Dek>> {
+        let cursor = self.deks().find(None, None).await.map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn get_deks_to_rewrap(&self, kek_id: &str, after_uid: &str, limit: i64) -> Result<Vec<Dek>> {
+        let options = FindOptions::builder().sort(doc! { "uid": 1 }).limit(limit).build();
+        let cursor = self
+            .deks()
+            .find(doc! { "kek_id": { "$ne": kek_id }, "uid": { "$gt": after_uid } }, options)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn rewrap_dek(&self, dek: &Dek, current_kek_id: Option<&str>) -> Result<bool> {
+        self.deks()
+            .update_one(
+                doc! { "kek_id": current_kek_id }
+                    .into_iter()
+                    .chain(dek_version_filter(&dek.uid, dek.version))
+                    .collect::<Document>(),
+                doc! {
+                    "$set": {
+                        "email": &dek.email,
+                        "dek": &dek.dek,
+                        "kek_id": &dek.kek_id,
+                        "updated_at": dek.updated_at,
+                    }
+                },
+                None,
+            )
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn get_dek_by_email_index(&self, email_index: &str) -> Result<Option<Dek>> {
+        self.deks()
+            .find_one(doc! { "email_index": email_index }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn set_dek_email_index(&self, uid: &str, email_index: &str) -> Result<bool> {
+        self.deks()
+            .update_one(
+                doc! { "uid": uid, "email_index": null },
+                doc! { "$set": { "email_index": email_index } },
+                None,
+            )
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_dek(&self, uid: &str) -> Result<bool> {
+        match self.deks().delete_one(doc! { "uid": uid }, None).await {
+            Ok(res) => Ok(res.deleted_count > 0),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to delete DEK".to_string(),
+            }),
+        }
+    }
+
+    // Transactions need MongoDB to run as a replica set, the server refuses to
+    // start with DEK_ROTATION_DAYS set on a standalone one.
+    async fn rotate_dek(&self, rotation: &DekRotation, current_version: i64) -> Result<bool> {
+        if !self.transactions {
+            return Err(Error::ServerError {
+                message: "DEK rotation needs MongoDB to run as a replica set".to_string(),
+            });
+        }
+        // From here on the writes of the user bump the dek and can't commit next
+        // to the rotation. The ones that started before are in once it has waited.
+        // A rotation that fails leaves it recorded, the writes stay guarded until
+        // the next one goes through.
+        let uid = rotation.dek.uid.as_str();
+        let recorded = self
+            .deks()
+            .update_one(
+                dek_version_filter(uid, current_version),
+                doc! { "$set": { "rotation_started_at": DateTime::now() } },
+                None,
+            )
+            .await
+            .map_err(server_error)?;
+        if recorded.matched_count == 0 {
+            return Ok(false);
+        }
+        tokio::time::sleep(ROTATION_WAIT).await;
+
+        // the new dek replaces the one with the rotation recorded on it
+        let mut session = self.client.start_session(None).await.map_err(server_error)?;
+        session.start_transaction(None).await.map_err(server_error)?;
+        match self.write_rotation(rotation, current_version, &mut session).await {
+            Ok(true) => session
+                .commit_transaction()
+                .await
+                .map(|_| true)
+                .map_err(server_error),
+            Ok(false) => session
+                .abort_transaction()
+                .await
+                .map(|_| false)
+                .map_err(server_error),
+            Err(e) => {
+                let _ = session.abort_transaction().await;
+                Err(e)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl OAuthStore for MongoStore {
+    async fn insert_client(&self, client: &OAuthClient) -> Result<()> {
+        self.oauth_clients()
+            .insert_one(client, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>> {
+        self.oauth_clients()
+            .find_one(doc! { "client_id": client_id }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn get_clients(&self) -> Result<Vec<OAuthClient>> {
+        let cursor = self
+            .oauth_clients()
+            .find(None, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn delete_client(&self, client_id: &str) -> Result<bool> {
+        self.oauth_clients()
+            .delete_one(doc! { "client_id": client_id }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_identity_provider(&self, provider: &IdentityProvider) -> Result<()> {
+        self.identity_providers()
+            .insert_one(provider, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_identity_provider(&self, provider_id: &str) -> Result<Option<IdentityProvider>> {
+        self.identity_providers()
+            .find_one(doc! { "provider_id": provider_id }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn get_identity_providers(&self) -> Result<Vec<IdentityProvider>> {
+        let cursor = self
+            .identity_providers()
+            .find(None, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn delete_identity_provider(&self, provider_id: &str) -> Result<bool> {
+        self.identity_providers()
+            .delete_one(doc! { "provider_id": provider_id }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_saml_connection(&self, connection: &SamlConnection) -> Result<()> {
+        self.saml_connections()
+            .insert_one(connection, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_saml_connection(&self, connection_id: &str) -> Result<Option<SamlConnection>> {
+        self.saml_connections()
+            .find_one(doc! { "connection_id": connection_id }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn get_saml_connections(&self) -> Result<Vec<SamlConnection>> {
+        let cursor = self
+            .saml_connections()
+            .find(None, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn delete_saml_connection(&self, connection_id: &str) -> Result<bool> {
+        self.saml_connections()
+            .delete_one(doc! { "connection_id": connection_id }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_ldap_connection(&self, connection: &LdapConnection) -> Result<()> {
+        self.ldap_connections()
+            .insert_one(connection, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_ldap_connections(&self) -> Result<Vec<LdapConnection>> {
+        let cursor = self
+            .ldap_connections()
+            .find(None, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn delete_ldap_connection(&self, connection_id: &str) -> Result<bool> {
+        self.ldap_connections()
+            .delete_one(doc! { "connection_id": connection_id }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_authorization_code(&self, code: &AuthorizationCode) -> Result<()> {
+        self.authorization_codes()
+            .insert_one(code, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn take_authorization_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>> {
+        self.authorization_codes()
+            .find_one_and_delete(doc! { "code_hash": code_hash }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_authorization_codes(&self) -> Result<u64> {
+        self.authorization_codes()
+            .delete_many(doc! { "expires_at": { "$lt": DateTime::now() } }, None)
+            .await
+            .map(|res| res.deleted_count)
+            .map_err(server_error)
+    }
+
+    async fn insert_service_account(&self, account: &ServiceAccount) -> Result<()> {
+        self.service_accounts()
+            .insert_one(account, None)
+            .await
+            .map(|_| ())
+            .map_err(server_error)
+    }
+
+    async fn get_service_account(&self, uid: &str) -> Result<Option<ServiceAccount>> {
+        self.service_accounts()
+            .find_one(doc! { "uid": uid }, None)
+            .await
+            .map_err(server_error)
+    }
+
+    async fn get_service_accounts(&self) -> Result<Vec<ServiceAccount>> {
+        let cursor = self
+            .service_accounts()
+            .find(None, None)
+            .await
+            .map_err(server_error)?;
+        cursor.try_collect().await.map_err(server_error)
+    }
+
+    async fn update_service_account(&self, account: &ServiceAccount) -> Result<bool> {
+        self.service_accounts()
+            .replace_one(doc! { "uid": &account.uid }, account, None)
+            .await
+            .map(|res| res.matched_count > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_service_account(&self, uid: &str) -> Result<bool> {
+        self.service_accounts()
+            .delete_one(doc! { "uid": uid }, None)
+            .await
+            .map(|res| res.deleted_count > 0)
+            .map_err(server_error)
+    }
+}
@@ -0,0 +1,1907 @@
+use async_trait::async_trait;
+use bson::{oid::ObjectId, DateTime};
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions, AnyQueryResult, AnyRow},
+    Any, AnyPool, Executor, Row, Transaction,
+};
+
+use crate::{
+    core::{
+        authorization_code::AuthorizationCode, dek::Dek,
+        dek_rotation::{DekRotation, UserRecords},
+        federation::FederationState,
+        identity_provider::IdentityProvider, ldap_connection::LdapConnection,
+        magic_link::MagicLink,
+        mfa_challenge::MfaChallenge, oauth_client::OAuthClient, otp::OtpCode,
+        rotated_refresh_token::RotatedRefreshToken, saml_connection::SamlConnection,
+        service_account::ServiceAccount, session::Session, user::User,
+        user_identity::UserIdentity,
+        webauthn::{WebauthnChallenge, WebauthnCredential},
+    },
+    errors::{Error, Result},
+    models::{
+        password_model::ForgetPasswordRequest,
+        user_model::{EmailVerificationRequest, UserBlockRequest},
+    },
+    traits::store::{KeyStore, OAuthStore, SessionStore, UserStore},
+};
+
+// Relational backend for SQLite and PostgreSQL. It goes through the sqlx `Any`
+// driver so the same queries are used for both, which is why all queries only
+// use `$n` placeholders and portable column types.
+#[derive(Clone)]
+pub struct SqlStore {
+    pool: AnyPool,
+}
+
+impl SqlStore {
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        install_default_drivers();
+
+        let pool = AnyPoolOptions::new()
+            .max_connections(10)
+            .connect(database_url)
+            .await
+            .map_err(server_error)?;
+
+        // bring the schema up to date before serving anything
+        match sqlx::migrate!("./migrations").run(&pool).await {
+            Ok(_) => Ok(Self { pool }),
+            Err(e) => Err(Error::ServerError {
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    // The writes of records encrypted with the DEK of a user run in a transaction
+    // that first updates the row of the dek, if it still has dek_version. That
+    // locks the row, so a write made while the dek is rotated waits for the
+    // rotation and then fails instead of landing next to it.
+    async fn begin_guarded(&self, uid: &str, dek_version: i64) -> Result<Transaction<'static, Any>> {
+        let mut tx = self.pool.begin().await.map_err(server_error)?;
+        let res = sqlx::query("UPDATE deks SET version = version WHERE uid = $1 AND version = $2")
+            .bind(uid)
+            .bind(dek_version)
+            .execute(&mut *tx)
+            .await
+            .map_err(server_error)?;
+        if res.rows_affected() == 0 {
+            return Err(Error::DekRotated {
+                message: "The DEK of the user changed, please try again".to_string(),
+            });
+        }
+        Ok(tx)
+    }
+}
+
+fn server_error(e: sqlx::Error) -> Error {
+    Error::ServerError {
+        message: e.to_string(),
+    }
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error().is_some_and(|e| e.is_unique_violation())
+}
+
+// commits a guarded write that went through, dropping the transaction rolls it back
+async fn commit_guarded(tx: Transaction<'static, Any>, res: sqlx::Result<AnyQueryResult>) -> Result<AnyQueryResult> {
+    let res = res.map_err(server_error)?;
+    tx.commit().await.map_err(server_error)?;
+    Ok(res)
+}
+
+async fn rows_of_user<T>(
+    tx: &mut Transaction<'static, Any>,
+    table: &str,
+    uid: &str,
+    from_row: fn(&AnyRow) -> Result<T>,
+) -> Result<Vec<T>> {
+    let rows = sqlx::query(&format!("SELECT * FROM {} WHERE uid = $1", table))
+        .bind(uid)
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(server_error)?;
+    rows.iter().map(from_row).collect()
+}
+
+fn object_id(row: &AnyRow) -> Result<ObjectId> {
+    let id: String = row.try_get("id").map_err(server_error)?;
+    ObjectId::parse_str(&id).map_err(|e| Error::ServerError {
+        message: e.to_string(),
+    })
+}
+
+fn datetime(row: &AnyRow, column: &str) -> Result<DateTime> {
+    let millis: i64 = row.try_get(column).map_err(server_error)?;
+    Ok(DateTime::from_millis(millis))
+}
+
+fn optional_datetime(row: &AnyRow, column: &str) -> Result<Option<DateTime>> {
+    let millis: Option<i64> = row.try_get(column).map_err(server_error)?;
+    Ok(millis.map(DateTime::from_millis))
+}
+
+fn flag(row: &AnyRow, column: &str) -> Result<bool> {
+    let value: i64 = row.try_get(column).map_err(server_error)?;
+    Ok(value != 0)
+}
+
+// field names end up in the query itself, so only plain column names are let through
+fn column(field: &str) -> Result<&str> {
+    if field.is_empty() || !field.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+        return Err(Error::ServerError {
+            message: format!("Invalid column {}", field),
+        });
+    }
+    Ok(field)
+}
+
+fn millis(datetime: &Option<DateTime>) -> Option<i64> {
+    datetime.map(|d| d.timestamp_millis())
+}
+
+fn user_from_row(row: &AnyRow) -> Result<User> {
+    let failed_login_attempts: i64 = row.try_get("failed_login_attempts").map_err(server_error)?;
+    let recovery_codes: String = row.try_get("recovery_codes").map_err(server_error)?;
+    Ok(User {
+        _id: object_id(row)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        name: row.try_get("name").map_err(server_error)?,
+        email: row.try_get("email").map_err(server_error)?,
+        role: row.try_get("role").map_err(server_error)?,
+        password: row.try_get("password").map_err(server_error)?,
+        email_verified: flag(row, "email_verified")?,
+        is_active: flag(row, "is_active")?,
+        failed_login_attempts: failed_login_attempts as i32,
+        blocked_until: optional_datetime(row, "blocked_until")?,
+        totp_secret: row.try_get("totp_secret").map_err(server_error)?,
+        totp_enabled: flag(row, "totp_enabled")?,
+        totp_last_step: row.try_get("totp_last_step").map_err(server_error)?,
+        recovery_codes: serde_json::from_str(&recovery_codes).map_err(|e| Error::ServerError {
+            message: e.to_string(),
+        })?,
+        phone: row.try_get("phone").map_err(server_error)?,
+        phone_verified: flag(row, "phone_verified")?,
+        has_password: flag(row, "has_password")?,
+        created_at: optional_datetime(row, "created_at")?,
+        updated_at: optional_datetime(row, "updated_at")?,
+    })
+}
+
+fn session_from_row(row: &AnyRow) -> Result<Session> {
+    Ok(Session {
+        uid: row.try_get("uid").map_err(server_error)?,
+        session_id: row.try_get("session_id").map_err(server_error)?,
+        email: row.try_get("email").map_err(server_error)?,
+        id_token: row.try_get("id_token").map_err(server_error)?,
+        refresh_token: row.try_get("refresh_token").map_err(server_error)?,
+        user_agent: row.try_get("user_agent").map_err(server_error)?,
+        os: row.try_get("os").map_err(server_error)?,
+        os_version: row.try_get("os_version").map_err(server_error)?,
+        vendor: row.try_get("vendor").map_err(server_error)?,
+        device: row.try_get("device").map_err(server_error)?,
+        browser: row.try_get("browser").map_err(server_error)?,
+        browser_version: row.try_get("browser_version").map_err(server_error)?,
+        is_revoked: flag(row, "is_revoked")?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn dek_from_row(row: &AnyRow) -> Result<Dek> {
+    Ok(Dek {
+        _id: object_id(row)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        email: row.try_get("email").map_err(server_error)?,
+        email_index: row.try_get("email_index").map_err(server_error)?,
+        dek: row.try_get("dek").map_err(server_error)?,
+        kek_id: row.try_get("kek_id").map_err(server_error)?,
+        version: row.try_get("version").map_err(server_error)?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn oauth_client_from_row(row: &AnyRow) -> Result<OAuthClient> {
+    // stored as a JSON array, there is no portable array column type
+    let redirect_uris: String = row.try_get("redirect_uris").map_err(server_error)?;
+    Ok(OAuthClient {
+        _id: object_id(row)?,
+        client_id: row.try_get("client_id").map_err(server_error)?,
+        name: row.try_get("name").map_err(server_error)?,
+        redirect_uris: serde_json::from_str(&redirect_uris).map_err(|e| Error::ServerError {
+            message: e.to_string(),
+        })?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn authorization_code_from_row(row: &AnyRow) -> Result<AuthorizationCode> {
+    Ok(AuthorizationCode {
+        _id: object_id(row)?,
+        code_hash: row.try_get("code_hash").map_err(server_error)?,
+        client_id: row.try_get("client_id").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        redirect_uri: row.try_get("redirect_uri").map_err(server_error)?,
+        code_challenge: row.try_get("code_challenge").map_err(server_error)?,
+        scope: row.try_get("scope").map_err(server_error)?,
+        nonce: row.try_get("nonce").map_err(server_error)?,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: datetime(row, "created_at")?,
+    })
+}
+
+fn rotated_refresh_token_from_row(row: &AnyRow) -> Result<RotatedRefreshToken> {
+    Ok(RotatedRefreshToken {
+        _id: object_id(row)?,
+        token_hash: row.try_get("token_hash").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        session_id: row.try_get("session_id").map_err(server_error)?,
+        expires_at: datetime(row, "expires_at")?,
+        rotated_at: datetime(row, "rotated_at")?,
+    })
+}
+
+fn mfa_challenge_from_row(row: &AnyRow) -> Result<MfaChallenge> {
+    let attempts: i64 = row.try_get("attempts").map_err(server_error)?;
+    Ok(MfaChallenge {
+        _id: object_id(row)?,
+        token_hash: row.try_get("token_hash").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        user_agent: row.try_get("user_agent").map_err(server_error)?,
+        attempts: attempts as i32,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: datetime(row, "created_at")?,
+    })
+}
+
+fn webauthn_credential_from_row(row: &AnyRow) -> Result<WebauthnCredential> {
+    Ok(WebauthnCredential {
+        _id: object_id(row)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        credential_id: row.try_get("credential_id").map_err(server_error)?,
+        public_key: row.try_get("public_key").map_err(server_error)?,
+        alg: row.try_get("alg").map_err(server_error)?,
+        sign_count: row.try_get("sign_count").map_err(server_error)?,
+        name: row.try_get("name").map_err(server_error)?,
+        created_at: datetime(row, "created_at")?,
+        last_used_at: optional_datetime(row, "last_used_at")?,
+    })
+}
+
+fn webauthn_challenge_from_row(row: &AnyRow) -> Result<WebauthnChallenge> {
+    Ok(WebauthnChallenge {
+        _id: object_id(row)?,
+        challenge_hash: row.try_get("challenge_hash").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        purpose: row.try_get("purpose").map_err(server_error)?,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: datetime(row, "created_at")?,
+    })
+}
+
+fn magic_link_from_row(row: &AnyRow) -> Result<MagicLink> {
+    Ok(MagicLink {
+        _id: object_id(row)?,
+        token_hash: row.try_get("token_hash").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        user_agent: row.try_get("user_agent").map_err(server_error)?,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: datetime(row, "created_at")?,
+    })
+}
+
+fn otp_code_from_row(row: &AnyRow) -> Result<OtpCode> {
+    let attempts: i64 = row.try_get("attempts").map_err(server_error)?;
+    Ok(OtpCode {
+        _id: object_id(row)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        channel: row.try_get("channel").map_err(server_error)?,
+        code_hash: row.try_get("code_hash").map_err(server_error)?,
+        user_agent: row.try_get("user_agent").map_err(server_error)?,
+        attempts: attempts as i32,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: datetime(row, "created_at")?,
+    })
+}
+
+fn identity_provider_from_row(row: &AnyRow) -> Result<IdentityProvider> {
+    Ok(IdentityProvider {
+        _id: object_id(row)?,
+        provider_id: row.try_get("provider_id").map_err(server_error)?,
+        name: row.try_get("name").map_err(server_error)?,
+        issuer: row.try_get("issuer").map_err(server_error)?,
+        client_id: row.try_get("client_id").map_err(server_error)?,
+        client_secret: row.try_get("client_secret").map_err(server_error)?,
+        scope: row.try_get("scope").map_err(server_error)?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn saml_connection_from_row(row: &AnyRow) -> Result<SamlConnection> {
+    // the lists are stored as JSON, there is no portable array column type
+    let certificates: String = row.try_get("certificates").map_err(server_error)?;
+    let domains: String = row.try_get("domains").map_err(server_error)?;
+    let role_mapping: String = row.try_get("role_mapping").map_err(server_error)?;
+    Ok(SamlConnection {
+        _id: object_id(row)?,
+        connection_id: row.try_get("connection_id").map_err(server_error)?,
+        name: row.try_get("name").map_err(server_error)?,
+        idp_entity_id: row.try_get("idp_entity_id").map_err(server_error)?,
+        sso_url: row.try_get("sso_url").map_err(server_error)?,
+        certificates: serde_json::from_str(&certificates).map_err(|e| Error::ServerError {
+            message: e.to_string(),
+        })?,
+        domains: serde_json::from_str(&domains).map_err(|e| Error::ServerError {
+            message: e.to_string(),
+        })?,
+        email_attribute: row.try_get("email_attribute").map_err(server_error)?,
+        name_attribute: row.try_get("name_attribute").map_err(server_error)?,
+        role_attribute: row.try_get("role_attribute").map_err(server_error)?,
+        role_mapping: serde_json::from_str(&role_mapping).map_err(|e| Error::ServerError {
+            message: e.to_string(),
+        })?,
+        default_role: row.try_get("default_role").map_err(server_error)?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn ldap_connection_from_row(row: &AnyRow) -> Result<LdapConnection> {
+    let domains: String = row.try_get("domains").map_err(server_error)?;
+    let role_mapping: String = row.try_get("role_mapping").map_err(server_error)?;
+    Ok(LdapConnection {
+        _id: object_id(row)?,
+        connection_id: row.try_get("connection_id").map_err(server_error)?,
+        name: row.try_get("name").map_err(server_error)?,
+        url: row.try_get("url").map_err(server_error)?,
+        start_tls: flag(row, "start_tls")?,
+        bind_dn: row.try_get("bind_dn").map_err(server_error)?,
+        search_base: row.try_get("search_base").map_err(server_error)?,
+        user_filter: row.try_get("user_filter").map_err(server_error)?,
+        domains: serde_json::from_str(&domains).map_err(|e| Error::ServerError {
+            message: e.to_string(),
+        })?,
+        name_attribute: row.try_get("name_attribute").map_err(server_error)?,
+        group_attribute: row.try_get("group_attribute").map_err(server_error)?,
+        role_mapping: serde_json::from_str(&role_mapping).map_err(|e| Error::ServerError {
+            message: e.to_string(),
+        })?,
+        default_role: row.try_get("default_role").map_err(server_error)?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn user_identity_from_row(row: &AnyRow) -> Result<UserIdentity> {
+    Ok(UserIdentity {
+        _id: object_id(row)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        provider_id: row.try_get("provider_id").map_err(server_error)?,
+        subject_hash: row.try_get("subject_hash").map_err(server_error)?,
+        subject: row.try_get("subject").map_err(server_error)?,
+        email: row.try_get("email").map_err(server_error)?,
+        created_at: datetime(row, "created_at")?,
+    })
+}
+
+fn federation_state_from_row(row: &AnyRow) -> Result<FederationState> {
+    Ok(FederationState {
+        _id: object_id(row)?,
+        token_hash: row.try_get("token_hash").map_err(server_error)?,
+        provider_id: row.try_get("provider_id").map_err(server_error)?,
+        nonce: row.try_get("nonce").map_err(server_error)?,
+        code_verifier: row.try_get("code_verifier").map_err(server_error)?,
+        redirect_url: row.try_get("redirect_url").map_err(server_error)?,
+        user_agent: row.try_get("user_agent").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        link_uid: row.try_get("link_uid").map_err(server_error)?,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: datetime(row, "created_at")?,
+    })
+}
+
+fn service_account_from_row(row: &AnyRow) -> Result<ServiceAccount> {
+    Ok(ServiceAccount {
+        _id: object_id(row)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        name: row.try_get("name").map_err(server_error)?,
+        secret: row.try_get("secret").map_err(server_error)?,
+        scope: row.try_get("scope").map_err(server_error)?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn forget_password_request_from_row(row: &AnyRow) -> Result<ForgetPasswordRequest> {
+    Ok(ForgetPasswordRequest {
+        _id: object_id(row)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        req_id: row.try_get("req_id").map_err(server_error)?,
+        email: row.try_get("email").map_err(server_error)?,
+        is_used: flag(row, "is_used")?,
+        valid_till: datetime(row, "valid_till")?,
+        created_at: datetime(row, "created_at")?,
+        updated_at: datetime(row, "updated_at")?,
+    })
+}
+
+fn email_verification_request_from_row(row: &AnyRow) -> Result<EmailVerificationRequest> {
+    Ok(EmailVerificationRequest {
+        _id: object_id(row)?,
+        req_id: row.try_get("req_id").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        email: row.try_get("email").map_err(server_error)?,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: optional_datetime(row, "created_at")?,
+        updated_at: optional_datetime(row, "updated_at")?,
+    })
+}
+
+fn block_request_from_row(row: &AnyRow) -> Result<UserBlockRequest> {
+    Ok(UserBlockRequest {
+        _id: object_id(row)?,
+        req_id: row.try_get("req_id").map_err(server_error)?,
+        uid: row.try_get("uid").map_err(server_error)?,
+        email: row.try_get("email").map_err(server_error)?,
+        is_used: flag(row, "is_used")?,
+        expires_at: datetime(row, "expires_at")?,
+        created_at: optional_datetime(row, "created_at")?,
+        updated_at: optional_datetime(row, "updated_at")?,
+    })
+}
+
+// The writes that run inside the transaction of a guarded write, so they take
+// any executor instead of always the pool.
+async fn update_user_row<'e, E>(executor: E, user: &User, recovery_codes: String) -> sqlx::Result<AnyQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    sqlx::query(
+        "UPDATE users SET name = $1, email = $2, role = $3, password = $4, email_verified = $5, is_active = $6,
+         failed_login_attempts = $7, blocked_until = $8, totp_secret = $9, totp_enabled = $10, totp_last_step = $11,
+         recovery_codes = $12, phone = $13, phone_verified = $14, has_password = $15, created_at = $16, updated_at = $17
+         WHERE uid = $18",
+    )
+    .bind(&user.name)
+    .bind(&user.email)
+    .bind(&user.role)
+    .bind(&user.password)
+    .bind(user.email_verified as i64)
+    .bind(user.is_active as i64)
+    .bind(user.failed_login_attempts as i64)
+    .bind(millis(&user.blocked_until))
+    .bind(&user.totp_secret)
+    .bind(user.totp_enabled as i64)
+    .bind(user.totp_last_step)
+    .bind(recovery_codes)
+    .bind(&user.phone)
+    .bind(user.phone_verified as i64)
+    .bind(user.has_password as i64)
+    .bind(millis(&user.created_at))
+    .bind(millis(&user.updated_at))
+    .bind(&user.uid)
+    .execute(executor)
+    .await
+}
+
+async fn insert_email_verification_request_row<'e, E>(executor: E, request: &EmailVerificationRequest) -> sqlx::Result<AnyQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    sqlx::query(
+        "INSERT INTO email_verification_requests (id, req_id, uid, email, expires_at, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(request._id.to_hex())
+    .bind(&request.req_id)
+    .bind(&request.uid)
+    .bind(&request.email)
+    .bind(request.expires_at.timestamp_millis())
+    .bind(millis(&request.created_at))
+    .bind(millis(&request.updated_at))
+    .execute(executor)
+    .await
+}
+
+async fn insert_block_request_row<'e, E>(executor: E, request: &UserBlockRequest) -> sqlx::Result<AnyQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    sqlx::query(
+        "INSERT INTO users_block_requests (id, req_id, uid, email, is_used, expires_at, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+    .bind(request._id.to_hex())
+    .bind(&request.req_id)
+    .bind(&request.uid)
+    .bind(&request.email)
+    .bind(request.is_used as i64)
+    .bind(request.expires_at.timestamp_millis())
+    .bind(millis(&request.created_at))
+    .bind(millis(&request.updated_at))
+    .execute(executor)
+    .await
+}
+
+async fn insert_webauthn_credential_row<'e, E>(executor: E, credential: &WebauthnCredential) -> sqlx::Result<AnyQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    sqlx::query(
+        "INSERT INTO webauthn_credentials (id, uid, credential_id, public_key, alg, sign_count, name, created_at, last_used_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(credential._id.to_hex())
+    .bind(&credential.uid)
+    .bind(&credential.credential_id)
+    .bind(&credential.public_key)
+    .bind(credential.alg)
+    .bind(credential.sign_count)
+    .bind(&credential.name)
+    .bind(credential.created_at.timestamp_millis())
+    .bind(millis(&credential.last_used_at))
+    .execute(executor)
+    .await
+}
+
+async fn insert_user_identity_row<'e, E>(executor: E, identity: &UserIdentity) -> sqlx::Result<AnyQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    sqlx::query(
+        "INSERT INTO user_identities (id, uid, provider_id, subject_hash, subject, email, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(identity._id.to_hex())
+    .bind(&identity.uid)
+    .bind(&identity.provider_id)
+    .bind(&identity.subject_hash)
+    .bind(&identity.subject)
+    .bind(&identity.email)
+    .bind(identity.created_at.timestamp_millis())
+    .execute(executor)
+    .await
+}
+
+async fn insert_session_row<'e, E>(executor: E, session: &Session) -> sqlx::Result<AnyQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    sqlx::query(
+        "INSERT INTO sessions (session_id, uid, email, id_token, refresh_token, user_agent, os, os_version, vendor, device, browser, browser_version, is_revoked, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+    )
+    .bind(&session.session_id)
+    .bind(&session.uid)
+    .bind(&session.email)
+    .bind(&session.id_token)
+    .bind(&session.refresh_token)
+    .bind(&session.user_agent)
+    .bind(&session.os)
+    .bind(&session.os_version)
+    .bind(&session.vendor)
+    .bind(&session.device)
+    .bind(&session.browser)
+    .bind(&session.browser_version)
+    .bind(session.is_revoked as i64)
+    .bind(session.created_at.timestamp_millis())
+    .bind(session.updated_at.timestamp_millis())
+    .execute(executor)
+    .await
+}
+
+async fn insert_rotated_refresh_token_row<'e, E>(executor: E, token: &RotatedRefreshToken) -> sqlx::Result<AnyQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    sqlx::query(
+        "INSERT INTO rotated_refresh_tokens (id, token_hash, uid, session_id, expires_at, rotated_at) VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(token._id.to_hex())
+    .bind(&token.token_hash)
+    .bind(&token.uid)
+    .bind(&token.session_id)
+    .bind(token.expires_at.timestamp_millis())
+    .bind(token.rotated_at.timestamp_millis())
+    .execute(executor)
+    .await
+}
+
+#[async_trait]
+impl UserStore for SqlStore {
+    async fn insert_user(&self, user: &User) -> Result<()> {
+        let recovery_codes = serde_json::to_string(&user.recovery_codes).map_err(|e| {
+            Error::ServerError {
+                message: e.to_string(),
+            }
+        })?;
+        match sqlx::query(
+            "INSERT INTO users (id, uid, name, email, role, password, email_verified, is_active, failed_login_attempts, blocked_until,
+             totp_secret, totp_enabled, totp_last_step, recovery_codes, phone, phone_verified, has_password, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+        )
+        .bind(user._id.to_hex())
+        .bind(&user.uid)
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(&user.role)
+        .bind(&user.password)
+        .bind(user.email_verified as i64)
+        .bind(user.is_active as i64)
+        .bind(user.failed_login_attempts as i64)
+        .bind(millis(&user.blocked_until))
+        .bind(&user.totp_secret)
+        .bind(user.totp_enabled as i64)
+        .bind(user.totp_last_step)
+        .bind(recovery_codes)
+        .bind(&user.phone)
+        .bind(user.phone_verified as i64)
+        .bind(user.has_password as i64)
+        .bind(millis(&user.created_at))
+        .bind(millis(&user.updated_at))
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to Insert User".to_string(),
+            }),
+        }
+    }
+
+    async fn get_user(&self, uid: &str) -> Result<Option<User>> {
+        let row = sqlx::query("SELECT * FROM users WHERE uid = $1")
+            .bind(uid)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(user_from_row).transpose()
+    }
+
+    async fn get_users(&self) -> Result<Vec<User>> {
+        let rows = sqlx::query("SELECT * FROM users")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(user_from_row).collect()
+    }
+
+    async fn count_users(&self) -> Result<u64> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM users")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(server_error)?;
+        let count: i64 = row.try_get("count").map_err(server_error)?;
+        Ok(count as u64)
+    }
+
+    async fn update_user(&self, user: &User, dek_version: i64) -> Result<bool> {
+        let recovery_codes = serde_json::to_string(&user.recovery_codes).map_err(|e| {
+            Error::ServerError {
+                message: e.to_string(),
+            }
+        })?;
+        let mut tx = self.begin_guarded(&user.uid, dek_version).await?;
+        let res = update_user_row(&mut *tx, user, recovery_codes).await;
+        commit_guarded(tx, res).await.map(|res| res.rows_affected() > 0)
+    }
+
+    async fn replace_user_field(&self, uid: &str, field: &str, current: &str, value: &str) -> Result<bool> {
+        let field = column(field)?;
+        sqlx::query(&format!("UPDATE users SET {0} = $1 WHERE uid = $2 AND {0} = $3", field))
+            .bind(value)
+            .bind(uid)
+            .bind(current)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn set_totp_last_step(&self, uid: &str, step: i64) -> Result<bool> {
+        sqlx::query(
+            "UPDATE users SET totp_last_step = $1 WHERE uid = $2 AND (totp_last_step IS NULL OR totp_last_step < $3)",
+        )
+        .bind(step)
+        .bind(uid)
+        .bind(step)
+        .execute(&self.pool)
+        .await
+        .map(|res| res.rows_affected() > 0)
+        .map_err(server_error)
+    }
+
+    // The codes are a JSON array in one column, so the new array is only written
+    // if the column still holds the one it was made from and made again if not.
+    async fn remove_recovery_code(&self, uid: &str, hash: &str) -> Result<bool> {
+        loop {
+            let current: Option<String> = sqlx::query_scalar("SELECT recovery_codes FROM users WHERE uid = $1")
+                .bind(uid)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(server_error)?;
+            let current = match current {
+                Some(current) => current,
+                None => return Ok(false),
+            };
+            let mut recovery_codes: Vec<String> = serde_json::from_str(&current).map_err(|e| Error::ServerError {
+                message: e.to_string(),
+            })?;
+            match recovery_codes.iter().position(|h| h == hash) {
+                Some(index) => recovery_codes.remove(index),
+                None => return Ok(false),
+            };
+            let recovery_codes = serde_json::to_string(&recovery_codes).map_err(|e| Error::ServerError {
+                message: e.to_string(),
+            })?;
+
+            let res = sqlx::query("UPDATE users SET recovery_codes = $1 WHERE uid = $2 AND recovery_codes = $3")
+                .bind(recovery_codes)
+                .bind(uid)
+                .bind(&current)
+                .execute(&self.pool)
+                .await
+                .map_err(server_error)?;
+            if res.rows_affected() > 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    async fn delete_user(&self, uid: &str) -> Result<bool> {
+        match sqlx::query("DELETE FROM users WHERE uid = $1")
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to delete User".to_string(),
+            }),
+        }
+    }
+
+    async fn insert_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<()> {
+        let mut tx = self.begin_guarded(&request.uid, dek_version).await?;
+        let res = sqlx::query(
+            "INSERT INTO forget_password_requests (id, uid, req_id, email, is_used, valid_till, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(request._id.to_hex())
+        .bind(&request.uid)
+        .bind(&request.req_id)
+        .bind(&request.email)
+        .bind(request.is_used as i64)
+        .bind(request.valid_till.timestamp_millis())
+        .bind(request.created_at.timestamp_millis())
+        .bind(request.updated_at.timestamp_millis())
+        .execute(&mut *tx)
+        .await;
+        commit_guarded(tx, res).await.map(|_| ())
+    }
+
+    async fn get_forget_password_request(&self, req_id: &str) -> Result<Option<ForgetPasswordRequest>> {
+        let row = sqlx::query("SELECT * FROM forget_password_requests WHERE req_id = $1")
+            .bind(req_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(forget_password_request_from_row).transpose()
+    }
+
+    async fn update_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<bool> {
+        let mut tx = self.begin_guarded(&request.uid, dek_version).await?;
+        let res = sqlx::query(
+            "UPDATE forget_password_requests SET email = $1, is_used = $2, valid_till = $3, created_at = $4, updated_at = $5
+             WHERE req_id = $6",
+        )
+        .bind(&request.email)
+        .bind(request.is_used as i64)
+        .bind(request.valid_till.timestamp_millis())
+        .bind(request.created_at.timestamp_millis())
+        .bind(request.updated_at.timestamp_millis())
+        .bind(&request.req_id)
+        .execute(&mut *tx)
+        .await;
+        commit_guarded(tx, res).await.map(|res| res.rows_affected() > 0)
+    }
+
+    async fn insert_email_verification_request(&self, request: &EmailVerificationRequest, dek_version: i64) -> Result<()> {
+        let mut tx = self.begin_guarded(&request.uid, dek_version).await?;
+        let res = insert_email_verification_request_row(&mut *tx, request).await;
+        commit_guarded(tx, res).await.map(|_| ())
+    }
+
+    async fn get_email_verification_request(&self, req_id: &str) -> Result<Option<EmailVerificationRequest>> {
+        let row = sqlx::query("SELECT * FROM email_verification_requests WHERE req_id = $1")
+            .bind(req_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(email_verification_request_from_row).transpose()
+    }
+
+
+    async fn delete_email_verification_request(&self, req_id: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM email_verification_requests WHERE req_id = $1")
+            .bind(req_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<()> {
+        let mut tx = self.begin_guarded(&request.uid, dek_version).await?;
+        let res = insert_block_request_row(&mut *tx, request).await;
+        commit_guarded(tx, res).await.map(|_| ())
+    }
+
+    async fn get_block_request(&self, req_id: &str) -> Result<Option<UserBlockRequest>> {
+        let row = sqlx::query("SELECT * FROM users_block_requests WHERE req_id = $1")
+            .bind(req_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(block_request_from_row).transpose()
+    }
+
+
+    async fn update_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<bool> {
+        let mut tx = self.begin_guarded(&request.uid, dek_version).await?;
+        let res = sqlx::query(
+            "UPDATE users_block_requests SET uid = $1, email = $2, is_used = $3, expires_at = $4, created_at = $5, updated_at = $6
+             WHERE req_id = $7",
+        )
+        .bind(&request.uid)
+        .bind(&request.email)
+        .bind(request.is_used as i64)
+        .bind(request.expires_at.timestamp_millis())
+        .bind(millis(&request.created_at))
+        .bind(millis(&request.updated_at))
+        .bind(&request.req_id)
+        .execute(&mut *tx)
+        .await;
+        commit_guarded(tx, res).await.map(|res| res.rows_affected() > 0)
+    }
+
+    async fn insert_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<()> {
+        let mut tx = self.begin_guarded(&credential.uid, dek_version).await?;
+        let res = insert_webauthn_credential_row(&mut *tx, credential).await;
+        commit_guarded(tx, res).await.map(|_| ())
+    }
+
+    async fn get_webauthn_credentials(&self, uid: &str) -> Result<Vec<WebauthnCredential>> {
+        let rows = sqlx::query("SELECT * FROM webauthn_credentials WHERE uid = $1")
+            .bind(uid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(webauthn_credential_from_row).collect()
+    }
+
+    async fn update_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<bool> {
+        let mut tx = self.begin_guarded(&credential.uid, dek_version).await?;
+        let res = sqlx::query(
+            "UPDATE webauthn_credentials SET public_key = $1, alg = $2, sign_count = $3, name = $4, last_used_at = $5
+             WHERE uid = $6 AND credential_id = $7",
+        )
+        .bind(&credential.public_key)
+        .bind(credential.alg)
+        .bind(credential.sign_count)
+        .bind(&credential.name)
+        .bind(millis(&credential.last_used_at))
+        .bind(&credential.uid)
+        .bind(&credential.credential_id)
+        .execute(&mut *tx)
+        .await;
+        commit_guarded(tx, res).await.map(|res| res.rows_affected() > 0)
+    }
+
+    async fn delete_webauthn_credential(&self, uid: &str, credential_id: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM webauthn_credentials WHERE uid = $1 AND credential_id = $2")
+            .bind(uid)
+            .bind(credential_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_webauthn_credentials_by_uid(&self, uid: &str) -> Result<u64> {
+        sqlx::query("DELETE FROM webauthn_credentials WHERE uid = $1")
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_user_identity(&self, identity: &UserIdentity, dek_version: i64) -> Result<()> {
+        let mut tx = self.begin_guarded(&identity.uid, dek_version).await?;
+        match insert_user_identity_row(&mut *tx, identity).await {
+            Err(e) if is_unique_violation(&e) => Err(Error::IdentityAlreadyLinked {
+                message: "This account is already linked to a user".to_string(),
+            }),
+            res => commit_guarded(tx, res).await.map(|_| ()),
+        }
+    }
+
+    async fn get_user_identity(&self, subject_hash: &str) -> Result<Option<UserIdentity>> {
+        let row = sqlx::query("SELECT * FROM user_identities WHERE subject_hash = $1")
+            .bind(subject_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(user_identity_from_row).transpose()
+    }
+
+    async fn get_user_identities(&self, uid: &str) -> Result<Vec<UserIdentity>> {
+        let rows = sqlx::query("SELECT * FROM user_identities WHERE uid = $1")
+            .bind(uid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(user_identity_from_row).collect()
+    }
+
+    async fn unlink_user_identity(&self, uid: &str, provider_id: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await.map_err(server_error)?;
+        // locks the row of the user, so the unlinks of a user run one after the other
+        sqlx::query("UPDATE users SET has_password = has_password WHERE uid = $1")
+            .bind(uid)
+            .execute(&mut *tx)
+            .await
+            .map_err(server_error)?;
+        let row = sqlx::query(
+            "SELECT
+                (SELECT COUNT(*) FROM users WHERE uid = $1 AND has_password <> 0) AS passwords,
+                (SELECT COUNT(*) FROM webauthn_credentials WHERE uid = $1) AS passkeys,
+                (SELECT COUNT(*) FROM user_identities WHERE uid = $1) AS identities",
+        )
+        .bind(uid)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(server_error)?;
+        let passwords: i64 = row.try_get("passwords").map_err(server_error)?;
+        let passkeys: i64 = row.try_get("passkeys").map_err(server_error)?;
+        let identities: i64 = row.try_get("identities").map_err(server_error)?;
+        if passwords == 0 && passkeys == 0 && identities <= 1 {
+            return Ok(false);
+        }
+
+        let res = sqlx::query("DELETE FROM user_identities WHERE uid = $1 AND provider_id = $2")
+            .bind(uid)
+            .bind(provider_id)
+            .execute(&mut *tx)
+            .await;
+        commit_guarded(tx, res).await.map(|res| res.rows_affected() > 0)
+    }
+
+    async fn delete_user_identities_by_uid(&self, uid: &str) -> Result<u64> {
+        sqlx::query("DELETE FROM user_identities WHERE uid = $1")
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqlStore {
+    async fn insert_session(&self, session: &Session, dek_version: i64) -> Result<()> {
+        let mut tx = self.begin_guarded(&session.uid, dek_version).await?;
+        let res = insert_session_row(&mut *tx, session).await;
+        commit_guarded(tx, res).await.map(|_| ())
+    }
+
+    async fn get_sessions(&self) -> Result<Vec<Session>> {
+        let rows = sqlx::query("SELECT * FROM sessions")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn get_sessions_by_uid(&self, uid: &str) -> Result<Vec<Session>> {
+        let rows = sqlx::query("SELECT * FROM sessions WHERE uid = $1")
+            .bind(uid)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn update_session(&self, session: &Session, dek_version: i64) -> Result<bool> {
+        let mut tx = self.begin_guarded(&session.uid, dek_version).await?;
+        let res = sqlx::query(
+            "UPDATE sessions SET uid = $1, email = $2, id_token = $3, refresh_token = $4, user_agent = $5, os = $6, os_version = $7,
+             vendor = $8, device = $9, browser = $10, browser_version = $11, is_revoked = $12, created_at = $13, updated_at = $14
+             WHERE session_id = $15",
+        )
+        .bind(&session.uid)
+        .bind(&session.email)
+        .bind(&session.id_token)
+        .bind(&session.refresh_token)
+        .bind(&session.user_agent)
+        .bind(&session.os)
+        .bind(&session.os_version)
+        .bind(&session.vendor)
+        .bind(&session.device)
+        .bind(&session.browser)
+        .bind(&session.browser_version)
+        .bind(session.is_revoked as i64)
+        .bind(session.created_at.timestamp_millis())
+        .bind(session.updated_at.timestamp_millis())
+        .bind(&session.session_id)
+        .execute(&mut *tx)
+        .await;
+        commit_guarded(tx, res).await.map(|res| res.rows_affected() > 0)
+    }
+
+    async fn refresh_session(&self, session: &Session, current_refresh_token: &str, dek_version: i64) -> Result<bool> {
+        let mut tx = self.begin_guarded(&session.uid, dek_version).await?;
+        let res = sqlx::query(
+            "UPDATE sessions SET id_token = $1, refresh_token = $2, updated_at = $3
+             WHERE session_id = $4 AND refresh_token = $5 AND is_revoked = 0",
+        )
+        .bind(&session.id_token)
+        .bind(&session.refresh_token)
+        .bind(session.updated_at.timestamp_millis())
+        .bind(&session.session_id)
+        .bind(current_refresh_token)
+        .execute(&mut *tx)
+        .await;
+        commit_guarded(tx, res).await.map(|res| res.rows_affected() > 0)
+    }
+
+    async fn replace_session_field(&self, session_id: &str, field: &str, current: &str, value: &str) -> Result<bool> {
+        let field = column(field)?;
+        sqlx::query(&format!(
+            "UPDATE sessions SET {0} = $1 WHERE session_id = $2 AND {0} = $3",
+            field
+        ))
+        .bind(value)
+        .bind(session_id)
+        .bind(current)
+        .execute(&self.pool)
+        .await
+        .map(|res| res.rows_affected() > 0)
+        .map_err(server_error)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM sessions WHERE session_id = $1")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn revoke_sessions_by_uid(&self, uid: &str) -> Result<u64> {
+        sqlx::query("UPDATE sessions SET is_revoked = $1 WHERE uid = $2")
+            .bind(1_i64)
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn delete_sessions_by_uid(&self, uid: &str) -> Result<u64> {
+        sqlx::query("DELETE FROM sessions WHERE uid = $1")
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_rotated_refresh_token(&self, token: &RotatedRefreshToken, dek_version: i64) -> Result<()> {
+        let mut tx = self.begin_guarded(&token.uid, dek_version).await?;
+        let res = insert_rotated_refresh_token_row(&mut *tx, token).await;
+        commit_guarded(tx, res).await.map(|_| ())
+    }
+
+    async fn get_rotated_refresh_token(&self, token_hash: &str) -> Result<Option<RotatedRefreshToken>> {
+        let row = sqlx::query("SELECT * FROM rotated_refresh_tokens WHERE token_hash = $1")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(rotated_refresh_token_from_row).transpose()
+    }
+
+
+    async fn delete_expired_rotated_refresh_tokens(&self) -> Result<u64> {
+        sqlx::query("DELETE FROM rotated_refresh_tokens WHERE expires_at < $1")
+            .bind(DateTime::now().timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_mfa_challenge(&self, challenge: &MfaChallenge) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO mfa_challenges (id, token_hash, uid, user_agent, attempts, expires_at, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(challenge._id.to_hex())
+        .bind(&challenge.token_hash)
+        .bind(&challenge.uid)
+        .bind(&challenge.user_agent)
+        .bind(challenge.attempts as i64)
+        .bind(challenge.expires_at.timestamp_millis())
+        .bind(challenge.created_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_mfa_challenge(&self, token_hash: &str) -> Result<Option<MfaChallenge>> {
+        let row = sqlx::query("SELECT * FROM mfa_challenges WHERE token_hash = $1")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(mfa_challenge_from_row).transpose()
+    }
+
+    async fn count_mfa_challenge_attempt(&self, token_hash: &str, max_attempts: i32) -> Result<bool> {
+        sqlx::query("UPDATE mfa_challenges SET attempts = attempts + 1 WHERE token_hash = $1 AND attempts < $2")
+            .bind(token_hash)
+            .bind(max_attempts as i64)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_mfa_challenge(&self, token_hash: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM mfa_challenges WHERE token_hash = $1")
+            .bind(token_hash)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_mfa_challenges(&self) -> Result<u64> {
+        sqlx::query("DELETE FROM mfa_challenges WHERE expires_at < $1")
+            .bind(DateTime::now().timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_webauthn_challenge(&self, challenge: &WebauthnChallenge) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO webauthn_challenges (id, challenge_hash, uid, purpose, expires_at, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(challenge._id.to_hex())
+        .bind(&challenge.challenge_hash)
+        .bind(&challenge.uid)
+        .bind(&challenge.purpose)
+        .bind(challenge.expires_at.timestamp_millis())
+        .bind(challenge.created_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn take_webauthn_challenge(&self, challenge_hash: &str) -> Result<Option<WebauthnChallenge>> {
+        // DELETE ... RETURNING makes the read and the delete a single statement on both databases
+        let row = sqlx::query("DELETE FROM webauthn_challenges WHERE challenge_hash = $1 RETURNING *")
+            .bind(challenge_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(webauthn_challenge_from_row).transpose()
+    }
+
+    async fn delete_expired_webauthn_challenges(&self) -> Result<u64> {
+        sqlx::query("DELETE FROM webauthn_challenges WHERE expires_at < $1")
+            .bind(DateTime::now().timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_magic_link(&self, magic_link: &MagicLink) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO magic_links (id, token_hash, uid, user_agent, expires_at, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(magic_link._id.to_hex())
+        .bind(&magic_link.token_hash)
+        .bind(&magic_link.uid)
+        .bind(&magic_link.user_agent)
+        .bind(magic_link.expires_at.timestamp_millis())
+        .bind(magic_link.created_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn take_magic_link(&self, token_hash: &str) -> Result<Option<MagicLink>> {
+        let row = sqlx::query("DELETE FROM magic_links WHERE token_hash = $1 RETURNING *")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(magic_link_from_row).transpose()
+    }
+
+    async fn delete_expired_magic_links(&self) -> Result<u64> {
+        sqlx::query("DELETE FROM magic_links WHERE expires_at < $1")
+            .bind(DateTime::now().timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_otp_code(&self, otp: &OtpCode) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO otp_codes (id, uid, channel, code_hash, user_agent, attempts, expires_at, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(otp._id.to_hex())
+        .bind(&otp.uid)
+        .bind(&otp.channel)
+        .bind(&otp.code_hash)
+        .bind(&otp.user_agent)
+        .bind(otp.attempts as i64)
+        .bind(otp.expires_at.timestamp_millis())
+        .bind(otp.created_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_otp_code(&self, uid: &str, channel: &str) -> Result<Option<OtpCode>> {
+        let row = sqlx::query("SELECT * FROM otp_codes WHERE uid = $1 AND channel = $2")
+            .bind(uid)
+            .bind(channel)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(otp_code_from_row).transpose()
+    }
+
+    async fn count_otp_attempt(&self, otp: &OtpCode, max_attempts: i32) -> Result<bool> {
+        sqlx::query("UPDATE otp_codes SET attempts = attempts + 1 WHERE id = $1 AND attempts < $2")
+            .bind(otp._id.to_hex())
+            .bind(max_attempts as i64)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_otp_code(&self, uid: &str, channel: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM otp_codes WHERE uid = $1 AND channel = $2")
+            .bind(uid)
+            .bind(channel)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_expired_otp_codes(&self) -> Result<u64> {
+        sqlx::query("DELETE FROM otp_codes WHERE expires_at < $1")
+            .bind(DateTime::now().timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_federation_state(&self, state: &FederationState) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO federation_states (id, token_hash, provider_id, nonce, code_verifier, redirect_url, user_agent, uid, link_uid, expires_at, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+        )
+        .bind(state._id.to_hex())
+        .bind(&state.token_hash)
+        .bind(&state.provider_id)
+        .bind(&state.nonce)
+        .bind(&state.code_verifier)
+        .bind(&state.redirect_url)
+        .bind(&state.user_agent)
+        .bind(&state.uid)
+        .bind(&state.link_uid)
+        .bind(state.expires_at.timestamp_millis())
+        .bind(state.created_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn take_federation_state(&self, token_hash: &str) -> Result<Option<FederationState>> {
+        let row = sqlx::query("DELETE FROM federation_states WHERE token_hash = $1 RETURNING *")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(federation_state_from_row).transpose()
+    }
+
+    async fn delete_expired_federation_states(&self) -> Result<u64> {
+        sqlx::query("DELETE FROM federation_states WHERE expires_at < $1")
+            .bind(DateTime::now().timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+}
+
+#[async_trait]
+impl KeyStore for SqlStore {
+    async fn insert_dek(&self, dek: &Dek) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO deks (id, uid, email, email_index, dek, kek_id, version, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(dek._id.to_hex())
+        .bind(&dek.uid)
+        .bind(&dek.email)
+        .bind(&dek.email_index)
+        .bind(&dek.dek)
+        .bind(&dek.kek_id)
+        .bind(dek.version)
+        .bind(dek.created_at.timestamp_millis())
+        .bind(dek.updated_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_dek(&self, uid: &str) -> Result<Option<Dek>> {
+        let row = sqlx::query("SELECT * FROM deks WHERE uid = $1")
+            .bind(uid)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(dek_from_row).transpose()
+    }
+
+    async fn get_deks(&self) -> Result<Vec<Dek>> {
+        let rows = sqlx::query("SELECT * FROM deks")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(dek_from_row).collect()
+    }
+
+    async fn get_deks_to_rewrap(&self, kek_id: &str, after_uid: &str, limit: i64) -> Result<Vec<Dek>> {
+        let rows = sqlx::query(
+            "SELECT * FROM deks WHERE (kek_id IS NULL OR kek_id <> $1) AND uid > $2 ORDER BY uid LIMIT $3",
+        )
+        .bind(kek_id)
+        .bind(after_uid)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(server_error)?;
+        rows.iter().map(dek_from_row).collect()
+    }
+
+    async fn rewrap_dek(&self, dek: &Dek, current_kek_id: Option<&str>) -> Result<bool> {
+        // = never matches NULL, so the deks without a kek id need IS NULL
+        let query = match current_kek_id {
+            Some(_) => "UPDATE deks SET email = $1, dek = $2, kek_id = $3, updated_at = $4 WHERE uid = $5 AND version = $6 AND kek_id = $7",
+            None => "UPDATE deks SET email = $1, dek = $2, kek_id = $3, updated_at = $4 WHERE uid = $5 AND version = $6 AND kek_id IS NULL",
+        };
+        let mut query = sqlx::query(query)
+            .bind(&dek.email)
+            .bind(&dek.dek)
+            .bind(&dek.kek_id)
+            .bind(dek.updated_at.timestamp_millis())
+            .bind(&dek.uid)
+            .bind(dek.version);
+        if let Some(current_kek_id) = current_kek_id {
+            query = query.bind(current_kek_id);
+        }
+        query
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn get_dek_by_email_index(&self, email_index: &str) -> Result<Option<Dek>> {
+        let row = sqlx::query("SELECT * FROM deks WHERE email_index = $1")
+            .bind(email_index)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(dek_from_row).transpose()
+    }
+
+    async fn set_dek_email_index(&self, uid: &str, email_index: &str) -> Result<bool> {
+        sqlx::query("UPDATE deks SET email_index = $1 WHERE uid = $2 AND email_index IS NULL")
+            .bind(email_index)
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn delete_dek(&self, uid: &str) -> Result<bool> {
+        match sqlx::query("DELETE FROM deks WHERE uid = $1")
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(_) => Err(Error::ServerError {
+                message: "Failed to delete DEK".to_string(),
+            }),
+        }
+    }
+
+    async fn rotate_dek(&self, rotation: &DekRotation, current_version: i64) -> Result<bool> {
+        let uid = rotation.dek.uid.as_str();
+        // dropping the transaction without committing it rolls everything back
+        let mut tx = self.pool.begin().await.map_err(server_error)?;
+
+        // The dek is updated first so its row stays locked until the commit. The
+        // guarded writes of the user wait for it, so the records read below are
+        // all there are.
+        let res = sqlx::query(
+            "UPDATE deks SET email = $1, dek = $2, kek_id = $3, version = $4, created_at = $5, updated_at = $6
+             WHERE uid = $7 AND version = $8",
+        )
+        .bind(&rotation.dek.email)
+        .bind(&rotation.dek.dek)
+        .bind(&rotation.dek.kek_id)
+        .bind(rotation.dek.version)
+        .bind(rotation.dek.created_at.timestamp_millis())
+        .bind(rotation.dek.updated_at.timestamp_millis())
+        .bind(uid)
+        .bind(current_version)
+        .execute(&mut *tx)
+        .await
+        .map_err(server_error)?;
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let user = sqlx::query("SELECT * FROM users WHERE uid = $1")
+            .bind(uid)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(server_error)?;
+        let user = match user {
+            Some(row) => user_from_row(&row)?,
+            None => {
+                return Err(Error::UserNotFound {
+                    message: "User not found".to_string(),
+                })
+            }
+        };
+        let records = UserRecords {
+            user,
+            sessions: rows_of_user(&mut tx, "sessions", uid, session_from_row).await?,
+            rotated_refresh_tokens: rows_of_user(&mut tx, "rotated_refresh_tokens", uid, rotated_refresh_token_from_row).await?,
+            webauthn_credentials: rows_of_user(&mut tx, "webauthn_credentials", uid, webauthn_credential_from_row).await?,
+            identities: rows_of_user(&mut tx, "user_identities", uid, user_identity_from_row).await?,
+            forget_password_requests: rows_of_user(&mut tx, "forget_password_requests", uid, forget_password_request_from_row).await?,
+            email_verification_requests: rows_of_user(&mut tx, "email_verification_requests", uid, email_verification_request_from_row).await?,
+            block_requests: rows_of_user(&mut tx, "users_block_requests", uid, block_request_from_row).await?,
+        };
+        let rotated = rotation.reencrypt(&records);
+
+        // Only the encrypted columns are written. The writes that don't need the
+        // dek, like a revoke or a used recovery code, don't wait for the rotation
+        // and are kept.
+        let user = &rotated.user;
+        sqlx::query(
+            "UPDATE users SET name = $1, email = $2, role = $3, password = $4, totp_secret = $5, phone = $6 WHERE uid = $7",
+        )
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(&user.role)
+        .bind(&user.password)
+        .bind(&user.totp_secret)
+        .bind(&user.phone)
+        .bind(uid)
+        .execute(&mut *tx)
+        .await
+        .map_err(server_error)?;
+        for (old, new) in records.sessions.iter().zip(&rotated.sessions) {
+            sqlx::query(
+                "UPDATE sessions SET session_id = $1, email = $2, id_token = $3, refresh_token = $4, user_agent = $5, os = $6,
+                 os_version = $7, vendor = $8, device = $9, browser = $10, browser_version = $11 WHERE session_id = $12",
+            )
+            .bind(&new.session_id)
+            .bind(&new.email)
+            .bind(&new.id_token)
+            .bind(&new.refresh_token)
+            .bind(&new.user_agent)
+            .bind(&new.os)
+            .bind(&new.os_version)
+            .bind(&new.vendor)
+            .bind(&new.device)
+            .bind(&new.browser)
+            .bind(&new.browser_version)
+            .bind(&old.session_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(server_error)?;
+        }
+        for new in &rotated.rotated_refresh_tokens {
+            sqlx::query("UPDATE rotated_refresh_tokens SET session_id = $1 WHERE token_hash = $2")
+                .bind(&new.session_id)
+                .bind(&new.token_hash)
+                .execute(&mut *tx)
+                .await
+                .map_err(server_error)?;
+        }
+        for (old, new) in records.webauthn_credentials.iter().zip(&rotated.webauthn_credentials) {
+            sqlx::query(
+                "UPDATE webauthn_credentials SET credential_id = $1, public_key = $2, name = $3 WHERE uid = $4 AND credential_id = $5",
+            )
+            .bind(&new.credential_id)
+            .bind(&new.public_key)
+            .bind(&new.name)
+            .bind(uid)
+            .bind(&old.credential_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(server_error)?;
+        }
+        for new in &rotated.identities {
+            sqlx::query("UPDATE user_identities SET subject = $1, email = $2 WHERE subject_hash = $3")
+                .bind(&new.subject)
+                .bind(&new.email)
+                .bind(&new.subject_hash)
+                .execute(&mut *tx)
+                .await
+                .map_err(server_error)?;
+        }
+        let requests = rotated
+            .forget_password_requests
+            .iter()
+            .map(|request| ("forget_password_requests", &request.req_id, &request.email))
+            .chain(
+                rotated
+                    .email_verification_requests
+                    .iter()
+                    .map(|request| ("email_verification_requests", &request.req_id, &request.email)),
+            )
+            .chain(
+                rotated
+                    .block_requests
+                    .iter()
+                    .map(|request| ("users_block_requests", &request.req_id, &request.email)),
+            );
+        for (table, req_id, email) in requests {
+            sqlx::query(&format!("UPDATE {} SET email = $1 WHERE req_id = $2", table))
+                .bind(email)
+                .bind(req_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(server_error)?;
+        }
+
+        tx.commit().await.map(|_| true).map_err(server_error)
+    }
+}
+
+#[async_trait]
+impl OAuthStore for SqlStore {
+    async fn insert_client(&self, client: &OAuthClient) -> Result<()> {
+        let redirect_uris = serde_json::to_string(&client.redirect_uris).map_err(|e| {
+            Error::ServerError {
+                message: e.to_string(),
+            }
+        })?;
+        sqlx::query(
+            "INSERT INTO oauth_clients (id, client_id, name, redirect_uris, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(client._id.to_hex())
+        .bind(&client.client_id)
+        .bind(&client.name)
+        .bind(redirect_uris)
+        .bind(client.created_at.timestamp_millis())
+        .bind(client.updated_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>> {
+        let row = sqlx::query("SELECT * FROM oauth_clients WHERE client_id = $1")
+            .bind(client_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(oauth_client_from_row).transpose()
+    }
+
+    async fn get_clients(&self) -> Result<Vec<OAuthClient>> {
+        let rows = sqlx::query("SELECT * FROM oauth_clients")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(oauth_client_from_row).collect()
+    }
+
+    async fn delete_client(&self, client_id: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM oauth_clients WHERE client_id = $1")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_identity_provider(&self, provider: &IdentityProvider) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO identity_providers (id, provider_id, name, issuer, client_id, client_secret, scope, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(provider._id.to_hex())
+        .bind(&provider.provider_id)
+        .bind(&provider.name)
+        .bind(&provider.issuer)
+        .bind(&provider.client_id)
+        .bind(&provider.client_secret)
+        .bind(&provider.scope)
+        .bind(provider.created_at.timestamp_millis())
+        .bind(provider.updated_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_identity_provider(&self, provider_id: &str) -> Result<Option<IdentityProvider>> {
+        let row = sqlx::query("SELECT * FROM identity_providers WHERE provider_id = $1")
+            .bind(provider_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(identity_provider_from_row).transpose()
+    }
+
+    async fn get_identity_providers(&self) -> Result<Vec<IdentityProvider>> {
+        let rows = sqlx::query("SELECT * FROM identity_providers")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(identity_provider_from_row).collect()
+    }
+
+    async fn delete_identity_provider(&self, provider_id: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM identity_providers WHERE provider_id = $1")
+            .bind(provider_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_saml_connection(&self, connection: &SamlConnection) -> Result<()> {
+        let to_json = |value: serde_json::Result<String>| {
+            value.map_err(|e| Error::ServerError {
+                message: e.to_string(),
+            })
+        };
+        let certificates = to_json(serde_json::to_string(&connection.certificates))?;
+        let domains = to_json(serde_json::to_string(&connection.domains))?;
+        let role_mapping = to_json(serde_json::to_string(&connection.role_mapping))?;
+        sqlx::query(
+            "INSERT INTO saml_connections (id, connection_id, name, idp_entity_id, sso_url, certificates, domains, email_attribute, name_attribute, role_attribute, role_mapping, default_role, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+        )
+        .bind(connection._id.to_hex())
+        .bind(&connection.connection_id)
+        .bind(&connection.name)
+        .bind(&connection.idp_entity_id)
+        .bind(&connection.sso_url)
+        .bind(certificates)
+        .bind(domains)
+        .bind(&connection.email_attribute)
+        .bind(&connection.name_attribute)
+        .bind(&connection.role_attribute)
+        .bind(role_mapping)
+        .bind(&connection.default_role)
+        .bind(connection.created_at.timestamp_millis())
+        .bind(connection.updated_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_saml_connection(&self, connection_id: &str) -> Result<Option<SamlConnection>> {
+        let row = sqlx::query("SELECT * FROM saml_connections WHERE connection_id = $1")
+            .bind(connection_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(saml_connection_from_row).transpose()
+    }
+
+    async fn get_saml_connections(&self) -> Result<Vec<SamlConnection>> {
+        let rows = sqlx::query("SELECT * FROM saml_connections")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(saml_connection_from_row).collect()
+    }
+
+    async fn delete_saml_connection(&self, connection_id: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM saml_connections WHERE connection_id = $1")
+            .bind(connection_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_ldap_connection(&self, connection: &LdapConnection) -> Result<()> {
+        let to_json = |value: serde_json::Result<String>| {
+            value.map_err(|e| Error::ServerError {
+                message: e.to_string(),
+            })
+        };
+        let domains = to_json(serde_json::to_string(&connection.domains))?;
+        let role_mapping = to_json(serde_json::to_string(&connection.role_mapping))?;
+        sqlx::query(
+            "INSERT INTO ldap_connections (id, connection_id, name, url, start_tls, bind_dn, search_base, user_filter, domains, name_attribute, group_attribute, role_mapping, default_role, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+        )
+        .bind(connection._id.to_hex())
+        .bind(&connection.connection_id)
+        .bind(&connection.name)
+        .bind(&connection.url)
+        .bind(connection.start_tls as i64)
+        .bind(&connection.bind_dn)
+        .bind(&connection.search_base)
+        .bind(&connection.user_filter)
+        .bind(domains)
+        .bind(&connection.name_attribute)
+        .bind(&connection.group_attribute)
+        .bind(role_mapping)
+        .bind(&connection.default_role)
+        .bind(connection.created_at.timestamp_millis())
+        .bind(connection.updated_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_ldap_connections(&self) -> Result<Vec<LdapConnection>> {
+        let rows = sqlx::query("SELECT * FROM ldap_connections")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(ldap_connection_from_row).collect()
+    }
+
+    async fn delete_ldap_connection(&self, connection_id: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM ldap_connections WHERE connection_id = $1")
+            .bind(connection_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+
+    async fn insert_authorization_code(&self, code: &AuthorizationCode) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO authorization_codes (id, code_hash, client_id, uid, redirect_uri, code_challenge, scope, nonce, expires_at, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(code._id.to_hex())
+        .bind(&code.code_hash)
+        .bind(&code.client_id)
+        .bind(&code.uid)
+        .bind(&code.redirect_uri)
+        .bind(&code.code_challenge)
+        .bind(&code.scope)
+        .bind(&code.nonce)
+        .bind(code.expires_at.timestamp_millis())
+        .bind(code.created_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn take_authorization_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>> {
+        // DELETE ... RETURNING makes the read and the delete a single statement on both databases
+        let row = sqlx::query("DELETE FROM authorization_codes WHERE code_hash = $1 RETURNING *")
+            .bind(code_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(authorization_code_from_row).transpose()
+    }
+
+    async fn delete_expired_authorization_codes(&self) -> Result<u64> {
+        sqlx::query("DELETE FROM authorization_codes WHERE expires_at < $1")
+            .bind(DateTime::now().timestamp_millis())
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(server_error)
+    }
+
+    async fn insert_service_account(&self, account: &ServiceAccount) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO service_accounts (id, uid, name, secret, scope, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(account._id.to_hex())
+        .bind(&account.uid)
+        .bind(&account.name)
+        .bind(&account.secret)
+        .bind(&account.scope)
+        .bind(account.created_at.timestamp_millis())
+        .bind(account.updated_at.timestamp_millis())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(server_error)
+    }
+
+    async fn get_service_account(&self, uid: &str) -> Result<Option<ServiceAccount>> {
+        let row = sqlx::query("SELECT * FROM service_accounts WHERE uid = $1")
+            .bind(uid)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(server_error)?;
+        row.as_ref().map(service_account_from_row).transpose()
+    }
+
+    async fn get_service_accounts(&self) -> Result<Vec<ServiceAccount>> {
+        let rows = sqlx::query("SELECT * FROM service_accounts")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(server_error)?;
+        rows.iter().map(service_account_from_row).collect()
+    }
+
+    async fn update_service_account(&self, account: &ServiceAccount) -> Result<bool> {
+        sqlx::query(
+            "UPDATE service_accounts SET name = $1, secret = $2, scope = $3, created_at = $4, updated_at = $5 WHERE uid = $6",
+        )
+        .bind(&account.name)
+        .bind(&account.secret)
+        .bind(&account.scope)
+        .bind(account.created_at.timestamp_millis())
+        .bind(account.updated_at.timestamp_millis())
+        .bind(&account.uid)
+        .execute(&self.pool)
+        .await
+        .map(|res| res.rows_affected() > 0)
+        .map_err(server_error)
+    }
+
+    async fn delete_service_account(&self, uid: &str) -> Result<bool> {
+        sqlx::query("DELETE FROM service_accounts WHERE uid = $1")
+            .bind(uid)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+            .map_err(server_error)
+    }
+}
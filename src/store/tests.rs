@@ -0,0 +1,434 @@
+// The contract every store keeps, run against the memory store and, with the
+// sqlite feature, against a SQLite file. The one-time records are taken once,
+// the CAS writes only go through with the current value and the DEK guarded
+// writes and rotations only with the current DEK version. A provider account is
+// linked once and the last way to sign in is never unlinked.
+use std::env;
+
+use bson::{oid::ObjectId, DateTime};
+use futures::future::join_all;
+
+use crate::{
+    core::{
+        authorization_code::AuthorizationCode, dek::Dek, dek_rotation::DekRotation,
+        federation::FederationState, magic_link::MagicLink, rotated_refresh_token::RotatedRefreshToken,
+        session::Session, user::User, user_identity::{subject_hash, UserIdentity},
+        webauthn::{WebauthnChallenge, WebauthnCredential},
+    },
+    errors::Error,
+    models::{
+        password_model::ForgetPasswordRequest,
+        user_model::{EmailVerificationRequest, UserBlockRequest},
+    },
+    store::memory_store::MemoryStore,
+    traits::{decryption::Decrypt, encryption::Encrypt, store::Store},
+    utils::encryption_utils::Encryption,
+};
+
+fn set_keys() {
+    env::set_var("SERVER_KEK", "11112222333344445555666677778888.aaaabbbbcccc");
+    env::set_var("EMAIL_INDEX_KEY", "abc");
+}
+
+fn in_a_minute() -> DateTime {
+    DateTime::from_millis(DateTime::now().timestamp_millis() + 60 * 1000)
+}
+
+fn session(uid: &str, session_id: &str, refresh_token: &str) -> Session {
+    Session {
+        uid: uid.to_string(),
+        session_id: session_id.to_string(),
+        email: "ada@example.com".to_string(),
+        id_token: "id token".to_string(),
+        refresh_token: refresh_token.to_string(),
+        user_agent: "curl".to_string(),
+        os: "Linux".to_string(),
+        os_version: "6".to_string(),
+        vendor: "".to_string(),
+        device: "pc".to_string(),
+        browser: "curl".to_string(),
+        browser_version: "8".to_string(),
+        is_revoked: false,
+        created_at: DateTime::now(),
+        updated_at: DateTime::now(),
+    }
+}
+
+// a user with a dek of version 0, stored as is
+async fn add_user(store: &dyn Store) -> User {
+    let user = User::new("Ada", "ada@example.com", "user", "secret");
+    store.insert_user(&user).await.unwrap();
+    store
+        .insert_dek(&Dek::new(&user.uid, &user.email, "dek"))
+        .await
+        .unwrap();
+    user
+}
+
+async fn takes_are_single_use(store: &dyn Store) {
+    store
+        .insert_magic_link(&MagicLink {
+            _id: ObjectId::new(),
+            token_hash: "magic".to_string(),
+            uid: "u1".to_string(),
+            user_agent: "curl".to_string(),
+            expires_at: in_a_minute(),
+            created_at: DateTime::now(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(store.take_magic_link("magic").await.unwrap().unwrap().uid, "u1");
+    assert!(store.take_magic_link("magic").await.unwrap().is_none());
+    assert!(store.take_magic_link("unknown").await.unwrap().is_none());
+
+    store
+        .insert_webauthn_challenge(&WebauthnChallenge {
+            _id: ObjectId::new(),
+            challenge_hash: "challenge".to_string(),
+            uid: None,
+            purpose: "authenticate".to_string(),
+            expires_at: in_a_minute(),
+            created_at: DateTime::now(),
+        })
+        .await
+        .unwrap();
+    assert!(store.take_webauthn_challenge("challenge").await.unwrap().is_some());
+    assert!(store.take_webauthn_challenge("challenge").await.unwrap().is_none());
+    assert!(store.take_webauthn_challenge("unknown").await.unwrap().is_none());
+
+    store
+        .insert_federation_state(&FederationState {
+            _id: ObjectId::new(),
+            token_hash: "state".to_string(),
+            provider_id: "idp".to_string(),
+            nonce: "nonce".to_string(),
+            code_verifier: "verifier".to_string(),
+            redirect_url: "https://app.example.com".to_string(),
+            user_agent: "curl".to_string(),
+            uid: None,
+            link_uid: None,
+            expires_at: in_a_minute(),
+            created_at: DateTime::now(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        store.take_federation_state("state").await.unwrap().unwrap().provider_id,
+        "idp"
+    );
+    assert!(store.take_federation_state("state").await.unwrap().is_none());
+    assert!(store.take_federation_state("unknown").await.unwrap().is_none());
+
+    store
+        .insert_authorization_code(&AuthorizationCode {
+            _id: ObjectId::new(),
+            code_hash: "code".to_string(),
+            client_id: "client".to_string(),
+            uid: "u1".to_string(),
+            redirect_uri: "https://app.example.com/callback".to_string(),
+            code_challenge: "challenge".to_string(),
+            scope: "openid".to_string(),
+            nonce: None,
+            expires_at: in_a_minute(),
+            created_at: DateTime::now(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        store.take_authorization_code("code").await.unwrap().unwrap().client_id,
+        "client"
+    );
+    assert!(store.take_authorization_code("code").await.unwrap().is_none());
+    assert!(store.take_authorization_code("unknown").await.unwrap().is_none());
+}
+
+async fn replaces_only_the_current_value(store: &dyn Store) {
+    let user = add_user(store).await;
+    assert!(!store.replace_user_field(&user.uid, "name", "Grace", "Ada L").await.unwrap());
+    assert!(store.replace_user_field(&user.uid, "name", "Ada", "Ada L").await.unwrap());
+    assert!(!store.replace_user_field(&user.uid, "name", "Ada", "Ada K").await.unwrap());
+    assert!(!store.replace_user_field("unknown", "name", "Ada L", "Ada K").await.unwrap());
+    assert_eq!(store.get_user(&user.uid).await.unwrap().unwrap().name, "Ada L");
+
+    store.insert_session(&session(&user.uid, "cas", "r1"), 0).await.unwrap();
+    assert!(store.replace_session_field("cas", "refresh_token", "r1", "r2").await.unwrap());
+    assert!(!store.replace_session_field("cas", "refresh_token", "r1", "r3").await.unwrap());
+    assert!(!store.replace_session_field("unknown", "refresh_token", "r2", "r3").await.unwrap());
+    let stored = store.get_sessions_by_uid(&user.uid).await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].refresh_token, "r2");
+}
+
+async fn refreshes_a_session_once(store: &dyn Store) {
+    let user = add_user(store).await;
+    store.insert_session(&session(&user.uid, "refresh", "r1"), 0).await.unwrap();
+
+    let refreshed = session(&user.uid, "refresh", "r2");
+    assert!(store.refresh_session(&refreshed, "r1", 0).await.unwrap());
+    // the other refresh read r1 too
+    assert!(!store.refresh_session(&session(&user.uid, "refresh", "r3"), "r1", 0).await.unwrap());
+    assert_eq!(store.get_sessions_by_uid(&user.uid).await.unwrap()[0].refresh_token, "r2");
+
+    store.revoke_sessions_by_uid(&user.uid).await.unwrap();
+    assert!(!store.refresh_session(&session(&user.uid, "refresh", "r3"), "r2", 0).await.unwrap());
+}
+
+async fn uses_second_factors_once(store: &dyn Store) {
+    let mut user = add_user(store).await;
+    assert!(store.set_totp_last_step(&user.uid, 5).await.unwrap());
+    assert!(!store.set_totp_last_step(&user.uid, 5).await.unwrap());
+    assert!(!store.set_totp_last_step(&user.uid, 4).await.unwrap());
+    assert!(store.set_totp_last_step(&user.uid, 6).await.unwrap());
+    assert_eq!(store.get_user(&user.uid).await.unwrap().unwrap().totp_last_step, Some(6));
+
+    user.recovery_codes = vec!["a".to_string(), "b".to_string()];
+    assert!(store.update_user(&user, 0).await.unwrap());
+    assert!(store.remove_recovery_code(&user.uid, "a").await.unwrap());
+    assert!(!store.remove_recovery_code(&user.uid, "a").await.unwrap());
+    assert!(!store.remove_recovery_code(&user.uid, "c").await.unwrap());
+    assert_eq!(
+        store.get_user(&user.uid).await.unwrap().unwrap().recovery_codes,
+        vec!["b".to_string()]
+    );
+}
+
+async fn guards_writes_with_the_dek_version(store: &dyn Store) {
+    let user = add_user(store).await;
+    assert!(matches!(
+        store.update_user(&user, 1).await,
+        Err(Error::DekRotated { .. })
+    ));
+    assert!(matches!(
+        store.insert_session(&session(&user.uid, "guarded", "r1"), 1).await,
+        Err(Error::DekRotated { .. })
+    ));
+    assert!(store.get_sessions_by_uid(&user.uid).await.unwrap().is_empty());
+
+    // a user without a dek has no version to write with
+    let other = User::new("Grace", "grace@example.com", "user", "secret");
+    store.insert_user(&other).await.unwrap();
+    assert!(matches!(
+        store.update_user(&other, 0).await,
+        Err(Error::DekRotated { .. })
+    ));
+}
+
+fn identity(uid: &str, provider_id: &str, subject: &str) -> UserIdentity {
+    UserIdentity {
+        _id: ObjectId::new(),
+        uid: uid.to_string(),
+        provider_id: provider_id.to_string(),
+        subject_hash: subject_hash(provider_id, subject),
+        subject: subject.to_string(),
+        email: None,
+        created_at: DateTime::now(),
+    }
+}
+
+async fn links_an_account_to_one_user(store: &dyn Store) {
+    let user = add_user(store).await;
+    let other = add_user(store).await;
+    store.insert_user_identity(&identity(&user.uid, "idp", "ada"), 0).await.unwrap();
+    assert!(matches!(
+        store.insert_user_identity(&identity(&other.uid, "idp", "ada"), 0).await,
+        Err(Error::IdentityAlreadyLinked { .. })
+    ));
+    assert_eq!(store.get_user_identities(&other.uid).await.unwrap().len(), 0);
+}
+
+async fn keeps_a_way_to_sign_in(store: &dyn Store) {
+    let mut user = User::new("Ada", "ada@example.com", "user", "");
+    user.has_password = false;
+    store.insert_user(&user).await.unwrap();
+    store
+        .insert_dek(&Dek::new(&user.uid, &user.email, "dek"))
+        .await
+        .unwrap();
+    store.insert_user_identity(&identity(&user.uid, "idp", "grace"), 0).await.unwrap();
+    store.insert_user_identity(&identity(&user.uid, "other", "grace"), 0).await.unwrap();
+
+    UserIdentity::unlink(store, &user.uid, "idp").await.unwrap();
+    assert!(matches!(
+        UserIdentity::unlink(store, &user.uid, "other").await,
+        Err(Error::LastLoginMethod { .. })
+    ));
+    assert!(matches!(
+        UserIdentity::unlink(store, &user.uid, "idp").await,
+        Err(Error::IdentityNotFound { .. })
+    ));
+
+    // two unlinks at the same time, only one of them can go through
+    store.insert_user_identity(&identity(&user.uid, "idp", "grace"), 0).await.unwrap();
+    let unlinks = join_all([
+        UserIdentity::unlink(store, &user.uid, "idp"),
+        UserIdentity::unlink(store, &user.uid, "other"),
+    ])
+    .await;
+    assert_eq!(unlinks.iter().filter(|unlink| unlink.is_ok()).count(), 1);
+    assert_eq!(store.get_user_identities(&user.uid).await.unwrap().len(), 1);
+}
+
+// every record of the user is re-encrypted with the new dek
+async fn rotates_every_record(store: &dyn Store) {
+    set_keys();
+    let dek = Dek::generate();
+    let user = User::new("Ada", "ada@example.com", "user", "secret")
+        .encrypt_and_add(store, &dek)
+        .await
+        .unwrap();
+    let before = Dek::new(&user.uid, &user.email, &dek)
+        .encrypt_and_add(store)
+        .await
+        .unwrap();
+    let encrypted = |data: &str| Encryption::encrypt_data(data, &dek);
+
+    session(&user.uid, "rotated", "r1").encrypt_add(store, &before).await.unwrap();
+    store
+        .insert_rotated_refresh_token(
+            &RotatedRefreshToken {
+                _id: ObjectId::new(),
+                token_hash: "rotated token".to_string(),
+                uid: user.uid.clone(),
+                session_id: encrypted("rotated"),
+                expires_at: in_a_minute(),
+                rotated_at: DateTime::now(),
+            },
+            before.version,
+        )
+        .await
+        .unwrap();
+    let credential = WebauthnCredential {
+        _id: ObjectId::new(),
+        uid: user.uid.clone(),
+        credential_id: "credential".to_string(),
+        public_key: "public key".to_string(),
+        alg: -7,
+        sign_count: 0,
+        name: "key".to_string(),
+        created_at: DateTime::now(),
+        last_used_at: None,
+    };
+    store
+        .insert_webauthn_credential(&credential.encrypt(&dek), before.version)
+        .await
+        .unwrap();
+    store
+        .insert_user_identity(
+            &UserIdentity {
+                _id: ObjectId::new(),
+                uid: user.uid.clone(),
+                provider_id: "idp".to_string(),
+                subject_hash: "subject hash".to_string(),
+                subject: encrypted("subject"),
+                email: Some(encrypted("ada@idp.example.com")),
+                created_at: DateTime::now(),
+            },
+            before.version,
+        )
+        .await
+        .unwrap();
+    store
+        .insert_forget_password_request(
+            &ForgetPasswordRequest {
+                _id: ObjectId::new(),
+                uid: user.uid.clone(),
+                email: encrypted("ada@example.com"),
+                req_id: "forget".to_string(),
+                is_used: false,
+                valid_till: in_a_minute(),
+                created_at: DateTime::now(),
+                updated_at: DateTime::now(),
+            },
+            before.version,
+        )
+        .await
+        .unwrap();
+    store
+        .insert_email_verification_request(
+            &EmailVerificationRequest {
+                _id: ObjectId::new(),
+                req_id: "verify".to_string(),
+                uid: user.uid.clone(),
+                email: encrypted("ada@example.com"),
+                expires_at: in_a_minute(),
+                created_at: Some(DateTime::now()),
+                updated_at: Some(DateTime::now()),
+            },
+            before.version,
+        )
+        .await
+        .unwrap();
+    store
+        .insert_block_request(
+            &UserBlockRequest {
+                _id: ObjectId::new(),
+                req_id: "block".to_string(),
+                uid: user.uid.clone(),
+                email: encrypted("ada@example.com"),
+                is_used: false,
+                expires_at: in_a_minute(),
+                created_at: Some(DateTime::now()),
+                updated_at: Some(DateTime::now()),
+            },
+            before.version,
+        )
+        .await
+        .unwrap();
+
+    DekRotation::rotate(store, &user.uid).await.unwrap();
+    let after = Dek::get(store, &user.uid).await.unwrap();
+    assert_eq!(after.version, before.version + 1);
+    let decrypted = |cipher_text: &str| Encryption::decrypt_data(cipher_text, &after.dek);
+
+    let stored_user = store.get_user(&user.uid).await.unwrap().unwrap();
+    assert_eq!(stored_user.decrypt(&after.dek).name, "Ada");
+    let sessions = store.get_sessions_by_uid(&user.uid).await.unwrap();
+    assert_eq!(sessions[0].decrypt(&after.dek).refresh_token, "r1");
+    let token = store.get_rotated_refresh_token("rotated token").await.unwrap().unwrap();
+    assert_eq!(decrypted(&token.session_id), "rotated");
+    let credentials = store.get_webauthn_credentials(&user.uid).await.unwrap();
+    assert_eq!(credentials[0].decrypt(&after.dek).credential_id, "credential");
+    let identity = store.get_user_identity("subject hash").await.unwrap().unwrap();
+    assert_eq!(decrypted(&identity.subject), "subject");
+    assert_eq!(decrypted(identity.email.as_deref().unwrap()), "ada@idp.example.com");
+    let request = store.get_forget_password_request("forget").await.unwrap().unwrap();
+    assert_eq!(decrypted(&request.email), "ada@example.com");
+    let request = store.get_email_verification_request("verify").await.unwrap().unwrap();
+    assert_eq!(decrypted(&request.email), "ada@example.com");
+    let request = store.get_block_request("block").await.unwrap().unwrap();
+    assert_eq!(decrypted(&request.email), "ada@example.com");
+
+    // the records can only be written with the new version now
+    assert!(matches!(
+        store.update_user(&stored_user, before.version).await,
+        Err(Error::DekRotated { .. })
+    ));
+    assert!(store.update_user(&stored_user, after.version).await.unwrap());
+}
+
+async fn keeps_the_contract(store: &dyn Store) {
+    takes_are_single_use(store).await;
+    replaces_only_the_current_value(store).await;
+    refreshes_a_session_once(store).await;
+    uses_second_factors_once(store).await;
+    guards_writes_with_the_dek_version(store).await;
+    links_an_account_to_one_user(store).await;
+    keeps_a_way_to_sign_in(store).await;
+    rotates_every_record(store).await;
+}
+
+#[tokio::test]
+async fn memory_store_keeps_the_contract() {
+    keeps_the_contract(&MemoryStore::new()).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sqlite_store_keeps_the_contract() {
+    let path = env::temp_dir().join(format!("store-contract-test-{}.db", std::process::id()));
+    let store = crate::store::sql_store::SqlStore::connect(&format!("sqlite://{}?mode=rwc", path.display()))
+        .await
+        .unwrap();
+    keeps_the_contract(&store).await;
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,13 @@
+use crate::errors::Result;
+
+// Wraps the DEKs, and the few secrets stored next to them, with a KEK that is
+// kept by the provider. The server uses the one selected with KEY_PROVIDER.
+
+pub trait KeyProvider: Send + Sync {
+    // the id of the KEK new values are wrapped with, stored next to them
+    fn key_id(&self) -> String;
+    fn wrap(&self, data: &str) -> Result<String>;
+    // Unwraps a value wrapped with the KEK of key_id. Values stored without an
+    // id are tried with every KEK. None when no KEK unwraps it.
+    fn unwrap(&self, wrapped: &str, key_id: Option<&str>) -> Option<String>;
+}
@@ -0,0 +1,24 @@
+use async_trait::async_trait;
+
+use crate::{
+    errors::Result,
+    utils::ldap_utils::{LdapEntry, LdapSearch},
+};
+
+// The directory the users of an LDAP connection sign in against. The server
+// talks to it over the network with Ldap3Directory.
+
+#[async_trait]
+pub trait LdapDirectory: Send + Sync {
+    // Binds as bind_dn with the password, then runs the search with the same
+    // connection and returns every entry it found. None when the directory
+    // refused the DN or password.
+    async fn bind_and_search(
+        &self,
+        url: &str,
+        start_tls: bool,
+        bind_dn: &str,
+        password: &str,
+        search: &LdapSearch<'_>,
+    ) -> Result<Option<Vec<LdapEntry>>>;
+}
@@ -1,2 +1,5 @@
 pub mod encryption;
-pub mod decryption;
\ No newline at end of file
+pub mod decryption;
+pub mod key_provider;
+pub mod ldap_directory;
+pub mod store;
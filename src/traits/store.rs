@@ -0,0 +1,185 @@
+use async_trait::async_trait;
+
+use crate::{
+    core::{
+        authorization_code::AuthorizationCode, dek::Dek, dek_rotation::DekRotation,
+        federation::FederationState,
+        identity_provider::IdentityProvider, ldap_connection::LdapConnection,
+        magic_link::MagicLink,
+        mfa_challenge::MfaChallenge, oauth_client::OAuthClient, otp::OtpCode,
+        rotated_refresh_token::RotatedRefreshToken, saml_connection::SamlConnection,
+        service_account::ServiceAccount, session::Session, user::User,
+        user_identity::UserIdentity,
+        webauthn::{WebauthnChallenge, WebauthnCredential},
+    },
+    errors::Result,
+    models::{
+        password_model::ForgetPasswordRequest,
+        user_model::{EmailVerificationRequest, UserBlockRequest},
+    },
+};
+
+// The stores only persist records, they never encrypt or decrypt anything.
+// Everything handed to them is already encrypted by the core modules, the
+// rotation handed to rotate_dek re-encrypts the records it reads itself.
+//
+// The writes of records encrypted with the DEK of a user take the version of the
+// DEK they were encrypted with. They fail with DekRotated instead of writing if
+// the DEK was rotated since, so nothing is ever stored under a DEK that is gone.
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn insert_user(&self, user: &User) -> Result<()>;
+    async fn get_user(&self, uid: &str) -> Result<Option<User>>;
+    async fn get_users(&self) -> Result<Vec<User>>;
+    async fn count_users(&self) -> Result<u64>;
+    // replaces the user with the same uid, returns false if there was none
+    async fn update_user(&self, user: &User, dek_version: i64) -> Result<bool>;
+    // sets a field of the user to value if it still is current, returns false if not
+    async fn replace_user_field(&self, uid: &str, field: &str, current: &str, value: &str) -> Result<bool>;
+    // records step as the last TOTP step used if it is later than the recorded one,
+    // returns false if it is not
+    async fn set_totp_last_step(&self, uid: &str, step: i64) -> Result<bool>;
+    // removes the recovery code with this hash, returns false if it is already gone
+    async fn remove_recovery_code(&self, uid: &str, hash: &str) -> Result<bool>;
+    async fn delete_user(&self, uid: &str) -> Result<bool>;
+
+    async fn insert_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<()>;
+    async fn get_forget_password_request(&self, req_id: &str) -> Result<Option<ForgetPasswordRequest>>;
+    async fn update_forget_password_request(&self, request: &ForgetPasswordRequest, dek_version: i64) -> Result<bool>;
+
+    async fn insert_email_verification_request(&self, request: &EmailVerificationRequest, dek_version: i64) -> Result<()>;
+    async fn get_email_verification_request(&self, req_id: &str) -> Result<Option<EmailVerificationRequest>>;
+    async fn delete_email_verification_request(&self, req_id: &str) -> Result<bool>;
+
+    async fn insert_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<()>;
+    async fn get_block_request(&self, req_id: &str) -> Result<Option<UserBlockRequest>>;
+    async fn update_block_request(&self, request: &UserBlockRequest, dek_version: i64) -> Result<bool>;
+
+    async fn insert_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<()>;
+    async fn get_webauthn_credentials(&self, uid: &str) -> Result<Vec<WebauthnCredential>>;
+    // credentials are matched on the stored (encrypted) credential_id
+    async fn update_webauthn_credential(&self, credential: &WebauthnCredential, dek_version: i64) -> Result<bool>;
+    async fn delete_webauthn_credential(&self, uid: &str, credential_id: &str) -> Result<bool>;
+    async fn delete_webauthn_credentials_by_uid(&self, uid: &str) -> Result<u64>;
+
+    async fn insert_user_identity(&self, identity: &UserIdentity, dek_version: i64) -> Result<()>;
+    async fn get_user_identity(&self, subject_hash: &str) -> Result<Option<UserIdentity>>;
+    async fn get_user_identities(&self, uid: &str) -> Result<Vec<UserIdentity>>;
+    // Deletes the identity if the user can still sign in without it, with a
+    // password, a passkey or another identity. The check and the delete happen
+    // together, so two unlinks can't take away the last two ways to sign in.
+    async fn unlink_user_identity(&self, uid: &str, provider_id: &str) -> Result<bool>;
+    async fn delete_user_identities_by_uid(&self, uid: &str) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert_session(&self, session: &Session, dek_version: i64) -> Result<()>;
+    async fn get_sessions(&self) -> Result<Vec<Session>>;
+    async fn get_sessions_by_uid(&self, uid: &str) -> Result<Vec<Session>>;
+    // sessions are matched on the stored (encrypted) session_id
+    async fn update_session(&self, session: &Session, dek_version: i64) -> Result<bool>;
+    // writes the new tokens of the session if it is not revoked and its refresh token
+    // is still current_refresh_token, returns false if another refresh got there first
+    async fn refresh_session(&self, session: &Session, current_refresh_token: &str, dek_version: i64) -> Result<bool>;
+    // sets a field of the session to value if it still is current, returns false if not
+    async fn replace_session_field(&self, session_id: &str, field: &str, current: &str, value: &str) -> Result<bool>;
+    async fn delete_session(&self, session_id: &str) -> Result<bool>;
+    async fn revoke_sessions_by_uid(&self, uid: &str) -> Result<u64>;
+    async fn delete_sessions_by_uid(&self, uid: &str) -> Result<u64>;
+
+    async fn insert_rotated_refresh_token(&self, token: &RotatedRefreshToken, dek_version: i64) -> Result<()>;
+    async fn get_rotated_refresh_token(&self, token_hash: &str) -> Result<Option<RotatedRefreshToken>>;
+    async fn delete_expired_rotated_refresh_tokens(&self) -> Result<u64>;
+
+    async fn insert_mfa_challenge(&self, challenge: &MfaChallenge) -> Result<()>;
+    async fn get_mfa_challenge(&self, token_hash: &str) -> Result<Option<MfaChallenge>>;
+    // counts an attempt at the challenge if it had fewer than max_attempts,
+    // returns false when they are used up or the challenge is gone
+    async fn count_mfa_challenge_attempt(&self, token_hash: &str, max_attempts: i32) -> Result<bool>;
+    async fn delete_mfa_challenge(&self, token_hash: &str) -> Result<bool>;
+    async fn delete_expired_mfa_challenges(&self) -> Result<u64>;
+
+    async fn insert_webauthn_challenge(&self, challenge: &WebauthnChallenge) -> Result<()>;
+    // removes the challenge while reading it so it can only ever be used once
+    async fn take_webauthn_challenge(&self, challenge_hash: &str) -> Result<Option<WebauthnChallenge>>;
+    async fn delete_expired_webauthn_challenges(&self) -> Result<u64>;
+
+    async fn insert_magic_link(&self, magic_link: &MagicLink) -> Result<()>;
+    // removes the link while reading it so it can only ever be used once
+    async fn take_magic_link(&self, token_hash: &str) -> Result<Option<MagicLink>>;
+    async fn delete_expired_magic_links(&self) -> Result<u64>;
+
+    async fn insert_otp_code(&self, otp: &OtpCode) -> Result<()>;
+    async fn get_otp_code(&self, uid: &str, channel: &str) -> Result<Option<OtpCode>>;
+    // counts an attempt at the code if it had fewer than max_attempts, returns
+    // false when they are used up or the code is gone
+    async fn count_otp_attempt(&self, otp: &OtpCode, max_attempts: i32) -> Result<bool>;
+    async fn delete_otp_code(&self, uid: &str, channel: &str) -> Result<bool>;
+    async fn delete_expired_otp_codes(&self) -> Result<u64>;
+
+    async fn insert_federation_state(&self, state: &FederationState) -> Result<()>;
+    // removes the state while reading it so it can only ever be used once
+    async fn take_federation_state(&self, token_hash: &str) -> Result<Option<FederationState>>;
+    async fn delete_expired_federation_states(&self) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    async fn insert_dek(&self, dek: &Dek) -> Result<()>;
+    async fn get_dek(&self, uid: &str) -> Result<Option<Dek>>;
+    async fn get_deks(&self) -> Result<Vec<Dek>>;
+    // up to limit deks not wrapped with kek_id, ordered by uid and starting after after_uid
+    async fn get_deks_to_rewrap(&self, kek_id: &str, after_uid: &str, limit: i64) -> Result<Vec<Dek>>;
+    // replaces the wrapped fields of the dek if it is still wrapped with current_kek_id
+    // and was not rotated since it was read
+    async fn rewrap_dek(&self, dek: &Dek, current_kek_id: Option<&str>) -> Result<bool>;
+    async fn get_dek_by_email_index(&self, email_index: &str) -> Result<Option<Dek>>;
+    // sets the email index of a dek that has none yet, returns false if it already had one
+    async fn set_dek_email_index(&self, uid: &str, email_index: &str) -> Result<bool>;
+    async fn delete_dek(&self, uid: &str) -> Result<bool>;
+    // Replaces the dek of the user with the rotated one if it still has
+    // current_version, returns false if not. In the same transaction every record
+    // of the user is read, re-encrypted by the rotation and updated in place, so
+    // writes made at the same time are either part of it or fail with DekRotated.
+    async fn rotate_dek(&self, rotation: &DekRotation, current_version: i64) -> Result<bool>;
+}
+
+#[async_trait]
+pub trait OAuthStore: Send + Sync {
+    async fn insert_client(&self, client: &OAuthClient) -> Result<()>;
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>>;
+    async fn get_clients(&self) -> Result<Vec<OAuthClient>>;
+    async fn delete_client(&self, client_id: &str) -> Result<bool>;
+
+    async fn insert_identity_provider(&self, provider: &IdentityProvider) -> Result<()>;
+    async fn get_identity_provider(&self, provider_id: &str) -> Result<Option<IdentityProvider>>;
+    async fn get_identity_providers(&self) -> Result<Vec<IdentityProvider>>;
+    async fn delete_identity_provider(&self, provider_id: &str) -> Result<bool>;
+
+    async fn insert_saml_connection(&self, connection: &SamlConnection) -> Result<()>;
+    async fn get_saml_connection(&self, connection_id: &str) -> Result<Option<SamlConnection>>;
+    async fn get_saml_connections(&self) -> Result<Vec<SamlConnection>>;
+    async fn delete_saml_connection(&self, connection_id: &str) -> Result<bool>;
+
+    async fn insert_ldap_connection(&self, connection: &LdapConnection) -> Result<()>;
+    async fn get_ldap_connections(&self) -> Result<Vec<LdapConnection>>;
+    async fn delete_ldap_connection(&self, connection_id: &str) -> Result<bool>;
+
+    async fn insert_authorization_code(&self, code: &AuthorizationCode) -> Result<()>;
+    // removes the code while reading it so it can only ever be redeemed once
+    async fn take_authorization_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>>;
+    async fn delete_expired_authorization_codes(&self) -> Result<u64>;
+
+    async fn insert_service_account(&self, account: &ServiceAccount) -> Result<()>;
+    async fn get_service_account(&self, uid: &str) -> Result<Option<ServiceAccount>>;
+    async fn get_service_accounts(&self) -> Result<Vec<ServiceAccount>>;
+    // replaces the account with the same uid, returns false if there was none
+    async fn update_service_account(&self, account: &ServiceAccount) -> Result<bool>;
+    async fn delete_service_account(&self, uid: &str) -> Result<bool>;
+}
+
+pub trait Store: UserStore + SessionStore + KeyStore + OAuthStore {}
+
+impl<T: UserStore + SessionStore + KeyStore + OAuthStore> Store for T {}
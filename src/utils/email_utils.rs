@@ -4,6 +4,7 @@ use lettre::message::header::ContentType;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
 
+#[derive(Clone)]
 pub struct Email {
     pub name: String,
     pub email: String,
@@ -22,6 +23,14 @@ impl Email {
     }
 
     pub async fn send(&self) {
+        #[cfg(not(test))]
+        self.deliver();
+        #[cfg(test)]
+        OUTBOX.lock().unwrap().push(self.clone());
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    fn deliver(&self) {
         let smtp_username = env::var("EMAIL").expect("EMAIL_ID must be set");
         let smtp_password = env::var("EMAIL_PASSWORD").expect("EMAIL_PASSWORD must be set");
         let name = env::var("MAIL_NAME").expect("NAME must be set");
@@ -52,3 +61,19 @@ impl Email {
         }
     }
 }
+
+// the emails sent by tests, which have no mail server to send them to
+#[cfg(test)]
+pub static OUTBOX: std::sync::Mutex<Vec<Email>> = std::sync::Mutex::new(Vec::new());
+
+// the bodies of the emails sent to the address so far
+#[cfg(test)]
+pub fn sent_to(email: &str) -> Vec<String> {
+    OUTBOX
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|sent| sent.email == email)
+        .map(|sent| sent.body.clone())
+        .collect()
+}
@@ -1,30 +1,184 @@
-use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit};
+use aes_gcm::{
+    aead::{Aead, OsRng},
+    AeadCore, Aes256Gcm, Key, KeyInit, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+
+// Values are stored as "$" followed by the base64url of the envelope:
+// version (1 byte) | random nonce (12 bytes) | ciphertext | tag (16 bytes).
+// Values stored before it are the hex of the ciphertext and tag alone,
+// encrypted with the iv that is part of every key, so they never start with $.
+const ENVELOPE_PREFIX: &str = "$";
+const ENVELOPE_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
 
 pub struct Encryption;
 
 impl Encryption {
+    // the key is the part of key_iv before the .
+    fn cipher(key_iv: &str) -> Option<Aes256Gcm> {
+        let key = key_iv.split('.').next().unwrap_or_default().as_bytes();
+        if key.len() != 32 {
+            return None;
+        }
+        Some(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)))
+    }
+
     pub fn encrypt_data(data: &str, key_iv: &str) -> String {
-        // split the key_iv into key and iv
-        let key_iv_vec: Vec<&str> = key_iv.split('.').collect();
-        let key_buff = Key::<Aes256Gcm>::from_slice(key_iv_vec[0].as_bytes().try_into().unwrap());
-        let cipher = Aes256Gcm::new(key_buff);
-        let cipher_text = cipher
-            .encrypt(key_iv_vec[1].as_bytes().into(), data.as_ref())
-            .unwrap();
-        // convert the cipher_text to string
-        return hex::encode(cipher_text);
+        let cipher = Self::cipher(key_iv).expect("Invalid encryption key");
+        // a fresh nonce every time, so the same value never gives the same ciphertext
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let cipher_text = cipher.encrypt(&nonce, data.as_bytes()).unwrap();
+        Self::seal(&nonce, &cipher_text)
+    }
+
+    // the envelope of an AES-GCM ciphertext, also used for the ones made outside of this process
+    pub fn seal(nonce: &[u8], cipher_text: &[u8]) -> String {
+        let mut envelope = Vec::with_capacity(1 + nonce.len() + cipher_text.len());
+        envelope.push(ENVELOPE_VERSION);
+        envelope.extend_from_slice(nonce);
+        envelope.extend_from_slice(cipher_text);
+        format!("{}{}", ENVELOPE_PREFIX, URL_SAFE_NO_PAD.encode(envelope))
+    }
+
+    // the nonce and ciphertext in an envelope, None for legacy values
+    pub fn open(value: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+        let envelope = URL_SAFE_NO_PAD.decode(value.strip_prefix(ENVELOPE_PREFIX)?).ok()?;
+        match envelope.split_first() {
+            Some((&ENVELOPE_VERSION, rest)) if rest.len() >= NONCE_LEN => {
+                let (nonce, cipher_text) = rest.split_at(NONCE_LEN);
+                Some((nonce.to_vec(), cipher_text.to_vec()))
+            }
+            _ => None,
+        }
+    }
+
+    // HMAC-SHA256 of the value with a key of its own, so encrypted values can
+    // still be looked up by it without their ciphertexts being deterministic
+    pub fn blind_index(data: &str, index_key: &str) -> String {
+        let pkey = PKey::hmac(index_key.as_bytes()).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey).unwrap();
+        signer.update(data.as_bytes()).unwrap();
+        hex::encode(signer.sign_to_vec().unwrap())
     }
 
     pub fn decrypt_data(cipher_text: &str, key_iv: &str) -> String {
-        // convert the cipher_text to bytes
-        let cipher_text = hex::decode(cipher_text).unwrap();
-        // split the key_iv into key and iv
-        let key_iv_vec: Vec<&str> = key_iv.split('.').collect();
-        let key_buff = Key::<Aes256Gcm>::from_slice(key_iv_vec[0].as_bytes().try_into().unwrap());
-        let cipher = Aes256Gcm::new(key_buff);
-        let data = cipher
-            .decrypt(key_iv_vec[1].as_bytes().into(), cipher_text.as_ref())
-            .unwrap();
-        return String::from_utf8(data).unwrap();
+        Self::try_decrypt_data(cipher_text, key_iv).expect("Could not decrypt the data")
+    }
+
+    // None when the value was not encrypted with this key or was changed
+    pub fn try_decrypt_data(cipher_text: &str, key_iv: &str) -> Option<String> {
+        let cipher = Self::cipher(key_iv)?;
+        let data = match Self::is_legacy(cipher_text) {
+            false => {
+                let (nonce, cipher_text) = Self::open(cipher_text)?;
+                cipher.decrypt(Nonce::from_slice(&nonce), cipher_text.as_ref()).ok()?
+            }
+            true => {
+                // the legacy format uses the iv after the . as the nonce
+                let iv = key_iv.split('.').nth(1)?.as_bytes();
+                if iv.len() != NONCE_LEN {
+                    return None;
+                }
+                let cipher_text = hex::decode(cipher_text).ok()?;
+                cipher.decrypt(Nonce::from_slice(iv), cipher_text.as_ref()).ok()?
+            }
+        };
+        String::from_utf8(data).ok()
+    }
+
+    // values in the legacy format share the nonce of their key and get re-encrypted
+    pub fn is_legacy(cipher_text: &str) -> bool {
+        !cipher_text.starts_with(ENVELOPE_PREFIX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "11112222333344445555666677778888.aaaabbbbcccc";
+    const OTHER_KEY: &str = "88887777666655554444333322221111.ccccbbbbaaaa";
+
+    // the envelope bytes of a value, to change them by hand
+    fn envelope(cipher_text: &str) -> Vec<u8> {
+        URL_SAFE_NO_PAD
+            .decode(cipher_text.strip_prefix(ENVELOPE_PREFIX).unwrap())
+            .unwrap()
+    }
+
+    fn seal_bytes(envelope: &[u8]) -> String {
+        format!("{}{}", ENVELOPE_PREFIX, URL_SAFE_NO_PAD.encode(envelope))
+    }
+
+    #[test]
+    fn round_trip() {
+        let cipher_text = Encryption::encrypt_data("jane@example.com", KEY);
+        assert!(!Encryption::is_legacy(&cipher_text));
+        assert_eq!(
+            Encryption::try_decrypt_data(&cipher_text, KEY).as_deref(),
+            Some("jane@example.com")
+        );
+    }
+
+    #[test]
+    fn same_value_never_gives_the_same_ciphertext() {
+        assert_ne!(
+            Encryption::encrypt_data("jane@example.com", KEY),
+            Encryption::encrypt_data("jane@example.com", KEY)
+        );
+    }
+
+    #[test]
+    fn legacy_hex_still_decrypts() {
+        // the old format: hex of ciphertext and tag, with the iv of the key as nonce
+        let cipher = Encryption::cipher(KEY).unwrap();
+        let legacy = hex::encode(
+            cipher
+                .encrypt(Nonce::from_slice(b"aaaabbbbcccc"), b"jane@example.com".as_ref())
+                .unwrap(),
+        );
+        assert!(Encryption::is_legacy(&legacy));
+        assert_eq!(
+            Encryption::try_decrypt_data(&legacy, KEY).as_deref(),
+            Some("jane@example.com")
+        );
+        assert_eq!(Encryption::try_decrypt_data(&legacy, OTHER_KEY), None);
+    }
+
+    #[test]
+    fn tampered_tag_is_rejected() {
+        let mut bytes = envelope(&Encryption::encrypt_data("jane@example.com", KEY));
+        *bytes.last_mut().unwrap() ^= 1;
+        assert_eq!(Encryption::try_decrypt_data(&seal_bytes(&bytes), KEY), None);
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let mut bytes = envelope(&Encryption::encrypt_data("jane@example.com", KEY));
+        bytes[1 + NONCE_LEN] ^= 1;
+        assert_eq!(Encryption::try_decrypt_data(&seal_bytes(&bytes), KEY), None);
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let cipher_text = Encryption::encrypt_data("jane@example.com", KEY);
+        assert_eq!(Encryption::try_decrypt_data(&cipher_text, OTHER_KEY), None);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut bytes = envelope(&Encryption::encrypt_data("jane@example.com", KEY));
+        bytes[0] = ENVELOPE_VERSION + 1;
+        let cipher_text = seal_bytes(&bytes);
+        assert_eq!(Encryption::open(&cipher_text), None);
+        assert_eq!(Encryption::try_decrypt_data(&cipher_text, KEY), None);
+    }
+
+    #[test]
+    fn truncated_envelope_is_rejected() {
+        assert_eq!(Encryption::try_decrypt_data("$AQ", KEY), None);
+        assert_eq!(Encryption::try_decrypt_data("$not base64!", KEY), None);
     }
 }
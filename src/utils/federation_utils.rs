@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::errors::{Error, Result};
+
+// the parts of the provider metadata (OpenID Connect Discovery 1.0) we use
+#[derive(Debug, Clone, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenEndpointResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenEndpointError {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    // some providers send "true" as a string
+    #[serde(default)]
+    pub email_verified: Option<Value>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+}
+
+impl IdTokenClaims {
+    pub fn is_email_verified(&self) -> bool {
+        match &self.email_verified {
+            Some(Value::Bool(verified)) => *verified,
+            Some(Value::String(verified)) => verified == "true",
+            _ => false,
+        }
+    }
+}
+
+// symmetric algorithms would need the client secret as key, we only accept signatures
+const ALLOWED_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+fn federation_failed(message: String) -> Error {
+    Error::FederationFailed { message }
+}
+
+// a provider that hangs must not hang the sign in with it
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap()
+}
+
+async fn get_json(url: &str) -> Result<Value> {
+    let response = match http_client().get(url).send().await {
+        Ok(response) => response,
+        Err(e) => return Err(federation_failed(format!("Failed to reach {}: {}", url, e))),
+    };
+    if !response.status().is_success() {
+        return Err(federation_failed(format!("{} answered with {}", url, response.status())));
+    }
+    match response.json::<Value>().await {
+        Ok(json) => Ok(json),
+        Err(e) => Err(federation_failed(format!("Invalid response from {}: {}", url, e))),
+    }
+}
+
+// fetches the provider metadata and makes sure it belongs to the issuer
+pub async fn discover(issuer: &str) -> Result<Discovery> {
+    let url = format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'));
+    let json = match get_json(&url).await {
+        Ok(json) => json,
+        Err(e) => return Err(e),
+    };
+    let discovery: Discovery = match serde_json::from_value(json) {
+        Ok(discovery) => discovery,
+        Err(e) => return Err(federation_failed(format!("Invalid provider metadata: {}", e))),
+    };
+
+    // the issuer of the metadata has to be exactly the one it was fetched for
+    if discovery.issuer != issuer {
+        return Err(federation_failed(format!(
+            "The provider metadata is for issuer {} instead of {}",
+            discovery.issuer, issuer
+        )));
+    }
+    Ok(discovery)
+}
+
+// redeems the authorization code at the token endpoint and returns the id token
+pub async fn exchange_code(
+    discovery: &Discovery,
+    client_id: &str,
+    client_secret: &str,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+) -> Result<String> {
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+        ("code_verifier", code_verifier),
+    ];
+    let response = match http_client()
+        .post(&discovery.token_endpoint)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .form(&params)
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(e) => return Err(federation_failed(format!("Failed to reach the token endpoint: {}", e))),
+    };
+
+    if !response.status().is_success() {
+        let status = response.status();
+        return match response.json::<TokenEndpointError>().await {
+            Ok(e) => Err(federation_failed(format!(
+                "The token endpoint answered with {}: {}",
+                e.error,
+                e.error_description.unwrap_or_default()
+            ))),
+            Err(_) => Err(federation_failed(format!("The token endpoint answered with {}", status))),
+        };
+    }
+
+    match response.json::<TokenEndpointResponse>().await {
+        Ok(TokenEndpointResponse { id_token: Some(id_token) }) => Ok(id_token),
+        Ok(_) => Err(federation_failed("The token endpoint did not return an id token".to_string())),
+        Err(e) => Err(federation_failed(format!("Invalid token endpoint response: {}", e))),
+    }
+}
+
+// Checks the signature of the id token against the provider keys and its iss,
+// aud and exp claims (OpenID Connect Core 1.0 section 3.1.3.7). The nonce is
+// left to the caller.
+pub async fn verify_id_token(discovery: &Discovery, client_id: &str, id_token: &str) -> Result<IdTokenClaims> {
+    let header = match decode_header(id_token) {
+        Ok(header) => header,
+        Err(e) => return Err(Error::InvalidToken { message: format!("Invalid id token: {}", e) }),
+    };
+    if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+        return Err(Error::InvalidToken {
+            message: format!("Id tokens signed with {:?} are not accepted", header.alg),
+        });
+    }
+
+    let json = match get_json(&discovery.jwks_uri).await {
+        Ok(json) => json,
+        Err(e) => return Err(e),
+    };
+    let jwks: JwkSet = match serde_json::from_value(json) {
+        Ok(jwks) => jwks,
+        Err(e) => return Err(federation_failed(format!("Invalid provider keys: {}", e))),
+    };
+
+    // without a kid the provider can only have a single key
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+    let key = match jwk.map(DecodingKey::from_jwk) {
+        Some(Ok(key)) => key,
+        Some(Err(e)) => return Err(federation_failed(format!("Unusable provider key: {}", e))),
+        None => {
+            return Err(Error::InvalidToken {
+                message: "The id token is not signed with a key of the provider".to_string(),
+            })
+        }
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&discovery.issuer]);
+    validation.set_audience(&[client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    match decode::<IdTokenClaims>(id_token, &key, &validation) {
+        Ok(data) => Ok(data.claims),
+        Err(e) => Err(Error::InvalidToken {
+            message: format!("Invalid id token: {}", e),
+        }),
+    }
+}
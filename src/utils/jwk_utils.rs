@@ -0,0 +1,82 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, Jwk, KeyAlgorithm, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use openssl::{rsa::Rsa, sha::sha256};
+
+use crate::errors::Error;
+
+// Builds the RSA JWK for a PEM encoded public key. The kid is the RFC 7638
+// thumbprint of the key, so anyone holding the key can recompute it.
+pub fn rsa_jwk(public_key: &[u8]) -> Result<Jwk, Error> {
+    let rsa = match Rsa::public_key_from_pem(public_key) {
+        Ok(rsa) => rsa,
+        Err(err) => {
+            return Err(Error::PublicKeyLoadError {
+                message: err.to_string(),
+            })
+        }
+    };
+    let n = URL_SAFE_NO_PAD.encode(rsa.n().to_vec());
+    let e = URL_SAFE_NO_PAD.encode(rsa.e().to_vec());
+
+    // members in lexicographic order without whitespace as required by the thumbprint spec
+    let thumbprint_input = format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n);
+    let kid = URL_SAFE_NO_PAD.encode(sha256(thumbprint_input.as_bytes()));
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::RS256),
+            key_id: Some(kid),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n,
+            e,
+        }),
+    })
+}
+
+pub fn key_id(public_key: &[u8]) -> Result<String, Error> {
+    let jwk = rsa_jwk(public_key)?;
+    match jwk.common.key_id {
+        Some(kid) => Ok(kid),
+        None => Err(Error::PublicKeyLoadError {
+            message: "Error deriving key id".to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::bn::BigNum;
+
+    use super::*;
+
+    // the example key of RFC 7638 section 3.1
+    const N: &str = "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw";
+    const E: &str = "AQAB";
+
+    fn public_key_pem() -> Vec<u8> {
+        let n = BigNum::from_slice(&URL_SAFE_NO_PAD.decode(N).unwrap()).unwrap();
+        let e = BigNum::from_slice(&URL_SAFE_NO_PAD.decode(E).unwrap()).unwrap();
+        Rsa::from_public_components(n, e).unwrap().public_key_to_pem().unwrap()
+    }
+
+    #[test]
+    fn kid_is_the_rfc_7638_thumbprint() {
+        let jwk = rsa_jwk(&public_key_pem()).unwrap();
+        assert_eq!(jwk.common.key_id.as_deref(), Some("NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"));
+        match jwk.algorithm {
+            AlgorithmParameters::RSA(params) => {
+                assert_eq!(params.n, N);
+                assert_eq!(params.e, E);
+            }
+            _ => panic!("expected an RSA key"),
+        }
+        assert_eq!(key_id(&public_key_pem()).unwrap(), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+    }
+}
@@ -0,0 +1,192 @@
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use ldap3::{dn_escape, ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+
+use crate::{
+    errors::{Error, Result},
+    traits::ldap_directory::LdapDirectory,
+};
+
+pub const LDAP_TIMEOUT_SECS: u64 = 10;
+// the result code of a bind with a wrong DN or password
+const LDAP_INVALID_CREDENTIALS: u32 = 49;
+
+// the entry of the user in the directory, attribute names are lowercase
+#[derive(Debug, Clone)]
+pub struct LdapEntry {
+    pub attributes: HashMap<String, Vec<String>>,
+}
+
+impl LdapEntry {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        match self.attributes.get(&name.to_lowercase()) {
+            Some(values) => values.first().map(|value| value.as_str()),
+            None => None,
+        }
+    }
+
+    pub fn attribute_values(&self, name: &str) -> Vec<String> {
+        self.attributes
+            .get(&name.to_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+// where and how the user is looked up after binding
+pub struct LdapSearch<'a> {
+    // the entry of the bind DN is read when empty
+    pub base: &'a str,
+    pub filter: &'a str,
+    pub attributes: Vec<&'a str>,
+}
+
+fn ldap_failed(e: impl ToString) -> Error {
+    Error::LdapFailed {
+        message: format!("LDAP request failed: {}", e.to_string()),
+    }
+}
+
+// Fills {username} (the part of the email before the @) and {email} into a
+// template. The values are escaped the way the template is used, DN or filter.
+pub fn fill_template(template: &str, email: &str, escape: fn(&str) -> String) -> String {
+    let username = email.split('@').next().unwrap_or(email);
+    template
+        .replace("{username}", &escape(username))
+        .replace("{email}", &escape(email))
+}
+
+pub fn escape_dn(value: &str) -> String {
+    dn_escape(value).into_owned()
+}
+
+pub fn escape_filter(value: &str) -> String {
+    ldap_escape(value).into_owned()
+}
+
+// Binds to the directory as the user to check the password, then reads the
+// entry of the user with the same connection. Returns None when the directory
+// refused the DN or password.
+pub async fn bind_and_search(
+    directory: &dyn LdapDirectory,
+    url: &str,
+    start_tls: bool,
+    bind_dn: &str,
+    password: &str,
+    search: &LdapSearch<'_>,
+) -> Result<Option<LdapEntry>> {
+    // a simple bind without a password is an anonymous bind, which always succeeds
+    if password.is_empty() {
+        return Ok(None);
+    }
+
+    let entries = match directory.bind_and_search(url, start_tls, bind_dn, password, search).await {
+        Ok(Some(entries)) => entries,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // a filter matching several entries could hand the user the groups of someone else
+    let mut entries = entries.into_iter();
+    match (entries.next(), entries.next()) {
+        (Some(entry), None) => Ok(Some(entry)),
+        _ => Err(ldap_failed("the search has to find exactly one entry for the user")),
+    }
+}
+
+// the directory at the url of the connection
+pub struct Ldap3Directory;
+
+#[async_trait]
+impl LdapDirectory for Ldap3Directory {
+    async fn bind_and_search(
+        &self,
+        url: &str,
+        start_tls: bool,
+        bind_dn: &str,
+        password: &str,
+        search: &LdapSearch<'_>,
+    ) -> Result<Option<Vec<LdapEntry>>> {
+        let timeout = Duration::from_secs(LDAP_TIMEOUT_SECS);
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(timeout)
+            .set_starttls(start_tls);
+        let (conn, mut ldap) = match LdapConnAsync::with_settings(settings, url).await {
+            Ok(connection) => connection,
+            Err(e) => return Err(ldap_failed(e)),
+        };
+        ldap3::drive!(conn);
+
+        let bind = match ldap.with_timeout(timeout).simple_bind(bind_dn, password).await {
+            Ok(result) => result,
+            Err(e) => return Err(ldap_failed(e)),
+        };
+        if bind.rc == LDAP_INVALID_CREDENTIALS {
+            let _ = ldap.unbind().await;
+            return Ok(None);
+        }
+        if bind.rc != 0 {
+            let _ = ldap.unbind().await;
+            return Err(ldap_failed(format!("the bind returned {} {}", bind.rc, bind.text)));
+        }
+
+        let (base, scope, filter) = if search.base.is_empty() {
+            (bind_dn, Scope::Base, "(objectClass=*)")
+        } else {
+            (search.base, Scope::Subtree, search.filter)
+        };
+        let result = ldap
+            .with_timeout(timeout)
+            .search(base, scope, filter, search.attributes.clone())
+            .await;
+        let _ = ldap.unbind().await;
+        match result.and_then(|result| result.success()) {
+            Ok((entries, _)) => Ok(Some(
+                entries
+                    .into_iter()
+                    .map(SearchEntry::construct)
+                    .map(|entry| LdapEntry {
+                        attributes: entry
+                            .attrs
+                            .into_iter()
+                            .map(|(name, values)| (name.to_lowercase(), values))
+                            .collect(),
+                    })
+                    .collect(),
+            )),
+            Err(e) => Err(ldap_failed(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_filter_values() {
+        assert_eq!(escape_filter("*)(uid=*"), "\\2a\\29\\28uid=\\2a");
+        assert_eq!(escape_filter("a\\b\0"), "a\\5cb\\00");
+        assert_eq!(escape_filter("ada@example.com"), "ada@example.com");
+    }
+
+    #[test]
+    fn escapes_dn_values() {
+        assert_eq!(escape_dn("Doe, John+1"), "Doe\\2c John\\2b1");
+        assert_eq!(escape_dn("#admin "), "\\23admin\\20");
+        assert_eq!(escape_dn("a\"b<c>d;e\\"), "a\\22b\\3cc\\3ed\\3be\\5c");
+        assert_eq!(escape_dn("ada"), "ada");
+    }
+
+    #[test]
+    fn fills_templates_with_escaped_values() {
+        assert_eq!(
+            fill_template("uid={username},ou=people,dc=example,dc=com", "a,ou=admins@example.com", escape_dn),
+            "uid=a\\2cou\\3dadmins,ou=people,dc=example,dc=com"
+        );
+        assert_eq!(
+            fill_template("(&(mail={email})(uid={username}))", "*)(uid=*@example.com", escape_filter),
+            "(&(mail=\\2a\\29\\28uid=\\2a@example.com)(uid=\\2a\\29\\28uid=\\2a))"
+        );
+    }
+}
@@ -1,5 +1,13 @@
 pub mod email_utils;
 pub mod encryption_utils;
+pub mod federation_utils;
+pub mod jwk_utils;
+pub mod ldap_utils;
+pub mod oauth_utils;
 pub mod password_utils;
+pub mod saml_utils;
 pub mod session_utils;
+pub mod sms_utils;
+pub mod totp_utils;
 pub mod validation_utils;
+pub mod webauthn_utils;
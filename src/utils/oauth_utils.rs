@@ -0,0 +1,77 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use openssl::{rand::rand_bytes, sha::sha256};
+
+// random url safe token made of `bytes` random bytes
+pub fn generate_token(bytes: usize) -> String {
+    let mut buf = vec![0; bytes];
+    rand_bytes(&mut buf).unwrap();
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+// tokens are high entropy, so a plain sha256 is enough to store them
+pub fn hash_token(token: &str) -> String {
+    hex::encode(sha256(token.as_bytes()))
+}
+
+// the S256 PKCE challenge of a code verifier (RFC 7636)
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(sha256(code_verifier.as_bytes()))
+}
+
+// verifiers are 43-128 characters of [A-Z] / [a-z] / [0-9] / "-" / "." / "_" / "~"
+pub fn is_valid_code_verifier(code_verifier: &str) -> bool {
+    (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+}
+
+// appends the params to the query of a redirect uri
+pub fn redirect_url(redirect_uri: &str, params: &[(&str, &str)]) -> String {
+    let params: Vec<(&str, &str)> = params.iter().filter(|(_, v)| !v.is_empty()).cloned().collect();
+    let query = serde_urlencoded::to_string(params).unwrap_or_default();
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", redirect_uri, separator, query)
+}
+
+// a scope token is any printable ascii except space, '"' and '\' (RFC 6749 section 3.3)
+pub fn is_valid_scope_token(scope: &str) -> bool {
+    !scope.is_empty()
+        && scope
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '"' && c != '\\')
+}
+
+// Reads client_id and client_secret from an `Authorization: Basic` header.
+// Our ids and secrets are base64url, so they never need form url decoding.
+pub fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7636 appendix B
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+    #[test]
+    fn matches_the_rfc_7636_example() {
+        assert_eq!(pkce_challenge(VERIFIER), "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+
+    #[test]
+    fn checks_the_verifier_characters_and_length() {
+        assert!(is_valid_code_verifier(VERIFIER));
+        assert!(is_valid_code_verifier(&"~".repeat(128)));
+        assert!(!is_valid_code_verifier(&VERIFIER[..42]));
+        assert!(!is_valid_code_verifier(&"a".repeat(129)));
+        assert!(!is_valid_code_verifier(&format!("{}+", &VERIFIER[..42])));
+    }
+}
@@ -0,0 +1,919 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::Write,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use flate2::{write::DeflateEncoder, Compression};
+use openssl::{
+    bn::BigNum,
+    ecdsa::EcdsaSig,
+    hash::{hash, MessageDigest},
+    pkey::Id,
+    sign::Verifier,
+    x509::X509,
+};
+use roxmltree::{Document, Node, NodeId, NodeType};
+
+use crate::{
+    errors::{Error, Result},
+    utils::{federation_utils::http_client, oauth_utils::redirect_url},
+};
+
+const NS_PROTOCOL: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const NS_ASSERTION: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const NS_METADATA: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const NS_DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+const NS_EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+
+const BINDING_REDIRECT: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+const BINDING_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const METHOD_BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const TRANSFORM_ENVELOPED: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+
+// transient name ids change with every sign in
+pub const NAME_ID_TRANSIENT: &str = "urn:oasis:names:tc:SAML:2.0:nameid-format:transient";
+
+// clocks of the identity provider and ours don't have to agree to the second
+pub const SAML_CLOCK_SKEW_SECS: i64 = 120;
+
+// the parts of the identity provider metadata we use
+#[derive(Debug, Clone)]
+pub struct IdpMetadata {
+    pub entity_id: String,
+    pub sso_url: String,
+    // base64 DER of the certificates the provider signs with
+    pub certificates: Vec<String>,
+}
+
+// what the identity provider asserted about the user
+#[derive(Debug, Clone)]
+pub struct SamlAssertion {
+    pub name_id: String,
+    pub name_id_format: String,
+    // keyed by Name and by FriendlyName
+    pub attributes: HashMap<String, Vec<String>>,
+}
+
+impl SamlAssertion {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attribute_values(name)
+            .iter()
+            .find(|value| !value.is_empty())
+            .map(|value| value.as_str())
+    }
+
+    pub fn attribute_values(&self, name: &str) -> &[String] {
+        match self.attributes.get(name) {
+            Some(values) => values,
+            None => &[],
+        }
+    }
+}
+
+// what a response has to match to sign a user in
+pub struct ExpectedResponse<'a> {
+    pub idp_entity_id: &'a str,
+    pub certificates: &'a [String],
+    pub sp_entity_id: &'a str,
+    pub acs_url: &'a str,
+    pub request_id: &'a str,
+}
+
+fn invalid_response(message: &str) -> Error {
+    Error::InvalidToken {
+        message: format!("Invalid SAML response: {}", message),
+    }
+}
+
+fn invalid_metadata(message: &str) -> Error {
+    Error::InvalidPayload {
+        message: format!("Invalid SAML metadata: {}", message),
+    }
+}
+
+pub fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn is(node: &Node, namespace: &str, name: &str) -> bool {
+    node.is_element() && node.tag_name().namespace() == Some(namespace) && node.tag_name().name() == name
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, namespace: &'a str, name: &'a str) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| is(child, namespace, name))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, namespace: &'a str, name: &'a str) -> Option<Node<'a, 'input>> {
+    children(node, namespace, name).next()
+}
+
+// all of the text: a comment in the middle is left out of the signature, it must not cut the value short
+fn text(node: Node) -> String {
+    let text: String = node
+        .descendants()
+        .filter(|node| node.is_text())
+        .filter_map(|node| node.text())
+        .collect();
+    text.trim().to_string()
+}
+
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let value: String = value.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    STANDARD.decode(value).ok()
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// loads the metadata an identity provider publishes
+pub async fn fetch_metadata(url: &str) -> Result<String> {
+    let response = match http_client().get(url).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            return Err(Error::FederationFailed {
+                message: format!("Failed to reach {}: {}", url, e),
+            })
+        }
+    };
+    if !response.status().is_success() {
+        return Err(Error::FederationFailed {
+            message: format!("{} answered with {}", url, response.status()),
+        });
+    }
+    match response.text().await {
+        Ok(xml) => Ok(xml),
+        Err(e) => Err(Error::FederationFailed {
+            message: format!("Invalid response from {}: {}", url, e),
+        }),
+    }
+}
+
+// reads the entity id, the sign in url and the signing certificates of an identity provider
+pub fn parse_metadata(xml: &str) -> Result<IdpMetadata> {
+    let doc = match Document::parse(xml) {
+        Ok(doc) => doc,
+        Err(e) => return Err(invalid_metadata(&e.to_string())),
+    };
+
+    // the metadata can describe several entities, only one may be an identity provider
+    let descriptors: Vec<Node> = doc
+        .descendants()
+        .filter(|node| is(node, NS_METADATA, "IDPSSODescriptor"))
+        .collect();
+    let descriptor = match descriptors.as_slice() {
+        [descriptor] => *descriptor,
+        _ => return Err(invalid_metadata("it has to describe exactly one identity provider")),
+    };
+
+    let entity_id = match descriptor.parent_element() {
+        Some(entity) if is(&entity, NS_METADATA, "EntityDescriptor") => entity.attribute("entityID").unwrap_or(""),
+        _ => "",
+    };
+    if entity_id.is_empty() {
+        return Err(invalid_metadata("the identity provider has no entityID"));
+    }
+
+    let sso_url = match children(descriptor, NS_METADATA, "SingleSignOnService")
+        .find(|service| service.attribute("Binding") == Some(BINDING_REDIRECT))
+        .and_then(|service| service.attribute("Location"))
+    {
+        Some(location) if location.starts_with("https://") || location.starts_with("http://") => location,
+        _ => return Err(invalid_metadata("the identity provider has no HTTP-Redirect SingleSignOnService")),
+    };
+
+    // keys without a use are for signing and encryption
+    let mut certificates: Vec<String> = Vec::new();
+    for key in children(descriptor, NS_METADATA, "KeyDescriptor") {
+        if key.attribute("use").unwrap_or("signing") != "signing" {
+            continue;
+        }
+        for certificate in key.descendants().filter(|node| is(node, NS_DSIG, "X509Certificate")) {
+            let der = match decode_base64(&text(certificate)) {
+                Some(der) => der,
+                None => return Err(invalid_metadata("a certificate is not base64")),
+            };
+            if X509::from_der(&der).is_err() {
+                return Err(invalid_metadata("a certificate can't be read"));
+            }
+            let certificate = STANDARD.encode(der);
+            if !certificates.contains(&certificate) {
+                certificates.push(certificate);
+            }
+        }
+    }
+    if certificates.is_empty() {
+        return Err(invalid_metadata("the identity provider has no signing certificate"));
+    }
+
+    Ok(IdpMetadata {
+        entity_id: entity_id.to_string(),
+        sso_url: sso_url.to_string(),
+        certificates,
+    })
+}
+
+// the metadata the identity provider is configured with
+pub fn sp_metadata(sp_entity_id: &str, acs_url: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<md:EntityDescriptor xmlns:md="{}" entityID="{}">
+  <md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{}">
+    <md:NameIDFormat>urn:oasis:names:tc:SAML:2.0:nameid-format:persistent</md:NameIDFormat>
+    <md:NameIDFormat>urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress</md:NameIDFormat>
+    <md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>
+  </md:SPSSODescriptor>
+</md:EntityDescriptor>
+"#,
+        NS_METADATA,
+        escape_xml(sp_entity_id),
+        NS_PROTOCOL,
+        BINDING_POST,
+        escape_xml(acs_url),
+    )
+}
+
+// The url that sends the browser to the identity provider with an AuthnRequest,
+// using the HTTP-Redirect binding (SAML Bindings section 3.4). The response
+// comes back to the acs url with the HTTP-POST binding.
+pub fn authn_request_url(sso_url: &str, request_id: &str, sp_entity_id: &str, acs_url: &str, relay_state: &str) -> String {
+    let request = format!(
+        r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" ProtocolBinding="{}"><saml:Issuer>{}</saml:Issuer><samlp:NameIDPolicy AllowCreate="true"/></samlp:AuthnRequest>"#,
+        NS_PROTOCOL,
+        NS_ASSERTION,
+        escape_xml(request_id),
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        escape_xml(sso_url),
+        escape_xml(acs_url),
+        BINDING_POST,
+        escape_xml(sp_entity_id),
+    );
+
+    // writing to a Vec can't fail
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(request.as_bytes()).unwrap();
+    let deflated = encoder.finish().unwrap();
+
+    redirect_url(
+        sso_url,
+        &[("SAMLRequest", &STANDARD.encode(deflated)), ("RelayState", relay_state)],
+    )
+}
+
+// Checks the signature, the issuer, the audience, the recipient and the
+// validity of the Response the identity provider posted back and returns its
+// assertion. None means the identity provider did not sign the user in.
+pub fn verify_response(saml_response: &str, expected: &ExpectedResponse) -> Result<Option<SamlAssertion>> {
+    let xml = match decode_base64(saml_response).map(String::from_utf8) {
+        Some(Ok(xml)) => xml,
+        _ => return Err(invalid_response("it is not base64 encoded XML")),
+    };
+    // a DTD is rejected by the parser, so no entities can be expanded
+    let doc = match Document::parse(&xml) {
+        Ok(doc) => doc,
+        Err(e) => return Err(invalid_response(&e.to_string())),
+    };
+
+    let response = doc.root_element();
+    if !is(&response, NS_PROTOCOL, "Response") {
+        return Err(invalid_response("it is not a Response"));
+    }
+
+    // a signature points to the element with its ID, the same ID twice could point it to another one
+    let mut ids = HashSet::new();
+    for node in doc.descendants() {
+        if let Some(id) = node.attribute("ID") {
+            if !ids.insert(id) {
+                return Err(invalid_response("an ID is used twice"));
+            }
+        }
+    }
+
+    if let Some(destination) = response.attribute("Destination") {
+        if destination != expected.acs_url {
+            return Err(invalid_response("it is meant for another destination"));
+        }
+    }
+    if let Some(in_response_to) = response.attribute("InResponseTo") {
+        if in_response_to != expected.request_id {
+            return Err(invalid_response("it answers another request"));
+        }
+    }
+    if let Some(issuer) = child(response, NS_ASSERTION, "Issuer") {
+        if text(issuer) != expected.idp_entity_id {
+            return Err(invalid_response("it is from another issuer"));
+        }
+    }
+
+    let status = child(response, NS_PROTOCOL, "Status")
+        .and_then(|status| child(status, NS_PROTOCOL, "StatusCode"))
+        .and_then(|code| code.attribute("Value"));
+    if status != Some(STATUS_SUCCESS) {
+        return Ok(None);
+    }
+
+    if doc.descendants().any(|node| is(&node, NS_ASSERTION, "EncryptedAssertion")) {
+        return Err(invalid_response("encrypted assertions are not supported"));
+    }
+    let assertions: Vec<Node> = doc
+        .descendants()
+        .filter(|node| is(node, NS_ASSERTION, "Assertion"))
+        .collect();
+    let assertion = match assertions.as_slice() {
+        [assertion] if assertion.parent_element().map(|parent| parent.id()) == Some(response.id()) => *assertion,
+        _ => return Err(invalid_response("it has to hold exactly one assertion")),
+    };
+
+    // either one covers the assertion
+    match (
+        verify_signature(response, expected.certificates),
+        verify_signature(assertion, expected.certificates),
+    ) {
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+        (Ok(false), Ok(false)) => {
+            return Err(invalid_response("neither the response nor the assertion is signed"))
+        }
+        _ => {}
+    }
+
+    match check_assertion(assertion, expected) {
+        Ok(assertion) => Ok(Some(assertion)),
+        Err(e) => Err(e),
+    }
+}
+
+// the conditions of SAML Profiles section 4.1.4.3 for the Web Browser SSO profile
+fn check_assertion(assertion: Node, expected: &ExpectedResponse) -> Result<SamlAssertion> {
+    let now = Utc::now();
+    let skew = Duration::seconds(SAML_CLOCK_SKEW_SECS);
+
+    match child(assertion, NS_ASSERTION, "Issuer") {
+        Some(issuer) if text(issuer) == expected.idp_entity_id => {}
+        _ => return Err(invalid_response("the assertion is from another issuer")),
+    }
+
+    let subject = match child(assertion, NS_ASSERTION, "Subject") {
+        Some(subject) => subject,
+        None => return Err(invalid_response("the assertion has no subject")),
+    };
+    let name_id = match child(subject, NS_ASSERTION, "NameID") {
+        Some(name_id) if !text(name_id).is_empty() => name_id,
+        _ => return Err(invalid_response("the assertion has no NameID")),
+    };
+
+    // the bearer confirmation ties the assertion to this service provider and request
+    let confirmed = children(subject, NS_ASSERTION, "SubjectConfirmation")
+        .filter(|confirmation| confirmation.attribute("Method") == Some(METHOD_BEARER))
+        .filter_map(|confirmation| child(confirmation, NS_ASSERTION, "SubjectConfirmationData"))
+        .any(|data| {
+            let not_on_or_after = data.attribute("NotOnOrAfter").and_then(parse_time);
+            let not_before = match data.attribute("NotBefore") {
+                Some(value) => parse_time(value),
+                None => Some(now),
+            };
+            data.attribute("Recipient") == Some(expected.acs_url)
+                && data.attribute("InResponseTo") == Some(expected.request_id)
+                && matches!(not_on_or_after, Some(time) if now < time + skew)
+                && matches!(not_before, Some(time) if now + skew >= time)
+        });
+    if !confirmed {
+        return Err(invalid_response("the assertion is not meant for this sign in"));
+    }
+
+    let conditions = match child(assertion, NS_ASSERTION, "Conditions") {
+        Some(conditions) => conditions,
+        None => return Err(invalid_response("the assertion has no conditions")),
+    };
+    if let Some(not_before) = conditions.attribute("NotBefore") {
+        match parse_time(not_before) {
+            Some(time) if now + skew >= time => {}
+            _ => return Err(invalid_response("the assertion is not valid yet")),
+        }
+    }
+    if let Some(not_on_or_after) = conditions.attribute("NotOnOrAfter") {
+        match parse_time(not_on_or_after) {
+            Some(time) if now < time + skew => {}
+            _ => return Err(invalid_response("the assertion has expired")),
+        }
+    }
+    // every restriction has to include us
+    let restrictions: Vec<Node> = children(conditions, NS_ASSERTION, "AudienceRestriction").collect();
+    let for_us = restrictions.iter().all(|restriction| {
+        children(*restriction, NS_ASSERTION, "Audience").any(|audience| text(audience) == expected.sp_entity_id)
+    });
+    if restrictions.is_empty() || !for_us {
+        return Err(invalid_response("the assertion is meant for another audience"));
+    }
+
+    let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+    for statement in children(assertion, NS_ASSERTION, "AttributeStatement") {
+        for attribute in children(statement, NS_ASSERTION, "Attribute") {
+            let values: Vec<String> = children(attribute, NS_ASSERTION, "AttributeValue")
+                .map(text)
+                .collect();
+            for name in [attribute.attribute("Name"), attribute.attribute("FriendlyName")]
+                .into_iter()
+                .flatten()
+            {
+                attributes.entry(name.to_string()).or_default().extend(values.clone());
+            }
+        }
+    }
+
+    Ok(SamlAssertion {
+        name_id: text(name_id),
+        name_id_format: name_id.attribute("Format").unwrap_or("").to_string(),
+        attributes,
+    })
+}
+
+fn signature_algorithm(algorithm: &str) -> Option<(MessageDigest, Id)> {
+    match algorithm {
+        "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256" => Some((MessageDigest::sha256(), Id::RSA)),
+        "http://www.w3.org/2001/04/xmldsig-more#rsa-sha384" => Some((MessageDigest::sha384(), Id::RSA)),
+        "http://www.w3.org/2001/04/xmldsig-more#rsa-sha512" => Some((MessageDigest::sha512(), Id::RSA)),
+        "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256" => Some((MessageDigest::sha256(), Id::EC)),
+        "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha384" => Some((MessageDigest::sha384(), Id::EC)),
+        "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha512" => Some((MessageDigest::sha512(), Id::EC)),
+        _ => None,
+    }
+}
+
+fn digest_algorithm(algorithm: &str) -> Option<MessageDigest> {
+    match algorithm {
+        "http://www.w3.org/2001/04/xmlenc#sha256" => Some(MessageDigest::sha256()),
+        "http://www.w3.org/2001/04/xmldsig-more#sha384" => Some(MessageDigest::sha384()),
+        "http://www.w3.org/2001/04/xmlenc#sha512" => Some(MessageDigest::sha512()),
+        _ => None,
+    }
+}
+
+// the prefixes of the InclusiveNamespaces of an exclusive canonicalization, #default is ""
+fn inclusive_prefixes(method: Node) -> Vec<String> {
+    match child(method, NS_EXC_C14N, "InclusiveNamespaces").and_then(|namespaces| namespaces.attribute("PrefixList")) {
+        Some(prefixes) => prefixes
+            .split_ascii_whitespace()
+            .map(|prefix| if prefix == "#default" { "" } else { prefix }.to_string())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// Verifies the enveloped signature of the element (XML Signature 1.1) with
+// one of the certificates. The signature may only reference the element
+// itself, with SHA-2 digests and exclusive canonicalization. Returns false
+// when the element is not signed.
+fn verify_signature(element: Node, certificates: &[String]) -> Result<bool> {
+    let signatures: Vec<Node> = children(element, NS_DSIG, "Signature").collect();
+    let signature = match signatures.as_slice() {
+        [] => return Ok(false),
+        [signature] => *signature,
+        _ => return Err(invalid_response("an element has more than one signature")),
+    };
+    let id = match element.attribute("ID") {
+        Some(id) if !id.is_empty() => id,
+        _ => return Err(invalid_response("a signed element has no ID")),
+    };
+
+    let signed_info = match child(signature, NS_DSIG, "SignedInfo") {
+        Some(signed_info) => signed_info,
+        None => return Err(invalid_response("the signature has no SignedInfo")),
+    };
+    let canonicalization = match child(signed_info, NS_DSIG, "CanonicalizationMethod") {
+        Some(method) if method.attribute("Algorithm") == Some(NS_EXC_C14N) => method,
+        _ => return Err(invalid_response("only exclusive canonicalization is supported")),
+    };
+    let (signature_digest, key_type) = match child(signed_info, NS_DSIG, "SignatureMethod")
+        .and_then(|method| method.attribute("Algorithm"))
+        .and_then(signature_algorithm)
+    {
+        Some(algorithm) => algorithm,
+        None => return Err(invalid_response("the signature algorithm is not supported")),
+    };
+
+    let references: Vec<Node> = children(signed_info, NS_DSIG, "Reference").collect();
+    let reference = match references.as_slice() {
+        [reference] => *reference,
+        _ => return Err(invalid_response("the signature has to have exactly one reference")),
+    };
+    if reference.attribute("URI") != Some(format!("#{}", id).as_str()) {
+        return Err(invalid_response("the signature does not reference the signed element"));
+    }
+
+    let mut prefixes = None;
+    if let Some(transforms) = child(reference, NS_DSIG, "Transforms") {
+        for transform in children(transforms, NS_DSIG, "Transform") {
+            match transform.attribute("Algorithm") {
+                Some(TRANSFORM_ENVELOPED) => {}
+                Some(NS_EXC_C14N) => prefixes = Some(inclusive_prefixes(transform)),
+                _ => return Err(invalid_response("a transform of the signature is not supported")),
+            }
+        }
+    }
+    let prefixes = match prefixes {
+        Some(prefixes) => prefixes,
+        None => return Err(invalid_response("only exclusive canonicalization is supported")),
+    };
+
+    let digest = match child(reference, NS_DSIG, "DigestMethod")
+        .and_then(|method| method.attribute("Algorithm"))
+        .and_then(digest_algorithm)
+    {
+        Some(digest) => digest,
+        None => return Err(invalid_response("the digest algorithm is not supported")),
+    };
+    let digest_value = match child(reference, NS_DSIG, "DigestValue").and_then(|value| decode_base64(&text(value))) {
+        Some(value) => value,
+        None => return Err(invalid_response("the signature has no digest")),
+    };
+    // enveloped: the element is digested without its signature
+    let canonical = canonicalize(element, Some(signature.id()), &prefixes);
+    match hash(digest, canonical.as_bytes()) {
+        Ok(computed) if computed.as_ref() == digest_value.as_slice() => {}
+        _ => return Err(invalid_response("the digest does not match, the element was changed")),
+    }
+
+    let signature_value = match child(signature, NS_DSIG, "SignatureValue").and_then(|value| decode_base64(&text(value))) {
+        Some(value) => value,
+        None => return Err(invalid_response("the signature has no value")),
+    };
+    let signed_info = canonicalize(signed_info, None, &inclusive_prefixes(canonicalization));
+    // the KeyInfo of the signature is ignored, only the certificates of the metadata are trusted
+    let valid = certificates.iter().any(|certificate| {
+        verify_with_certificate(certificate, signature_digest, key_type, signed_info.as_bytes(), &signature_value)
+    });
+    if !valid {
+        return Err(invalid_response("the signature does not match a certificate of the identity provider"));
+    }
+    Ok(true)
+}
+
+fn verify_with_certificate(certificate: &str, digest: MessageDigest, key_type: Id, data: &[u8], signature: &[u8]) -> bool {
+    let key = match decode_base64(certificate).and_then(|der| X509::from_der(&der).ok()).and_then(|x509| x509.public_key().ok()) {
+        Some(key) if key.id() == key_type => key,
+        _ => return false,
+    };
+
+    // XML signatures hold ECDSA signatures as r and s next to each other, openssl wants DER
+    let signature = if key_type == Id::EC {
+        let (r, s) = signature.split_at(signature.len() / 2);
+        let der = match (BigNum::from_slice(r), BigNum::from_slice(s)) {
+            (Ok(r), Ok(s)) => EcdsaSig::from_private_components(r, s).and_then(|sig| sig.to_der()),
+            _ => return false,
+        };
+        match der {
+            Ok(der) => der,
+            Err(_) => return false,
+        }
+    } else {
+        signature.to_vec()
+    };
+
+    let mut verifier = match Verifier::new(digest, &key) {
+        Ok(verifier) => verifier,
+        Err(_) => return false,
+    };
+    verifier.verify_oneshot(&signature, data).unwrap_or(false)
+}
+
+// Exclusive XML Canonicalization 1.0 without comments (the subtree of the
+// element, leaving out skip). A namespace is only rendered on the elements
+// that use it, or as in inclusive canonicalization for the listed prefixes.
+// The test fixtures are signed with another implementation, see
+// tests/fixtures/saml/generate.py.
+fn canonicalize(element: Node, skip: Option<NodeId>, inclusive_prefixes: &[String]) -> String {
+    let mut out = String::new();
+    canonicalize_element(element, skip, inclusive_prefixes, &[], &mut out);
+    out
+}
+
+// roxmltree resolves the names, but the prefixes have to stay as written
+fn element_qname<'input>(element: Node<'_, 'input>) -> &'input str {
+    let rest = &element.document().input_text()[element.range().start + 1..];
+    let end = rest
+        .find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        .unwrap_or(rest.len());
+    &rest[..end]
+}
+
+fn prefix(qname: &str) -> &str {
+    match qname.split_once(':') {
+        Some((prefix, _)) => prefix,
+        None => "",
+    }
+}
+
+fn namespace_uri<'a>(element: Node<'a, '_>, prefix: &str) -> Option<&'a str> {
+    if prefix.is_empty() {
+        element.default_namespace()
+    } else {
+        element.lookup_namespace_uri(Some(prefix))
+    }
+}
+
+fn canonicalize_element(
+    element: Node,
+    skip: Option<NodeId>,
+    inclusive_prefixes: &[String],
+    rendered: &[(String, String)],
+    out: &mut String,
+) {
+    let input = element.document().input_text();
+    let qname = element_qname(element);
+
+    // the prefixes the element and its attributes use
+    let mut used: BTreeSet<&str> = BTreeSet::new();
+    used.insert(prefix(qname));
+    for attribute in element.attributes() {
+        let attribute_prefix = prefix(&input[attribute.range_qname()]);
+        if !attribute_prefix.is_empty() && attribute_prefix != "xml" {
+            used.insert(attribute_prefix);
+        }
+    }
+    for inclusive_prefix in inclusive_prefixes {
+        if namespace_uri(element, inclusive_prefix).is_some() {
+            used.insert(inclusive_prefix);
+        }
+    }
+
+    out.push('<');
+    out.push_str(qname);
+
+    // sorted by prefix, the default namespace first
+    let mut in_scope = rendered.to_vec();
+    for used_prefix in used {
+        let uri = namespace_uri(element, used_prefix).unwrap_or("");
+        let current = match rendered.iter().rev().find(|(rendered_prefix, _)| rendered_prefix == used_prefix) {
+            Some((_, uri)) => uri.as_str(),
+            None => "",
+        };
+        if uri == current {
+            continue;
+        }
+        if used_prefix.is_empty() {
+            out.push_str(" xmlns=\"");
+        } else {
+            out.push_str(" xmlns:");
+            out.push_str(used_prefix);
+            out.push_str("=\"");
+        }
+        out.push_str(&escape_c14n_attribute(uri));
+        out.push('"');
+        in_scope.push((used_prefix.to_string(), uri.to_string()));
+    }
+
+    // sorted by namespace uri and then local name, attributes without a namespace first
+    let mut attributes: Vec<_> = element.attributes().collect();
+    attributes.sort_by(|a, b| (a.namespace().unwrap_or(""), a.name()).cmp(&(b.namespace().unwrap_or(""), b.name())));
+    for attribute in attributes {
+        out.push(' ');
+        out.push_str(&input[attribute.range_qname()]);
+        out.push_str("=\"");
+        out.push_str(&escape_c14n_attribute(attribute.value()));
+        out.push('"');
+    }
+    out.push('>');
+
+    for node in element.children() {
+        match node.node_type() {
+            NodeType::Element if Some(node.id()) != skip => {
+                canonicalize_element(node, skip, inclusive_prefixes, &in_scope, out)
+            }
+            NodeType::Text => out.push_str(&escape_c14n_text(node.text().unwrap_or(""))),
+            NodeType::PI => {
+                if let Some(pi) = node.pi() {
+                    out.push_str("<?");
+                    out.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        out.push(' ');
+                        out.push_str(value);
+                    }
+                    out.push_str("?>");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(qname);
+    out.push('>');
+}
+
+fn escape_c14n_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+fn escape_c14n_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+// The fixtures are signed by tests/fixtures/saml/generate.py, which also
+// describes them.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = include_str!("../../tests/fixtures/saml/metadata.xml");
+    const SIGNED_ASSERTION: &str = include_str!("../../tests/fixtures/saml/signed_assertion.xml");
+    const SIGNED_RESPONSE: &str = include_str!("../../tests/fixtures/saml/signed_response.xml");
+
+    const SP: &str = "https://sp.example.com/saml/acme/metadata";
+    const ACS: &str = "https://sp.example.com/saml/acme/acs";
+    const REQUEST: &str = "_request1";
+
+    fn verify_with(xml: &str, sp_entity_id: &str, acs_url: &str, request_id: &str) -> Result<Option<SamlAssertion>> {
+        let metadata = parse_metadata(METADATA).unwrap();
+        let expected = ExpectedResponse {
+            idp_entity_id: &metadata.entity_id,
+            certificates: &metadata.certificates,
+            sp_entity_id,
+            acs_url,
+            request_id,
+        };
+        verify_response(&STANDARD.encode(xml), &expected)
+    }
+
+    fn verify(xml: &str) -> Result<Option<SamlAssertion>> {
+        verify_with(xml, SP, ACS, REQUEST)
+    }
+
+    // the response was turned down for the reason it should have been
+    fn assert_rejected(result: Result<Option<SamlAssertion>>, reason: &str) {
+        match result {
+            Err(Error::InvalidToken { message }) => assert!(message.contains(reason), "{}", message),
+            other => panic!("expected a rejection for {}, got {:?}", reason, other),
+        }
+    }
+
+    // the assertion of the response, as it was signed
+    fn assertion_of(xml: &str) -> &str {
+        let start = xml.find("<saml:Assertion").unwrap();
+        let end = xml.find("</saml:Assertion>").unwrap() + "</saml:Assertion>".len();
+        &xml[start..end]
+    }
+
+    // the assertion with another user and ID in it and without its signature
+    fn forged(xml: &str) -> String {
+        let assertion = assertion_of(xml)
+            .replace("ada@example.com", "eve@example.com")
+            .replace("ID=\"_assertion1\"", "ID=\"_forged\"");
+        let start = assertion.find("<ds:Signature").unwrap();
+        let end = assertion.find("</ds:Signature>").unwrap() + "</ds:Signature>".len();
+        format!("{}{}", &assertion[..start], &assertion[end..])
+    }
+
+    #[test]
+    fn reads_the_provider_metadata() {
+        let metadata = parse_metadata(METADATA).unwrap();
+        assert_eq!(metadata.entity_id, "https://idp.example.com");
+        assert_eq!(metadata.sso_url, "https://idp.example.com/sso");
+        assert_eq!(metadata.certificates.len(), 1);
+    }
+
+    #[test]
+    fn accepts_a_signed_assertion() {
+        let assertion = verify(SIGNED_ASSERTION).unwrap().unwrap();
+        assert_eq!(assertion.name_id, "ada@example.com");
+        assert_eq!(assertion.name_id_format, "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress");
+        assert_eq!(assertion.attribute("displayName"), Some("Ada Lovelace"));
+        assert_eq!(assertion.attribute("urn:oid:2.16.840.1.113730.3.1.241"), Some("Ada Lovelace"));
+    }
+
+    #[test]
+    fn accepts_a_signed_response() {
+        let assertion = verify(SIGNED_RESPONSE).unwrap().unwrap();
+        assert_eq!(assertion.name_id, "ada@example.com");
+    }
+
+    #[test]
+    fn rejects_a_tampered_assertion() {
+        for xml in [SIGNED_ASSERTION, SIGNED_RESPONSE] {
+            assert_rejected(verify(&xml.replace("Ada Lovelace", "Eve")), "the digest does not match");
+            assert_rejected(
+                verify(&xml.replace(">ada@example.com<", ">eve@example.com<")),
+                "the digest does not match",
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_an_unsigned_response() {
+        let unsigned = SIGNED_ASSERTION.replace(assertion_of(SIGNED_ASSERTION), &forged(SIGNED_ASSERTION));
+        assert_rejected(verify(&unsigned), "neither the response nor the assertion is signed");
+    }
+
+    #[test]
+    fn rejects_wrapped_assertions() {
+        let signed = assertion_of(SIGNED_ASSERTION);
+        let forged = forged(SIGNED_ASSERTION);
+
+        // the signed assertion tucked away next to the forged one
+        let hidden = SIGNED_ASSERTION.replace(
+            signed,
+            &format!("<samlp:Extensions>{}</samlp:Extensions>{}", signed, forged),
+        );
+        assert_rejected(verify(&hidden), "exactly one assertion");
+
+        // the forged assertion with the signature of the signed one
+        let signature_start = signed.find("<ds:Signature").unwrap();
+        let signature_end = signed.find("</ds:Signature>").unwrap() + "</ds:Signature>".len();
+        let issuer_end = forged.find("</saml:Issuer>").unwrap() + "</saml:Issuer>".len();
+        let resigned = format!(
+            "{}{}{}",
+            &forged[..issuer_end],
+            &signed[signature_start..signature_end],
+            &forged[issuer_end..]
+        );
+        assert_rejected(
+            verify(&SIGNED_ASSERTION.replace(signed, &resigned)),
+            "does not reference the signed element",
+        );
+
+        // a second assertion in a signed response
+        let appended = SIGNED_RESPONSE.replace("</samlp:Response>", &format!("{}</samlp:Response>", forged));
+        assert_rejected(verify(&appended), "exactly one assertion");
+    }
+
+    #[test]
+    fn rejects_a_duplicate_id() {
+        let duplicate = SIGNED_ASSERTION.replace("<samlp:Status>", "<samlp:Status ID=\"_assertion1\">");
+        assert_rejected(verify(&duplicate), "an ID is used twice");
+    }
+
+    #[test]
+    fn reads_the_whole_name_id_around_a_comment() {
+        let assertion = verify(include_str!("../../tests/fixtures/saml/comment_in_name_id.xml"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(assertion.name_id, "ada@example.com.evil.com");
+    }
+
+    #[test]
+    fn rejects_an_assertion_for_another_audience() {
+        assert_rejected(
+            verify(include_str!("../../tests/fixtures/saml/other_audience.xml")),
+            "meant for another audience",
+        );
+        assert_rejected(
+            verify_with(SIGNED_ASSERTION, "https://other.example.com/metadata", ACS, REQUEST),
+            "meant for another audience",
+        );
+    }
+
+    #[test]
+    fn rejects_an_assertion_for_another_recipient() {
+        assert_rejected(
+            verify(include_str!("../../tests/fixtures/saml/other_recipient.xml")),
+            "not meant for this sign in",
+        );
+        assert_rejected(
+            verify_with(SIGNED_ASSERTION, SP, "https://other.example.com/acs", REQUEST),
+            "meant for another destination",
+        );
+    }
+
+    #[test]
+    fn rejects_an_assertion_for_another_request() {
+        assert_rejected(
+            verify(include_str!("../../tests/fixtures/saml/other_request.xml")),
+            "not meant for this sign in",
+        );
+        assert_rejected(
+            verify_with(SIGNED_ASSERTION, SP, ACS, "_request2"),
+            "answers another request",
+        );
+    }
+
+    #[test]
+    fn rejects_an_expired_assertion() {
+        assert_rejected(
+            verify(include_str!("../../tests/fixtures/saml/expired.xml")),
+            "the assertion has expired",
+        );
+        assert_rejected(
+            verify(include_str!("../../tests/fixtures/saml/expired_confirmation.xml")),
+            "not meant for this sign in",
+        );
+    }
+}
@@ -1,49 +1,46 @@
 use jsonwebtoken as jwt;
-use jwt::{DecodingKey, EncodingKey, Header, Validation};
-use openssl::pkey::PKey;
-use openssl::rsa::Rsa;
+use jwt::{Header, Validation};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, env, fs};
+use std::{collections::HashMap, env, sync::Arc};
 
-use crate::{core::user::User, errors::Error};
+use crate::{
+    core::{
+        key_cache::{CachedKey, KeyCache},
+        user::User,
+    },
+    errors::Error,
+    utils::oauth_utils::generate_token,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct IDToken {
     pub uid: String,
+    // same as uid, the standard OIDC subject claim
+    #[serde(default)]
+    pub sub: String,
     iss: String,
-    iat: usize,
-    exp: usize,
+    // the OAuth client the token was issued to, not set for tokens from /auth/signin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    // the scope granted to the OAuth client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    pub iat: usize,
+    pub exp: usize,
     token_type: String,
     pub data: Option<HashMap<String, String>>,
 }
 
-pub fn load_private_key() -> Result<Vec<u8>, Error> {
-    let private_key_content = fs::read("private_key.pem");
-    let rsa = Rsa::private_key_from_pem(&private_key_content.unwrap()).unwrap();
-    let private_key = PKey::from_rsa(rsa).unwrap();
-    match private_key.private_key_to_pem_pkcs8() {
-        Ok(key) => return Ok(key),
-        Err(_) => {
-            return Err(Error::PrivateKeyLoadError {
-                message: "Error loading private key".to_string(),
-            });
-        }
-    };
-}
-
-// Load public key from the private key
-pub fn load_public_key() -> Result<Vec<u8>, Error> {
-    let private_key_content = fs::read("private_key.pem");
-    let rsa = Rsa::private_key_from_pem(&private_key_content.unwrap()).unwrap();
-    let private_key = PKey::from_rsa(rsa).unwrap();
-    match private_key.public_key_to_pem() {
-        Ok(key) => return Ok(key),
-        Err(_) => {
-            return Err(Error::PublicKeyLoadError {
-                message: "Error deriving public key from private key".to_string(),
-            });
-        }
-    };
+// the key the token was signed with, picked by the kid in its header
+fn verification_key(keys: &KeyCache, token: &str) -> Result<Arc<CachedKey>, Error> {
+    match jwt::decode_header(token) {
+        Ok(header) => keys.verification_key(header.kid.as_deref()),
+        Err(_) => Err(Error::InvalidToken {
+            message: "Invalid token".to_string(),
+        }),
+    }
 }
 
 impl IDToken {
@@ -52,7 +49,11 @@ impl IDToken {
             env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
         Self {
             uid: user.uid.to_string(),
+            sub: user.uid.to_string(),
             iss: server_url,
+            aud: None,
+            nonce: None,
+            scope: None,
             iat: chrono::Utc::now().timestamp() as usize,
             exp: chrono::Utc::now().timestamp() as usize + 3600, // 1h
             token_type: "id".to_string(),
@@ -73,20 +74,19 @@ impl IDToken {
         }
     }
 
-    pub fn sign(&self) -> Result<String, Error> {
-        let private_key = load_private_key()?;
-        let header = Header::new(jwt::Algorithm::RS256);
-        let encoding_key = match EncodingKey::from_rsa_pem(&private_key) {
-            Ok(key) => key,
-            Err(err) => {
-                eprintln!("Error creating decoding key: {}", err);
-                return Err(Error::PublicKeyLoadError {
-                    message: (err.to_string()),
-                });
-            }
-        };
+    pub fn for_client(mut self, client_id: &str, nonce: Option<&str>, scope: &str) -> Self {
+        self.aud = Some(client_id.to_string());
+        self.nonce = nonce.map(|n| n.to_string());
+        self.scope = Some(scope.to_string());
+        self
+    }
 
-        match jwt::encode(&header, &self, &encoding_key) {
+    pub fn sign(&self, keys: &KeyCache) -> Result<String, Error> {
+        let signing_key = keys.signing_key()?;
+        let mut header = Header::new(jwt::Algorithm::RS256);
+        header.kid = Some(signing_key.kid.clone());
+
+        match jwt::encode(&header, &self, &signing_key.encoding_key) {
             Ok(token) => return Ok(token),
             Err(err) => {
                 return Err(Error::IdTokenCreationError {
@@ -96,36 +96,36 @@ impl IDToken {
         };
     }
 
-    pub fn verify(token: &str) -> Result<(Self, bool), Error> {
-        let public_key = load_public_key()?;
-        let validation = Validation::new(jwt::Algorithm::RS256);
-        // Try to create a DecodingKey from the public key
-        let decoding_key = match DecodingKey::from_rsa_pem(&public_key) {
-            Ok(key) => key,
-            Err(err) => {
-                eprintln!("Error creating decoding key: {}", err);
-                return Err(Error::PublicKeyLoadError {
-                    message: (err.to_string()),
-                });
-            }
-        };
+    pub fn verify(keys: &KeyCache, token: &str) -> Result<(Self, bool), Error> {
+        let mut validation = Validation::new(jwt::Algorithm::RS256);
+        // a session is valid no matter which client the token was issued to
+        validation.validate_aud = false;
+        let verification_key = verification_key(keys, token)?;
+        let decoding_key = &verification_key.decoding_key;
         // return false if the token is not valid
-        match jwt::decode::<IDToken>(&token, &decoding_key, &validation) {
-            Ok(val) => {
+        match jwt::decode::<IDToken>(&token, decoding_key, &validation) {
+            Ok(val) if val.claims.token_type == "id" => {
                 let token_data = val.claims;
                 Ok((token_data, true))
             }
+            Ok(_) => Err(Error::InvalidToken {
+                message: "Invalid token".to_string(),
+            }),
             Err(e) => match e.kind() {
                 // check if ExpiredSignature
                 jwt::errors::ErrorKind::ExpiredSignature => {
                     // get token claims even if it is expired to check the data by decoding it with exp flag set to false
                     let mut validation = Validation::new(jwt::Algorithm::RS256);
                     validation.validate_exp = false;
-                    match jwt::decode::<IDToken>(&token, &decoding_key, &validation) {
-                        Ok(val) => {
+                    validation.validate_aud = false;
+                    match jwt::decode::<IDToken>(&token, decoding_key, &validation) {
+                        Ok(val) if val.claims.token_type == "id" => {
                             let token_data = val.claims;
                             Ok((token_data, false))
                         }
+                        Ok(_) => Err(Error::InvalidToken {
+                            message: "Invalid token".to_string(),
+                        }),
                         Err(_) => {
                             return Err(Error::ServerError {
                                 message: "Error decoding token".to_string(),
@@ -150,14 +150,84 @@ impl IDToken {
     
 }
 
+// Access token of a service account (client_credentials grant). It has no uid
+// claim, so it can never pass as an ID token or a refresh token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessToken {
+    pub sub: String,
+    iss: String,
+    pub client_id: String,
+    pub scope: String,
+    pub iat: usize,
+    pub exp: usize,
+    pub jti: String,
+    token_type: String,
+}
+
+impl AccessToken {
+    pub fn new(client_id: &str, scope: &str, ttl_secs: usize) -> Self {
+        let server_url =
+            env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+        Self {
+            sub: client_id.to_string(),
+            iss: server_url,
+            client_id: client_id.to_string(),
+            scope: scope.to_string(),
+            iat: chrono::Utc::now().timestamp() as usize,
+            exp: chrono::Utc::now().timestamp() as usize + ttl_secs,
+            jti: generate_token(16),
+            token_type: "access".to_string(),
+        }
+    }
+
+    pub fn sign(&self, keys: &KeyCache) -> Result<String, Error> {
+        let signing_key = keys.signing_key()?;
+        let mut header = Header::new(jwt::Algorithm::RS256);
+        header.kid = Some(signing_key.kid.clone());
+
+        match jwt::encode(&header, &self, &signing_key.encoding_key) {
+            Ok(token) => Ok(token),
+            Err(err) => Err(Error::AccessTokenCreationError {
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    pub fn verify(keys: &KeyCache, token: &str) -> Result<Self, Error> {
+        let validation = Validation::new(jwt::Algorithm::RS256);
+        let verification_key = verification_key(keys, token)?;
+        match jwt::decode::<AccessToken>(token, &verification_key.decoding_key, &validation) {
+            Ok(val) if val.claims.token_type == "access" => Ok(val.claims),
+            Ok(_) => Err(Error::InvalidToken {
+                message: "Invalid token".to_string(),
+            }),
+            Err(e) => match e.kind() {
+                jwt::errors::ErrorKind::ExpiredSignature => Err(Error::ExpiredSignature {
+                    message: "Expired signature".to_string(),
+                }),
+                jwt::errors::ErrorKind::InvalidSignature => Err(Error::SignatureVerificationError {
+                    message: "Invalid signature".to_string(),
+                }),
+                _ => Err(Error::InvalidToken {
+                    message: "Invalid token".to_string(),
+                }),
+            },
+        }
+    }
+}
+
 // RefreshToken struct
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefreshToken {
     pub uid: String,
     iss: String,
-    iat: usize,
-    exp: usize,
+    pub iat: usize,
+    pub exp: usize,
     scope: String,
+    // makes every refresh token unique, a rotated token must never match a current one
+    #[serde(default)]
+    pub jti: String,
+    token_type: String,
     pub data: Option<HashMap<String, String>>,
 }
 
@@ -171,24 +241,18 @@ impl RefreshToken {
             iat: chrono::Utc::now().timestamp() as usize,
             exp: chrono::Utc::now().timestamp() as usize + (3600 * 24 * 45), // 45 days
             scope: "get_new_id_token".to_string(),
+            jti: generate_token(16),
+            token_type: "refresh".to_string(),
             data: None,
         }
     }
 
-    pub fn sign(&self) -> Result<String, Error> {
-        let private_key = load_private_key()?;
-        let header = Header::new(jwt::Algorithm::RS256);
-        let encoding_key = match EncodingKey::from_rsa_pem(&private_key) {
-            Ok(key) => key,
-            Err(err) => {
-                eprintln!("Error creating decoding key: {}", err);
-                return Err(Error::PublicKeyLoadError {
-                    message: (err.to_string()),
-                });
-            }
-        };
+    pub fn sign(&self, keys: &KeyCache) -> Result<String, Error> {
+        let signing_key = keys.signing_key()?;
+        let mut header = Header::new(jwt::Algorithm::RS256);
+        header.kid = Some(signing_key.kid.clone());
 
-        match jwt::encode(&header, &self, &encoding_key) {
+        match jwt::encode(&header, &self, &signing_key.encoding_key) {
             Ok(token) => return Ok(token),
             Err(err) => {
                 return Err(Error::RefreshTokenCreationError {
@@ -198,44 +262,66 @@ impl RefreshToken {
         };
     }
 
-    pub fn verify(token: &str) -> Result<Self, Error> {
-        let public_key = load_public_key()?;
+    pub fn verify(keys: &KeyCache, token: &str) -> Result<Self, Error> {
         let validation = Validation::new(jwt::Algorithm::RS256);
-        // Try to create a DecodingKey from the public key
-        let decoding_key = match DecodingKey::from_rsa_pem(&public_key) {
-            Ok(key) => key,
-            Err(err) => {
-                eprintln!("Error creating decoding key: {}", err);
-                return Err(Error::PublicKeyLoadError {
-                    message: (err.to_string()),
-                });
-            }
-        };
+        let verification_key = verification_key(keys, token)?;
+        let decoding_key = &verification_key.decoding_key;
         // return false if the token is not valid
-        match jwt::decode::<RefreshToken>(&token, &decoding_key, &validation) {
-            Ok(val) => {
-                let token_data = val.claims;
-                Ok(token_data)
-            }
-            Err(e) => match e {
-                // check if ExpiredSignature
-                _ if e.to_string().contains("ExpiredSignature") => {
-                    return Err(Error::ExpiredSignature {
-                        message: "Expired signature".to_string(),
-                    })
-                }
-                // check if InvalidSignature
-                _ if e.to_string().contains("InvalidSignature") => {
-                    return Err(Error::SignatureVerificationError {
-                        message: "Invalid signature".to_string(),
-                    })
-                }
-                _ => {
-                    return Err(Error::InvalidToken {
-                        message: "Invalid token".to_string(),
-                    })
-                }
+        match jwt::decode::<RefreshToken>(&token, decoding_key, &validation) {
+            Ok(val) if val.claims.token_type == "refresh" => Ok(val.claims),
+            Ok(_) => Err(Error::InvalidToken {
+                message: "Invalid token".to_string(),
+            }),
+            Err(e) => match e.kind() {
+                jwt::errors::ErrorKind::ExpiredSignature => Err(Error::ExpiredSignature {
+                    message: "Expired signature".to_string(),
+                }),
+                jwt::errors::ErrorKind::InvalidSignature => Err(Error::SignatureVerificationError {
+                    message: "Invalid signature".to_string(),
+                }),
+                _ => Err(Error::InvalidToken {
+                    message: "Invalid token".to_string(),
+                }),
             },
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User::new("Ada", "ada@example.com", "user", "")
+    }
+
+    #[test]
+    fn id_token_does_not_pass_as_refresh_token() {
+        let keys = KeyCache::for_tests();
+        let token = IDToken::new(&user()).sign(&keys).unwrap();
+        assert!(IDToken::verify(&keys, &token).is_ok());
+        assert!(matches!(
+            RefreshToken::verify(&keys, &token),
+            Err(Error::InvalidToken { .. })
+        ));
+    }
+
+    #[test]
+    fn refresh_token_does_not_pass_as_id_token() {
+        let keys = KeyCache::for_tests();
+        let token = RefreshToken::new("u1").sign(&keys).unwrap();
+        assert!(RefreshToken::verify(&keys, &token).is_ok());
+        assert!(matches!(
+            IDToken::verify(&keys, &token),
+            Err(Error::InvalidToken { .. })
+        ));
+    }
+
+    #[test]
+    fn access_token_passes_as_neither() {
+        let keys = KeyCache::for_tests();
+        let token = AccessToken::new("client", "read", 60).sign(&keys).unwrap();
+        assert!(IDToken::verify(&keys, &token).is_err());
+        assert!(RefreshToken::verify(&keys, &token).is_err());
+    }
+}
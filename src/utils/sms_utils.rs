@@ -0,0 +1,76 @@
+use std::{env, fs::OpenOptions, io::Write};
+
+use async_trait::async_trait;
+
+use crate::errors::{Error, Result};
+
+// Delivers text messages. Add an implementation for a real provider and pick
+// it in `sender`, the rest of the code only sees `Sms`.
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+    async fn send(&self, phone: &str, body: &str) -> Result<()>;
+}
+
+// prints the messages, for development
+pub struct StdoutSender;
+
+#[async_trait]
+impl SmsSender for StdoutSender {
+    async fn send(&self, phone: &str, body: &str) -> Result<()> {
+        println!(">> SMS to {}: {}", phone, body);
+        Ok(())
+    }
+}
+
+// appends the messages to a file, one per line, for tests
+pub struct FileSender {
+    pub path: String,
+}
+
+#[async_trait]
+impl SmsSender for FileSender {
+    async fn send(&self, phone: &str, body: &str) -> Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path);
+        match file.and_then(|mut file| writeln!(file, "{}\t{}", phone, body)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::ServerError {
+                message: format!("Could not write the SMS to {}: {}", self.path, e),
+            }),
+        }
+    }
+}
+
+// the sender is chosen with SMS_PROVIDER, stdout when it is not set
+fn sender() -> Result<Box<dyn SmsSender>> {
+    match env::var("SMS_PROVIDER").unwrap_or_default().as_str() {
+        "" | "stdout" => Ok(Box::new(StdoutSender)),
+        "file" => Ok(Box::new(FileSender {
+            path: env::var("SMS_FILE").unwrap_or_else(|_| "sms.log".to_string()),
+        })),
+        provider => Err(Error::ServerError {
+            message: format!("Unknown SMS_PROVIDER: {}", provider),
+        }),
+    }
+}
+
+pub struct Sms {
+    pub phone: String,
+    pub body: String,
+}
+
+impl Sms {
+    pub fn new(phone: &str, body: &str) -> Self {
+        Sms {
+            phone: phone.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    pub async fn send(&self) -> Result<()> {
+        let sender = match sender() {
+            Ok(sender) => sender,
+            Err(e) => return Err(e),
+        };
+        sender.send(&self.phone, &self.body).await
+    }
+}
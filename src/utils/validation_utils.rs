@@ -7,6 +7,12 @@ impl Validation {
         re.is_match(email)
     }
 
+    pub fn phone(phone: &str) -> bool {
+        // E.164, a + and up to 15 digits with the country code first
+        let re = regex::Regex::new(r"^\+[1-9][0-9]{1,14}$").unwrap();
+        re.is_match(phone)
+    }
+
     pub fn password(password: &str) -> bool {
         // Minimum length requirement
         let min_length = 8;
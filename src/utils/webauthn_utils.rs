@@ -0,0 +1,256 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use openssl::{
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    rsa::Rsa,
+    sha::sha256,
+    sign::Verifier,
+};
+use serde::Deserialize;
+
+// authenticator data flags (WebAuthn section 6.1)
+pub const FLAG_USER_PRESENT: u8 = 0x01;
+pub const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// COSE algorithms we accept, every authenticator supports at least one of them
+pub const ALG_ES256: i64 = -7;
+pub const ALG_RS256: i64 = -257;
+
+// the fields of the client data we check, the rest is ignored
+#[derive(Deserialize, Debug)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub challenge: String,
+    pub origin: String,
+}
+
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    // DER encoded SubjectPublicKeyInfo, ready for openssl
+    pub public_key: Vec<u8>,
+    pub alg: i64,
+}
+
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+// browsers send base64url, some libraries still pad it
+pub fn decode_base64url(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()
+}
+
+pub fn encode_base64url(value: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(value)
+}
+
+pub fn rp_id_hash(rp_id: &str) -> Vec<u8> {
+    sha256(rp_id.as_bytes()).to_vec()
+}
+
+pub fn parse_client_data(client_data_json: &[u8]) -> Option<ClientData> {
+    serde_json::from_slice(client_data_json).ok()
+}
+
+// returns the attestation format and the authenticator data of an attestation object
+pub fn parse_attestation_object(attestation_object: &[u8]) -> Option<(String, Vec<u8>)> {
+    let value: Value = ciborium::de::from_reader(attestation_object).ok()?;
+    let mut fmt = None;
+    let mut auth_data = None;
+    for (key, value) in value.into_map().ok()? {
+        match key.as_text() {
+            Some("fmt") => fmt = value.into_text().ok(),
+            Some("authData") => auth_data = value.into_bytes().ok(),
+            _ => {}
+        }
+    }
+    Some((fmt?, auth_data?))
+}
+
+pub fn parse_authenticator_data(data: &[u8]) -> Option<AuthenticatorData> {
+    if data.len() < 37 {
+        return None;
+    }
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes(data[33..37].try_into().ok()?);
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // 16 bytes aaguid, 2 bytes length, the credential id and then the COSE key
+        let rest = data.get(37 + 16..)?;
+        let id_length = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+        let credential_id = rest.get(2..2 + id_length)?.to_vec();
+        let mut cose_key = rest.get(2 + id_length..)?;
+        let cose_key: Value = ciborium::de::from_reader(&mut cose_key).ok()?;
+        let (public_key, alg) = cose_key_to_der(cose_key)?;
+        Some(AttestedCredential {
+            credential_id,
+            public_key,
+            alg,
+        })
+    } else {
+        None
+    };
+
+    Some(AuthenticatorData {
+        rp_id_hash: data[..32].to_vec(),
+        flags,
+        sign_count,
+        attested_credential,
+    })
+}
+
+// turns an EC2 P-256 or RSA COSE key (RFC 8152) into a DER public key
+fn cose_key_to_der(cose_key: Value) -> Option<(Vec<u8>, i64)> {
+    let mut kty = None;
+    let mut alg = None;
+    let mut params: Vec<(i64, Value)> = Vec::new();
+    for (key, value) in cose_key.into_map().ok()? {
+        let label: i64 = key.as_integer()?.try_into().ok()?;
+        match label {
+            1 => kty = value.as_integer().and_then(|v| i64::try_from(v).ok()),
+            3 => alg = value.as_integer().and_then(|v| i64::try_from(v).ok()),
+            _ => params.push((label, value)),
+        }
+    }
+    let param = |label: i64| -> Option<Vec<u8>> {
+        params
+            .iter()
+            .find(|(l, _)| *l == label)
+            .and_then(|(_, v)| v.as_bytes().cloned())
+    };
+
+    let pkey = match (kty?, alg?) {
+        // EC2 on P-256
+        (2, ALG_ES256) => {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).ok()?;
+            let x = BigNum::from_slice(&param(-2)?).ok()?;
+            let y = BigNum::from_slice(&param(-3)?).ok()?;
+            let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y).ok()?;
+            PKey::from_ec_key(key).ok()?
+        }
+        (3, ALG_RS256) => {
+            let n = BigNum::from_slice(&param(-1)?).ok()?;
+            let e = BigNum::from_slice(&param(-2)?).ok()?;
+            PKey::from_rsa(Rsa::from_public_components(n, e).ok()?).ok()?
+        }
+        _ => return None,
+    };
+    Some((pkey.public_key_to_der().ok()?, alg?))
+}
+
+// the signature covers the authenticator data and the hash of the client data
+pub fn verify_signature(
+    public_key: &[u8],
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> bool {
+    let pkey = match PKey::public_key_from_der(public_key) {
+        Ok(pkey) => pkey,
+        Err(_) => return false,
+    };
+    let mut verifier = match Verifier::new(MessageDigest::sha256(), &pkey) {
+        Ok(verifier) => verifier,
+        Err(_) => return false,
+    };
+    if verifier.update(authenticator_data).is_err()
+        || verifier.update(&sha256(client_data_json)).is_err()
+    {
+        return false;
+    }
+    verifier.verify(signature).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::webauthn_model::{AssertionCredential, RegistrationCredential};
+
+    // made by tests/fixtures/webauthn/generate.py
+    const REGISTRATION: &str = include_str!("../../tests/fixtures/webauthn/registration.json");
+    const ASSERTION: &str = include_str!("../../tests/fixtures/webauthn/assertion.json");
+
+    fn registration() -> RegistrationCredential {
+        serde_json::from_str(REGISTRATION).unwrap()
+    }
+
+    fn assertion() -> AssertionCredential {
+        serde_json::from_str(ASSERTION).unwrap()
+    }
+
+    // the authenticator data of the registration, with the attested credential
+    fn registration_auth_data() -> Vec<u8> {
+        let attestation_object = decode_base64url(&registration().response.attestation_object).unwrap();
+        let (fmt, auth_data) = parse_attestation_object(&attestation_object).unwrap();
+        assert_eq!(fmt, "none");
+        auth_data
+    }
+
+    #[test]
+    fn parses_the_attested_credential() {
+        let auth_data = parse_authenticator_data(&registration_auth_data()).unwrap();
+        assert_eq!(auth_data.rp_id_hash, rp_id_hash("localhost"));
+        assert_eq!(auth_data.flags & (FLAG_USER_PRESENT | FLAG_USER_VERIFIED), FLAG_USER_PRESENT | FLAG_USER_VERIFIED);
+        assert_eq!(auth_data.sign_count, 0);
+
+        let attested = auth_data.attested_credential.unwrap();
+        assert_eq!(encode_base64url(&attested.credential_id), registration().id);
+        assert_eq!(attested.alg, ALG_ES256);
+        let pkey = PKey::public_key_from_der(&attested.public_key).unwrap();
+        assert_eq!(pkey.ec_key().unwrap().group().curve_name(), Some(Nid::X9_62_PRIME256V1));
+    }
+
+    #[test]
+    fn parses_the_assertion_data() {
+        let auth_data = decode_base64url(&assertion().response.authenticator_data).unwrap();
+        let auth_data = parse_authenticator_data(&auth_data).unwrap();
+        assert_eq!(auth_data.rp_id_hash, rp_id_hash("localhost"));
+        assert_eq!(auth_data.sign_count, 1);
+        assert!(auth_data.attested_credential.is_none());
+
+        let client_data = parse_client_data(&decode_base64url(&assertion().response.client_data_json).unwrap()).unwrap();
+        assert_eq!(client_data.type_, "webauthn.get");
+        assert_eq!(client_data.origin, "http://localhost:8080");
+    }
+
+    #[test]
+    fn verifies_the_assertion_signature_with_the_attested_key() {
+        let public_key = parse_authenticator_data(&registration_auth_data())
+            .unwrap()
+            .attested_credential
+            .unwrap()
+            .public_key;
+        let response = assertion().response;
+        let auth_data = decode_base64url(&response.authenticator_data).unwrap();
+        let client_data_json = decode_base64url(&response.client_data_json).unwrap();
+        let signature = decode_base64url(&response.signature).unwrap();
+        assert!(verify_signature(&public_key, &auth_data, &client_data_json, &signature));
+
+        // any change to what was signed breaks it
+        let mut other_auth_data = auth_data.clone();
+        other_auth_data[36] += 1;
+        assert!(!verify_signature(&public_key, &other_auth_data, &client_data_json, &signature));
+        let other_client_data = String::from_utf8(client_data_json.clone()).unwrap().replace("8080", "8081");
+        assert!(!verify_signature(&public_key, &auth_data, other_client_data.as_bytes(), &signature));
+        assert!(!verify_signature(&public_key, &auth_data, &client_data_json, &signature[1..]));
+    }
+
+    #[test]
+    fn rejects_truncated_authenticator_data() {
+        let auth_data = registration_auth_data();
+        // shorter than the fixed part, cut in the credential id and cut in the COSE key
+        for length in [0, 36, 37 + 16 + 2 + 10, auth_data.len() - 1] {
+            assert!(parse_authenticator_data(&auth_data[..length]).is_none(), "{} bytes", length);
+        }
+        assert!(parse_attestation_object(&decode_base64url(&registration().response.attestation_object).unwrap()[..20]).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+#!/usr/bin/env python3
+# Writes the signed SAML responses the tests of src/utils/saml_utils.rs check,
+# and the metadata of the identity provider that signed them. They are
+# canonicalized with the C14N 2.0 of the Python standard library rather than
+# our code. For these documents it gives the same bytes as exclusive
+# canonicalization, so the tests also check ours against it.
+#
+#     python3 tests/fixtures/saml/generate.py
+#
+# Needs the cryptography package. Every run makes a new key, so the fixtures
+# are only ever regenerated together.
+import base64
+import datetime
+import hashlib
+import os
+import xml.etree.ElementTree as ET
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+from cryptography.x509.oid import NameOID
+
+DIR = os.path.dirname(os.path.abspath(__file__))
+
+NS_PROTOCOL = "urn:oasis:names:tc:SAML:2.0:protocol"
+NS_ASSERTION = "urn:oasis:names:tc:SAML:2.0:assertion"
+NS_DSIG = "http://www.w3.org/2000/09/xmldsig#"
+
+IDP = "https://idp.example.com"
+SP = "https://sp.example.com/saml/acme/metadata"
+ACS = "https://sp.example.com/saml/acme/acs"
+REQUEST = "_request1"
+
+ISSUED = "2024-01-01T00:00:00Z"
+VALID_TILL = "2099-01-01T00:00:00Z"
+EXPIRED = "2024-01-01T00:05:00Z"
+
+key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+name = x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, "idp.example.com")])
+certificate = (
+    x509.CertificateBuilder()
+    .subject_name(name)
+    .issuer_name(name)
+    .public_key(key.public_key())
+    .serial_number(x509.random_serial_number())
+    .not_valid_before(datetime.datetime(2024, 1, 1))
+    .not_valid_after(datetime.datetime(2099, 1, 1))
+    .sign(key, hashes.SHA256())
+)
+certificate_b64 = base64.b64encode(certificate.public_bytes(serialization.Encoding.DER)).decode()
+
+
+# the canonical form of an element written out with the namespaces it uses,
+# without its signature
+def canonicalize(xml):
+    return ET.canonicalize(xml, exclude_tags={"{%s}Signature" % NS_DSIG}).encode()
+
+
+def signature(element_id, canonical):
+    digest = base64.b64encode(hashlib.sha256(canonical).digest()).decode()
+    signed_info = (
+        f'<ds:SignedInfo xmlns:ds="{NS_DSIG}">'
+        '<ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"></ds:CanonicalizationMethod>'
+        '<ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"></ds:SignatureMethod>'
+        f'<ds:Reference URI="#{element_id}">'
+        "<ds:Transforms>"
+        '<ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"></ds:Transform>'
+        '<ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"></ds:Transform>'
+        "</ds:Transforms>"
+        '<ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"></ds:DigestMethod>'
+        f"<ds:DigestValue>{digest}</ds:DigestValue>"
+        "</ds:Reference>"
+        "</ds:SignedInfo>"
+    )
+    value = key.sign(canonicalize(signed_info), padding.PKCS1v15(), hashes.SHA256())
+    return (
+        f'<ds:Signature xmlns:ds="{NS_DSIG}">'
+        + signed_info.replace(f' xmlns:ds="{NS_DSIG}"', "", 1)
+        + f"<ds:SignatureValue>{base64.b64encode(value).decode()}</ds:SignatureValue>"
+        + f"<ds:KeyInfo><ds:X509Data><ds:X509Certificate>{certificate_b64}</ds:X509Certificate></ds:X509Data></ds:KeyInfo>"
+        + "</ds:Signature>"
+    )
+
+
+# the signature goes right after the Issuer
+def signed(element_id, xml):
+    issuer_end = xml.index("</saml:Issuer>") + len("</saml:Issuer>")
+    return xml[:issuer_end] + signature(element_id, canonicalize(xml)) + xml[issuer_end:]
+
+
+def assertion(
+    sign=True,
+    name_id="ada@example.com",
+    audience=SP,
+    recipient=ACS,
+    in_response_to=REQUEST,
+    confirmation_valid_till=VALID_TILL,
+    valid_till=VALID_TILL,
+):
+    xml = f"""<saml:Assertion xmlns:saml="{NS_ASSERTION}" ID="_assertion1" IssueInstant="{ISSUED}" Version="2.0">
+    <saml:Issuer>{IDP}</saml:Issuer>
+    <saml:Subject>
+      <saml:NameID Format="urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress">{name_id}</saml:NameID>
+      <saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer">
+        <saml:SubjectConfirmationData InResponseTo="{in_response_to}" NotOnOrAfter="{confirmation_valid_till}" Recipient="{recipient}"/>
+      </saml:SubjectConfirmation>
+    </saml:Subject>
+    <saml:Conditions NotBefore="{ISSUED}" NotOnOrAfter="{valid_till}">
+      <saml:AudienceRestriction>
+        <saml:Audience>{audience}</saml:Audience>
+      </saml:AudienceRestriction>
+    </saml:Conditions>
+    <saml:AttributeStatement>
+      <saml:Attribute FriendlyName="displayName" Name="urn:oid:2.16.840.1.113730.3.1.241">
+        <saml:AttributeValue>Ada Lovelace</saml:AttributeValue>
+      </saml:Attribute>
+    </saml:AttributeStatement>
+  </saml:Assertion>"""
+    return signed("_assertion1", xml) if sign else xml
+
+
+def response(assertion_xml, sign=False):
+    xml = f"""<samlp:Response xmlns:samlp="{NS_PROTOCOL}" xmlns:saml="{NS_ASSERTION}" Destination="{ACS}" ID="_response1" InResponseTo="{REQUEST}" IssueInstant="{ISSUED}" Version="2.0">
+  <saml:Issuer>{IDP}</saml:Issuer>
+  <samlp:Status>
+    <samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/>
+  </samlp:Status>
+  {assertion_xml}
+</samlp:Response>
+"""
+    return signed("_response1", xml) if sign else xml
+
+
+def write(name, xml):
+    with open(os.path.join(DIR, name), "w") as file:
+        file.write(xml)
+
+
+write(
+    "metadata.xml",
+    f"""<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" xmlns:ds="{NS_DSIG}" entityID="{IDP}">
+  <md:IDPSSODescriptor protocolSupportEnumeration="{NS_PROTOCOL}">
+    <md:KeyDescriptor use="signing">
+      <ds:KeyInfo><ds:X509Data><ds:X509Certificate>{certificate_b64}</ds:X509Certificate></ds:X509Data></ds:KeyInfo>
+    </md:KeyDescriptor>
+    <md:SingleSignOnService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect" Location="{IDP}/sso"/>
+  </md:IDPSSODescriptor>
+</md:EntityDescriptor>
+""",
+)
+write("signed_assertion.xml", response(assertion()))
+write("signed_response.xml", response(assertion(sign=False), sign=True))
+# the identity provider vouched for the whole value, the comment is added on the way
+write(
+    "comment_in_name_id.xml",
+    response(assertion(name_id="ada@example.com.evil.com")).replace(
+        "ada@example.com.evil.com", "ada@example.com<!---->.evil.com"
+    ),
+)
+write("other_audience.xml", response(assertion(audience="https://other.example.com/metadata")))
+write("other_recipient.xml", response(assertion(recipient="https://other.example.com/acs")))
+write("other_request.xml", response(assertion(in_response_to="_request2")))
+write("expired.xml", response(assertion(valid_till=EXPIRED)))
+write("expired_confirmation.xml", response(assertion(confirmation_valid_till=EXPIRED)))
@@ -0,0 +1,111 @@
+#!/usr/bin/env python3
+# Writes the passkey registration and sign in the tests of src/core/webauthn.rs
+# and src/utils/webauthn_utils.rs check: an ES256 credential with the "none"
+# attestation and an assertion made with it. They are for the relying party
+# the server is by default, localhost with the origin http://localhost:8080.
+#
+#     python3 tests/fixtures/webauthn/generate.py
+#
+# Needs the cryptography package. Every run makes a new key, so the fixtures
+# are only ever regenerated together.
+import base64
+import hashlib
+import json
+import os
+import struct
+
+from cryptography.hazmat.primitives import hashes
+from cryptography.hazmat.primitives.asymmetric import ec
+
+DIR = os.path.dirname(os.path.abspath(__file__))
+
+RP_ID = "localhost"
+ORIGIN = "http://localhost:8080"
+
+FLAG_USER_PRESENT = 0x01
+FLAG_USER_VERIFIED = 0x04
+FLAG_ATTESTED_CREDENTIAL_DATA = 0x40
+
+
+def b64url(data):
+    return base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+
+
+# the part of CBOR (RFC 8949) the attestation object and the COSE key use
+def cbor(value):
+    def head(major, length):
+        if length < 24:
+            return bytes([major << 5 | length])
+        if length < 0x100:
+            return bytes([major << 5 | 24, length])
+        return bytes([major << 5 | 25]) + struct.pack(">H", length)
+
+    if isinstance(value, int):
+        return head(0, value) if value >= 0 else head(1, -1 - value)
+    if isinstance(value, bytes):
+        return head(2, len(value)) + value
+    if isinstance(value, str):
+        return head(3, len(value.encode())) + value.encode()
+    if isinstance(value, dict):
+        return head(5, len(value)) + b"".join(cbor(k) + cbor(v) for k, v in value.items())
+    raise TypeError(value)
+
+
+key = ec.generate_private_key(ec.SECP256R1())
+numbers = key.public_key().public_numbers()
+# EC2 key on P-256 for ES256 (RFC 8152 section 13.1.1)
+cose_key = {1: 2, 3: -7, -1: 1, -2: numbers.x.to_bytes(32, "big"), -3: numbers.y.to_bytes(32, "big")}
+credential_id = hashlib.sha256(b"webauthn fixture credential").digest()
+rp_id_hash = hashlib.sha256(RP_ID.encode()).digest()
+
+
+def client_data(ceremony, challenge):
+    return json.dumps(
+        {"type": ceremony, "challenge": b64url(challenge), "origin": ORIGIN, "crossOrigin": False},
+        separators=(",", ":"),
+    ).encode()
+
+
+def write(name, credential):
+    with open(os.path.join(DIR, name), "w") as file:
+        json.dump(credential, file, indent=2)
+        file.write("\n")
+
+
+auth_data = (
+    rp_id_hash
+    + bytes([FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL_DATA])
+    + struct.pack(">I", 0)
+    + bytes(16)
+    + struct.pack(">H", len(credential_id))
+    + credential_id
+    + cbor(cose_key)
+)
+write(
+    "registration.json",
+    {
+        "id": b64url(credential_id),
+        "type": "public-key",
+        "response": {
+            "clientDataJSON": b64url(client_data("webauthn.create", b"registration challenge")),
+            "attestationObject": b64url(cbor({"fmt": "none", "attStmt": {}, "authData": auth_data})),
+        },
+    },
+)
+
+auth_data = rp_id_hash + bytes([FLAG_USER_PRESENT | FLAG_USER_VERIFIED]) + struct.pack(">I", 1)
+client_data_json = client_data("webauthn.get", b"authentication challenge")
+signature = key.sign(auth_data + hashlib.sha256(client_data_json).digest(), ec.ECDSA(hashes.SHA256()))
+write(
+    "assertion.json",
+    {
+        "id": b64url(credential_id),
+        "type": "public-key",
+        "response": {
+            "clientDataJSON": b64url(client_data_json),
+            "authenticatorData": b64url(auth_data),
+            "signature": b64url(signature),
+            "userHandle": None,
+        },
+    },
+)